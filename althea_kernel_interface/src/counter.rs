@@ -1,3 +1,7 @@
+use super::ebpf;
+use super::iptables::add_iptables_rule;
+use super::nftables;
+use super::nftables::FirewallBackend;
 use super::KernelInterface;
 
 use std::collections::HashMap;
@@ -8,7 +12,7 @@ use regex::Regex;
 
 use failure::Error;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub enum FilterTarget {
     Input,
     Output,
@@ -118,74 +122,94 @@ add zxcv 1234:5678:9801:2345:6789:0123:4567:8902,wg0 packets 123456789 bytes 987
     }
 }
 
-impl dyn KernelInterface {
-    pub fn init_counter(&self, target: &FilterTarget) -> Result<(), Error> {
-        self.run_command(
-            "ipset",
-            &[
-                "create",
-                target.set_name(),
-                "hash:net,iface",
-                "family",
-                "inet6",
-                "counters",
-            ],
-        )?;
-        self.add_iptables_rule(
-            "ip6tables",
-            &[
-                "-w",
-                "-I",
-                target.table(),
-                "1",
-                "-m",
-                "set",
-                "!",
-                "--match-set",
-                target.set_name(),
-                &format!("dst,{}", target.interface()),
-                "-j",
-                "SET",
-                "--add-set",
-                target.set_name(),
-                &format!("dst,{}", target.interface()),
-            ],
-        )?;
-        Ok(())
+pub(crate) fn init_counter<K: KernelInterface + ?Sized>(
+    ki: &K,
+    target: &FilterTarget,
+) -> Result<(), Error> {
+    if ebpf::enabled() {
+        match ebpf::init_counter(ki, target) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                error!("Failed to set up eBPF counters, falling back {:?}", e);
+                ebpf::set_enabled(false);
+            }
+        }
     }
+    if ki.firewall_backend() == FirewallBackend::Nftables {
+        return nftables::init_counter(ki, target);
+    }
+    ki.run_command(
+        "ipset",
+        &[
+            "create",
+            target.set_name(),
+            "hash:net,iface",
+            "family",
+            "inet6",
+            "counters",
+        ],
+    )?;
+    add_iptables_rule(
+        ki,
+        "ip6tables",
+        &[
+            "-w",
+            "-I",
+            target.table(),
+            "1",
+            "-m",
+            "set",
+            "!",
+            "--match-set",
+            target.set_name(),
+            &format!("dst,{}", target.interface()),
+            "-j",
+            "SET",
+            "--add-set",
+            target.set_name(),
+            &format!("dst,{}", target.interface()),
+        ],
+    )?;
+    Ok(())
+}
 
-    pub fn read_counters(
-        &self,
-        target: &FilterTarget,
-    ) -> Result<HashMap<(IpAddr, String), u64>, Error> {
-        self.run_command(
-            "ipset",
-            &[
-                "create",
-                &format!("tmp_{}", target.set_name()),
-                "hash:net,iface",
-                "family",
-                "inet6",
-                "counters",
-            ],
-        )?;
+pub(crate) fn read_counters<K: KernelInterface + ?Sized>(
+    ki: &K,
+    target: &FilterTarget,
+) -> Result<HashMap<(IpAddr, String), u64>, Error> {
+    if ebpf::enabled() {
+        return ebpf::read_counters(ki, target);
+    }
+    if ki.firewall_backend() == FirewallBackend::Nftables {
+        return nftables::read_counters(ki, target);
+    }
+    ki.run_command(
+        "ipset",
+        &[
+            "create",
+            &format!("tmp_{}", target.set_name()),
+            "hash:net,iface",
+            "family",
+            "inet6",
+            "counters",
+        ],
+    )?;
 
-        self.run_command(
-            "ipset",
-            &[
-                "swap",
-                &format!("tmp_{}", target.set_name()),
-                target.set_name(),
-            ],
-        )?;
+    ki.run_command(
+        "ipset",
+        &[
+            "swap",
+            &format!("tmp_{}", target.set_name()),
+            target.set_name(),
+        ],
+    )?;
 
-        let output = self.run_command("ipset", &["save", &format!("tmp_{}", target.set_name())])?;
-        let res = parse_ipset(&String::from_utf8(output.stdout)?);
-        trace!("ipset parsed into {:?}", res);
+    let output = ki.run_command("ipset", &["save", &format!("tmp_{}", target.set_name())])?;
+    let res = parse_ipset(&String::from_utf8(output.stdout)?);
+    trace!("ipset parsed into {:?}", res);
 
-        self.run_command("ipset", &["destroy", &format!("tmp_{}", target.set_name())])?;
-        res
-    }
+    ki.run_command("ipset", &["destroy", &format!("tmp_{}", target.set_name())])?;
+    res
 }
 
 #[test]
@@ -0,0 +1,643 @@
+//! Traffic counters kept by small eBPF programs attached to the tc hooks of every tunnel, an
+//! alternative to the ipset counters in counter.rs that is much cheaper to read since we only
+//! walk the destinations that actually saw traffic instead of saving and parsing a whole ipset.
+//!
+//! Each direction gets a program and a hash map counting bytes and packets per (slot, interface,
+//! destination). The slot is read from a one element array map on every packet, reading the
+//! counters flips the slot and then drains the entries of the old one, so packets counted while
+//! we read land in the new slot. A packet whose program read the slot just before the flip can
+//! still add to the old one, so the drain waits SLOT_GRACE first, the programs run to completion
+//! in softirq context and are long done by then. Entries are taken out with an atomic lookup and
+//! delete where the kernel supports it for hash maps (5.14 and newer), on older kernels the
+//! lookup and delete are separate calls that rely on the grace period alone.
+//!
+//! tc sees traffic to us and traffic we forward alike, so everything is counted under the Input
+//! and Output targets and the forward targets read as empty. The traffic watchers add the two
+//! together so the totals come out the same as with iptables.
+//!
+//! The programs are assembled by hand below since we can't ship a compiler for them, see
+//! `counter_program` for what they do.
+
+use super::counter::FilterTarget;
+use super::netlink;
+use super::KernelInterface;
+use failure::Error;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_MAP_LOOKUP_ELEM: libc::c_long = 1;
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_MAP_DELETE_ELEM: libc::c_long = 3;
+const BPF_MAP_GET_NEXT_KEY: libc::c_long = 4;
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_MAP_LOOKUP_AND_DELETE_ELEM: libc::c_long = 21;
+
+const BPF_MAP_TYPE_HASH: u32 = 1;
+const BPF_MAP_TYPE_ARRAY: u32 = 2;
+const BPF_PROG_TYPE_SCHED_CLS: u32 = 3;
+const BPF_ANY: u64 = 0;
+const BPF_NOEXIST: i32 = 1;
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+
+const BPF_FUNC_MAP_LOOKUP_ELEM: i32 = 1;
+const BPF_FUNC_MAP_UPDATE_ELEM: i32 = 2;
+const BPF_FUNC_SKB_LOAD_BYTES: i32 = 26;
+
+/// offsets into struct __sk_buff
+const SKB_LEN: i16 = 0;
+const SKB_PROTOCOL: i16 = 16;
+const SKB_IFINDEX: i16 = 40;
+/// offset of the destination address in an ipv6 header, tunnels have no link layer header
+const IPV6_DADDR: i32 = 24;
+const ETH_P_IPV6: u16 = 0x86dd;
+
+const KEY_SIZE: usize = 24;
+const VALUE_SIZE: usize = 16;
+const MAX_ENTRIES: u32 = 65536;
+const BPF_LOG_SIZE: usize = 65536;
+/// How long after flipping the slot we wait for programs that read the old one to finish
+const SLOT_GRACE: Duration = Duration::from_millis(10);
+
+static EBPF_ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref COUNTERS: Mutex<HashMap<FilterTarget, Counter>> = Mutex::new(HashMap::new());
+}
+
+pub(crate) fn set_enabled(enabled: bool) {
+    EBPF_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub(crate) fn enabled() -> bool {
+    EBPF_ENABLED.load(Ordering::SeqCst)
+}
+
+/// A bpf object, closed on drop
+struct Fd(libc::c_int);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+/// The arguments of the bpf syscall are a union of structs, we only ever need to set a few u32
+/// and u64 fields at known offsets
+struct Attr([u8; 128]);
+
+impl Attr {
+    fn new() -> Attr {
+        Attr([0; 128])
+    }
+
+    fn u32(mut self, offset: usize, value: u32) -> Attr {
+        self.0[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn u64(mut self, offset: usize, value: u64) -> Attr {
+        self.0[offset..offset + 8].copy_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn ptr<T>(self, offset: usize, value: *const T) -> Attr {
+        self.u64(offset, value as usize as u64)
+    }
+}
+
+fn bpf(cmd: libc::c_long, attr: &Attr) -> io::Result<libc::c_int> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr.0.as_ptr(),
+            attr.0.len() as libc::c_uint,
+        )
+    };
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res as libc::c_int)
+    }
+}
+
+fn create_map(
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+) -> Result<Fd, Error> {
+    let attr = Attr::new()
+        .u32(0, map_type)
+        .u32(4, key_size)
+        .u32(8, value_size)
+        .u32(12, max_entries);
+    Ok(Fd(bpf(BPF_MAP_CREATE, &attr)?))
+}
+
+fn map_attr(map: &Fd, key: &[u8], value: *const u8) -> Attr {
+    Attr::new()
+        .u32(0, map.0 as u32)
+        .ptr(8, key.as_ptr())
+        .ptr(16, value)
+}
+
+fn map_lookup(map: &Fd, key: &[u8], value: &mut [u8]) -> io::Result<()> {
+    bpf(BPF_MAP_LOOKUP_ELEM, &map_attr(map, key, value.as_mut_ptr())).map(|_| ())
+}
+
+fn map_update(map: &Fd, key: &[u8], value: &[u8]) -> io::Result<()> {
+    bpf(
+        BPF_MAP_UPDATE_ELEM,
+        &map_attr(map, key, value.as_ptr()).u64(24, BPF_ANY),
+    )
+    .map(|_| ())
+}
+
+fn map_delete(map: &Fd, key: &[u8]) -> io::Result<()> {
+    bpf(
+        BPF_MAP_DELETE_ELEM,
+        &Attr::new().u32(0, map.0 as u32).ptr(8, key.as_ptr()),
+    )
+    .map(|_| ())
+}
+
+/// Removes an entry and returns its value, atomically if the kernel can do it for this map
+fn map_take(map: &Fd, key: &[u8], value: &mut [u8]) -> io::Result<()> {
+    match bpf(
+        BPF_MAP_LOOKUP_AND_DELETE_ELEM,
+        &map_attr(map, key, value.as_mut_ptr()),
+    ) {
+        Ok(_) => Ok(()),
+        Err(_) => {
+            map_lookup(map, key, value)?;
+            map_delete(map, key)
+        }
+    }
+}
+
+/// Returns every key in a map, the first call gets no key so that the kernel starts from the
+/// beginning
+fn map_keys(map: &Fd) -> io::Result<Vec<[u8; KEY_SIZE]>> {
+    let mut keys = Vec::new();
+    let mut next = [0u8; KEY_SIZE];
+    let mut attr = Attr::new().u32(0, map.0 as u32).ptr(16, next.as_mut_ptr());
+    loop {
+        match bpf(BPF_MAP_GET_NEXT_KEY, &attr) {
+            Ok(_) => {
+                keys.push(next);
+                let current = keys.last().unwrap().as_ptr();
+                attr = attr.ptr(8, current);
+            }
+            Err(ref e) if e.raw_os_error() == Some(libc::ENOENT) => return Ok(keys),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A single eBPF instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Insn {
+    code: u8,
+    dst: u8,
+    src: u8,
+    off: i16,
+    imm: i32,
+}
+
+impl Insn {
+    fn to_bytes(self) -> [u8; 8] {
+        // the registers share a byte as two bitfields, which end up in opposite halves on
+        // big endian machines
+        let regs = if cfg!(target_endian = "little") {
+            self.dst | self.src << 4
+        } else {
+            self.dst << 4 | self.src
+        };
+        let off = self.off.to_ne_bytes();
+        let imm = self.imm.to_ne_bytes();
+        [
+            self.code, regs, off[0], off[1], imm[0], imm[1], imm[2], imm[3],
+        ]
+    }
+}
+
+const R0: u8 = 0;
+const R1: u8 = 1;
+const R2: u8 = 2;
+const R3: u8 = 3;
+const R4: u8 = 4;
+const R6: u8 = 6;
+const R7: u8 = 7;
+const FP: u8 = 10;
+
+/// Just enough of an assembler to write the counter program, jumps go to labels that are
+/// resolved when the program is finished
+#[derive(Default)]
+struct Asm {
+    insns: Vec<Insn>,
+    labels: HashMap<&'static str, usize>,
+    jumps: Vec<(usize, &'static str)>,
+}
+
+impl Asm {
+    fn insn(&mut self, code: u8, dst: u8, src: u8, off: i16, imm: i32) -> &mut Asm {
+        self.insns.push(Insn {
+            code,
+            dst,
+            src,
+            off,
+            imm,
+        });
+        self
+    }
+
+    fn label(&mut self, name: &'static str) -> &mut Asm {
+        self.labels.insert(name, self.insns.len());
+        self
+    }
+
+    fn mov(&mut self, dst: u8, src: u8) -> &mut Asm {
+        self.insn(0xbf, dst, src, 0, 0)
+    }
+
+    fn mov_imm(&mut self, dst: u8, imm: i32) -> &mut Asm {
+        self.insn(0xb7, dst, 0, 0, imm)
+    }
+
+    fn add_imm(&mut self, dst: u8, imm: i32) -> &mut Asm {
+        self.insn(0x07, dst, 0, 0, imm)
+    }
+
+    /// dst = *(u32 *)(src + off)
+    fn load_u32(&mut self, dst: u8, src: u8, off: i16) -> &mut Asm {
+        self.insn(0x61, dst, src, off, 0)
+    }
+
+    /// *(u32 *)(dst + off) = src
+    fn store_u32(&mut self, dst: u8, off: i16, src: u8) -> &mut Asm {
+        self.insn(0x63, dst, src, off, 0)
+    }
+
+    /// *(u32 *)(dst + off) = imm
+    fn store_u32_imm(&mut self, dst: u8, off: i16, imm: i32) -> &mut Asm {
+        self.insn(0x62, dst, 0, off, imm)
+    }
+
+    /// *(u64 *)(dst + off) = src
+    fn store_u64(&mut self, dst: u8, off: i16, src: u8) -> &mut Asm {
+        self.insn(0x7b, dst, src, off, 0)
+    }
+
+    /// lock *(u64 *)(dst + off) += src
+    fn atomic_add_u64(&mut self, dst: u8, off: i16, src: u8) -> &mut Asm {
+        self.insn(0xdb, dst, src, off, 0)
+    }
+
+    /// Loads a map's file descriptor, the kernel swaps it for a pointer to the map
+    fn load_map(&mut self, dst: u8, map: &Fd) -> &mut Asm {
+        self.insn(0x18, dst, BPF_PSEUDO_MAP_FD, 0, map.0)
+            .insn(0, 0, 0, 0, 0)
+    }
+
+    /// dst = fp + off, a pointer to the stack
+    fn stack_ptr(&mut self, dst: u8, off: i32) -> &mut Asm {
+        self.mov(dst, FP).add_imm(dst, off)
+    }
+
+    fn call(&mut self, helper: i32) -> &mut Asm {
+        self.insn(0x85, 0, 0, 0, helper)
+    }
+
+    fn jump(&mut self, code: u8, dst: u8, imm: i32, label: &'static str) -> &mut Asm {
+        self.jumps.push((self.insns.len(), label));
+        self.insn(code, dst, 0, 0, imm)
+    }
+
+    fn jump_eq(&mut self, dst: u8, imm: i32, label: &'static str) -> &mut Asm {
+        self.jump(0x15, dst, imm, label)
+    }
+
+    fn jump_ne(&mut self, dst: u8, imm: i32, label: &'static str) -> &mut Asm {
+        self.jump(0x55, dst, imm, label)
+    }
+
+    fn exit(&mut self) -> &mut Asm {
+        self.insn(0x95, 0, 0, 0, 0)
+    }
+
+    fn finish(&mut self) -> Result<Vec<Insn>, Error> {
+        for &(pos, label) in &self.jumps {
+            let target = match self.labels.get(label) {
+                Some(target) => *target,
+                None => bail!("Jump to undefined label {}", label),
+            };
+            self.insns[pos].off = (target as i64 - pos as i64 - 1) as i16;
+        }
+        Ok(self.insns.clone())
+    }
+}
+
+/// The program counting one direction of traffic. The stack holds the counter key at fp-24
+/// (slot, ifindex, destination), a new counter value at fp-40 (bytes, packets) and the key of
+/// the slot map at fp-44
+fn counter_program(slot_map: &Fd, counter_map: &Fd) -> Result<Vec<Insn>, Error> {
+    Asm::default()
+        .mov(R6, R1)
+        // only ipv6 traffic is billed
+        .load_u32(R0, R6, SKB_PROTOCOL)
+        .jump_ne(R0, i32::from(ETH_P_IPV6.to_be()), "out")
+        // look up the active slot
+        .store_u32_imm(FP, -44, 0)
+        .load_map(R1, slot_map)
+        .stack_ptr(R2, -44)
+        .call(BPF_FUNC_MAP_LOOKUP_ELEM)
+        .jump_eq(R0, 0, "out")
+        .load_u32(R7, R0, 0)
+        .store_u32(FP, -24, R7)
+        .load_u32(R7, R6, SKB_IFINDEX)
+        .store_u32(FP, -20, R7)
+        // copy the destination address into the key
+        .mov(R1, R6)
+        .mov_imm(R2, IPV6_DADDR)
+        .stack_ptr(R3, -16)
+        .mov_imm(R4, 16)
+        .call(BPF_FUNC_SKB_LOAD_BYTES)
+        .jump_ne(R0, 0, "out")
+        .load_map(R1, counter_map)
+        .stack_ptr(R2, -24)
+        .call(BPF_FUNC_MAP_LOOKUP_ELEM)
+        .jump_ne(R0, 0, "add")
+        // first packet to this destination, create the counter
+        .load_u32(R1, R6, SKB_LEN)
+        .store_u64(FP, -40, R1)
+        .mov_imm(R1, 1)
+        .store_u64(FP, -32, R1)
+        .load_map(R1, counter_map)
+        .stack_ptr(R2, -24)
+        .stack_ptr(R3, -40)
+        .mov_imm(R4, BPF_NOEXIST)
+        .call(BPF_FUNC_MAP_UPDATE_ELEM)
+        .jump_eq(R0, 0, "out")
+        // another cpu created it first, add to theirs
+        .load_map(R1, counter_map)
+        .stack_ptr(R2, -24)
+        .call(BPF_FUNC_MAP_LOOKUP_ELEM)
+        .jump_eq(R0, 0, "out")
+        .label("add")
+        .load_u32(R1, R6, SKB_LEN)
+        .atomic_add_u64(R0, 0, R1)
+        .mov_imm(R1, 1)
+        .atomic_add_u64(R0, 8, R1)
+        .label("out")
+        // TC_ACT_OK, we only count
+        .mov_imm(R0, 0)
+        .exit()
+        .finish()
+}
+
+fn load_program(insns: &[Insn]) -> Result<Fd, Error> {
+    let code: Vec<u8> = insns.iter().flat_map(|i| i.to_bytes().to_vec()).collect();
+    let license = b"GPL\0";
+    let mut log = vec![0u8; BPF_LOG_SIZE];
+    let attr = Attr::new()
+        .u32(0, BPF_PROG_TYPE_SCHED_CLS)
+        .u32(4, insns.len() as u32)
+        .ptr(8, code.as_ptr())
+        .ptr(16, license.as_ptr())
+        .u32(24, 1)
+        .u32(28, log.len() as u32)
+        .ptr(32, log.as_mut_ptr());
+    match bpf(BPF_PROG_LOAD, &attr) {
+        Ok(fd) => Ok(Fd(fd)),
+        Err(e) => {
+            let end = log.iter().position(|b| *b == 0).unwrap_or(log.len());
+            bail!(
+                "Failed to load counter program {:?} {}",
+                e,
+                String::from_utf8_lossy(&log[..end])
+            )
+        }
+    }
+}
+
+/// The program and maps for one direction along with the interfaces it's attached to
+struct Counter {
+    slot_map: Fd,
+    counter_map: Fd,
+    program: Fd,
+    slot: u32,
+    ingress: bool,
+    /// interface index to name for every tunnel the program is attached to
+    attached: HashMap<u32, String>,
+}
+
+impl Counter {
+    fn new(ingress: bool) -> Result<Counter, Error> {
+        let slot_map = create_map(BPF_MAP_TYPE_ARRAY, 4, 4, 1)?;
+        let counter_map = create_map(
+            BPF_MAP_TYPE_HASH,
+            KEY_SIZE as u32,
+            VALUE_SIZE as u32,
+            MAX_ENTRIES,
+        )?;
+        let program = load_program(&counter_program(&slot_map, &counter_map)?)?;
+        Ok(Counter {
+            slot_map,
+            counter_map,
+            program,
+            slot: 0,
+            ingress,
+            attached: HashMap::new(),
+        })
+    }
+
+    /// Attaches the program to tunnels that were created since the last call. A tunnel that
+    /// can't be attached to, usually because it went away in the meantime, is skipped and tried
+    /// again on the next call rather than holding up the others
+    fn attach<K: KernelInterface + ?Sized>(&mut self, ki: &K) -> Result<(), Error> {
+        let mut current = HashMap::new();
+        for link in netlink::get_links()? {
+            if is_tunnel(&link.name) {
+                current.insert(link.index, link.name);
+            }
+        }
+        // interfaces that went away also took the program with them, their last counts are
+        // still read since we remember their names until then
+        for (index, name) in current {
+            if self.attached.contains_key(&index) {
+                self.attached.insert(index, name);
+                continue;
+            }
+            match self.attach_to(ki, index, &name) {
+                Ok(()) => {
+                    self.attached.insert(index, name);
+                }
+                Err(e) => error!("Failed to attach traffic counter to {} {:?}", name, e),
+            }
+        }
+        Ok(())
+    }
+
+    fn attach_to<K: KernelInterface + ?Sized>(
+        &self,
+        ki: &K,
+        index: u32,
+        name: &str,
+    ) -> Result<(), Error> {
+        let output = ki.run_command("tc", &["qdisc", "replace", "dev", name, "clsact"])?;
+        if !output.status.success() {
+            bail!(
+                "Failed to add clsact qdisc to {} {}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        netlink::attach_tc_bpf(index, self.ingress, self.program.0, "rita_counter")
+    }
+
+    /// Flips the active slot and drains the old one once nothing can be writing to it
+    fn read(&mut self) -> Result<HashMap<(IpAddr, String), u64>, Error> {
+        let old = self.slot;
+        self.slot ^= 1;
+        map_update(
+            &self.slot_map,
+            &0u32.to_ne_bytes(),
+            &self.slot.to_ne_bytes(),
+        )?;
+        thread::sleep(SLOT_GRACE);
+
+        let mut res = HashMap::new();
+        for key in map_keys(&self.counter_map)? {
+            if u32::from_ne_bytes([key[0], key[1], key[2], key[3]]) != old {
+                continue;
+            }
+            let mut value = [0u8; VALUE_SIZE];
+            map_take(&self.counter_map, &key, &mut value)?;
+
+            let (index, ip, bytes) = parse_entry(&key, &value);
+            match self.attached.get(&index) {
+                Some(name) => *res.entry((ip, name.clone())).or_insert(0) += bytes,
+                None => warn!("Traffic counted on unknown interface {}", index),
+            }
+        }
+
+        // forget interfaces that are gone now that their traffic has been read
+        let links: Vec<u32> = netlink::get_links()?.iter().map(|l| l.index).collect();
+        self.attached.retain(|index, _| links.contains(index));
+        Ok(res)
+    }
+}
+
+/// Tunnels are named wg followed by a number, the same interfaces the ipset counters report
+fn is_tunnel(name: &str) -> bool {
+    name.starts_with("wg") && name.len() > 2 && name[2..].chars().all(|c| c.is_ascii_digit())
+}
+
+/// Turns a counter map entry into (interface index, destination, bytes), counting the same 40
+/// bytes of overhead per packet as the ipset counters
+fn parse_entry(key: &[u8; KEY_SIZE], value: &[u8; VALUE_SIZE]) -> (u32, IpAddr, u64) {
+    let mut index = [0u8; 4];
+    index.copy_from_slice(&key[4..8]);
+    let mut addr = [0u8; 16];
+    addr.copy_from_slice(&key[8..24]);
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&value[0..8]);
+    let mut packets = [0u8; 8];
+    packets.copy_from_slice(&value[8..16]);
+    (
+        u32::from_ne_bytes(index),
+        IpAddr::V6(Ipv6Addr::from(addr)),
+        u64::from_ne_bytes(bytes) + u64::from_ne_bytes(packets) * 40,
+    )
+}
+
+/// Loads the programs for both directions the first time it's called so that if eBPF doesn't
+/// work here we find out before any counter has been set up
+pub(crate) fn init_counter<K: KernelInterface + ?Sized>(
+    ki: &K,
+    _target: &FilterTarget,
+) -> Result<(), Error> {
+    let mut counters = COUNTERS.lock().unwrap();
+    if !counters.is_empty() {
+        return Ok(());
+    }
+    let mut input = Counter::new(true)?;
+    input.attach(ki)?;
+    let mut output = Counter::new(false)?;
+    output.attach(ki)?;
+    counters.insert(FilterTarget::Input, input);
+    counters.insert(FilterTarget::Output, output);
+    Ok(())
+}
+
+pub(crate) fn read_counters<K: KernelInterface + ?Sized>(
+    ki: &K,
+    target: &FilterTarget,
+) -> Result<HashMap<(IpAddr, String), u64>, Error> {
+    let mut counters = COUNTERS.lock().unwrap();
+    match target {
+        FilterTarget::ForwardInput | FilterTarget::ForwardOutput => Ok(HashMap::new()),
+        _ => match counters.get_mut(target) {
+            Some(counter) => {
+                // attach first so new tunnels are counted from this tick on, what they carried
+                // before that is lost either way
+                counter.attach(ki)?;
+                counter.read()
+            }
+            None => bail!("eBPF counter {:?} was never initialized", target),
+        },
+    }
+}
+
+impl dyn KernelInterface {
+    /// Counts traffic with eBPF programs instead of the firewall, needs to be called before the
+    /// counters are initialized. If the programs can't be loaded the firewall is used after all
+    pub fn set_ebpf_counters(&self, enabled: bool) {
+        set_enabled(enabled)
+    }
+}
+
+#[test]
+fn test_counter_program_jumps() {
+    let map = Fd(-1);
+    let insns = counter_program(&map, &map).unwrap();
+    assert_eq!(insns.last().unwrap().code, 0x95);
+    for (pos, insn) in insns.iter().enumerate() {
+        if insn.code == 0x15 || insn.code == 0x55 {
+            let target = pos as i64 + 1 + i64::from(insn.off);
+            assert!(target > pos as i64 && target < insns.len() as i64);
+        }
+    }
+}
+
+#[test]
+fn test_parse_entry() {
+    let mut key = [0u8; KEY_SIZE];
+    key[4..8].copy_from_slice(&7u32.to_ne_bytes());
+    key[8..24].copy_from_slice(&"fd00::1".parse::<Ipv6Addr>().unwrap().octets());
+    let mut value = [0u8; VALUE_SIZE];
+    value[0..8].copy_from_slice(&1000u64.to_ne_bytes());
+    value[8..16].copy_from_slice(&10u64.to_ne_bytes());
+    assert_eq!(
+        parse_entry(&key, &value),
+        (7, "fd00::1".parse().unwrap(), 1000 + 10 * 40)
+    );
+}
+
+#[test]
+fn test_is_tunnel() {
+    assert!(is_tunnel("wg0"));
+    assert!(is_tunnel("wg42"));
+    assert!(!is_tunnel("wg"));
+    assert!(!is_tunnel("wg_exit"));
+    assert!(!is_tunnel("eth0"));
+}
@@ -1,3 +1,5 @@
+use super::nftables;
+use super::nftables::FirewallBackend;
 use super::{KernelInterface, KernelInterfaceError};
 
 use failure::Error;
@@ -47,21 +49,25 @@ impl dyn KernelInterface {
         }
 
         // block rita hello port on the exit tunnel
-        self.add_iptables_rule(
-            "iptables",
-            &[
-                "-I",
-                "OUTPUT",
-                "-o",
-                "wg_exit",
-                "-p",
-                "tcp",
-                "--dport",
-                &format!("{}", rita_hello_port),
-                "-j",
-                "DROP",
-            ],
-        )?;
+        if self.firewall_backend() == FirewallBackend::Nftables {
+            nftables::block_exit_hello_port(self, rita_hello_port)?;
+        } else {
+            self.add_iptables_rule(
+                "iptables",
+                &[
+                    "-I",
+                    "OUTPUT",
+                    "-o",
+                    "wg_exit",
+                    "-p",
+                    "tcp",
+                    "--dport",
+                    &format!("{}", rita_hello_port),
+                    "-j",
+                    "DROP",
+                ],
+            )?;
+        }
 
         let prev_ip: Result<Ipv4Addr, Error> = self.get_global_device_ip_v4("wg_exit");
 
@@ -160,6 +166,9 @@ impl dyn KernelInterface {
     /// these same rules, there is no forward spec here becuase forward is in general
     /// allowed on the routers and we stick to restricting input and output.
     pub fn create_client_nat_rules(&self, _lan_nic: &str) -> Result<(), Error> {
+        if self.firewall_backend() == FirewallBackend::Nftables {
+            return nftables::create_client_nat_rules(self);
+        }
         self.add_iptables_rule(
             "iptables",
             &[
@@ -196,12 +205,18 @@ impl dyn KernelInterface {
     /// blocks the client nat by inserting a blocker in the start of the special lan forwarding
     /// table created by openwrt.
     pub fn block_client_nat(&self) -> Result<(), Error> {
+        if self.firewall_backend() == FirewallBackend::Nftables {
+            return nftables::block_client_nat(self);
+        }
         self.add_iptables_rule("iptables", &["-I", "zone_lan_forward", "-j", "REJECT"])?;
         Ok(())
     }
 
     /// Removes the block created by block_client_nat() will fail if not run after that command
     pub fn restore_client_nat(&self) -> Result<(), Error> {
+        if self.firewall_backend() == FirewallBackend::Nftables {
+            return nftables::restore_client_nat(self);
+        }
         self.add_iptables_rule("iptables", &["-D", "zone_lan_forward", "-j", "REJECT"])?;
         Ok(())
     }
@@ -1,3 +1,5 @@
+use super::nftables;
+use super::nftables::FirewallBackend;
 use super::{KernelInterface, KernelInterfaceError};
 use althea_types::WgKey;
 use failure::Error;
@@ -120,6 +122,9 @@ impl dyn KernelInterface {
     }
 
     pub fn setup_nat(&self, external_interface: &str) -> Result<(), Error> {
+        if self.firewall_backend() == FirewallBackend::Nftables {
+            return nftables::setup_nat(self, external_interface);
+        }
         self.add_iptables_rule(
             "iptables",
             &[
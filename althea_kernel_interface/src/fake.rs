@@ -0,0 +1,467 @@
+//! An in memory kernel for tests. It implements the typed operations of the KernelInterface
+//! trait against its own tables of interfaces, routes, qdiscs, and counters so that tests can
+//! check what state their code left behind instead of matching on command lines. Operations that
+//! are still only available as commands are recorded and succeed with empty output, or return
+//! whatever a mock set with set_mock returns. `ip link set dev <name> up|down` is applied to the
+//! interface as well, it's how the tunnel setup brings interfaces up.
+
+use super::{
+    CommandRunner, FilterTarget, FirewallBackend, IpRoute, KernelInterface, WgPeer, WgUsage,
+};
+use althea_types::WgKey;
+use failure::Error;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+
+/// The traffic shaping configured on an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Qdisc {
+    /// latency protecting shaping with an optional bandwidth in mbit
+    Codel { bandwidth: Option<usize> },
+    /// a classless limit in kbit
+    Tbf { rate_kbit: u32 },
+    /// the classful limit used by exits, min and max bandwidth of each class in kbit
+    Htb {
+        classes: HashMap<Ipv4Addr, (u32, u32)>,
+    },
+}
+
+/// An interface known to the fake kernel
+#[derive(Debug, Clone, Default)]
+pub struct FakeInterface {
+    pub up: bool,
+    pub wireguard: bool,
+    pub listen_port: Option<u16>,
+    pub peers: HashMap<WgKey, WgPeer>,
+    pub wg_usage: HashMap<WgKey, WgUsage>,
+    pub qdisc: Option<Qdisc>,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    interfaces: HashMap<String, FakeInterface>,
+    routes: HashMap<IpRoute, Vec<String>>,
+    counters: HashMap<FilterTarget, HashMap<(IpAddr, String), u64>>,
+    commands: Vec<(String, Vec<String>)>,
+}
+
+type Mock = Box<dyn FnMut(String, Vec<String>) -> Result<Output, Error> + Send>;
+
+/// Cheap to clone, every clone shares the same state so a test can hold on to one while the
+/// code under test uses another as its KI
+#[derive(Clone, Default)]
+pub struct FakeKernel {
+    state: Arc<Mutex<FakeState>>,
+    mock: Arc<Mutex<Option<Mock>>>,
+}
+
+impl fmt::Debug for FakeKernel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FakeKernel")
+            .field("state", &self.state)
+            .field("mocked", &self.mock.lock().unwrap().is_some())
+            .finish()
+    }
+}
+
+impl FakeKernel {
+    pub fn new() -> FakeKernel {
+        FakeKernel::default()
+    }
+
+    /// Adds an interface that is already up, like a physical nic
+    pub fn add_interface(&self, name: &str) {
+        self.state.lock().unwrap().interfaces.insert(
+            name.to_string(),
+            FakeInterface {
+                up: true,
+                ..FakeInterface::default()
+            },
+        );
+    }
+
+    pub fn interface(&self, name: &str) -> Option<FakeInterface> {
+        self.state.lock().unwrap().interfaces.get(name).cloned()
+    }
+
+    pub fn qdisc(&self, iface_name: &str) -> Option<Qdisc> {
+        self.interface(iface_name).and_then(|iface| iface.qdisc)
+    }
+
+    pub fn route(&self, to: &IpRoute) -> Option<Vec<String>> {
+        self.state.lock().unwrap().routes.get(to).cloned()
+    }
+
+    /// Counts traffic as if it had passed through the filter for the target
+    pub fn add_counter_bytes(&self, target: FilterTarget, ip: IpAddr, iface: &str, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        let counter = state
+            .counters
+            .entry(target)
+            .or_default()
+            .entry((ip, iface.to_string()))
+            .or_insert(0);
+        *counter += bytes;
+    }
+
+    /// Sets the total usage WireGuard reports for a peer
+    pub fn set_wg_usage(&self, iface_name: &str, key: WgKey, usage: WgUsage) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        match state.interfaces.get_mut(iface_name) {
+            Some(iface) => {
+                iface.wg_usage.insert(key, usage);
+                Ok(())
+            }
+            None => bail!("No interface by the name {}", iface_name),
+        }
+    }
+
+    /// Every command run against this kernel, in order
+    pub fn commands(&self) -> Vec<(String, Vec<String>)> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    fn with_interface<T>(
+        &self,
+        iface_name: &str,
+        f: impl FnOnce(&mut FakeInterface) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        match self.state.lock().unwrap().interfaces.get_mut(iface_name) {
+            Some(iface) => f(iface),
+            None => bail!("No interface by the name {}", iface_name),
+        }
+    }
+}
+
+impl CommandRunner for FakeKernel {
+    fn run_command(&self, program: &str, args: &[&str]) -> Result<Output, Error> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.commands.push((program.to_string(), args.clone()));
+            if let ("ip", ["link", "set", "dev", name, updown]) = (
+                program,
+                args.iter()
+                    .map(|a| a.as_str())
+                    .collect::<Vec<&str>>()
+                    .as_slice(),
+            ) {
+                match (state.interfaces.get_mut(*name), *updown) {
+                    (Some(iface), "up") => iface.up = true,
+                    (Some(iface), "down") => iface.up = false,
+                    (None, "up") | (None, "down") => {
+                        bail!("Cannot find device \"{}\"", name)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        match self.mock.lock().unwrap().as_mut() {
+            Some(mock) => mock(program.to_string(), args),
+            None => Ok(Output {
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                status: ExitStatus::from_raw(0),
+            }),
+        }
+    }
+
+    /// Commands are still recorded and applied to the fake's state, the mock only decides
+    /// their output
+    fn set_mock(&self, mock: Box<dyn FnMut(String, Vec<String>) -> Result<Output, Error> + Send>) {
+        *self.mock.lock().unwrap() = Some(mock);
+    }
+}
+
+impl KernelInterface for FakeKernel {
+    fn firewall_backend(&self) -> FirewallBackend {
+        FirewallBackend::Iptables
+    }
+
+    fn get_interfaces(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .interfaces
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    fn del_interface(&self, name: &str) -> Result<(), Error> {
+        match self.state.lock().unwrap().interfaces.remove(name) {
+            Some(_) => Ok(()),
+            None => bail!("No interface by the name {}", name),
+        }
+    }
+
+    fn is_iface_up(&self, dev: &str) -> Option<bool> {
+        self.interface(dev).map(|iface| iface.up)
+    }
+
+    fn create_wg_interface(&self, name: &str) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        // like `ip link add` an existing interface is left alone and that's not an error
+        if state.interfaces.contains_key(name) {
+            return Ok(());
+        }
+        state.interfaces.insert(
+            name.to_string(),
+            FakeInterface {
+                wireguard: true,
+                ..FakeInterface::default()
+            },
+        );
+        Ok(())
+    }
+
+    fn get_peers(&self, iface_name: &str) -> Result<Vec<WgKey>, Error> {
+        self.with_interface(iface_name, |iface| {
+            Ok(iface.peers.keys().cloned().collect())
+        })
+    }
+
+    fn set_peer(
+        &self,
+        iface_name: &str,
+        listen_port: u16,
+        _private_key_path: &Path,
+        peer: &WgPeer,
+    ) -> Result<(), Error> {
+        self.with_interface(iface_name, |iface| {
+            if !iface.wireguard {
+                bail!("{} is not a WireGuard interface", iface_name);
+            }
+            iface.listen_port = Some(listen_port);
+            iface.peers.insert(peer.public_key, peer.clone());
+            Ok(())
+        })
+    }
+
+    fn read_wg_counters(&self, wg_name: &str) -> Result<HashMap<WgKey, WgUsage>, Error> {
+        self.with_interface(wg_name, |iface| Ok(iface.wg_usage.clone()))
+    }
+
+    fn get_default_route(&self) -> Option<Vec<String>> {
+        self.route(&IpRoute::DefaultRoute)
+    }
+
+    fn add_route(&self, to: &IpRoute, route: &[String]) -> Result<(), Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .routes
+            .insert(*to, route.to_vec());
+        Ok(())
+    }
+
+    fn has_qdisc(&self, iface_name: &str) -> Result<bool, Error> {
+        self.with_interface(iface_name, |iface| Ok(iface.qdisc.is_some()))
+    }
+
+    fn has_class(&self, ip: &Ipv4Addr, iface_name: &str) -> Result<bool, Error> {
+        self.with_interface(iface_name, |iface| match iface.qdisc {
+            Some(Qdisc::Htb { ref classes }) => Ok(classes.contains_key(ip)),
+            _ => Ok(false),
+        })
+    }
+
+    fn has_limit(&self, iface_name: &str) -> Result<bool, Error> {
+        self.with_interface(iface_name, |iface| match iface.qdisc {
+            Some(Qdisc::Tbf { .. }) | Some(Qdisc::Htb { .. }) => Ok(true),
+            _ => Ok(false),
+        })
+    }
+
+    fn set_codel_shaping(&self, iface_name: &str, speed: Option<usize>) -> Result<(), Error> {
+        self.with_interface(iface_name, |iface| {
+            iface.qdisc = Some(Qdisc::Codel { bandwidth: speed });
+            Ok(())
+        })
+    }
+
+    fn set_classless_limit(&self, iface_name: &str, bw: u32) -> Result<(), Error> {
+        self.with_interface(iface_name, |iface| {
+            iface.qdisc = Some(Qdisc::Tbf { rate_kbit: bw });
+            Ok(())
+        })
+    }
+
+    fn create_root_classful_limit(&self, iface_name: &str) -> Result<(), Error> {
+        self.with_interface(iface_name, |iface| {
+            iface.qdisc = Some(Qdisc::Htb {
+                classes: HashMap::new(),
+            });
+            Ok(())
+        })
+    }
+
+    fn set_class_limit(
+        &self,
+        iface_name: &str,
+        min_bw: u32,
+        max_bw: u32,
+        ip: &Ipv4Addr,
+    ) -> Result<(), Error> {
+        self.with_interface(iface_name, |iface| match iface.qdisc {
+            Some(Qdisc::Htb { ref mut classes }) => {
+                classes.insert(*ip, (min_bw, max_bw));
+                Ok(())
+            }
+            _ => bail!("No root classful limit on {}", iface_name),
+        })
+    }
+
+    fn delete_qdisc(&self, iface_name: &str) -> Result<(), Error> {
+        self.with_interface(iface_name, |iface| {
+            iface.qdisc = None;
+            Ok(())
+        })
+    }
+
+    fn init_counter(&self, target: &FilterTarget) -> Result<(), Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .counters
+            .entry(*target)
+            .or_default();
+        Ok(())
+    }
+
+    fn read_counters(
+        &self,
+        target: &FilterTarget,
+    ) -> Result<HashMap<(IpAddr, String), u64>, Error> {
+        match self.state.lock().unwrap().counters.get_mut(target) {
+            Some(counters) => Ok(std::mem::take(counters)),
+            None => bail!("Counter {:?} was never initialized", target),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn test_key() -> WgKey {
+        "8BeCExnthLe5ou0EYec5jNqJ/PduZ1x2o7lpXJOpgXk="
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_wg_interfaces() {
+        let ki = FakeKernel::new();
+        ki.create_wg_interface("wg0").unwrap();
+        assert_eq!(ki.is_iface_up("wg0"), Some(false));
+        ki.run_command("ip", &["link", "set", "dev", "wg0", "up"])
+            .unwrap();
+        assert_eq!(ki.is_iface_up("wg0"), Some(true));
+        assert!(ki
+            .run_command("ip", &["link", "set", "dev", "wg9", "up"])
+            .is_err());
+
+        let endpoint: SocketAddr = "[fe80::1]:60000".parse().unwrap();
+        let peer = WgPeer {
+            public_key: test_key(),
+            endpoint,
+            endpoint_iface: Some("eth0".to_string()),
+            allowed_ips: vec!["::/0".to_string()],
+            persistent_keepalive: 5,
+        };
+        ki.set_peer("wg0", 60001, Path::new("/tmp/priv"), &peer)
+            .unwrap();
+        assert_eq!(ki.get_peers("wg0").unwrap(), vec![test_key()]);
+        assert_eq!(ki.interface("wg0").unwrap().listen_port, Some(60001));
+
+        ki.set_wg_usage(
+            "wg0",
+            test_key(),
+            WgUsage {
+                upload: 10,
+                download: 20,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            ki.read_wg_counters("wg0").unwrap()[&test_key()].download,
+            20
+        );
+
+        // creating it again keeps it as it is, peers and all
+        ki.create_wg_interface("wg0").unwrap();
+        assert_eq!(ki.get_peers("wg0").unwrap(), vec![test_key()]);
+        assert_eq!(ki.is_iface_up("wg0"), Some(true));
+
+        ki.del_interface("wg0").unwrap();
+        assert!(ki.get_interfaces().unwrap().is_empty());
+        assert!(ki.get_peers("wg0").is_err());
+    }
+
+    #[test]
+    fn test_traffic_control() {
+        let ki = FakeKernel::new();
+        ki.add_interface("wg_exit");
+        let ip = "172.168.1.2".parse().unwrap();
+        assert!(ki.set_class_limit("wg_exit", 1000, 2000, &ip).is_err());
+
+        ki.create_root_classful_limit("wg_exit").unwrap();
+        assert!(!ki.has_class(&ip, "wg_exit").unwrap());
+        ki.set_class_limit("wg_exit", 1000, 2000, &ip).unwrap();
+        assert!(ki.has_class(&ip, "wg_exit").unwrap());
+        assert!(ki.has_limit("wg_exit").unwrap());
+
+        ki.set_codel_shaping("wg_exit", None).unwrap();
+        assert!(!ki.has_limit("wg_exit").unwrap());
+        ki.delete_qdisc("wg_exit").unwrap();
+        assert!(!ki.has_qdisc("wg_exit").unwrap());
+    }
+
+    #[test]
+    fn test_counters() {
+        let ki = FakeKernel::new();
+        let ip: IpAddr = "fd00::1".parse().unwrap();
+        assert!(ki.read_counters(&FilterTarget::Input).is_err());
+
+        ki.init_counter(&FilterTarget::Input).unwrap();
+        ki.add_counter_bytes(FilterTarget::Input, ip, "wg0", 100);
+        ki.add_counter_bytes(FilterTarget::Input, ip, "wg0", 50);
+        let counters = ki.read_counters(&FilterTarget::Input).unwrap();
+        assert_eq!(counters[&(ip, "wg0".to_string())], 150);
+        assert!(ki.read_counters(&FilterTarget::Input).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_routes_and_commands() {
+        let ki = FakeKernel::new();
+        let route = vec![
+            "default".to_string(),
+            "via".to_string(),
+            "192.168.1.1".to_string(),
+        ];
+        ki.add_route(&IpRoute::DefaultRoute, &route).unwrap();
+        assert_eq!(ki.get_default_route(), Some(route));
+
+        ki.run_command("ip", &["route", "flush", "cache"]).unwrap();
+        assert_eq!(ki.commands()[0].0, "ip");
+
+        ki.set_mock(Box::new(|_, _| {
+            Ok(Output {
+                stdout: b"mocked".to_vec(),
+                stderr: Vec::new(),
+                status: ExitStatus::from_raw(0),
+            })
+        }));
+        let output = ki.run_command("wg", &["show"]).unwrap();
+        assert_eq!(output.stdout, b"mocked");
+        assert_eq!(ki.commands().len(), 2);
+    }
+}
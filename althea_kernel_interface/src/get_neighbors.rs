@@ -1,3 +1,5 @@
+use super::netlink;
+use super::netlink::try_netlink;
 use super::KernelInterface;
 
 use std::collections::HashSet;
@@ -12,6 +14,23 @@ impl dyn KernelInterface {
     /// Returns a vector of neighbors reachable over layer 2, giving IP address of each.
     /// Implemented with `ip neighbor` on Linux.
     pub fn get_neighbors(&self) -> Result<Vec<(IpAddr, String)>, Error> {
+        if let Some((neighbors, links)) = try_netlink(self, "list neighbors", || {
+            Ok((netlink::get_neighbors()?, netlink::get_links()?))
+        }) {
+            let vec: Vec<(IpAddr, String)> = neighbors
+                .into_iter()
+                .filter(|neigh| neigh.usable)
+                .filter_map(|neigh| {
+                    links
+                        .iter()
+                        .find(|link| link.index == neigh.index)
+                        .map(|link| (neigh.ip, link.name.clone()))
+                })
+                .collect();
+            trace!("Got neighbors {:?}", vec);
+            return Ok(vec);
+        }
+
         let output = self.run_command("ip", &["neighbor"])?;
         trace!("Got {:?} from `ip neighbor`", output);
 
@@ -1,3 +1,5 @@
+use super::netlink;
+use super::netlink::try_netlink;
 use super::KernelInterface;
 
 use regex::Regex;
@@ -6,31 +8,35 @@ use failure::Error;
 use std::net::IpAddr;
 use std::str::from_utf8;
 
-impl dyn KernelInterface {
-    /// Returns all existing interfaces
-    pub fn get_interfaces(&self) -> Result<Vec<String>, Error> {
-        let links = String::from_utf8(self.run_command("ip", &["link"])?.stdout)?;
+pub(crate) fn get_interfaces<K: KernelInterface + ?Sized>(ki: &K) -> Result<Vec<String>, Error> {
+    if let Some(links) = try_netlink(ki, "list interfaces", netlink::get_links) {
+        let vec: Vec<String> = links.into_iter().map(|link| link.name).collect();
+        trace!("interfaces: {:?}", vec);
+        return Ok(vec);
+    }
 
-        let mut vec = Vec::new();
+    let links = String::from_utf8(ki.run_command("ip", &["link"])?.stdout)?;
 
-        lazy_static! {
-            static ref RE: Regex =
-                Regex::new(r"[0-9]+: (.*?)(:|@)").expect("Unable to compile regular expression");
-        }
-        for caps in RE.captures_iter(&links) {
-            vec.push(String::from(&caps[1]));
-        }
+    let mut vec = Vec::new();
 
-        trace!("interfaces: {:?}", vec);
-        Ok(vec)
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"[0-9]+: (.*?)(:|@)").expect("Unable to compile regular expression");
     }
-
-    /// Deletes an named interface
-    pub fn del_interface(&self, name: &str) -> Result<(), Error> {
-        self.run_command("ip", &["link", "del", "dev", name])?;
-        Ok(())
+    for caps in RE.captures_iter(&links) {
+        vec.push(String::from(&caps[1]));
     }
 
+    trace!("interfaces: {:?}", vec);
+    Ok(vec)
+}
+
+pub(crate) fn del_interface<K: KernelInterface + ?Sized>(ki: &K, name: &str) -> Result<(), Error> {
+    ki.run_command("ip", &["link", "del", "dev", name])?;
+    Ok(())
+}
+
+impl dyn KernelInterface {
     pub fn iface_status(&self, iface: &str) -> Result<String, Error> {
         // cat so we can mock
         let output = self.run_command("cat", &[&format!("/sys/class/net/{}/operstate", iface)])?;
@@ -41,6 +47,13 @@ impl dyn KernelInterface {
     }
 
     pub fn get_wg_remote_ip(&self, name: &str) -> Result<IpAddr, Error> {
+        if let Some(peers) = try_netlink(self, "list wg peers", || netlink::get_wg_peers(name)) {
+            match peers.iter().find_map(|peer| peer.endpoint) {
+                Some(endpoint) => return Ok(endpoint.ip()),
+                None => bail!("No peer on {} has an endpoint", name),
+            }
+        }
+
         let output = self.run_command("wg", &["show", name, "endpoints"])?;
         let stdout = String::from_utf8(output.stdout)?;
 
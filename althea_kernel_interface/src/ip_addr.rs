@@ -1,26 +1,28 @@
+use super::netlink;
+use super::netlink::try_netlink;
 use super::KernelInterface;
 
 use failure::Error;
 use std::net::Ipv4Addr;
 
-impl dyn KernelInterface {
-    /// Returns a bool based on device state, "UP" or "DOWN", "UNKNOWN" is
-    /// interpreted as DOWN
-    pub fn is_iface_up(&self, dev: &str) -> Option<bool> {
-        let output = self
-            .run_command("ip", &["addr", "show", "dev", dev])
-            .unwrap();
+pub(crate) fn is_iface_up<K: KernelInterface + ?Sized>(ki: &K, dev: &str) -> Option<bool> {
+    if let Some(link) = try_netlink(ki, "get interface state", || netlink::get_link(dev)) {
+        return link.map(|link| link.up);
+    }
 
-        // Get the first line, check if it has state "UP"
-        match String::from_utf8(output.stdout) {
-            Ok(stdout) => match stdout.lines().next() {
-                Some(line) => Some(line.contains("state UP")),
-                _ => None,
-            },
+    let output = ki.run_command("ip", &["addr", "show", "dev", dev]).unwrap();
+
+    // Get the first line, check if it has state "UP"
+    match String::from_utf8(output.stdout) {
+        Ok(stdout) => match stdout.lines().next() {
+            Some(line) => Some(line.contains("state UP")),
             _ => None,
-        }
+        },
+        _ => None,
     }
+}
 
+impl dyn KernelInterface {
     /// Adds an ipv4 address to a given interface, true is returned when
     /// the ip is added, false if it is already there and Error if the interface
     /// does not exist or some other error has occured
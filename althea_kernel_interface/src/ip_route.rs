@@ -1,9 +1,12 @@
+use super::netlink;
+use super::netlink::try_netlink;
 use super::KernelInterface;
 
 use std::net::IpAddr;
 
 use failure::Error;
 
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub enum IpRoute {
     /// For creating default routes
     DefaultRoute,
@@ -20,42 +23,52 @@ impl ToString for IpRoute {
     }
 }
 
-impl dyn KernelInterface {
-    pub fn get_default_route(&self) -> Option<Vec<String>> {
-        let output = self
-            .run_command("ip", &["route", "list", "default"])
-            .unwrap();
-
-        let stdout = String::from_utf8(output.stdout).unwrap();
-
-        // Get the first line that starts with "default", and
-        // convert token separated by whitespace into a valid
-        // result of type Vec<String>. Otherwise returns
-        // None if it couldn't be found.
-        Some(
-            stdout
-                .lines()
-                .filter(|line| line.starts_with("default"))
-                .nth(0)?
-                .split_whitespace() // Extract first
-                .map(|s| s.to_string())
-                .collect(),
-        )
+pub(crate) fn get_default_route<K: KernelInterface + ?Sized>(ki: &K) -> Option<Vec<String>> {
+    if let Some((routes, links)) = try_netlink(ki, "get the default route", || {
+        Ok((netlink::get_default_routes()?, netlink::get_links()?))
+    }) {
+        return routes
+            .first()
+            .map(|route| netlink::route_to_tokens(route, &links));
     }
 
-    pub fn set_route<T: ToString>(&self, to: &T, route: &[String]) -> Result<(), Error> {
-        let to = to.to_string();
-        let mut def_route = vec!["route", "add", &to];
+    let output = ki.run_command("ip", &["route", "list", "default"]).unwrap();
 
-        let tokens = route.iter().skip(1);
-        def_route.reserve_exact(tokens.len());
-        for token in tokens {
-            def_route.push(&token);
-        }
-        self.run_command("ip", &def_route)?;
-        Ok(())
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    // Get the first line that starts with "default", and
+    // convert token separated by whitespace into a valid
+    // result of type Vec<String>. Otherwise returns
+    // None if it couldn't be found.
+    Some(
+        stdout
+            .lines()
+            .filter(|line| line.starts_with("default"))
+            .nth(0)?
+            .split_whitespace() // Extract first
+            .map(|s| s.to_string())
+            .collect(),
+    )
+}
+
+pub(crate) fn add_route<K: KernelInterface + ?Sized>(
+    ki: &K,
+    to: &IpRoute,
+    route: &[String],
+) -> Result<(), Error> {
+    let to = to.to_string();
+    let mut def_route = vec!["route", "add", &to];
+
+    let tokens = route.iter().skip(1);
+    def_route.reserve_exact(tokens.len());
+    for token in tokens {
+        def_route.push(&token);
     }
+    ki.run_command("ip", &def_route)?;
+    Ok(())
+}
 
+impl dyn KernelInterface {
     pub fn update_settings_route(
         &self,
         settings_default_route: &mut Vec<String>,
@@ -79,7 +92,7 @@ impl dyn KernelInterface {
     ) -> Result<(), Error> {
         self.update_settings_route(settings_default_route)?;
 
-        self.set_route(&IpRoute::ToAddr(*endpoint_ip), &settings_default_route)?;
+        self.add_route(&IpRoute::ToAddr(*endpoint_ip), &settings_default_route)?;
         Ok(())
     }
 
@@ -90,13 +103,13 @@ impl dyn KernelInterface {
         match self.get_default_route() {
             Some(route) => {
                 if route.contains(&String::from("wg_exit")) {
-                    self.set_route(&IpRoute::DefaultRoute, settings_default_route)?;
+                    self.add_route(&IpRoute::DefaultRoute, settings_default_route)?;
                 } else {
                     *settings_default_route = route;
                 }
             }
             None => {
-                self.set_route(&IpRoute::DefaultRoute, settings_default_route)?;
+                self.add_route(&IpRoute::DefaultRoute, settings_default_route)?;
             }
         };
         Ok(())
@@ -189,7 +202,7 @@ default via 192.168.9.1 dev wifiinterface proto dhcp metric 1200
 }
 
 #[test]
-fn test_set_route() {
+fn test_add_route() {
     use crate::KI;
     use std::net::Ipv4Addr;
     use std::os::unix::process::ExitStatusExt;
@@ -214,7 +227,7 @@ fn test_set_route() {
         }
     }));
 
-    KI.set_route(
+    KI.add_route(
         &IpRoute::ToAddr(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
         &vec!["token1".into(), "token2".into(), "token3".into()],
     )
@@ -222,7 +235,7 @@ fn test_set_route() {
 }
 
 #[test]
-fn test_set_default_route() {
+fn test_add_default_route() {
     use crate::KI;
     use std::os::unix::process::ExitStatusExt;
     use std::process::ExitStatus;
@@ -246,6 +259,6 @@ fn test_set_default_route() {
         }
     }));
 
-    KI.set_route(&IpRoute::DefaultRoute, &vec![])
+    KI.add_route(&IpRoute::DefaultRoute, &vec![])
         .expect("Unable to set default route");
 }
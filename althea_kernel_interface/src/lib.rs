@@ -19,8 +19,10 @@ mod counter;
 mod create_wg_key;
 mod delete_tunnel;
 mod dns;
+mod ebpf;
 mod exit_client_tunnel;
 mod exit_server_tunnel;
+pub mod fake;
 pub mod file_io;
 mod fs_sync;
 mod get_neighbors;
@@ -31,6 +33,8 @@ mod iptables;
 mod is_openwrt;
 mod link_local_tools;
 mod manipulate_uci;
+mod netlink;
+mod nftables;
 pub mod open_tunnel;
 mod openwrt_ubus;
 pub mod opkg_feeds;
@@ -38,15 +42,25 @@ mod ping_check;
 mod set_system_password;
 mod setup_wg_if;
 mod traffic_control;
+pub mod uci_transaction;
 mod udp_socket_table;
 pub mod wg_iface_counter;
 
 pub use crate::counter::FilterTarget;
 pub use crate::create_wg_key::WgKeypair;
 pub use crate::exit_server_tunnel::ExitClient;
+pub use crate::ip_route::IpRoute;
+pub use crate::nftables::FirewallBackend;
+pub use crate::open_tunnel::WgPeer;
+pub use crate::uci_transaction::UciTransaction;
+pub use crate::wg_iface_counter::WgUsage;
 
+use althea_types::WgKey;
 use failure::Error;
+use std::collections::HashMap;
 use std::net::AddrParseError;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
 use std::string::FromUtf8Error;
 
 #[derive(Debug, Fail)]
@@ -88,7 +102,7 @@ lazy_static! {
 
 #[cfg(not(test))]
 lazy_static! {
-    pub static ref KI: Box<dyn KernelInterface> = Box::new(LinuxCommandRunner {});
+    pub static ref KI: Box<dyn KernelInterface> = Box::new(LinuxNetlinkRunner::default());
 }
 
 pub trait CommandRunner {
@@ -182,7 +196,193 @@ impl CommandRunner for TestCommandRunner {
     }
 }
 
-pub trait KernelInterface: CommandRunner + Sync + Send {}
+/// Talks to the kernel over netlink for links, addresses, routes, neighbors, and WireGuard peers
+/// and runs commands for everything else, or if netlink fails. Once a mock is set netlink is no
+/// longer used and every command goes to the mock instead
+#[derive(Default)]
+pub struct LinuxNetlinkRunner {
+    mock: Mutex<Option<Box<dyn FnMut(String, Vec<String>) -> Result<Output, Error> + Send>>>,
+}
+
+impl CommandRunner for LinuxNetlinkRunner {
+    fn run_command(&self, program: &str, args: &[&str]) -> Result<Output, Error> {
+        match self.mock.lock().unwrap().as_mut() {
+            Some(mock) => mock(
+                program.to_string(),
+                args.iter().map(|a| a.to_string()).collect(),
+            ),
+            None => LinuxCommandRunner.run_command(program, args),
+        }
+    }
+
+    fn set_mock(&self, mock: Box<dyn FnMut(String, Vec<String>) -> Result<Output, Error> + Send>) {
+        *self.mock.lock().unwrap() = Some(mock)
+    }
+}
+
+/// Typed operations on the kernel. The default implementations run commands (or talk netlink)
+/// through the CommandRunner, implementations that keep their own state like the FakeKernel
+/// override them instead
+pub trait KernelInterface: CommandRunner + Sync + Send {
+    /// Returns true if this kernel interface should try netlink before running a command
+    fn use_netlink(&self) -> bool {
+        false
+    }
+
+    /// Returns whether counters and NAT are set up with iptables or nftables, detected once
+    /// from the tools installed on the system
+    fn firewall_backend(&self) -> FirewallBackend {
+        nftables::firewall_backend(self)
+    }
+
+    /// Returns all existing interfaces
+    fn get_interfaces(&self) -> Result<Vec<String>, Error> {
+        interface_tools::get_interfaces(self)
+    }
+
+    /// Deletes an named interface
+    fn del_interface(&self, name: &str) -> Result<(), Error> {
+        interface_tools::del_interface(self, name)
+    }
+
+    /// Returns a bool based on device state, "UP" or "DOWN", "UNKNOWN" is
+    /// interpreted as DOWN
+    fn is_iface_up(&self, dev: &str) -> Option<bool> {
+        ip_addr::is_iface_up(self, dev)
+    }
+
+    /// Creates a new WireGuard interface with the given name
+    fn create_wg_interface(&self, name: &str) -> Result<(), Error> {
+        setup_wg_if::create_wg_interface(self, name)
+    }
+
+    /// Returns the public keys of every peer on a WireGuard interface
+    fn get_peers(&self, iface_name: &str) -> Result<Vec<WgKey>, Error> {
+        setup_wg_if::get_peers(self, iface_name)
+    }
+
+    /// Adds or updates a peer on a WireGuard interface, setting the interface's listen port and
+    /// private key along the way
+    fn set_peer(
+        &self,
+        iface_name: &str,
+        listen_port: u16,
+        private_key_path: &Path,
+        peer: &WgPeer,
+    ) -> Result<(), Error> {
+        open_tunnel::set_peer(self, iface_name, listen_port, private_key_path, peer)
+    }
+
+    /// Takes a wg interface name and provides upload and download since creation in bytes
+    /// in a hashmap indexed by peer WireGuard key
+    fn read_wg_counters(&self, wg_name: &str) -> Result<HashMap<WgKey, WgUsage>, Error> {
+        wg_iface_counter::read_wg_counters(self, wg_name)
+    }
+
+    /// Returns the default route as the tokens `ip route` would print for it
+    fn get_default_route(&self) -> Option<Vec<String>> {
+        ip_route::get_default_route(self)
+    }
+
+    /// Adds a route to the destination, or replaces the existing one, using the given route
+    /// tokens as printed by `ip route`
+    fn add_route(&self, to: &IpRoute, route: &[String]) -> Result<(), Error> {
+        ip_route::add_route(self, to, route)
+    }
+
+    /// Determines if the provided interface has a configured qdisc
+    fn has_qdisc(&self, iface_name: &str) -> Result<bool, Error> {
+        traffic_control::has_qdisc(self, iface_name)
+    }
+
+    /// Determines if the provided flow is assigned
+    fn has_class(&self, ip: &Ipv4Addr, iface_name: &str) -> Result<bool, Error> {
+        traffic_control::has_class(self, ip, iface_name)
+    }
+
+    /// Determines if the provided interface has a configured limit
+    fn has_limit(&self, iface_name: &str) -> Result<bool, Error> {
+        traffic_control::has_limit(self, iface_name)
+    }
+
+    /// This sets up latency protecting flow control, either cake on openwrt
+    /// or fq_codel on older devices/kernels
+    fn set_codel_shaping(&self, iface_name: &str, speed: Option<usize>) -> Result<(), Error> {
+        traffic_control::set_codel_shaping(self, iface_name, speed)
+    }
+
+    /// Creates a qdisc limit with the given bandwidth tuned for the correct rate
+    /// this limit uses tbf which is classless and faster since we leave prioritization
+    /// to the fq_codel on the ingress and egress interfaces
+    fn set_classless_limit(&self, iface_name: &str, bw: u32) -> Result<(), Error> {
+        traffic_control::set_classless_limit(self, iface_name, bw)
+    }
+
+    /// Creates the root limit on the wg_exit tunnel for the exit, under which all other classes
+    /// operate
+    fn create_root_classful_limit(&self, iface_name: &str) -> Result<(), Error> {
+        traffic_control::create_root_classful_limit(self, iface_name)
+    }
+
+    /// Adds or changes the traffic class limiting a single exit user
+    fn set_class_limit(
+        &self,
+        iface_name: &str,
+        min_bw: u32,
+        max_bw: u32,
+        ip: &Ipv4Addr,
+    ) -> Result<(), Error> {
+        traffic_control::set_class_limit(self, iface_name, min_bw, max_bw, ip)
+    }
+
+    /// deletes the interface qdisc
+    fn delete_qdisc(&self, iface_name: &str) -> Result<(), Error> {
+        traffic_control::delete_qdisc(self, iface_name)
+    }
+
+    /// Creates the ipset and iptables rule that count traffic for the target
+    fn init_counter(&self, target: &FilterTarget) -> Result<(), Error> {
+        counter::init_counter(self, target)
+    }
+
+    /// Returns the bytes counted per destination and interface since the last read and resets
+    /// the counters
+    fn read_counters(
+        &self,
+        target: &FilterTarget,
+    ) -> Result<HashMap<(IpAddr, String), u64>, Error> {
+        counter::read_counters(self, target)
+    }
+}
 
 impl KernelInterface for LinuxCommandRunner {}
-impl KernelInterface for TestCommandRunner {}
+impl KernelInterface for TestCommandRunner {
+    fn firewall_backend(&self) -> FirewallBackend {
+        FirewallBackend::Iptables
+    }
+}
+impl KernelInterface for LinuxNetlinkRunner {
+    fn use_netlink(&self) -> bool {
+        self.mock.lock().unwrap().is_none()
+    }
+}
+
+#[test]
+fn test_netlink_runner_mock() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    let ki = LinuxNetlinkRunner::default();
+    assert!(ki.use_netlink());
+    ki.set_mock(Box::new(|program, args| {
+        assert_eq!(program, "ip");
+        assert_eq!(args, vec!["link"]);
+        Ok(Output {
+            stdout: b"mocked".to_vec(),
+            stderr: Vec::new(),
+            status: ExitStatus::from_raw(0),
+        })
+    }));
+    assert!(!ki.use_netlink());
+    assert_eq!(ki.run_command("ip", &["link"]).unwrap().stdout, b"mocked");
+}
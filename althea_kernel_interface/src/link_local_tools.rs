@@ -1,3 +1,5 @@
+use super::netlink;
+use super::netlink::try_netlink;
 use super::{KernelInterface, KernelInterfaceError};
 
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
@@ -9,6 +11,26 @@ use failure::Error;
 impl dyn KernelInterface {
     /// This gets our link local ip for a given device
     pub fn get_link_local_device_ip(&self, dev: &str) -> Result<Ipv6Addr, KernelInterfaceError> {
+        if let Some(addresses) =
+            try_netlink(self, "list addresses", || netlink::get_link_addresses(dev))
+        {
+            let addresses = match addresses {
+                Some(addresses) => addresses,
+                None => return Err(KernelInterfaceError::NoInterfaceError(dev.to_string())),
+            };
+            return addresses
+                .iter()
+                .find_map(|address| match address.ip {
+                    IpAddr::V6(ip) if address.scope == netlink::RT_SCOPE_LINK => Some(ip),
+                    _ => None,
+                })
+                .ok_or_else(|| {
+                    KernelInterfaceError::AddressNotReadyError(
+                        "No address seems to be available yet".to_string(),
+                    )
+                });
+        }
+
         let output = self.run_command("ip", &["addr", "show", "dev", dev, "scope", "link"])?;
         trace!("Got {:?} from `ip addr`", output);
 
@@ -38,6 +60,26 @@ impl dyn KernelInterface {
 
     /// This gets our global ip for a given device
     pub fn get_global_device_ip(&self, dev: &str) -> Result<Ipv6Addr, Error> {
+        if let Some(addresses) =
+            try_netlink(self, "list addresses", || netlink::get_link_addresses(dev))
+        {
+            let global =
+                addresses
+                    .unwrap_or_default()
+                    .iter()
+                    .find_map(|address| match address.ip {
+                        IpAddr::V6(ip) if address.scope == netlink::RT_SCOPE_UNIVERSE => Some(ip),
+                        _ => None,
+                    });
+            return match global {
+                Some(ip) => Ok(ip),
+                None => Err(KernelInterfaceError::RuntimeError(
+                    "No global found or no interface found".to_string(),
+                )
+                .into()),
+            };
+        }
+
         let output = self.run_command("ip", &["addr", "show", "dev", dev, "scope", "global"])?;
         trace!("Got {:?} from `ip addr`", output);
 
@@ -60,6 +102,26 @@ impl dyn KernelInterface {
     }
 
     pub fn get_global_device_ip_v4(&self, dev: &str) -> Result<Ipv4Addr, Error> {
+        if let Some(addresses) =
+            try_netlink(self, "list addresses", || netlink::get_link_addresses(dev))
+        {
+            let global =
+                addresses
+                    .unwrap_or_default()
+                    .iter()
+                    .find_map(|address| match address.ip {
+                        IpAddr::V4(ip) if address.scope == netlink::RT_SCOPE_UNIVERSE => Some(ip),
+                        _ => None,
+                    });
+            return match global {
+                Some(ip) => Ok(ip),
+                None => Err(KernelInterfaceError::RuntimeError(
+                    "No global found or no interface found".to_string(),
+                )
+                .into()),
+            };
+        }
+
         let output = self.run_command("ip", &["addr", "show", "dev", dev, "scope", "global"])?;
         trace!("Got {:?} from `ip addr`", output);
 
@@ -127,6 +189,15 @@ impl dyn KernelInterface {
     }
     /// Returns the ifidx of the provided interface
     pub fn get_iface_index(&self, name: &str) -> Result<u32, Error> {
+        if let Some(link) = try_netlink(self, "list interfaces", || netlink::get_link(name)) {
+            return match link {
+                Some(link) => Ok(link.index),
+                None => Err(
+                    KernelInterfaceError::RuntimeError("Interface not found".to_string()).into(),
+                ),
+            };
+        }
+
         let links = String::from_utf8(self.run_command("ip", &["link"])?.stdout)?;
 
         lazy_static! {
@@ -0,0 +1,835 @@
+//! A small netlink client that asks the kernel directly for the things we otherwise get by running
+//! `ip` and `wg` and parsing their output. Links, addresses, routes, and neighbors come from
+//! rtnetlink and WireGuard peers come from the `wireguard` generic netlink family. Only the dump
+//! requests we actually use are implemented, every request opens its own socket so there is no
+//! shared state to worry about between threads.
+//!
+//! It can also attach the tc programs used by the eBPF traffic counters, which need the program's
+//! file descriptor and so can't be attached by running `tc`.
+//!
+//! Kernel interfaces that return true from `KernelInterface::use_netlink` try these first, if
+//! anything goes wrong here the caller falls back to running the command.
+
+use super::KernelInterface;
+use failure::Error;
+use std::collections::HashSet;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const NLMSG_HDRLEN: usize = 16;
+const NLA_HDRLEN: usize = 4;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_MULTI: u16 = 0x2;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+const NLM_F_REPLACE: u16 = 0x100;
+const NLM_F_CREATE: u16 = 0x400;
+const NLA_F_NESTED: u16 = 0x8000;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_GETLINK: u16 = 18;
+const RTM_NEWADDR: u16 = 20;
+const RTM_GETADDR: u16 = 22;
+const RTM_NEWROUTE: u16 = 24;
+const RTM_GETROUTE: u16 = 26;
+const RTM_NEWNEIGH: u16 = 28;
+const RTM_GETNEIGH: u16 = 30;
+
+const RTM_NEWTFILTER: u16 = 44;
+
+const IFLA_IFNAME: u16 = 3;
+const IFLA_OPERSTATE: u16 = 16;
+const IF_OPER_UP: u8 = 6;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+pub const RT_SCOPE_UNIVERSE: u8 = 0;
+pub const RT_SCOPE_LINK: u8 = 253;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_PRIORITY: u16 = 6;
+const RTA_PREFSRC: u16 = 7;
+const RTA_TABLE: u16 = 15;
+pub const RT_TABLE_MAIN: u32 = 254;
+const RTN_UNICAST: u8 = 1;
+
+const NDA_DST: u16 = 1;
+const NDA_LLADDR: u16 = 2;
+const NUD_REACHABLE: u16 = 0x02;
+const NUD_STALE: u16 = 0x04;
+const NUD_DELAY: u16 = 0x08;
+
+const TCA_KIND: u16 = 1;
+const TCA_OPTIONS: u16 = 2;
+const TCA_BPF_FD: u16 = 6;
+const TCA_BPF_NAME: u16 = 7;
+const TCA_BPF_FLAGS: u16 = 8;
+const TCA_BPF_FLAG_ACT_DIRECT: u32 = 1;
+const TC_H_CLSACT: u32 = 0xffff_fff1;
+const TC_H_MIN_INGRESS: u32 = 0xfff2;
+const TC_H_MIN_EGRESS: u32 = 0xfff3;
+const ETH_P_ALL: u16 = 0x0003;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const WG_GENL_NAME: &str = "wireguard";
+const WG_GENL_VERSION: u8 = 1;
+const WG_CMD_GET_DEVICE: u8 = 0;
+const WGDEVICE_A_IFNAME: u16 = 2;
+const WGDEVICE_A_PEERS: u16 = 8;
+const WGPEER_A_PUBLIC_KEY: u16 = 1;
+const WGPEER_A_ENDPOINT: u16 = 4;
+const WGPEER_A_LAST_HANDSHAKE_TIME: u16 = 6;
+const WGPEER_A_RX_BYTES: u16 = 7;
+const WGPEER_A_TX_BYTES: u16 = 8;
+
+/// How long to wait on the kernel before giving up and falling back to commands
+const RECV_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub index: u32,
+    pub name: String,
+    /// true if the operational state is UP, what `ip` prints as `state UP`
+    pub up: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    pub index: u32,
+    pub ip: IpAddr,
+    pub prefix_len: u8,
+    pub scope: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub dst: Option<IpAddr>,
+    pub dst_len: u8,
+    pub gateway: Option<IpAddr>,
+    pub oif: Option<u32>,
+    pub prefsrc: Option<IpAddr>,
+    pub priority: Option<u32>,
+    pub table: u32,
+    pub protocol: u8,
+    pub scope: u8,
+    pub kind: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neighbor {
+    pub ip: IpAddr,
+    pub index: u32,
+    /// true if the neighbor has a link layer address and is REACHABLE, STALE, or DELAY,
+    /// the neighbors `ip neighbor` output was filtered down to
+    pub usable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WgPeer {
+    pub public_key: [u8; 32],
+    pub endpoint: Option<SocketAddr>,
+    /// None if there has never been a handshake
+    pub last_handshake: Option<SystemTime>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+/// An open netlink socket, closed on drop
+struct Socket {
+    fd: libc::c_int,
+    seq: u32,
+}
+
+impl Socket {
+    fn new(protocol: libc::c_int) -> Result<Socket, Error> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                protocol,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let socket = Socket { fd, seq: 0 };
+
+        let timeout = libc::timeval {
+            tv_sec: RECV_TIMEOUT.as_secs() as libc::time_t,
+            tv_usec: 0,
+        };
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(socket)
+    }
+
+    /// Sends a dump request and returns the payload of every message in the reply
+    fn dump(&mut self, msg_type: u16, payload: &[u8]) -> Result<Vec<(u16, Vec<u8>)>, Error> {
+        self.request(msg_type, NLM_F_REQUEST | NLM_F_DUMP, payload)
+    }
+
+    fn request(
+        &mut self,
+        msg_type: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> Result<Vec<(u16, Vec<u8>)>, Error> {
+        self.seq += 1;
+        let seq = self.seq;
+        let mut msg = Vec::with_capacity(NLMSG_HDRLEN + payload.len());
+        msg.extend_from_slice(&((NLMSG_HDRLEN + payload.len()) as u32).to_ne_bytes());
+        msg.extend_from_slice(&msg_type.to_ne_bytes());
+        msg.extend_from_slice(&flags.to_ne_bytes());
+        msg.extend_from_slice(&seq.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(payload);
+
+        let mut kernel: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        kernel.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let sent = unsafe {
+            libc::sendto(
+                self.fd,
+                msg.as_ptr() as *const libc::c_void,
+                msg.len(),
+                0,
+                &kernel as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut replies = Vec::new();
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let len =
+                unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if len < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let mut more = false;
+            for (header, payload) in split_messages(&buf[..len as usize])? {
+                if header.seq != seq {
+                    continue;
+                }
+                match header.msg_type {
+                    NLMSG_DONE => return Ok(replies),
+                    NLMSG_ERROR => {
+                        let code = read_i32(payload, 0)?;
+                        // an error of zero is an ack
+                        if code != 0 {
+                            bail!(
+                                "netlink request failed with {}",
+                                io::Error::from_raw_os_error(-code)
+                            );
+                        }
+                        return Ok(replies);
+                    }
+                    msg_type => {
+                        more |= header.flags & NLM_F_MULTI != 0;
+                        replies.push((msg_type, payload.to_vec()));
+                    }
+                }
+            }
+            // replies that aren't part of a dump come in a single message
+            if !more && !replies.is_empty() {
+                return Ok(replies);
+            }
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+struct Header {
+    msg_type: u16,
+    flags: u16,
+    seq: u32,
+}
+
+/// Splits a buffer read from a netlink socket into its messages
+fn split_messages(mut buf: &[u8]) -> Result<Vec<(Header, &[u8])>, Error> {
+    let mut messages = Vec::new();
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = read_u32(buf, 0)? as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            bail!("Invalid netlink message length {}", len);
+        }
+        let header = Header {
+            msg_type: read_u16(buf, 4)?,
+            flags: read_u16(buf, 6)?,
+            seq: read_u32(buf, 8)?,
+        };
+        messages.push((header, &buf[NLMSG_HDRLEN..len]));
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    Ok(messages)
+}
+
+/// Splits a buffer of netlink attributes into (type, value) pairs
+fn attributes(mut buf: &[u8]) -> Result<Vec<(u16, &[u8])>, Error> {
+    let mut attrs = Vec::new();
+    while buf.len() >= NLA_HDRLEN {
+        let len = read_u16(buf, 0)? as usize;
+        if len < NLA_HDRLEN || len > buf.len() {
+            bail!("Invalid netlink attribute length {}", len);
+        }
+        let attr_type = read_u16(buf, 2)? & NLA_TYPE_MASK;
+        attrs.push((attr_type, &buf[NLA_HDRLEN..len]));
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    Ok(attrs)
+}
+
+fn push_attribute(buf: &mut Vec<u8>, attr_type: u16, value: &[u8]) {
+    buf.extend_from_slice(&((NLA_HDRLEN + value.len()) as u16).to_ne_bytes());
+    buf.extend_from_slice(&attr_type.to_ne_bytes());
+    buf.extend_from_slice(value);
+    buf.resize(align(buf.len()), 0);
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Result<u16, Error> {
+    match buf.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_ne_bytes([bytes[0], bytes[1]])),
+        None => bail!("Netlink message too short"),
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32, Error> {
+    match buf.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => bail!("Netlink message too short"),
+    }
+}
+
+fn read_i32(buf: &[u8], offset: usize) -> Result<i32, Error> {
+    Ok(read_u32(buf, offset)? as i32)
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> Result<u64, Error> {
+    match buf.get(offset..offset + 8) {
+        Some(bytes) => {
+            let mut array = [0u8; 8];
+            array.copy_from_slice(bytes);
+            Ok(u64::from_ne_bytes(array))
+        }
+        None => bail!("Netlink message too short"),
+    }
+}
+
+fn read_string(buf: &[u8]) -> String {
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).to_string()
+}
+
+fn read_ip(buf: &[u8]) -> Result<IpAddr, Error> {
+    match buf.len() {
+        4 => Ok(IpAddr::V4(Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3]))),
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(buf);
+            Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        len => bail!("Invalid address length {}", len),
+    }
+}
+
+/// Parses a struct sockaddr_in or sockaddr_in6
+fn read_sockaddr(buf: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    // the family and port are in every sockaddr, the address itself is checked below
+    let (family, port) = match buf.get(..4) {
+        Some(head) => (
+            i32::from(u16::from_ne_bytes([head[0], head[1]])),
+            u16::from_be_bytes([head[2], head[3]]),
+        ),
+        None => bail!("Netlink sockaddr too short"),
+    };
+    if family == libc::AF_INET && buf.len() >= 8 {
+        let ip = Ipv4Addr::new(buf[4], buf[5], buf[6], buf[7]);
+        Ok(Some(SocketAddr::V4(SocketAddrV4::new(ip, port))))
+    } else if family == libc::AF_INET6 && buf.len() >= 28 {
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(&buf[8..24]);
+        let scope_id = read_u32(buf, 24)?;
+        Ok(Some(SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::from(octets),
+            port,
+            0,
+            scope_id,
+        ))))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn get_links() -> Result<Vec<Link>, Error> {
+    let mut socket = Socket::new(libc::NETLINK_ROUTE)?;
+    // struct ifinfomsg, all zeros to dump every family
+    let replies = socket.dump(RTM_GETLINK, &[0u8; 16])?;
+    let mut links = Vec::new();
+    for (msg_type, payload) in replies {
+        if msg_type != RTM_NEWLINK || payload.len() < 16 {
+            continue;
+        }
+        let mut link = Link {
+            index: read_u32(&payload, 4)?,
+            name: String::new(),
+            up: false,
+        };
+        for (attr_type, value) in attributes(&payload[16..])? {
+            match attr_type {
+                IFLA_IFNAME => link.name = read_string(value),
+                IFLA_OPERSTATE => link.up = value.first() == Some(&IF_OPER_UP),
+                _ => {}
+            }
+        }
+        links.push(link);
+    }
+    Ok(links)
+}
+
+pub fn get_link(name: &str) -> Result<Option<Link>, Error> {
+    Ok(get_links()?.into_iter().find(|link| link.name == name))
+}
+
+pub fn get_addresses() -> Result<Vec<Address>, Error> {
+    let mut socket = Socket::new(libc::NETLINK_ROUTE)?;
+    // struct ifaddrmsg
+    let replies = socket.dump(RTM_GETADDR, &[0u8; 8])?;
+    let mut addresses = Vec::new();
+    for (msg_type, payload) in replies {
+        if msg_type != RTM_NEWADDR || payload.len() < 8 {
+            continue;
+        }
+        let mut address = None;
+        let mut local = None;
+        for (attr_type, value) in attributes(&payload[8..])? {
+            match attr_type {
+                IFA_ADDRESS => address = Some(read_ip(value)?),
+                IFA_LOCAL => local = Some(read_ip(value)?),
+                _ => {}
+            }
+        }
+        // on point to point links IFA_ADDRESS is the remote end, IFA_LOCAL is always ours
+        if let Some(ip) = local.or(address) {
+            addresses.push(Address {
+                index: read_u32(&payload, 4)?,
+                ip,
+                prefix_len: payload[1],
+                scope: payload[3],
+            });
+        }
+    }
+    Ok(addresses)
+}
+
+/// Returns the addresses on the named interface, None if there is no such interface
+pub fn get_link_addresses(name: &str) -> Result<Option<Vec<Address>>, Error> {
+    let link = match get_link(name)? {
+        Some(link) => link,
+        None => return Ok(None),
+    };
+    Ok(Some(
+        get_addresses()?
+            .into_iter()
+            .filter(|address| address.index == link.index)
+            .collect(),
+    ))
+}
+
+/// Dumps the routes of a single address family
+pub fn get_routes(family: libc::c_int) -> Result<Vec<Route>, Error> {
+    let mut socket = Socket::new(libc::NETLINK_ROUTE)?;
+    // struct rtmsg
+    let mut request = [0u8; 12];
+    request[0] = family as u8;
+    let replies = socket.dump(RTM_GETROUTE, &request)?;
+    let mut routes = Vec::new();
+    for (msg_type, payload) in replies {
+        if msg_type != RTM_NEWROUTE || payload.len() < 12 {
+            continue;
+        }
+        let mut route = Route {
+            dst: None,
+            dst_len: payload[1],
+            gateway: None,
+            oif: None,
+            prefsrc: None,
+            priority: None,
+            table: u32::from(payload[4]),
+            protocol: payload[5],
+            scope: payload[6],
+            kind: payload[7],
+        };
+        for (attr_type, value) in attributes(&payload[12..])? {
+            match attr_type {
+                RTA_DST => route.dst = Some(read_ip(value)?),
+                RTA_GATEWAY => route.gateway = Some(read_ip(value)?),
+                RTA_OIF => route.oif = Some(read_u32(value, 0)?),
+                RTA_PREFSRC => route.prefsrc = Some(read_ip(value)?),
+                RTA_PRIORITY => route.priority = Some(read_u32(value, 0)?),
+                RTA_TABLE => route.table = read_u32(value, 0)?,
+                _ => {}
+            }
+        }
+        routes.push(route);
+    }
+    Ok(routes)
+}
+
+/// The IPv4 default routes in the main table, what `ip route list default` prints
+pub fn get_default_routes() -> Result<Vec<Route>, Error> {
+    Ok(get_routes(libc::AF_INET)?
+        .into_iter()
+        .filter(|r| r.dst_len == 0 && r.table == RT_TABLE_MAIN && r.kind == RTN_UNICAST)
+        .collect())
+}
+
+/// Formats a route the way `ip route` would print it, so it can be handed back to `ip route add`
+pub fn route_to_tokens(route: &Route, links: &[Link]) -> Vec<String> {
+    let mut tokens = Vec::new();
+    match route.dst {
+        Some(dst) if route.dst_len != 0 => tokens.push(format!("{}/{}", dst, route.dst_len)),
+        _ => tokens.push("default".to_string()),
+    }
+    if let Some(gateway) = route.gateway {
+        tokens.push("via".to_string());
+        tokens.push(gateway.to_string());
+    }
+    if let Some(oif) = route.oif {
+        if let Some(link) = links.iter().find(|l| l.index == oif) {
+            tokens.push("dev".to_string());
+            tokens.push(link.name.clone());
+        }
+    }
+    // ip leaves out the boot protocol since it's the default
+    let protocol = match route.protocol {
+        3 => None,
+        2 => Some("kernel".to_string()),
+        4 => Some("static".to_string()),
+        9 => Some("ra".to_string()),
+        16 => Some("dhcp".to_string()),
+        42 => Some("babel".to_string()),
+        other => Some(other.to_string()),
+    };
+    if let Some(protocol) = protocol {
+        tokens.push("proto".to_string());
+        tokens.push(protocol);
+    }
+    if route.scope == RT_SCOPE_LINK {
+        tokens.push("scope".to_string());
+        tokens.push("link".to_string());
+    }
+    if let Some(prefsrc) = route.prefsrc {
+        tokens.push("src".to_string());
+        tokens.push(prefsrc.to_string());
+    }
+    if let Some(priority) = route.priority {
+        tokens.push("metric".to_string());
+        tokens.push(priority.to_string());
+    }
+    tokens
+}
+
+pub fn get_neighbors() -> Result<Vec<Neighbor>, Error> {
+    let mut socket = Socket::new(libc::NETLINK_ROUTE)?;
+    // struct ndmsg
+    let replies = socket.dump(RTM_GETNEIGH, &[0u8; 12])?;
+    let mut neighbors = Vec::new();
+    for (msg_type, payload) in replies {
+        if msg_type != RTM_NEWNEIGH || payload.len() < 12 {
+            continue;
+        }
+        let state = read_u16(&payload, 8)?;
+        let mut ip = None;
+        let mut has_lladdr = false;
+        for (attr_type, value) in attributes(&payload[12..])? {
+            match attr_type {
+                NDA_DST => ip = read_ip(value).ok(),
+                NDA_LLADDR => has_lladdr = true,
+                _ => {}
+            }
+        }
+        if let Some(ip) = ip {
+            neighbors.push(Neighbor {
+                ip,
+                index: read_u32(&payload, 4)?,
+                usable: has_lladdr && state & (NUD_REACHABLE | NUD_STALE | NUD_DELAY) != 0,
+            });
+        }
+    }
+    Ok(neighbors)
+}
+
+/// Looks up the id the kernel assigned to a generic netlink family
+fn resolve_family(socket: &mut Socket, name: &str) -> Result<u16, Error> {
+    // struct genlmsghdr then the family name
+    let mut request = vec![CTRL_CMD_GETFAMILY, 1, 0, 0];
+    let mut family_name = name.as_bytes().to_vec();
+    family_name.push(0);
+    push_attribute(&mut request, CTRL_ATTR_FAMILY_NAME, &family_name);
+
+    for (msg_type, payload) in socket.request(GENL_ID_CTRL, NLM_F_REQUEST, &request)? {
+        if msg_type != GENL_ID_CTRL || payload.len() < 4 {
+            continue;
+        }
+        for (attr_type, value) in attributes(&payload[4..])? {
+            if attr_type == CTRL_ATTR_FAMILY_ID {
+                return read_u16(value, 0);
+            }
+        }
+    }
+    bail!("Generic netlink family {} not found", name)
+}
+
+fn parse_wg_peer(buf: &[u8], peer: &mut WgPeer) -> Result<(), Error> {
+    for (attr_type, value) in attributes(buf)? {
+        match attr_type {
+            WGPEER_A_PUBLIC_KEY if value.len() == 32 => peer.public_key.copy_from_slice(value),
+            WGPEER_A_ENDPOINT => peer.endpoint = read_sockaddr(value)?,
+            WGPEER_A_LAST_HANDSHAKE_TIME => {
+                // struct __kernel_timespec
+                let secs = read_u64(value, 0)?;
+                let nanos = read_u64(value, 8)?;
+                if secs != 0 || nanos != 0 {
+                    peer.last_handshake = Some(UNIX_EPOCH + Duration::new(secs, nanos as u32));
+                }
+            }
+            WGPEER_A_RX_BYTES => peer.rx_bytes = read_u64(value, 0)?,
+            WGPEER_A_TX_BYTES => peer.tx_bytes = read_u64(value, 0)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns the peers of a WireGuard interface in the order the kernel lists them
+pub fn get_wg_peers(iface: &str) -> Result<Vec<WgPeer>, Error> {
+    let mut socket = Socket::new(libc::NETLINK_GENERIC)?;
+    let family = resolve_family(&mut socket, WG_GENL_NAME)?;
+
+    let mut request = vec![WG_CMD_GET_DEVICE, WG_GENL_VERSION, 0, 0];
+    let mut name = iface.as_bytes().to_vec();
+    name.push(0);
+    push_attribute(&mut request, WGDEVICE_A_IFNAME, &name);
+
+    let mut peers: Vec<WgPeer> = Vec::new();
+    let mut seen: HashSet<[u8; 32]> = HashSet::new();
+    for (msg_type, payload) in socket.dump(family, &request)? {
+        if msg_type != family || payload.len() < 4 {
+            continue;
+        }
+        for (attr_type, value) in attributes(&payload[4..])? {
+            if attr_type != WGDEVICE_A_PEERS {
+                continue;
+            }
+            for (_, peer_attrs) in attributes(value)? {
+                let mut peer = WgPeer::default();
+                parse_wg_peer(peer_attrs, &mut peer)?;
+                // a peer with a lot of allowed ips is split over several messages, only the
+                // first one carries anything but the key and the allowed ips
+                if seen.insert(peer.public_key) {
+                    peers.push(peer);
+                }
+            }
+        }
+    }
+    Ok(peers)
+}
+
+/// Attaches a loaded tc classifier program to the ingress or egress hook of an interface in
+/// direct action mode, replacing whatever we attached there before. The interface needs a
+/// clsact qdisc, the filter keeps the program loaded until it is replaced or the interface goes
+pub fn attach_tc_bpf(ifindex: u32, ingress: bool, prog_fd: i32, name: &str) -> Result<(), Error> {
+    let mut socket = Socket::new(libc::NETLINK_ROUTE)?;
+    let parent = (TC_H_CLSACT & 0xffff_0000)
+        | if ingress {
+            TC_H_MIN_INGRESS
+        } else {
+            TC_H_MIN_EGRESS
+        };
+    // the filter priority goes in the upper half and the protocol in network order in the lower
+    let info = (1u32 << 16) | u32::from(ETH_P_ALL.to_be());
+
+    // struct tcmsg
+    let mut request = vec![libc::AF_UNSPEC as u8, 0, 0, 0];
+    request.extend_from_slice(&(ifindex as i32).to_ne_bytes());
+    request.extend_from_slice(&1u32.to_ne_bytes());
+    request.extend_from_slice(&parent.to_ne_bytes());
+    request.extend_from_slice(&info.to_ne_bytes());
+    push_attribute(&mut request, TCA_KIND, b"bpf\0");
+
+    let mut options = Vec::new();
+    push_attribute(&mut options, TCA_BPF_FD, &(prog_fd as u32).to_ne_bytes());
+    let mut name = name.as_bytes().to_vec();
+    name.push(0);
+    push_attribute(&mut options, TCA_BPF_NAME, &name);
+    push_attribute(
+        &mut options,
+        TCA_BPF_FLAGS,
+        &TCA_BPF_FLAG_ACT_DIRECT.to_ne_bytes(),
+    );
+    push_attribute(&mut request, TCA_OPTIONS | NLA_F_NESTED, &options);
+
+    socket.request(
+        RTM_NEWTFILTER,
+        NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE,
+        &request,
+    )?;
+    Ok(())
+}
+
+/// Runs the netlink version of an operation if this kernel interface uses netlink. Returns
+/// None if it doesn't or if netlink failed, in which case the caller should run the command
+pub(crate) fn try_netlink<K: KernelInterface + ?Sized, T>(
+    ki: &K,
+    what: &str,
+    f: impl FnOnce() -> Result<T, Error>,
+) -> Option<T> {
+    if !ki.use_netlink() {
+        return None;
+    }
+    match f() {
+        Ok(res) => Some(res),
+        Err(e) => {
+            warn!(
+                "Failed to {} over netlink, running a command instead {:?}",
+                what, e
+            );
+            None
+        }
+    }
+}
+
+#[test]
+fn test_attributes_round_trip() {
+    let mut buf = Vec::new();
+    push_attribute(&mut buf, IFLA_IFNAME, b"wg0\0");
+    push_attribute(&mut buf, IFLA_OPERSTATE, &[IF_OPER_UP]);
+    // padded out to four bytes each
+    assert_eq!(buf.len(), 16);
+    let attrs = attributes(&buf).unwrap();
+    assert_eq!(attrs.len(), 2);
+    assert_eq!(attrs[0], (IFLA_IFNAME, &b"wg0\0"[..]));
+    assert_eq!(read_string(attrs[0].1), "wg0");
+    assert_eq!(attrs[1], (IFLA_OPERSTATE, &[IF_OPER_UP][..]));
+}
+
+#[test]
+fn test_parse_wg_peer() {
+    let mut buf = Vec::new();
+    push_attribute(&mut buf, WGPEER_A_PUBLIC_KEY, &[7u8; 32]);
+    // struct sockaddr_in for 71.8.186.226:60000
+    let mut endpoint = Vec::new();
+    endpoint.extend_from_slice(&(libc::AF_INET as u16).to_ne_bytes());
+    endpoint.extend_from_slice(&60000u16.to_be_bytes());
+    endpoint.extend_from_slice(&[71, 8, 186, 226]);
+    endpoint.extend_from_slice(&[0u8; 8]);
+    push_attribute(&mut buf, WGPEER_A_ENDPOINT, &endpoint);
+    let mut handshake = Vec::new();
+    handshake.extend_from_slice(&1_536_936_247u64.to_ne_bytes());
+    handshake.extend_from_slice(&0u64.to_ne_bytes());
+    push_attribute(&mut buf, WGPEER_A_LAST_HANDSHAKE_TIME, &handshake);
+    push_attribute(&mut buf, WGPEER_A_RX_BYTES, &821_519_724u64.to_ne_bytes());
+    push_attribute(
+        &mut buf,
+        WGPEER_A_TX_BYTES,
+        &13_592_616_000u64.to_ne_bytes(),
+    );
+
+    let mut peer = WgPeer::default();
+    parse_wg_peer(&buf, &mut peer).unwrap();
+    assert_eq!(peer.public_key, [7u8; 32]);
+    assert_eq!(peer.endpoint, Some("71.8.186.226:60000".parse().unwrap()));
+    assert_eq!(
+        peer.last_handshake,
+        Some(UNIX_EPOCH + Duration::from_secs(1_536_936_247))
+    );
+    assert_eq!(peer.rx_bytes, 821_519_724);
+    assert_eq!(peer.tx_bytes, 13_592_616_000);
+}
+
+#[test]
+fn test_truncated_wg_endpoint() {
+    // a family with the port cut short
+    let mut buf = Vec::new();
+    push_attribute(&mut buf, WGPEER_A_ENDPOINT, &[2, 0, 0xea]);
+    let mut peer = WgPeer::default();
+    assert!(parse_wg_peer(&buf, &mut peer).is_err());
+    assert_eq!(peer.endpoint, None);
+
+    assert!(read_sockaddr(&[]).is_err());
+    // an address cut short is skipped like an unknown family
+    let mut endpoint = Vec::new();
+    endpoint.extend_from_slice(&(libc::AF_INET6 as u16).to_ne_bytes());
+    endpoint.extend_from_slice(&60000u16.to_be_bytes());
+    endpoint.extend_from_slice(&[0u8; 8]);
+    assert_eq!(read_sockaddr(&endpoint).unwrap(), None);
+}
+
+#[test]
+fn test_route_to_tokens() {
+    let links = vec![Link {
+        index: 2,
+        name: "eth0".to_string(),
+        up: true,
+    }];
+    let route = Route {
+        dst: None,
+        dst_len: 0,
+        gateway: Some("192.168.1.1".parse().unwrap()),
+        oif: Some(2),
+        prefsrc: None,
+        priority: Some(100),
+        table: RT_TABLE_MAIN,
+        protocol: 16,
+        scope: RT_SCOPE_UNIVERSE,
+        kind: RTN_UNICAST,
+    };
+    assert_eq!(
+        route_to_tokens(&route, &links),
+        vec![
+            "default",
+            "via",
+            "192.168.1.1",
+            "dev",
+            "eth0",
+            "proto",
+            "dhcp",
+            "metric",
+            "100"
+        ]
+    );
+}
@@ -0,0 +1,672 @@
+//! Newer OpenWrt releases and Linux distributions ship nftables and no longer have iptables or
+//! ipset installed. This module implements traffic counting, NAT, and client blocking with the
+//! `nft` command for those systems. Everything rita adds lives in its own tables so it can't
+//! collide with the rules of the system firewall, and every rule carries a comment naming it so
+//! that adding a rule twice is a no-op, just like add_iptables_rule.
+
+use super::counter::FilterTarget;
+use super::KernelInterface;
+use failure::Error;
+use regex::Regex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// The inet table holding the counters and filter rules
+pub const RITA_TABLE: &str = "rita";
+/// The ip table holding the NAT rules, nat chains in inet tables need newer kernels
+pub const RITA_NAT_TABLE: &str = "rita_nat";
+
+/// Which firewall tooling the system uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallBackend {
+    Iptables,
+    Nftables,
+}
+
+lazy_static! {
+    static ref FIREWALL_BACKEND: RwLock<Option<FirewallBackend>> = RwLock::new(None);
+    /// Which of its two sets each counter's rule is currently filling
+    static ref ACTIVE_SETS: RwLock<HashMap<FilterTarget, u8>> = RwLock::new(HashMap::new());
+}
+
+/// Uses nftables if the nft binary is present and iptables is either missing or is itself a
+/// wrapper around nftables, in which case ipset matches can't be relied on
+pub(crate) fn detect_firewall_backend<K: KernelInterface + ?Sized>(ki: &K) -> FirewallBackend {
+    let has_nft = match ki.run_command("nft", &["--version"]) {
+        Ok(output) => output.status.success(),
+        Err(_) => false,
+    };
+    if !has_nft {
+        return FirewallBackend::Iptables;
+    }
+    match ki.run_command("iptables", &["-V"]) {
+        Ok(ref output) if output.status.success() => {
+            if String::from_utf8_lossy(&output.stdout).contains("nf_tables") {
+                FirewallBackend::Nftables
+            } else {
+                FirewallBackend::Iptables
+            }
+        }
+        _ => FirewallBackend::Nftables,
+    }
+}
+
+/// Returns the backend detected the first time this was called, the tools installed don't
+/// change while we are running
+pub(crate) fn firewall_backend<K: KernelInterface + ?Sized>(ki: &K) -> FirewallBackend {
+    if let Some(backend) = *FIREWALL_BACKEND.read().unwrap() {
+        return backend;
+    }
+    let backend = detect_firewall_backend(ki);
+    info!("Using {:?} for traffic counters and NAT", backend);
+    *FIREWALL_BACKEND.write().unwrap() = Some(backend);
+    backend
+}
+
+/// The chain in the rita table a filter target's rule goes into
+fn chain(target: &FilterTarget) -> &'static str {
+    match target {
+        FilterTarget::Input => "input",
+        FilterTarget::Output => "output",
+        FilterTarget::ForwardInput | FilterTarget::ForwardOutput => "forward",
+    }
+}
+
+fn interface_match(target: &FilterTarget) -> &'static str {
+    match target.interface() {
+        "src" => "iifname",
+        _ => "oifname",
+    }
+}
+
+fn run_nft<K: KernelInterface + ?Sized>(ki: &K, args: &[&str]) -> Result<String, Error> {
+    let output = ki.run_command("nft", args)?;
+    if !output.status.success() {
+        bail!(
+            "nft {:?} failed with {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Creates the rita tables and their base chains if they don't exist yet
+fn setup_tables<K: KernelInterface + ?Sized>(ki: &K) -> Result<(), Error> {
+    run_nft(ki, &["add", "table", "inet", RITA_TABLE])?;
+    for hook in &["input", "output", "forward"] {
+        run_nft(
+            ki,
+            &[
+                "add",
+                "chain",
+                "inet",
+                RITA_TABLE,
+                hook,
+                &format!("{{ type filter hook {} priority 0; policy accept; }}", hook),
+            ],
+        )?;
+    }
+    run_nft(ki, &["add", "table", "ip", RITA_NAT_TABLE])?;
+    run_nft(
+        ki,
+        &[
+            "add",
+            "chain",
+            "ip",
+            RITA_NAT_TABLE,
+            "postrouting",
+            "{ type nat hook postrouting priority 100; policy accept; }",
+        ],
+    )?;
+    Ok(())
+}
+
+/// Adds a rule to a chain unless a rule with the same name is already there, the name is
+/// stored in the rule's comment. Rules are appended unless insert is set
+fn add_nft_rule<K: KernelInterface + ?Sized>(
+    ki: &K,
+    family: &str,
+    table: &str,
+    chain: &str,
+    name: &str,
+    rule: &str,
+    insert: bool,
+) -> Result<(), Error> {
+    let comment = format!("comment \"{}\"", name);
+    let existing = run_nft(ki, &["list", "chain", family, table, chain])?;
+    if existing.contains(&comment) {
+        return Ok(());
+    }
+    let command = if insert { "insert" } else { "add" };
+    run_nft(
+        ki,
+        &[
+            command,
+            "rule",
+            family,
+            table,
+            chain,
+            &format!("{} {}", rule, comment),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Deletes the rule with the given name from a chain, does nothing if it isn't there
+fn delete_nft_rule<K: KernelInterface + ?Sized>(
+    ki: &K,
+    family: &str,
+    table: &str,
+    chain: &str,
+    name: &str,
+) -> Result<(), Error> {
+    let comment = format!("comment \"{}\"", name);
+    let existing = run_nft(ki, &["-a", "list", "chain", family, table, chain])?;
+    for line in existing.lines().filter(|l| l.contains(&comment)) {
+        if let Some(handle) = line.split("# handle ").nth(1) {
+            run_nft(
+                ki,
+                &[
+                    "delete",
+                    "rule",
+                    family,
+                    table,
+                    chain,
+                    "handle",
+                    handle.trim(),
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// One of the two sets counting traffic for the target
+fn counter_set(target: &FilterTarget, slot: u8) -> String {
+    format!("{}_{}", target.set_name(), slot)
+}
+
+/// The chain holding only the rule that fills the target's active set, so that the rule can be
+/// swapped without touching anything else
+fn counter_chain(target: &FilterTarget) -> String {
+    format!("{}_count", target.set_name())
+}
+
+/// A single nft transaction that points the counter chain at the given set
+fn fill_set_batch(target: &FilterTarget, slot: u8) -> String {
+    let chain = counter_chain(target);
+    format!(
+        "flush chain inet {table} {chain}; add rule inet {table} {chain} meta nfproto ipv6 update @{set} {{ ip6 daddr . {interface} counter }}",
+        table = RITA_TABLE,
+        chain = chain,
+        set = counter_set(target, slot),
+        interface = interface_match(target)
+    )
+}
+
+/// Creates the two sets counting traffic for the target and the chain that fills one of them,
+/// the sets are keyed by destination address and interface just like the ipset used with
+/// iptables. Having two lets read_counters swap them instead of reading and flushing the set
+/// that is being filled
+pub(crate) fn init_counter<K: KernelInterface + ?Sized>(
+    ki: &K,
+    target: &FilterTarget,
+) -> Result<(), Error> {
+    setup_tables(ki)?;
+    for slot in 0..2 {
+        run_nft(
+            ki,
+            &[
+                "add",
+                "set",
+                "inet",
+                RITA_TABLE,
+                &counter_set(target, slot),
+                "{ type ipv6_addr . ifname; flags dynamic; size 65535; }",
+            ],
+        )?;
+    }
+    let chain = counter_chain(target);
+    run_nft(ki, &["add", "chain", "inet", RITA_TABLE, &chain])?;
+    run_nft(ki, &[&fill_set_batch(target, 0)])?;
+    ACTIVE_SETS.write().unwrap().insert(*target, 0);
+    // older versions filled a single set straight from the base chain
+    delete_nft_rule(
+        ki,
+        "inet",
+        RITA_TABLE,
+        self::chain(target),
+        target.set_name(),
+    )?;
+    add_nft_rule(
+        ki,
+        "inet",
+        RITA_TABLE,
+        self::chain(target),
+        &format!("{}_jump", target.set_name()),
+        &format!("jump {}", chain),
+        true,
+    )
+}
+
+pub(crate) fn parse_nft_set(input: &str) -> Result<HashMap<(IpAddr, String), u64>, Error> {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r#"([a-f0-9:]+) \. "?(wg\d+)"? counter packets (\d+) bytes (\d+)"#)
+                .expect("Unable to compile regular expression");
+    }
+    let mut map = HashMap::new();
+
+    // example element `fd00::1 . "wg0" counter packets 28 bytes 2212`
+
+    for caps in RE.captures_iter(input) {
+        map.insert(
+            (IpAddr::from_str(&caps[1])?, String::from(&caps[2])),
+            caps[4].parse::<u64>()? + caps[3].parse::<u64>()? * 40,
+        );
+    }
+    Ok(map)
+}
+
+/// Reads the counters for the target. The rule is first pointed at the other set in a single
+/// transaction, like an ipset swap, then the set that was being filled is listed and emptied.
+/// If reading fails its counts stay in the set and are read the next time it's swapped out
+pub(crate) fn read_counters<K: KernelInterface + ?Sized>(
+    ki: &K,
+    target: &FilterTarget,
+) -> Result<HashMap<(IpAddr, String), u64>, Error> {
+    let old = *ACTIVE_SETS.read().unwrap().get(target).unwrap_or(&0);
+    let new = old ^ 1;
+    run_nft(ki, &[&fill_set_batch(target, new)])?;
+    ACTIVE_SETS.write().unwrap().insert(*target, new);
+
+    let set = counter_set(target, old);
+    let output = run_nft(ki, &["list", "set", "inet", RITA_TABLE, &set])?;
+    run_nft(ki, &["flush", "set", "inet", RITA_TABLE, &set])?;
+    let res = parse_nft_set(&output);
+    trace!("nft set parsed into {:?}", res);
+    res
+}
+
+pub(crate) fn setup_nat<K: KernelInterface + ?Sized>(
+    ki: &K,
+    external_interface: &str,
+) -> Result<(), Error> {
+    setup_tables(ki)?;
+    add_nft_rule(
+        ki,
+        "ip",
+        RITA_NAT_TABLE,
+        "postrouting",
+        "rita_exit_masquerade",
+        &format!("oifname \"{}\" masquerade", external_interface),
+        false,
+    )?;
+    add_nft_rule(
+        ki,
+        "inet",
+        RITA_TABLE,
+        "forward",
+        "rita_exit_forward_out",
+        &format!(
+            "iifname \"wg_exit\" oifname \"{}\" accept",
+            external_interface
+        ),
+        false,
+    )?;
+    add_nft_rule(
+        ki,
+        "inet",
+        RITA_TABLE,
+        "forward",
+        "rita_exit_forward_in",
+        &format!(
+            "iifname \"{}\" oifname \"wg_exit\" ct state related,established accept",
+            external_interface
+        ),
+        false,
+    )?;
+    Ok(())
+}
+
+pub(crate) fn block_exit_hello_port<K: KernelInterface + ?Sized>(
+    ki: &K,
+    rita_hello_port: u16,
+) -> Result<(), Error> {
+    setup_tables(ki)?;
+    add_nft_rule(
+        ki,
+        "inet",
+        RITA_TABLE,
+        "output",
+        "rita_block_exit_hello",
+        &format!("oifname \"wg_exit\" tcp dport {} drop", rita_hello_port),
+        true,
+    )
+}
+
+pub(crate) fn create_client_nat_rules<K: KernelInterface + ?Sized>(ki: &K) -> Result<(), Error> {
+    setup_tables(ki)?;
+    add_nft_rule(
+        ki,
+        "ip",
+        RITA_NAT_TABLE,
+        "postrouting",
+        "rita_client_masquerade",
+        "oifname \"wg_exit\" masquerade",
+        false,
+    )?;
+    add_nft_rule(
+        ki,
+        "inet",
+        RITA_TABLE,
+        "forward",
+        "rita_client_clamp_mss",
+        "tcp flags syn tcp option maxseg size set rt mtu",
+        false,
+    )
+}
+
+/// The lan forwarding chain of the OpenWrt fw4 firewall, the nftables equivalent of fw3's
+/// zone_lan_forward
+const FW4_LAN_FORWARD: (&str, &str, &str) = ("inet", "fw4", "forward_lan");
+const BLOCK_CLIENT_NAT: &str = "rita_block_client_nat";
+
+pub(crate) fn block_client_nat<K: KernelInterface + ?Sized>(ki: &K) -> Result<(), Error> {
+    let (family, table, chain) = FW4_LAN_FORWARD;
+    add_nft_rule(ki, family, table, chain, BLOCK_CLIENT_NAT, "reject", true)
+}
+
+pub(crate) fn restore_client_nat<K: KernelInterface + ?Sized>(ki: &K) -> Result<(), Error> {
+    let (family, table, chain) = FW4_LAN_FORWARD;
+    delete_nft_rule(ki, family, table, chain, BLOCK_CLIENT_NAT)
+}
+
+#[test]
+fn test_parse_nft_set() {
+    let data = r#"table inet rita {
+	set rita_input {
+		type ipv6_addr . ifname
+		size 65535
+		flags dynamic
+		elements = { fd00::1 . "wg42" counter packets 10 bytes 1000,
+			     fd00::2 . "wg0" counter packets 1 bytes 100 }
+	}
+}
+"#;
+    let result = parse_nft_set(data).unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(
+        result[&("fd00::1".parse().unwrap(), "wg42".to_string())],
+        1000 + 10 * 40
+    );
+    assert_eq!(
+        result[&("fd00::2".parse().unwrap(), "wg0".to_string())],
+        100 + 40
+    );
+}
+
+#[test]
+fn test_detect_firewall_backend() {
+    use crate::KI;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    let output = |stdout: &str, code| Output {
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: Vec::new(),
+        status: ExitStatus::from_raw(code),
+    };
+
+    KI.set_mock(Box::new(move |program, _args| match program.as_str() {
+        "nft" => Ok(output("nftables v1.0.2 (Lester Gooch)", 0)),
+        "iptables" => Ok(output("iptables v1.8.7 (nf_tables)", 0)),
+        _ => panic!("Unexpected call {}", program),
+    }));
+    assert_eq!(detect_firewall_backend(&**KI), FirewallBackend::Nftables);
+
+    KI.set_mock(Box::new(move |program, _args| match program.as_str() {
+        "nft" => Ok(output("nftables v1.0.2 (Lester Gooch)", 0)),
+        "iptables" => Ok(output("iptables v1.8.7 (legacy)", 0)),
+        _ => panic!("Unexpected call {}", program),
+    }));
+    assert_eq!(detect_firewall_backend(&**KI), FirewallBackend::Iptables);
+
+    KI.set_mock(Box::new(move |program, _args| match program.as_str() {
+        "nft" => bail!("No such file or directory"),
+        _ => panic!("Unexpected call {}", program),
+    }));
+    assert_eq!(detect_firewall_backend(&**KI), FirewallBackend::Iptables);
+}
+
+#[test]
+fn test_block_and_restore_client_nat() {
+    use crate::KI;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    let output = |stdout: &str| Output {
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: Vec::new(),
+        status: ExitStatus::from_raw(0),
+    };
+
+    let mut counter = 0;
+    KI.set_mock(Box::new(move |program, args| {
+        assert_eq!(program, "nft");
+        counter += 1;
+        match counter {
+            1 => {
+                assert_eq!(args, vec!["list", "chain", "inet", "fw4", "forward_lan"]);
+                Ok(output("table inet fw4 {\n}\n"))
+            }
+            2 => {
+                assert_eq!(
+                    args,
+                    vec![
+                        "insert",
+                        "rule",
+                        "inet",
+                        "fw4",
+                        "forward_lan",
+                        "reject comment \"rita_block_client_nat\""
+                    ]
+                );
+                Ok(output(""))
+            }
+            3 => {
+                assert_eq!(
+                    args,
+                    vec!["-a", "list", "chain", "inet", "fw4", "forward_lan"]
+                );
+                Ok(output(
+                    "\t\treject comment \"rita_block_client_nat\" # handle 42\n\
+                     \t\tjump accept_to_wan # handle 7\n",
+                ))
+            }
+            4 => {
+                assert_eq!(
+                    args,
+                    vec![
+                        "delete",
+                        "rule",
+                        "inet",
+                        "fw4",
+                        "forward_lan",
+                        "handle",
+                        "42"
+                    ]
+                );
+                Ok(output(""))
+            }
+            _ => panic!("Unexpected call {} {:?} {:?}", counter, program, args),
+        }
+    }));
+    block_client_nat(&**KI).unwrap();
+    restore_client_nat(&**KI).unwrap();
+}
+
+/// A runner of its own for tests that check a whole command sequence, so that other tests
+/// setting the global mock can't interleave with it. Every call is recorded
+#[cfg(test)]
+fn recording_runner(
+    respond: impl Fn(&[String]) -> String + Send + 'static,
+) -> (
+    crate::TestCommandRunner,
+    std::sync::Arc<std::sync::Mutex<Vec<Vec<String>>>>,
+) {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+    use std::sync::{Arc, Mutex};
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let recorded = calls.clone();
+    let runner = crate::TestCommandRunner {
+        run_command: Arc::new(Mutex::new(Box::new(move |program, args| {
+            assert_eq!(program, "nft");
+            let stdout = respond(&args);
+            recorded.lock().unwrap().push(args);
+            Ok(Output {
+                stdout: stdout.into_bytes(),
+                stderr: Vec::new(),
+                status: ExitStatus::from_raw(0),
+            })
+        }))),
+    };
+    (runner, calls)
+}
+
+#[cfg(test)]
+fn setup_tables_calls() -> Vec<Vec<String>> {
+    let mut calls = vec![vec!["add", "table", "inet", "rita"]];
+    for hook in &["input", "output", "forward"] {
+        calls.push(vec![
+            "add",
+            "chain",
+            "inet",
+            "rita",
+            hook,
+            match *hook {
+                "input" => "{ type filter hook input priority 0; policy accept; }",
+                "output" => "{ type filter hook output priority 0; policy accept; }",
+                _ => "{ type filter hook forward priority 0; policy accept; }",
+            },
+        ]);
+    }
+    calls.push(vec!["add", "table", "ip", "rita_nat"]);
+    calls.push(vec![
+        "add",
+        "chain",
+        "ip",
+        "rita_nat",
+        "postrouting",
+        "{ type nat hook postrouting priority 100; policy accept; }",
+    ]);
+    calls
+        .into_iter()
+        .map(|call| call.iter().map(|arg| arg.to_string()).collect())
+        .collect()
+}
+
+#[test]
+fn test_init_and_read_counter() {
+    let (ki, calls) = recording_runner(|args| {
+        if args[0] == "-a" {
+            // the rule left behind by an older version
+            "\t\tmeta nfproto ipv6 update @rita_input { ip6 daddr . iifname counter } comment \"rita_input\" # handle 5\n".to_string()
+        } else if args.starts_with(&["list".to_string(), "set".to_string()]) {
+            "elements = { fd00::1 . \"wg0\" counter packets 2 bytes 200 }".to_string()
+        } else {
+            String::new()
+        }
+    });
+
+    init_counter(&ki, &FilterTarget::Input).unwrap();
+    let res = read_counters(&ki, &FilterTarget::Input).unwrap();
+    assert_eq!(
+        res[&("fd00::1".parse().unwrap(), "wg0".to_string())],
+        200 + 2 * 40
+    );
+    // the next read swaps back
+    read_counters(&ki, &FilterTarget::Input).unwrap();
+
+    let batch = |set: &str| {
+        vec![format!(
+            "flush chain inet rita rita_input_count; add rule inet rita rita_input_count meta nfproto ipv6 update @{} {{ ip6 daddr . iifname counter }}",
+            set
+        )]
+    };
+    let strings = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+    let set_options = "{ type ipv6_addr . ifname; flags dynamic; size 65535; }";
+    let mut expected = setup_tables_calls();
+    expected.extend(vec![
+        strings(&["add", "set", "inet", "rita", "rita_input_0", set_options]),
+        strings(&["add", "set", "inet", "rita", "rita_input_1", set_options]),
+        strings(&["add", "chain", "inet", "rita", "rita_input_count"]),
+        batch("rita_input_0"),
+        strings(&["-a", "list", "chain", "inet", "rita", "input"]),
+        strings(&["delete", "rule", "inet", "rita", "input", "handle", "5"]),
+        strings(&["list", "chain", "inet", "rita", "input"]),
+        strings(&[
+            "insert",
+            "rule",
+            "inet",
+            "rita",
+            "input",
+            "jump rita_input_count comment \"rita_input_jump\"",
+        ]),
+        // the rule moves to the other set before the one it filled is read
+        batch("rita_input_1"),
+        strings(&["list", "set", "inet", "rita", "rita_input_0"]),
+        strings(&["flush", "set", "inet", "rita", "rita_input_0"]),
+        batch("rita_input_0"),
+        strings(&["list", "set", "inet", "rita", "rita_input_1"]),
+        strings(&["flush", "set", "inet", "rita", "rita_input_1"]),
+    ]);
+    assert_eq!(*calls.lock().unwrap(), expected);
+}
+
+#[test]
+fn test_setup_nat() {
+    let (ki, calls) = recording_runner(|_args| String::new());
+    setup_nat(&ki, "eth0").unwrap();
+
+    let mut expected = setup_tables_calls();
+    for (family, table, chain, rule) in &[
+        (
+            "ip",
+            "rita_nat",
+            "postrouting",
+            "oifname \"eth0\" masquerade comment \"rita_exit_masquerade\"",
+        ),
+        (
+            "inet",
+            "rita",
+            "forward",
+            "iifname \"wg_exit\" oifname \"eth0\" accept comment \"rita_exit_forward_out\"",
+        ),
+        (
+            "inet",
+            "rita",
+            "forward",
+            "iifname \"eth0\" oifname \"wg_exit\" ct state related,established accept comment \"rita_exit_forward_in\"",
+        ),
+    ] {
+        expected.push(
+            ["list", "chain", family, table, chain]
+                .iter()
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        expected.push(
+            ["add", "rule", family, table, chain, rule]
+                .iter()
+                .map(|a| a.to_string())
+                .collect(),
+        );
+    }
+    assert_eq!(*calls.lock().unwrap(), expected);
+}
@@ -51,6 +51,55 @@ fn socket_to_string(endpoint: &SocketAddr, interface_name: Option<String>) -> St
     }
 }
 
+/// A WireGuard peer as configured on one of our interfaces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WgPeer {
+    pub public_key: WgKey,
+    pub endpoint: SocketAddr,
+    /// the interface a link local endpoint is reachable on
+    pub endpoint_iface: Option<String>,
+    pub allowed_ips: Vec<String>,
+    pub persistent_keepalive: u16,
+}
+
+pub(crate) fn set_peer<K: KernelInterface + ?Sized>(
+    ki: &K,
+    interface: &str,
+    listen_port: u16,
+    private_key_path: &Path,
+    peer: &WgPeer,
+) -> Result<(), Error> {
+    let socket_connect_str = socket_to_string(&peer.endpoint, peer.endpoint_iface.clone());
+    trace!("socket conenct string: {}", socket_connect_str);
+    let output = ki.run_command(
+        "wg",
+        &[
+            "set",
+            interface,
+            "listen-port",
+            &format!("{}", listen_port),
+            "private-key",
+            private_key_path.to_str().unwrap(),
+            "peer",
+            &format!("{}", peer.public_key),
+            "endpoint",
+            &socket_connect_str,
+            "allowed-ips",
+            &peer.allowed_ips.join(","),
+            "persistent-keepalive",
+            &format!("{}", peer.persistent_keepalive),
+        ],
+    )?;
+    if !output.stderr.is_empty() {
+        return Err(KernelInterfaceError::RuntimeError(format!(
+            "received error from wg command: {}",
+            String::from_utf8(output.stderr)?
+        ))
+        .into());
+    }
+    Ok(())
+}
+
 impl dyn KernelInterface {
     pub fn open_tunnel(
         &self,
@@ -76,39 +125,23 @@ impl dyn KernelInterface {
             }
         };
 
-        let allowed_addresses = match allowed_ipv4_address {
-            None => "::/0".to_string(),
-            Some(_) => format!("::/0,0.0.0.0/0"),
+        let allowed_ips = match allowed_ipv4_address {
+            None => vec!["::/0".to_string()],
+            Some(_) => vec!["::/0".to_string(), "0.0.0.0/0".to_string()],
         };
 
-        let socket_connect_str = socket_to_string(endpoint, phy_name);
-        trace!("socket conenct string: {}", socket_connect_str);
-        let output = self.run_command(
-            "wg",
-            &[
-                "set",
-                &interface,
-                "listen-port",
-                &format!("{}", port),
-                "private-key",
-                &format!("{}", private_key_path.to_str().unwrap()),
-                "peer",
-                &format!("{}", remote_pub_key),
-                "endpoint",
-                &socket_connect_str,
-                "allowed-ips",
-                &allowed_addresses,
-                "persistent-keepalive",
-                "5",
-            ],
+        self.set_peer(
+            interface,
+            port,
+            private_key_path,
+            &WgPeer {
+                public_key: *remote_pub_key,
+                endpoint: *endpoint,
+                endpoint_iface: phy_name,
+                allowed_ips,
+                persistent_keepalive: 5,
+            },
         )?;
-        if !output.stderr.is_empty() {
-            return Err(KernelInterfaceError::RuntimeError(format!(
-                "received error from wg command: {}",
-                String::from_utf8(output.stderr)?
-            ))
-            .into());
-        }
         let _output = self.run_command(
             "ip",
             &["address", "add", &format!("{}", own_ip), "dev", &interface],
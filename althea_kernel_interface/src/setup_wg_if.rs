@@ -1,3 +1,5 @@
+use super::netlink;
+use super::netlink::try_netlink;
 use super::{KernelInterface, KernelInterfaceError};
 use althea_types::WgKey;
 use failure::err_msg;
@@ -6,26 +8,56 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use failure::Error;
 
-impl dyn KernelInterface {
-    pub fn get_peers(&self, iface_name: &str) -> Result<Vec<WgKey>, Error> {
-        let output = self.run_command("wg", &["show", iface_name, "peers"])?;
+pub(crate) fn get_peers<K: KernelInterface + ?Sized>(
+    ki: &K,
+    iface_name: &str,
+) -> Result<Vec<WgKey>, Error> {
+    if let Some(peers) = try_netlink(ki, "list wg peers", || netlink::get_wg_peers(iface_name)) {
+        return Ok(peers
+            .into_iter()
+            .map(|peer| WgKey::from(peer.public_key))
+            .collect());
+    }
 
-        let output = from_utf8(&output.stdout)?;
+    let output = ki.run_command("wg", &["show", iface_name, "peers"])?;
 
-        let mut peers = Vec::new();
+    let output = from_utf8(&output.stdout)?;
 
-        for l in output.lines() {
-            let parsed = l.parse();
-            if parsed.is_ok() {
-                peers.push(parsed.unwrap());
-            } else {
-                warn!("Could not parse peer! {}", l);
-            }
+    let mut peers = Vec::new();
+
+    for l in output.lines() {
+        let parsed = l.parse();
+        if parsed.is_ok() {
+            peers.push(parsed.unwrap());
+        } else {
+            warn!("Could not parse peer! {}", l);
         }
+    }
 
-        Ok(peers)
+    Ok(peers)
+}
+
+pub(crate) fn create_wg_interface<K: KernelInterface + ?Sized>(
+    ki: &K,
+    name: &str,
+) -> Result<(), Error> {
+    let output = ki.run_command("ip", &["link", "add", &name, "type", "wireguard"])?;
+    let stderr = String::from_utf8(output.stderr)?;
+    if !stderr.is_empty() {
+        if stderr.contains("exists") {
+            return Ok(());
+        } else {
+            return Err(KernelInterfaceError::RuntimeError(format!(
+                "received error adding wg link: {}",
+                stderr
+            ))
+            .into());
+        }
     }
+    Ok(())
+}
 
+impl dyn KernelInterface {
     /// checks the existing interfaces to find an interface name that isn't in use.
     /// then calls iproute2 to set up a new interface.
     pub fn setup_wg_if(&self) -> Result<String, Error> {
@@ -37,30 +69,27 @@ impl dyn KernelInterface {
             if_num += 1;
         }
         let interface = format!("wg{}", if_num);
-        self.setup_wg_if_named(&interface)?;
+        self.create_wg_interface(&interface)?;
         Ok(interface)
     }
 
-    /// calls iproute2 to set up a new interface with a given name.
-    pub fn setup_wg_if_named(&self, name: &str) -> Result<(), Error> {
-        let output = self.run_command("ip", &["link", "add", &name, "type", "wireguard"])?;
-        let stderr = String::from_utf8(output.stderr)?;
-        if !stderr.is_empty() {
-            if stderr.contains("exists") {
-                return Ok(());
-            } else {
-                return Err(KernelInterfaceError::RuntimeError(format!(
-                    "received error adding wg link: {}",
-                    stderr
-                ))
-                .into());
-            }
-        }
-        Ok(())
-    }
-
     /// Returns the number of clients that are active on the wg_exit tunnel
     pub fn get_wg_exit_clients_online(&self) -> Result<u32, Error> {
+        if let Some(peers) = try_netlink(self, "list wg peers", || netlink::get_wg_peers("wg_exit"))
+        {
+            let now = SystemTime::now();
+            let online = peers
+                .iter()
+                .filter_map(|peer| peer.last_handshake)
+                .filter(|handshake| match now.duration_since(*handshake) {
+                    Ok(elapsed) => elapsed < Duration::new(600, 0),
+                    // handshakes from the future are as recent as it gets
+                    Err(_) => true,
+                })
+                .count();
+            return Ok(online as u32);
+        }
+
         let output = self.run_command("wg", &["show", "wg_exit", "latest-handshakes"])?;
         let mut num: u32 = 0;
         let out = String::from_utf8(output.stdout)?;
@@ -0,0 +1,403 @@
+//! Changing several UCI options one after another can leave a router unreachable if anything
+//! goes wrong halfway through, or if the finished config simply doesn't work. A UciTransaction
+//! stages every change first and reverts all of them if any fails to stage. Once everything is
+//! staged it snapshots the config files, commits, reloads the affected services and then keeps
+//! running a connectivity check until it passes. If it doesn't pass before the timeout the
+//! snapshot is put back and the services are reloaded again, much like "commit confirmed" on
+//! network operating systems. The same happens if the commit or the reload itself fails.
+//!
+//! UCI stages changes in one shared place per config, so only one transaction can exist at a
+//! time, starting another waits until the first one is applied or dropped.
+
+use super::KernelInterface;
+use failure::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where UCI keeps its committed config files
+pub const UCI_CONFIG_DIR: &str = "/etc/config";
+/// How often the connectivity check runs while waiting for it to pass
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+lazy_static! {
+    /// True while a transaction is in progress, the condvar wakes those waiting to start one
+    static ref TRANSACTION_LOCK: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+}
+
+/// Marks a transaction in progress until dropped. Unlike a MutexGuard it can be sent to
+/// another thread, so a transaction can be staged in one thread and applied in another
+struct TransactionLock;
+
+impl TransactionLock {
+    /// Blocks until no other transaction is in progress
+    fn acquire() -> TransactionLock {
+        let (lock, condvar) = &*TRANSACTION_LOCK;
+        // a transaction that panicked left nothing behind that the next one can't deal with
+        let mut busy = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while *busy {
+            busy = condvar
+                .wait(busy)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        *busy = true;
+        TransactionLock
+    }
+}
+
+impl Drop for TransactionLock {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*TRANSACTION_LOCK;
+        *lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = false;
+        condvar.notify_one();
+    }
+}
+
+/// A set of UCI changes to one or more configs that are applied together
+pub struct UciTransaction<'a> {
+    ki: &'a (dyn KernelInterface + 'static),
+    configs: Vec<String>,
+    config_dir: PathBuf,
+    /// the first change that failed to stage, nothing is staged after it
+    error: Option<Error>,
+    _lock: TransactionLock,
+}
+
+impl<'a> UciTransaction<'a> {
+    /// Starts a transaction over the given configs, for example "network". Every change made
+    /// through it must be to one of them. Blocks while another transaction is in progress
+    pub fn new(ki: &'a (dyn KernelInterface + 'static), configs: &[&str]) -> UciTransaction<'a> {
+        let lock = TransactionLock::acquire();
+        UciTransaction {
+            ki,
+            configs: configs.iter().map(|c| c.to_string()).collect(),
+            config_dir: PathBuf::from(UCI_CONFIG_DIR),
+            error: None,
+            _lock: lock,
+        }
+    }
+
+    /// Snapshots and restores config files from somewhere other than /etc/config
+    pub fn with_config_dir(mut self, config_dir: &Path) -> UciTransaction<'a> {
+        self.config_dir = config_dir.to_path_buf();
+        self
+    }
+
+    fn stage(
+        &mut self,
+        key: &str,
+        change: impl FnOnce(&(dyn KernelInterface + 'static)) -> Result<(), Error>,
+    ) {
+        if self.error.is_some() {
+            return;
+        }
+        let config = key.split('.').next().unwrap_or("");
+        if !self.configs.iter().any(|c| c == config) {
+            self.error = Some(format_err!(
+                "{} is not part of the transaction over {:?}",
+                key,
+                self.configs
+            ));
+            return;
+        }
+        if let Err(e) = change(self.ki) {
+            self.error = Some(e);
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.stage(key, |ki| ki.set_uci_var(key, value))
+    }
+
+    pub fn delete(&mut self, key: &str) {
+        self.stage(key, |ki| ki.del_uci_var(key))
+    }
+
+    pub fn set_list(&mut self, key: &str, value: &[&str]) {
+        self.stage(key, |ki| ki.set_uci_list(key, value))
+    }
+
+    /// Reads a value including the changes staged so far
+    pub fn get(&self, key: &str) -> Result<String, Error> {
+        self.ki.get_uci_var(key)
+    }
+
+    /// Throws away everything staged in the transaction's configs
+    pub fn revert(&self) -> Result<(), Error> {
+        for config in self.configs.iter() {
+            self.ki.uci_revert(config)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the error of the first change that failed to stage, if any
+    pub fn staging_error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Commits the staged changes and reloads the given init.d services, then waits up to
+    /// timeout for check to return true. If it never does, or the commit or reload fails, the
+    /// configs are restored to what they were before the commit and the services are reloaded
+    /// again. If staging failed nothing is committed, the staged changes are reverted and the
+    /// staging error returned
+    pub fn apply(
+        mut self,
+        services: &[&str],
+        timeout: Duration,
+        check: impl Fn() -> bool,
+    ) -> Result<(), Error> {
+        if let Some(e) = self.error.take() {
+            let res = self.revert();
+            bail!(
+                "Error staging UCI changes {:?}, revert attempted: {:?}",
+                e,
+                res
+            );
+        }
+
+        let snapshot = self.snapshot()?;
+        let res = self.commit(services).and_then(|_| {
+            if wait_for(timeout, check) {
+                Ok(())
+            } else {
+                bail!("Connectivity check failed within {}s", timeout.as_secs())
+            }
+        });
+        let e = match res {
+            Ok(()) => {
+                info!("UCI changes to {:?} confirmed", self.configs);
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        error!(
+            "Applying UCI changes to {:?} failed with {:?}, rolling back",
+            self.configs, e
+        );
+        // reload even if restoring failed part way, whatever was restored should take effect
+        let restored = self.restore(&snapshot);
+        let reloaded = self.reload(services);
+        bail!(
+            "{}, changes to {:?} rolled back: restore {:?} reload {:?}",
+            e,
+            self.configs,
+            restored,
+            reloaded
+        )
+    }
+
+    fn commit(&self, services: &[&str]) -> Result<(), Error> {
+        for config in self.configs.iter() {
+            self.ki.uci_commit(config)?;
+        }
+        self.reload(services)
+    }
+
+    fn snapshot(&self) -> Result<HashMap<String, String>, Error> {
+        let mut snapshot = HashMap::new();
+        for config in self.configs.iter() {
+            let contents = fs::read_to_string(self.config_dir.join(config))?;
+            snapshot.insert(config.clone(), contents);
+        }
+        Ok(snapshot)
+    }
+
+    fn restore(&self, snapshot: &HashMap<String, String>) -> Result<(), Error> {
+        for (config, contents) in snapshot.iter() {
+            // anything staged since would otherwise be laid on top of the restored file
+            self.ki.uci_revert(config)?;
+            fs::write(self.config_dir.join(config), contents)?;
+        }
+        self.ki.fs_sync()?;
+        Ok(())
+    }
+
+    fn reload(&self, services: &[&str]) -> Result<(), Error> {
+        for service in services {
+            self.ki.refresh_initd(service)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs check every CHECK_INTERVAL until it passes or the timeout is up
+fn wait_for(timeout: Duration, check: impl Fn() -> bool) -> bool {
+    let start = Instant::now();
+    loop {
+        if check() {
+            return true;
+        }
+        if Instant::now() - start >= timeout {
+            return false;
+        }
+        thread::sleep(CHECK_INTERVAL.min(timeout));
+    }
+}
+
+impl dyn KernelInterface {
+    /// Starts a UciTransaction over the given configs
+    pub fn uci_transaction(&self, configs: &[&str]) -> UciTransaction<'_> {
+        UciTransaction::new(self, configs)
+    }
+}
+
+#[test]
+fn test_uci_transaction_rollback() {
+    use crate::KI;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+    use std::sync::{Arc, Mutex};
+
+    let dir = std::env::temp_dir().join("rita_uci_transaction_test");
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("network");
+    fs::write(&config, "config interface 'lan'\n").unwrap();
+
+    let commands = Arc::new(Mutex::new(Vec::new()));
+    let recorded = commands.clone();
+    let written = config.clone();
+    KI.set_mock(Box::new(move |program, args| {
+        // a commit that breaks the router
+        if args.first().map(|a| a.as_str()) == Some("commit") {
+            fs::write(&written, "config interface 'broken'\n").unwrap();
+        }
+        recorded
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", program, args.join(" ")));
+        Ok(Output {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            status: ExitStatus::from_raw(0),
+        })
+    }));
+
+    let mut tx = KI.uci_transaction(&["network"]).with_config_dir(&dir);
+    tx.set("network.backhaul", "interface");
+    tx.delete("network.rita_eth0");
+    let res = tx.apply(&["network"], Duration::from_millis(0), || false);
+    assert!(res.is_err());
+    assert_eq!(
+        fs::read_to_string(&config).unwrap(),
+        "config interface 'lan'\n"
+    );
+    assert_eq!(
+        *commands.lock().unwrap(),
+        vec![
+            "uci set network.backhaul=interface",
+            "uci delete network.rita_eth0",
+            "uci commit network",
+            "/etc/init.d/network reload",
+            "uci revert network",
+            "sync ",
+            "/etc/init.d/network reload",
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_uci_transaction_staging_error() {
+    use crate::KI;
+
+    let mut tx = KI.uci_transaction(&["network"]);
+    tx.set("wireless.mesh.disabled", "1");
+    assert!(tx.staging_error().is_some());
+}
+
+#[test]
+fn test_uci_transaction_failed_commit() {
+    use crate::TestCommandRunner;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+    use std::sync::Arc;
+
+    let dir = std::env::temp_dir().join("rita_uci_transaction_commit_test");
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("network");
+    fs::write(&config, "config interface 'lan'\n").unwrap();
+
+    let commands = Arc::new(Mutex::new(Vec::new()));
+    let recorded = commands.clone();
+    let written = config.clone();
+    let ki = TestCommandRunner {
+        run_command: Arc::new(Mutex::new(Box::new(move |program, args| {
+            recorded
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", program, args.join(" ")));
+            // the commit gets part way and fails
+            let code = if args.first().map(|a| a.as_str()) == Some("commit") {
+                fs::write(&written, "config interface 'half'\n").unwrap();
+                1
+            } else {
+                0
+            };
+            Ok(Output {
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+                status: ExitStatus::from_raw(code << 8),
+            })
+        }))),
+    };
+
+    let mut tx = UciTransaction::new(&ki, &["network"]).with_config_dir(&dir);
+    tx.set("network.backhaul", "interface");
+    assert!(tx
+        .apply(&["network"], Duration::from_secs(60), || true)
+        .is_err());
+    assert_eq!(
+        fs::read_to_string(&config).unwrap(),
+        "config interface 'lan'\n"
+    );
+    assert_eq!(
+        *commands.lock().unwrap(),
+        vec![
+            "uci set network.backhaul=interface",
+            "uci commit network",
+            "uci revert network",
+            "sync ",
+            "/etc/init.d/network reload",
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_uci_transaction_other_thread() {
+    use crate::TestCommandRunner;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+    use std::sync::Arc;
+
+    lazy_static! {
+        static ref RUNNER: TestCommandRunner = TestCommandRunner {
+            run_command: Arc::new(Mutex::new(Box::new(|_program, _args| {
+                Ok(Output {
+                    stdout: Vec::new(),
+                    stderr: Vec::new(),
+                    status: ExitStatus::from_raw(0),
+                })
+            }))),
+        };
+    }
+
+    let dir = std::env::temp_dir().join("rita_uci_transaction_thread_test");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("network"), "config interface 'lan'\n").unwrap();
+
+    let mut tx = UciTransaction::new(&*RUNNER, &["network"]).with_config_dir(&dir);
+    tx.set("network.backhaul", "interface");
+    // staged here and applied elsewhere, the next transaction can start once it's done
+    thread::spawn(move || tx.apply(&["network"], Duration::from_secs(1), || true))
+        .join()
+        .unwrap()
+        .unwrap();
+    let tx = UciTransaction::new(&*RUNNER, &["network"]);
+    assert!(tx.staging_error().is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -9,6 +9,8 @@ use std::collections::HashMap;
 
 use althea_types::WgKey;
 
+use super::netlink;
+use super::netlink::try_netlink;
 use super::{KernelInterface, KernelInterfaceError};
 
 #[derive(Clone, Debug, Copy)]
@@ -44,46 +46,57 @@ pub fn prepare_usage_history(
     }
 }
 
-impl dyn KernelInterface {
-    /// Takes a wg interface name and provides upload and download since creation in bytes
-    /// in a hashmap indexed by peer WireGuard key
-    pub fn read_wg_counters(&self, wg_name: &str) -> Result<HashMap<WgKey, WgUsage>, Error> {
-        let output = self.run_command("wg", &["show", wg_name, "transfer"])?;
-        if !output.stderr.is_empty() {
-            return Err(KernelInterfaceError::RuntimeError(format!(
-                "received error from wg command: {}",
-                String::from_utf8(output.stderr)?
-            ))
-            .into());
-        }
+pub(crate) fn read_wg_counters<K: KernelInterface + ?Sized>(
+    ki: &K,
+    wg_name: &str,
+) -> Result<HashMap<WgKey, WgUsage>, Error> {
+    if let Some(peers) = try_netlink(ki, "read wg counters", || netlink::get_wg_peers(wg_name)) {
+        return Ok(peers
+            .into_iter()
+            .map(|peer| {
+                let usage = WgUsage {
+                    upload: peer.tx_bytes,
+                    download: peer.rx_bytes,
+                };
+                (WgKey::from(peer.public_key), usage)
+            })
+            .collect());
+    }
 
-        lazy_static! {
-            static ref RE: Regex = Regex::new(
-                r"(?P<key>[+/=0-9a-zA-Z]+)\t(?P<download>[0-9]+)\t(?P<upload>[0-9]+)\n*"
-            )
-            .expect("Unable to compile regular expression");
-        }
+    let output = ki.run_command("wg", &["show", wg_name, "transfer"])?;
+    if !output.stderr.is_empty() {
+        return Err(KernelInterfaceError::RuntimeError(format!(
+            "received error from wg command: {}",
+            String::from_utf8(output.stderr)?
+        ))
+        .into());
+    }
 
-        let mut result = HashMap::new();
-        for item in RE.captures_iter(&String::from_utf8(output.stdout)?) {
-            let usage = WgUsage {
-                upload: item["upload"].parse()?,
-                download: item["download"].parse()?,
-            };
-            match item["key"].parse() {
-                Ok(key) => {
-                    result.insert(key, usage);
-                }
-                Err(e) => warn!(
-                    "Failed to parse WgKey {} with {:?}",
-                    item["key"].to_string(),
-                    e
-                ),
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"(?P<key>[+/=0-9a-zA-Z]+)\t(?P<download>[0-9]+)\t(?P<upload>[0-9]+)\n*")
+                .expect("Unable to compile regular expression");
+    }
+
+    let mut result = HashMap::new();
+    for item in RE.captures_iter(&String::from_utf8(output.stdout)?) {
+        let usage = WgUsage {
+            upload: item["upload"].parse()?,
+            download: item["download"].parse()?,
+        };
+        match item["key"].parse() {
+            Ok(key) => {
+                result.insert(key, usage);
             }
+            Err(e) => warn!(
+                "Failed to parse WgKey {} with {:?}",
+                item["key"].to_string(),
+                e
+            ),
         }
-
-        Ok(result)
     }
+
+    Ok(result)
 }
 
 #[test]
@@ -1,6 +1,7 @@
 use crate::wg_key::WgKey;
 use arrayvec::ArrayString;
 use clarity::Address;
+use clarity::Signature;
 use failure::Error;
 use num256::Uint256;
 use std::collections::hash_map::DefaultHasher;
@@ -235,6 +236,9 @@ pub struct ExitClientIdentity {
     pub global: Identity,
     pub reg_details: ExitRegistrationDetails,
     pub low_balance: Option<bool>,
+    /// set when our withdraw policy has held a withdraw that the owner should hear about
+    #[serde(default)]
+    pub withdraw_held: Option<bool>,
 }
 
 /// Wrapper for secure box containing an exit client identity
@@ -256,7 +260,13 @@ pub struct EncryptedExitState {
 pub enum ExitVerifMode {
     Phone,
     Email,
+    /// registration requires manual approval by the exit operator. Clients built before this
+    /// mode existed can't parse it, so exits shouldn't switch to it until their clients are updated
+    Approval,
     Off,
+    /// a mode added after this version, we can't register with an exit using it
+    #[serde(other)]
+    Unknown,
 }
 
 fn default_verif_mode() -> ExitVerifMode {
@@ -276,9 +286,28 @@ pub struct ExitDetails {
     pub verif_mode: ExitVerifMode,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
 pub struct ExitClientDetails {
     pub client_internal_ip: IpAddr,
+    /// The service plan the exit has assigned to this client, None if the
+    /// exit wide price from ExitDetails applies
+    #[serde(default)]
+    pub service_plan: Option<ExitServicePlan>,
+}
+
+/// A service plan assigned to a client by an exit, when present it overrides
+/// the exit_price in ExitDetails for this specific client
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct ExitServicePlan {
+    pub name: String,
+    /// Price in wei per byte
+    pub price_per_byte: u64,
+    /// Bandwidth in kbit/s the client is guaranteed while in good standing
+    pub guaranteed_kbit: u32,
+    /// Bandwidth in kbit/s the client may burst to when capacity is available
+    pub burst_kbit: u32,
+    /// Maximum bytes the client may use in a month, None for no cap
+    pub monthly_data_cap: Option<u64>,
 }
 
 #[cfg(feature = "actix")]
@@ -374,4 +403,33 @@ pub struct OracleUpdate {
     pub release_feed: Option<String>,
     /// A json payload to be merged into the existing settings
     pub merge_json: serde_json::Value,
+    /// Increased by the oracle with every new update, a unix timestamp works. Signed updates
+    /// that aren't newer than the last one applied are refused so an old update can't be
+    /// replayed, older unsigned oracles leave it out
+    #[serde(default)]
+    pub sequence: u64,
+}
+
+/// An OracleUpdate along with signatures over it. The update is carried as the exact json
+/// string that was signed so that checking the signatures doesn't depend on how it would be
+/// serialized again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedOracleUpdate {
+    /// a json encoded OracleUpdate
+    pub update: String,
+    /// signatures over the keccak256 hash of update
+    pub signatures: Vec<Signature>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_verif_mode() {
+        let mode: ExitVerifMode = serde_json::from_str("\"Approval\"").unwrap();
+        assert_eq!(mode, ExitVerifMode::Approval);
+        let mode: ExitVerifMode = serde_json::from_str("\"Fingerprint\"").unwrap();
+        assert_eq!(mode, ExitVerifMode::Unknown);
+    }
 }
@@ -0,0 +1,16 @@
+#![no_main]
+use babel_monitor::parser::parse_babel_line;
+use babel_monitor::parser::parse_babel_output;
+use libfuzzer_sys::fuzz_target;
+
+// Babel output is untrusted as far as the parser is concerned, it should never panic and
+// anything it accepts must come out the same after being written back and parsed again
+fuzz_target!(|data: &[u8]| {
+    if let Ok(output) = std::str::from_utf8(data) {
+        if let Ok(lines) = parse_babel_output(output) {
+            for line in lines {
+                assert_eq!(parse_babel_line(&line.to_string()).unwrap(), line);
+            }
+        }
+    }
+});
@@ -0,0 +1,467 @@
+//! An in process stand in for babeld's management interface, for tests that need something
+//! to talk to on the babel port. It listens on a real TCP socket on localhost, sends the
+//! `ALTHEA 0.1` preamble and answers `dump`, `monitor`, `fee`, `metric-factor`, `interface`,
+//! and `flush interface` the way babeld does. The route and neighbor tables are scripted by the
+//! test, changes are pushed to any connection that has run `monitor` just like the real thing.
+
+use crate::parser::BabelAction;
+use crate::parser::BabelEntry;
+use crate::parser::BabelLine;
+use crate::parser::Interface;
+use crate::parser::XRoute;
+use crate::Neighbor;
+use crate::Route;
+use ipnetwork::IpNetwork;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+const PREAMBLE: &str =
+    "ALTHEA 0.1\nversion babeld-fake\nhost fake-babel\nmy-id 00:00:00:00:00:00:00:01\nok\n";
+
+#[derive(Default)]
+struct FakeBabelState {
+    local_fee: u32,
+    metric_factor: u32,
+    interfaces: BTreeMap<String, Interface>,
+    neighbors: BTreeMap<String, Neighbor>,
+    xroutes: BTreeMap<String, XRoute>,
+    routes: BTreeMap<String, Route>,
+    /// every command received, in order
+    commands: Vec<String>,
+    /// connections that have asked to be sent updates
+    monitors: Vec<TcpStream>,
+    /// every open connection, so they can be closed when the fake is dropped
+    connections: Vec<TcpStream>,
+}
+
+impl FakeBabelState {
+    fn dump(&self) -> String {
+        let mut out = String::new();
+        let mut push = |line: BabelLine| {
+            out.push_str(&line.to_string());
+            out.push('\n');
+        };
+        push(BabelLine::LocalFee(self.local_fee));
+        push(BabelLine::MetricFactor(self.metric_factor));
+        let add = |entry| BabelLine::Update {
+            action: BabelAction::Add,
+            entry,
+        };
+        for iface in self.interfaces.values() {
+            push(add(BabelEntry::Interface(iface.clone())));
+        }
+        for neigh in self.neighbors.values() {
+            push(add(BabelEntry::Neighbor(neigh.clone())));
+        }
+        for xroute in self.xroutes.values() {
+            push(add(BabelEntry::XRoute(xroute.clone())));
+        }
+        for route in self.routes.values() {
+            push(add(BabelEntry::Route(route.clone())));
+        }
+        out
+    }
+
+    /// Sends an update to every monitoring connection, dropping the ones that have gone away
+    fn notify(&mut self, action: BabelAction, entry: BabelEntry) {
+        let line = format!("{}\n", BabelLine::Update { action, entry });
+        self.monitors
+            .retain(|stream| (&*stream).write_all(line.as_bytes()).is_ok());
+    }
+
+    /// Runs a single command and returns the full response including the terminator
+    fn handle_command(&mut self, command: &str, stream: &TcpStream) -> String {
+        self.commands.push(command.to_string());
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["dump"] => self.dump() + "ok\n",
+            ["monitor"] => {
+                if let Ok(stream) = stream.try_clone() {
+                    self.monitors.push(stream);
+                }
+                self.dump() + "ok\n"
+            }
+            ["unmonitor"] => {
+                let addr = stream.peer_addr().ok();
+                self.monitors.retain(|s| s.peer_addr().ok() != addr);
+                "ok\n".to_string()
+            }
+            ["fee", fee] => match fee.parse() {
+                Ok(fee) => {
+                    self.local_fee = fee;
+                    "ok\n".to_string()
+                }
+                Err(_) => "bad\n".to_string(),
+            },
+            ["metric-factor", factor] => match factor.parse() {
+                Ok(factor) => {
+                    self.metric_factor = factor;
+                    "ok\n".to_string()
+                }
+                Err(_) => "bad\n".to_string(),
+            },
+            // options after the interface name are accepted and ignored
+            ["interface", name, ..] => {
+                let iface = Interface {
+                    name: name.to_string(),
+                    up: true,
+                    ipv6: None,
+                    ipv4: None,
+                };
+                let action = if self.interfaces.contains_key(*name) {
+                    BabelAction::Change
+                } else {
+                    BabelAction::Add
+                };
+                self.interfaces.insert(name.to_string(), iface.clone());
+                self.notify(action, BabelEntry::Interface(iface));
+                "ok\n".to_string()
+            }
+            ["flush", "interface", name] => {
+                self.flush_interface(name);
+                "ok\n".to_string()
+            }
+            _ => "bad\n".to_string(),
+        }
+    }
+
+    /// Like babeld removing an interface drops every neighbor and route that used it
+    fn flush_interface(&mut self, name: &str) {
+        let routes: Vec<Route> = self
+            .routes
+            .values()
+            .filter(|r| r.iface == name)
+            .cloned()
+            .collect();
+        for route in routes {
+            self.routes.remove(&route.id);
+            self.notify(BabelAction::Flush, BabelEntry::Route(route));
+        }
+        let neighbors: Vec<Neighbor> = self
+            .neighbors
+            .values()
+            .filter(|n| n.iface == name)
+            .cloned()
+            .collect();
+        for neigh in neighbors {
+            self.neighbors.remove(&neigh.id);
+            self.notify(BabelAction::Flush, BabelEntry::Neighbor(neigh));
+        }
+        if let Some(iface) = self.interfaces.remove(name) {
+            self.notify(BabelAction::Flush, BabelEntry::Interface(iface));
+        }
+    }
+}
+
+pub struct FakeBabel {
+    port: u16,
+    state: Arc<Mutex<FakeBabelState>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl FakeBabel {
+    /// Starts a fake Babel listening on a random port on [::1], the same address
+    /// `open_babel_stream` connects to
+    pub fn start() -> io::Result<FakeBabel> {
+        let listener = TcpListener::bind("[::1]:0")?;
+        let port = listener.local_addr()?.port();
+        let state = Arc::new(Mutex::new(FakeBabelState::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let thread_state = state.clone();
+        let thread_stopped = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Fake babel failed to accept {:?}", e);
+                        continue;
+                    }
+                };
+                let state = thread_state.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, state) {
+                        trace!("Fake babel connection closed with {:?}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(FakeBabel {
+            port,
+            state,
+            stopped,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Adds a route, or replaces the route with the same id
+    pub fn add_route(&self, route: Route) {
+        let mut state = self.state.lock().unwrap();
+        let action = match state.routes.insert(route.id.clone(), route.clone()) {
+            Some(_) => BabelAction::Change,
+            None => BabelAction::Add,
+        };
+        state.notify(action, BabelEntry::Route(route));
+    }
+
+    pub fn remove_route(&self, id: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(route) = state.routes.remove(id) {
+            state.notify(BabelAction::Flush, BabelEntry::Route(route));
+        }
+    }
+
+    /// Adds a neighbor, or replaces the neighbor with the same id
+    pub fn add_neighbor(&self, neigh: Neighbor) {
+        let mut state = self.state.lock().unwrap();
+        let action = match state.neighbors.insert(neigh.id.clone(), neigh.clone()) {
+            Some(_) => BabelAction::Change,
+            None => BabelAction::Add,
+        };
+        state.notify(action, BabelEntry::Neighbor(neigh));
+    }
+
+    pub fn remove_neighbor(&self, id: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(neigh) = state.neighbors.remove(id) {
+            state.notify(BabelAction::Flush, BabelEntry::Neighbor(neigh));
+        }
+    }
+
+    pub fn add_xroute(&self, xroute: XRoute) {
+        let mut state = self.state.lock().unwrap();
+        state.xroutes.insert(xroute.id.clone(), xroute.clone());
+        state.notify(BabelAction::Add, BabelEntry::XRoute(xroute));
+    }
+
+    pub fn local_fee(&self) -> u32 {
+        self.state.lock().unwrap().local_fee
+    }
+
+    pub fn metric_factor(&self) -> u32 {
+        self.state.lock().unwrap().metric_factor
+    }
+
+    /// The names of the interfaces Babel has been asked to run on
+    pub fn interfaces(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .interfaces
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Every command received so far, in order
+    pub fn commands(&self) -> Vec<String> {
+        self.state.lock().unwrap().commands.clone()
+    }
+}
+
+impl Drop for FakeBabel {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for stream in self.state.lock().unwrap().connections.drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        // wake up the accept loop so that it notices we're done
+        let _ = TcpStream::connect(SocketAddr::new("::1".parse().unwrap(), self.port));
+    }
+}
+
+fn serve(stream: TcpStream, state: Arc<Mutex<FakeBabelState>>) -> io::Result<()> {
+    state.lock().unwrap().connections.push(stream.try_clone()?);
+    (&stream).write_all(PREAMBLE.as_bytes())?;
+    let reader = BufReader::new(stream.try_clone()?);
+    for line in reader.lines() {
+        let line = line?;
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        if command == "quit" {
+            break;
+        }
+        // hold the lock while writing so pushed updates can't land in the middle of a response
+        let mut state = state.lock().unwrap();
+        let response = state.handle_command(command, &stream);
+        (&stream).write_all(response.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Builds an installed route with reasonable defaults for the fields tests rarely care about
+pub fn make_route(id: &str, prefix: IpNetwork, neigh_ip: IpAddr, iface: &str, price: u32) -> Route {
+    Route {
+        id: id.to_string(),
+        iface: iface.to_string(),
+        xroute: false,
+        installed: true,
+        neigh_ip,
+        prefix,
+        from: if prefix.is_ipv4() {
+            "0.0.0.0/0".parse().unwrap()
+        } else {
+            "::/0".parse().unwrap()
+        },
+        router_id: "00:00:00:00:00:00:00:02".to_string(),
+        metric: 256,
+        refmetric: 0,
+        full_path_rtt: 10.0,
+        price,
+        fee: price,
+    }
+}
+
+/// Builds a neighbor with a perfect link
+pub fn make_neighbor(id: &str, address: IpAddr, iface: &str) -> Neighbor {
+    Neighbor {
+        id: id.to_string(),
+        address,
+        iface: iface.to_string(),
+        reach: 0xffff,
+        txcost: 256,
+        rxcost: 256,
+        rtt: 10.0,
+        rttcost: 0,
+        cost: 256,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_local_fee;
+    use crate::monitor;
+    use crate::open_babel_stream;
+    use crate::parse_neighs;
+    use crate::parse_routes;
+    use crate::set_local_fee;
+    use crate::start_connection;
+    use crate::tables::BabelTables;
+    use crate::unmonitor;
+    use futures::future::Future;
+    use tokio::runtime::current_thread::Runtime;
+
+    fn fake_with_routes() -> FakeBabel {
+        let babel = FakeBabel::start().unwrap();
+        babel.add_neighbor(make_neighbor("1", "fe80::1".parse().unwrap(), "wg0"));
+        babel.add_route(make_route(
+            "10",
+            "fd00::2/128".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+            "wg0",
+            500,
+        ));
+        babel.add_route(make_route(
+            "11",
+            "fd00::3/128".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+            "wg0",
+            700,
+        ));
+        babel
+    }
+
+    #[test]
+    fn dump_scripted_tables() {
+        let babel = fake_with_routes();
+        let mut runtime = Runtime::new().unwrap();
+        let (routes, neighs) = runtime
+            .block_on(
+                open_babel_stream(babel.port())
+                    .from_err()
+                    .and_then(start_connection)
+                    .and_then(parse_routes)
+                    .and_then(|(stream, routes)| {
+                        parse_neighs(stream).map(move |(_stream, neighs)| (routes, neighs))
+                    }),
+            )
+            .unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].price, 500);
+        assert_eq!(neighs.len(), 1);
+        assert_eq!(neighs[0].iface, "wg0");
+    }
+
+    #[test]
+    fn commands_change_state() {
+        let babel = fake_with_routes();
+        let mut runtime = Runtime::new().unwrap();
+        let fee = runtime
+            .block_on(
+                open_babel_stream(babel.port())
+                    .from_err()
+                    .and_then(start_connection)
+                    .and_then(|stream| set_local_fee(stream, 42))
+                    .and_then(|stream| monitor(stream, "wg0"))
+                    .and_then(|stream| monitor(stream, "wg1"))
+                    .and_then(|stream| unmonitor(stream, "wg0"))
+                    .and_then(get_local_fee),
+            )
+            .unwrap()
+            .1;
+        assert_eq!(fee, 42);
+        assert_eq!(babel.local_fee(), 42);
+        assert_eq!(babel.interfaces(), vec!["wg1".to_string()]);
+        assert_eq!(babel.commands()[0], "fee 42");
+    }
+
+    fn read_until_ok(
+        lines: &mut dyn Iterator<Item = io::Result<String>>,
+        tables: &mut BabelTables,
+    ) {
+        for line in lines {
+            let line = line.unwrap();
+            if line == "ok" {
+                break;
+            }
+            tables.apply_line(&line).unwrap();
+        }
+    }
+
+    #[test]
+    fn monitor_pushes_changes() {
+        let babel = fake_with_routes();
+        let stream =
+            TcpStream::connect(SocketAddr::new("::1".parse().unwrap(), babel.port())).unwrap();
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut tables = BabelTables::default();
+        // the preamble then the initial dump
+        read_until_ok(&mut lines, &mut tables);
+        (&stream).write_all(b"monitor\n").unwrap();
+        read_until_ok(&mut lines, &mut tables);
+        assert_eq!(tables.routes().len(), 2);
+
+        babel.remove_route("10");
+        tables.apply_line(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(tables.routes().len(), 1);
+        assert_eq!(tables.routes()[0].id, "11");
+
+        babel.remove_neighbor("1");
+        tables.apply_line(&lines.next().unwrap().unwrap()).unwrap();
+        assert!(tables.neighbors().is_empty());
+    }
+}
@@ -20,14 +20,11 @@ use futures::future::result as future_result;
 use futures::future::Either;
 use futures::future::Future;
 use ipnetwork::IpNetwork;
-use std::error::Error as ErrorTrait;
 use std::f32;
-use std::fmt::Debug;
 use std::iter::Iterator;
 use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::str;
-use std::str::FromStr;
 use std::time::Duration;
 use std::time::Instant;
 use tokio::io::read;
@@ -36,6 +33,12 @@ use tokio::net::tcp::ConnectFuture;
 use tokio::net::TcpStream;
 use tokio::timer::Delay;
 
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
+pub mod parser;
+pub mod route_prices;
+pub mod tables;
+
 #[derive(Debug, Fail)]
 pub enum BabelMonitorError {
     #[fail(display = "variable '{}' not found in '{}'", _0, _1)]
@@ -56,6 +59,9 @@ pub enum BabelMonitorError {
     TokioError(String),
 }
 
+use crate::parser::parse_babel_line;
+use crate::parser::BabelEntry;
+use crate::parser::BabelLine;
 use crate::BabelMonitorError::{
     CommandFailed, InvalidPreamble, LocalFeeNotFound, NoNeighbor, NoTerminator, ReadFailed,
     TokioError, VariableNotFound,
@@ -77,23 +83,7 @@ fn find_babel_val(val: &str, line: &str) -> Result<String, Error> {
     Err(VariableNotFound(String::from(val), String::from(line)).into())
 }
 
-fn find_and_parse_babel_val<T: FromStr>(val: &str, line: &str) -> Result<T, Error>
-where
-    <T as FromStr>::Err: Debug + ErrorTrait + Sync + Send + 'static,
-{
-    match find_babel_val(val, line) {
-        Ok(string_val) => match string_val.parse() {
-            Ok(parsed_val) => Ok(parsed_val),
-            Err(e) => {
-                warn!("Error parsing {} from {} with {:?}", val, line, e);
-                Err(e.into())
-            }
-        },
-        Err(e) => Err(e),
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Route {
     pub id: String,
     pub iface: String,
@@ -101,6 +91,10 @@ pub struct Route {
     pub installed: bool,
     pub neigh_ip: IpAddr,
     pub prefix: IpNetwork,
+    /// the source prefix, for source specific routes
+    pub from: IpNetwork,
+    /// the id of the router that originated this route
+    pub router_id: String,
     pub metric: u16,
     pub refmetric: u16,
     pub full_path_rtt: f32,
@@ -108,7 +102,7 @@ pub struct Route {
     pub fee: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Neighbor {
     pub id: String,
     pub address: IpAddr,
@@ -393,53 +387,10 @@ fn parse_neighs_sync(output: String) -> Result<Vec<Neighbor>, Error> {
     for entry in output.split('\n') {
         if entry.contains("add neighbour") {
             found_neigh = true;
-            let neigh = Neighbor {
-                id: match find_babel_val("neighbour", entry) {
-                    Ok(val) => val,
-                    Err(_) => continue,
-                },
-                address: match find_and_parse_babel_val("address", entry) {
-                    Ok(entry) => entry,
-                    Err(_) => continue,
-                },
-                iface: match find_babel_val("if", entry) {
-                    Ok(val) => val,
-                    Err(_) => continue,
-                },
-                reach: match find_babel_val("reach", entry) {
-                    Ok(val) => match u16::from_str_radix(&val, 16) {
-                        Ok(val) => val,
-                        Err(e) => {
-                            warn!("Failed to convert reach {:?} {}", e, entry);
-                            continue;
-                        }
-                    },
-                    Err(_) => continue,
-                },
-                txcost: match find_and_parse_babel_val("txcost", entry) {
-                    Ok(entry) => entry,
-                    Err(_) => continue,
-                },
-                rxcost: match find_and_parse_babel_val("rxcost", entry) {
-                    Ok(entry) => entry,
-                    Err(_) => continue,
-                },
-                rtt: match find_and_parse_babel_val("rtt", entry) {
-                    Ok(entry) => entry,
-                    // it's possible that our neigh does not have rtt enabled, handle
-                    Err(_) => 0.0,
-                },
-                rttcost: match find_and_parse_babel_val("rttcost", entry) {
-                    Ok(entry) => entry,
-                    // it's possible that our neigh does not have rtt enabled, handle
-                    Err(_) => 0,
-                },
-                cost: match find_and_parse_babel_val("cost", entry) {
-                    Ok(entry) => entry,
-                    Err(_) => continue,
-                },
-            };
-            vector.push(neigh);
+            match parse_neighbor_line(entry) {
+                Ok(neigh) => vector.push(neigh),
+                Err(e) => warn!("{}", e),
+            }
         }
     }
     if vector.is_empty() && found_neigh {
@@ -448,6 +399,17 @@ fn parse_neighs_sync(output: String) -> Result<Vec<Neighbor>, Error> {
     Ok(vector)
 }
 
+/// Parses a single neighbour line, either from a dump or a monitor update
+pub fn parse_neighbor_line(entry: &str) -> Result<Neighbor, Error> {
+    match parse_babel_line(entry)? {
+        BabelLine::Update {
+            entry: BabelEntry::Neighbor(neigh),
+            ..
+        } => Ok(neigh),
+        _ => bail!("Not a neighbour line: {}", entry),
+    }
+}
+
 pub fn parse_routes(
     stream: TcpStream,
 ) -> impl Future<Item = (TcpStream, Vec<Route>), Error = Error> {
@@ -469,51 +431,10 @@ pub fn parse_routes_sync(babel_out: String) -> Result<Vec<Route>, Error> {
         if entry.contains("add route") {
             trace!("Parsing 'add route' entry: {}", entry);
             found_route = true;
-            let route = Route {
-                id: match find_babel_val("route", entry) {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                },
-                iface: match find_babel_val("if", entry) {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                },
-                xroute: false,
-                installed: match find_babel_val("installed", entry) {
-                    Ok(value) => value.contains("yes"),
-                    Err(_) => continue,
-                },
-                neigh_ip: match find_and_parse_babel_val("via", entry) {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                },
-                prefix: match find_and_parse_babel_val("prefix", entry) {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                },
-                metric: match find_and_parse_babel_val("metric", entry) {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                },
-                refmetric: match find_and_parse_babel_val("refmetric", entry) {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                },
-                full_path_rtt: match find_and_parse_babel_val("full-path-rtt", entry) {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                },
-                price: match find_and_parse_babel_val("price", entry) {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                },
-                fee: match find_and_parse_babel_val("fee", entry) {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                },
-            };
-
-            vector.push(route);
+            match parse_route_line(entry) {
+                Ok(route) => vector.push(route),
+                Err(e) => warn!("{}", e),
+            }
         }
     }
     if vector.is_empty() && found_route {
@@ -522,6 +443,17 @@ pub fn parse_routes_sync(babel_out: String) -> Result<Vec<Route>, Error> {
     Ok(vector)
 }
 
+/// Parses a single route line, either from a dump or a monitor update
+pub fn parse_route_line(entry: &str) -> Result<Route, Error> {
+    match parse_babel_line(entry)? {
+        BabelLine::Update {
+            entry: BabelEntry::Route(route),
+            ..
+        } => Ok(route),
+        _ => bail!("Not a route line: {}", entry),
+    }
+}
+
 /// In this function we take a route snapshot then loop over the routes list twice
 /// to find the neighbor local address and then the route to the destination
 /// via that neighbor. This could be dramatically more efficient if we had the neighbors
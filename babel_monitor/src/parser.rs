@@ -0,0 +1,745 @@
+//! A typed model of everything Babel writes to its management socket. Every line is parsed
+//! into a `BabelLine`, the `add`, `change`, and `flush` lines used by `dump` and `monitor` carry
+//! one of the table entries Babel knows about. Parsing is strict, a line that is missing a field
+//! or has a value that doesn't parse produces an error naming the line and the field at fault.
+//! Every `BabelLine` can be written back out in the same format Babel uses.
+
+use crate::Neighbor;
+use crate::Route;
+use ipnetwork::IpNetwork;
+use std::collections::HashMap;
+use std::error::Error as ErrorTrait;
+use std::fmt;
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BabelAction {
+    Add,
+    Change,
+    Flush,
+}
+
+impl Display for BabelAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BabelAction::Add => write!(f, "add"),
+            BabelAction::Change => write!(f, "change"),
+            BabelAction::Flush => write!(f, "flush"),
+        }
+    }
+}
+
+/// An interface Babel has been told to run on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interface {
+    pub name: String,
+    pub up: bool,
+    pub ipv6: Option<Ipv6Addr>,
+    pub ipv4: Option<Ipv4Addr>,
+}
+
+/// A route exported by this node, these are the prefixes we're advertising
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct XRoute {
+    pub id: String,
+    pub prefix: IpNetwork,
+    pub from: IpNetwork,
+    pub metric: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BabelEntry {
+    Interface(Interface),
+    Neighbor(Neighbor),
+    XRoute(XRoute),
+    Route(Route),
+}
+
+/// A single line of output from the Babel management socket
+#[derive(Debug, Clone, PartialEq)]
+pub enum BabelLine {
+    /// The first line of the preamble, the protocol name followed by the version, for
+    /// example `ALTHEA 0.1`
+    Protocol {
+        name: String,
+        version: String,
+    },
+    Version(String),
+    Host(String),
+    MyId(String),
+    LocalFee(u32),
+    LocalPrice(u32),
+    MetricFactor(u32),
+    Update {
+        action: BabelAction,
+        entry: BabelEntry,
+    },
+    /// the command succeeded
+    Ok,
+    /// the command was not understood
+    Bad,
+    /// the command was understood but failed
+    No,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BabelParseErrorKind {
+    Empty,
+    UnknownLine,
+    /// a line of a known type without the id or name that follows the type
+    MissingId,
+    MissingField(String),
+    DuplicateField(String),
+    /// a key at the end of the line with no value after it
+    DanglingKey(String),
+    UnexpectedToken(String),
+    InvalidValue {
+        field: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl Display for BabelParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BabelParseErrorKind::Empty => write!(f, "empty line"),
+            BabelParseErrorKind::UnknownLine => write!(f, "unknown line type"),
+            BabelParseErrorKind::MissingId => write!(f, "missing id"),
+            BabelParseErrorKind::MissingField(field) => write!(f, "missing field '{}'", field),
+            BabelParseErrorKind::DuplicateField(field) => {
+                write!(f, "field '{}' appears more than once", field)
+            }
+            BabelParseErrorKind::DanglingKey(key) => write!(f, "no value for field '{}'", key),
+            BabelParseErrorKind::UnexpectedToken(token) => {
+                write!(f, "unexpected token '{}'", token)
+            }
+            BabelParseErrorKind::InvalidValue {
+                field,
+                value,
+                reason,
+            } => write!(
+                f,
+                "invalid value '{}' for field '{}': {}",
+                value, field, reason
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BabelParseError {
+    /// one indexed line number when parsing a block of output
+    pub line_number: Option<usize>,
+    pub line: String,
+    pub kind: BabelParseErrorKind,
+}
+
+impl Display for BabelParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line_number {
+            Some(number) => write!(
+                f,
+                "Babel output line {} '{}': {}",
+                number, self.line, self.kind
+            ),
+            None => write!(f, "Babel output line '{}': {}", self.line, self.kind),
+        }
+    }
+}
+
+impl ErrorTrait for BabelParseError {}
+
+/// The key value pairs that follow the id on update lines
+struct Fields<'a> {
+    values: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(tokens: &[&'a str]) -> Result<Fields<'a>, BabelParseErrorKind> {
+        let mut values = HashMap::new();
+        for pair in tokens.chunks(2) {
+            match pair {
+                [key, value] => {
+                    if values.insert(*key, *value).is_some() {
+                        return Err(BabelParseErrorKind::DuplicateField(key.to_string()));
+                    }
+                }
+                [key] => return Err(BabelParseErrorKind::DanglingKey(key.to_string())),
+                _ => unreachable!(),
+            }
+        }
+        Ok(Fields { values })
+    }
+
+    fn optional<T: FromStr>(&self, field: &str) -> Result<Option<T>, BabelParseErrorKind>
+    where
+        <T as FromStr>::Err: Display,
+    {
+        match self.values.get(field) {
+            Some(value) => match value.parse() {
+                Ok(parsed) => Ok(Some(parsed)),
+                Err(e) => Err(invalid(field, value, e)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn required<T: FromStr>(&self, field: &str) -> Result<T, BabelParseErrorKind>
+    where
+        <T as FromStr>::Err: Display,
+    {
+        match self.optional(field)? {
+            Some(value) => Ok(value),
+            None => Err(BabelParseErrorKind::MissingField(field.to_string())),
+        }
+    }
+
+    fn required_float(&self, field: &str) -> Result<f32, BabelParseErrorKind> {
+        check_finite(field, self.required(field)?)
+    }
+
+    fn required_bool(&self, field: &str, yes: &str, no: &str) -> Result<bool, BabelParseErrorKind> {
+        match self.values.get(field) {
+            Some(value) if *value == yes => Ok(true),
+            Some(value) if *value == no => Ok(false),
+            Some(value) => Err(invalid(
+                field,
+                value,
+                format!("expected '{}' or '{}'", yes, no),
+            )),
+            None => Err(BabelParseErrorKind::MissingField(field.to_string())),
+        }
+    }
+}
+
+fn invalid<E: Display>(field: &str, value: &str, reason: E) -> BabelParseErrorKind {
+    BabelParseErrorKind::InvalidValue {
+        field: field.to_string(),
+        value: value.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Babel never prints nan or infinity and they would make the parsed values incomparable
+fn check_finite(field: &str, value: f32) -> Result<f32, BabelParseErrorKind> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(invalid(field, &value.to_string(), "not a finite number"))
+    }
+}
+
+fn parse_interface(name: &str, fields: &Fields) -> Result<Interface, BabelParseErrorKind> {
+    Ok(Interface {
+        name: name.to_string(),
+        up: fields.required_bool("up", "true", "false")?,
+        ipv6: fields.optional("ipv6")?,
+        ipv4: fields.optional("ipv4")?,
+    })
+}
+
+fn parse_neighbor(id: &str, fields: &Fields) -> Result<Neighbor, BabelParseErrorKind> {
+    let reach: String = fields.required("reach")?;
+    Ok(Neighbor {
+        id: id.to_string(),
+        address: fields.required("address")?,
+        iface: fields.required("if")?,
+        reach: match u16::from_str_radix(&reach, 16) {
+            Ok(reach) => reach,
+            Err(e) => return Err(invalid("reach", &reach, e)),
+        },
+        rxcost: fields.required("rxcost")?,
+        txcost: fields.required("txcost")?,
+        // neighbors without timestamps enabled have no rtt
+        rtt: match fields.optional("rtt")? {
+            Some(rtt) => check_finite("rtt", rtt)?,
+            None => 0.0,
+        },
+        rttcost: fields.optional("rttcost")?.unwrap_or(0),
+        cost: fields.required("cost")?,
+    })
+}
+
+fn parse_xroute(id: &str, fields: &Fields) -> Result<XRoute, BabelParseErrorKind> {
+    Ok(XRoute {
+        id: id.to_string(),
+        prefix: fields.required("prefix")?,
+        from: fields.required("from")?,
+        metric: fields.required("metric")?,
+    })
+}
+
+fn parse_route(id: &str, fields: &Fields) -> Result<Route, BabelParseErrorKind> {
+    Ok(Route {
+        id: id.to_string(),
+        iface: fields.required("if")?,
+        xroute: false,
+        installed: fields.required_bool("installed", "yes", "no")?,
+        neigh_ip: fields.required::<IpAddr>("via")?,
+        prefix: fields.required("prefix")?,
+        from: fields.required("from")?,
+        router_id: fields.required("id")?,
+        metric: fields.required("metric")?,
+        refmetric: fields.required("refmetric")?,
+        full_path_rtt: fields.required_float("full-path-rtt")?,
+        price: fields.required("price")?,
+        fee: fields.required("fee")?,
+    })
+}
+
+fn parse_update(action: BabelAction, tokens: &[&str]) -> Result<BabelLine, BabelParseErrorKind> {
+    let (kind, id, rest) = match tokens {
+        [kind, id, rest @ ..] => (*kind, *id, rest),
+        [_kind] => return Err(BabelParseErrorKind::MissingId),
+        [] => return Err(BabelParseErrorKind::UnknownLine),
+    };
+    let fields = Fields::new(rest)?;
+    let entry = match kind {
+        "interface" => BabelEntry::Interface(parse_interface(id, &fields)?),
+        "neighbour" => BabelEntry::Neighbor(parse_neighbor(id, &fields)?),
+        "xroute" => BabelEntry::XRoute(parse_xroute(id, &fields)?),
+        "route" => BabelEntry::Route(parse_route(id, &fields)?),
+        _ => return Err(BabelParseErrorKind::UnknownLine),
+    };
+    Ok(BabelLine::Update { action, entry })
+}
+
+/// Parses lines that are a fixed keyword followed by a single number
+fn parse_number(field: &str, tokens: &[&str]) -> Result<u32, BabelParseErrorKind> {
+    match tokens {
+        [value] => value.parse().map_err(|e| invalid(field, value, e)),
+        [] => Err(BabelParseErrorKind::MissingField(field.to_string())),
+        [_, extra, ..] => Err(BabelParseErrorKind::UnexpectedToken(extra.to_string())),
+    }
+}
+
+/// Parses lines that are a keyword followed by free text
+fn parse_text(field: &str, tokens: &[&str]) -> Result<String, BabelParseErrorKind> {
+    if tokens.is_empty() {
+        Err(BabelParseErrorKind::MissingField(field.to_string()))
+    } else {
+        Ok(tokens.join(" "))
+    }
+}
+
+fn parse_line_kind(tokens: &[&str]) -> Result<BabelLine, BabelParseErrorKind> {
+    match tokens {
+        [] => Err(BabelParseErrorKind::Empty),
+        ["ok"] => Ok(BabelLine::Ok),
+        ["bad"] => Ok(BabelLine::Bad),
+        ["no"] => Ok(BabelLine::No),
+        ["add", rest @ ..] => parse_update(BabelAction::Add, rest),
+        ["change", rest @ ..] => parse_update(BabelAction::Change, rest),
+        ["flush", rest @ ..] => parse_update(BabelAction::Flush, rest),
+        ["local", "fee", rest @ ..] => Ok(BabelLine::LocalFee(parse_number("fee", rest)?)),
+        ["local", "price", rest @ ..] => Ok(BabelLine::LocalPrice(parse_number("price", rest)?)),
+        ["metric", "factor", rest @ ..] => {
+            Ok(BabelLine::MetricFactor(parse_number("factor", rest)?))
+        }
+        ["version", rest @ ..] => Ok(BabelLine::Version(parse_text("version", rest)?)),
+        ["host", rest @ ..] => Ok(BabelLine::Host(parse_text("host", rest)?)),
+        ["my-id", rest @ ..] => Ok(BabelLine::MyId(parse_text("my-id", rest)?)),
+        [name @ "ALTHEA", version] | [name @ "BABEL", version] => Ok(BabelLine::Protocol {
+            name: name.to_string(),
+            version: version.to_string(),
+        }),
+        _ => Err(BabelParseErrorKind::UnknownLine),
+    }
+}
+
+/// Parses a single line of Babel output
+pub fn parse_babel_line(line: &str) -> Result<BabelLine, BabelParseError> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    parse_line_kind(&tokens).map_err(|kind| BabelParseError {
+        line_number: None,
+        line: line.to_string(),
+        kind,
+    })
+}
+
+/// Parses a block of Babel output such as a dump, blank lines are skipped and the first line that
+/// fails to parse is returned as an error along with its line number
+pub fn parse_babel_output(output: &str) -> Result<Vec<BabelLine>, BabelParseError> {
+    let mut lines = Vec::new();
+    for (index, line) in output.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_babel_line(line) {
+            Ok(parsed) => lines.push(parsed),
+            Err(mut e) => {
+                e.line_number = Some(index + 1);
+                return Err(e);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+fn write_opt<T: Display>(f: &mut fmt::Formatter, key: &str, value: &Option<T>) -> fmt::Result {
+    match value {
+        Some(value) => write!(f, " {} {}", key, value),
+        None => Ok(()),
+    }
+}
+
+impl Display for BabelEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BabelEntry::Interface(iface) => {
+                write!(f, "interface {} up {}", iface.name, iface.up)?;
+                write_opt(f, "ipv6", &iface.ipv6)?;
+                write_opt(f, "ipv4", &iface.ipv4)
+            }
+            BabelEntry::Neighbor(neigh) => write!(
+                f,
+                "neighbour {} address {} if {} reach {:04x} rxcost {} txcost {} rtt {} rttcost {} cost {}",
+                neigh.id,
+                neigh.address,
+                neigh.iface,
+                neigh.reach,
+                neigh.rxcost,
+                neigh.txcost,
+                neigh.rtt,
+                neigh.rttcost,
+                neigh.cost
+            ),
+            BabelEntry::XRoute(xroute) => write!(
+                f,
+                "xroute {} prefix {} from {} metric {}",
+                xroute.id, xroute.prefix, xroute.from, xroute.metric
+            ),
+            BabelEntry::Route(route) => write!(
+                f,
+                "route {} prefix {} from {} installed {} id {} metric {} price {} fee {} refmetric {} full-path-rtt {} via {} if {}",
+                route.id,
+                route.prefix,
+                route.from,
+                if route.installed { "yes" } else { "no" },
+                route.router_id,
+                route.metric,
+                route.price,
+                route.fee,
+                route.refmetric,
+                route.full_path_rtt,
+                route.neigh_ip,
+                route.iface
+            ),
+        }
+    }
+}
+
+impl Display for BabelLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BabelLine::Protocol { name, version } => write!(f, "{} {}", name, version),
+            BabelLine::Version(version) => write!(f, "version {}", version),
+            BabelLine::Host(host) => write!(f, "host {}", host),
+            BabelLine::MyId(id) => write!(f, "my-id {}", id),
+            BabelLine::LocalFee(fee) => write!(f, "local fee {}", fee),
+            BabelLine::LocalPrice(price) => write!(f, "local price {}", price),
+            BabelLine::MetricFactor(factor) => write!(f, "metric factor {}", factor),
+            BabelLine::Update { action, entry } => write!(f, "{} {}", action, entry),
+            BabelLine::Ok => write!(f, "ok"),
+            BabelLine::Bad => write!(f, "bad"),
+            BabelLine::No => write!(f, "no"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    static DUMP: &str = include_str!("../fixtures/dump.txt");
+    static MONITOR: &str = include_str!("../fixtures/monitor.txt");
+
+    fn count_entries(lines: &[BabelLine], f: fn(&BabelEntry) -> bool) -> usize {
+        lines
+            .iter()
+            .filter(|l| match l {
+                BabelLine::Update { entry, .. } => f(entry),
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn parse_dump_fixture() {
+        let lines = parse_babel_output(DUMP).unwrap();
+        assert_eq!(
+            count_entries(&lines, |e| matches!(e, BabelEntry::Route(_))),
+            6
+        );
+        assert_eq!(
+            count_entries(&lines, |e| matches!(e, BabelEntry::Neighbor(_))),
+            4
+        );
+        assert_eq!(
+            count_entries(&lines, |e| matches!(e, BabelEntry::XRoute(_))),
+            2
+        );
+        assert_eq!(lines[0], BabelLine::LocalFee(1024));
+        assert_eq!(lines[1], BabelLine::MetricFactor(1900));
+        assert_eq!(
+            lines[2],
+            BabelLine::Update {
+                action: BabelAction::Add,
+                entry: BabelEntry::Interface(Interface {
+                    name: "lo".to_string(),
+                    up: false,
+                    ipv6: None,
+                    ipv4: None,
+                })
+            }
+        );
+        assert_eq!(lines.last(), Some(&BabelLine::Ok));
+    }
+
+    #[test]
+    fn parse_monitor_fixture() {
+        let lines = parse_babel_output(MONITOR).unwrap();
+        assert_eq!(
+            lines[0],
+            BabelLine::Protocol {
+                name: "ALTHEA".to_string(),
+                version: "0.1".to_string()
+            }
+        );
+        assert_eq!(
+            lines[3],
+            BabelLine::MyId("ba:27:eb:ff:fe:09:06:dd".to_string())
+        );
+        let flushes = lines
+            .iter()
+            .filter(|l| {
+                matches!(
+                    l,
+                    BabelLine::Update {
+                        action: BabelAction::Flush,
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(flushes, 2);
+    }
+
+    #[test]
+    fn fixtures_round_trip() {
+        for fixture in &[DUMP, MONITOR] {
+            let lines = parse_babel_output(fixture).unwrap();
+            let written: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+            let reparsed = parse_babel_output(&written.join("\n")).unwrap();
+            assert_eq!(lines, reparsed);
+        }
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let output = "local fee 1024\nadd route 14f0820 prefix 10.28.7.7/32 from 0.0.0.0/0 \
+                      installed yes id ba:27:eb:ff:fe:5b:fe:c7 metric banana price 3072 fee 3072 \
+                      refmetric 638 full-path-rtt 22.805 via fe80::e914:2335:a76:bda3 if wlan0\nok";
+        let e = parse_babel_output(output).unwrap_err();
+        assert_eq!(e.line_number, Some(2));
+        assert!(e.line.starts_with("add route 14f0820"));
+        assert!(e.to_string().contains("line 2"));
+        match e.kind {
+            BabelParseErrorKind::InvalidValue { field, value, .. } => {
+                assert_eq!(field, "metric");
+                assert_eq!(value, "banana");
+            }
+            other => panic!("wrong error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn specific_errors() {
+        let kind = |line: &str| parse_babel_line(line).unwrap_err().kind;
+        assert_eq!(kind(""), BabelParseErrorKind::Empty);
+        assert_eq!(kind("frobnicate all"), BabelParseErrorKind::UnknownLine);
+        assert_eq!(kind("add route"), BabelParseErrorKind::MissingId);
+        assert_eq!(
+            kind("add xroute 1 prefix 10.0.0.0/8 from ::/0"),
+            BabelParseErrorKind::MissingField("metric".to_string())
+        );
+        assert_eq!(
+            kind("add xroute 1 prefix 10.0.0.0/8 from ::/0 metric"),
+            BabelParseErrorKind::DanglingKey("metric".to_string())
+        );
+        assert_eq!(
+            kind("add xroute 1 prefix 10.0.0.0/8 from ::/0 metric 0 metric 1"),
+            BabelParseErrorKind::DuplicateField("metric".to_string())
+        );
+        assert_eq!(
+            kind("local fee 1 2"),
+            BabelParseErrorKind::UnexpectedToken("2".to_string())
+        );
+        match kind("add interface wlan0 up maybe") {
+            BabelParseErrorKind::InvalidValue { field, .. } => assert_eq!(field, "up"),
+            other => panic!("wrong error {:?}", other),
+        }
+    }
+
+    fn arb_id() -> impl Strategy<Value = String> {
+        "[0-9a-f]{1,8}"
+    }
+
+    fn arb_iface() -> impl Strategy<Value = String> {
+        "[a-z]{1,6}[0-9]{0,3}"
+    }
+
+    fn arb_rtt() -> impl Strategy<Value = f32> {
+        (0u32..10_000_000).prop_map(|v| v as f32 / 1000.0)
+    }
+
+    fn arb_ip() -> impl Strategy<Value = IpAddr> {
+        prop_oneof![
+            any::<[u8; 4]>().prop_map(|o| IpAddr::V4(o.into())),
+            any::<[u8; 16]>().prop_map(|o| IpAddr::V6(o.into())),
+        ]
+    }
+
+    fn arb_network() -> impl Strategy<Value = IpNetwork> {
+        arb_ip().prop_flat_map(|ip| {
+            let max: u8 = if ip.is_ipv4() { 32 } else { 128 };
+            (0..=max).prop_map(move |prefix| IpNetwork::new(ip, prefix).unwrap())
+        })
+    }
+
+    fn arb_entry() -> impl Strategy<Value = BabelEntry> {
+        prop_oneof![
+            (
+                arb_iface(),
+                any::<bool>(),
+                proptest::option::of(any::<[u8; 16]>()),
+                proptest::option::of(any::<[u8; 4]>())
+            )
+                .prop_map(|(name, up, ipv6, ipv4)| BabelEntry::Interface(Interface {
+                    name,
+                    up,
+                    ipv6: ipv6.map(Ipv6Addr::from),
+                    ipv4: ipv4.map(Ipv4Addr::from),
+                })),
+            (
+                arb_id(),
+                arb_ip(),
+                arb_iface(),
+                any::<u16>(),
+                any::<(u16, u16, u16, u16)>(),
+                arb_rtt()
+            )
+                .prop_map(|(id, address, iface, reach, costs, rtt)| {
+                    BabelEntry::Neighbor(Neighbor {
+                        id,
+                        address,
+                        iface,
+                        reach,
+                        txcost: costs.0,
+                        rxcost: costs.1,
+                        rtt,
+                        rttcost: costs.2,
+                        cost: costs.3,
+                    })
+                }),
+            (arb_id(), arb_network(), arb_network(), any::<u16>()).prop_map(
+                |(id, prefix, from, metric)| BabelEntry::XRoute(XRoute {
+                    id,
+                    prefix,
+                    from,
+                    metric,
+                })
+            ),
+            (
+                (arb_id(), arb_iface(), any::<bool>(), arb_ip()),
+                (arb_network(), arb_network(), "([0-9a-f]{2}:){7}[0-9a-f]{2}"),
+                (
+                    any::<u16>(),
+                    any::<u16>(),
+                    arb_rtt(),
+                    any::<u32>(),
+                    any::<u32>()
+                )
+            )
+                .prop_map(
+                    |(
+                        (id, iface, installed, neigh_ip),
+                        (prefix, from, router_id),
+                        (metric, refmetric, full_path_rtt, price, fee),
+                    )| BabelEntry::Route(Route {
+                        id,
+                        iface,
+                        xroute: false,
+                        installed,
+                        neigh_ip,
+                        prefix,
+                        from,
+                        router_id,
+                        metric,
+                        refmetric,
+                        full_path_rtt,
+                        price,
+                        fee,
+                    })
+                ),
+        ]
+    }
+
+    fn arb_action() -> impl Strategy<Value = BabelAction> {
+        prop_oneof![
+            Just(BabelAction::Add),
+            Just(BabelAction::Change),
+            Just(BabelAction::Flush),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn updates_round_trip(action in arb_action(), entry in arb_entry()) {
+            let line = BabelLine::Update { action, entry };
+            prop_assert_eq!(parse_babel_line(&line.to_string()).unwrap(), line);
+        }
+
+        #[test]
+        fn numbers_round_trip(value in any::<u32>()) {
+            for line in &[
+                BabelLine::LocalFee(value),
+                BabelLine::LocalPrice(value),
+                BabelLine::MetricFactor(value),
+            ] {
+                prop_assert_eq!(&parse_babel_line(&line.to_string()).unwrap(), line);
+            }
+        }
+
+        /// arbitrary input must never panic, and anything that does parse must
+        /// survive being written back out
+        #[test]
+        fn arbitrary_lines_never_panic(line in "\\PC{0,200}") {
+            if let Ok(parsed) = parse_babel_line(&line) {
+                prop_assert_eq!(parse_babel_line(&parsed.to_string()).unwrap(), parsed);
+            }
+        }
+
+        /// mangling a single token of a valid line must produce an error or a valid line,
+        /// never a panic
+        #[test]
+        fn mangled_tokens(
+            action in arb_action(),
+            entry in arb_entry(),
+            index in any::<prop::sample::Index>(),
+            replacement in "\\PC{0,12}"
+        ) {
+            let line = BabelLine::Update { action, entry }.to_string();
+            let mut tokens: Vec<&str> = line.split(' ').collect();
+            let i = index.index(tokens.len());
+            tokens[i] = &replacement;
+            let _ = parse_babel_line(&tokens.join(" "));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Every route Babel reports carries the total price of the path to the destination and the fee
+//! charged by the neighbor it goes through. This module collects the routes Babel knows about for
+//! a single destination and breaks their prices down as far as that information lets us see, the
+//! hops beyond our neighbor are only visible as a single sum.
+
+use crate::Route;
+use failure::Error;
+use ipnetwork::IpNetwork;
+use std::net::IpAddr;
+
+/// Babel prices are in wei per byte
+pub const BYTES_PER_GB: u64 = 1_000_000_000;
+
+/// The price of one route to a destination
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoutePrice {
+    /// the link local address of the neighbor this route goes through
+    pub neigh_ip: IpAddr,
+    pub iface: String,
+    pub installed: bool,
+    pub metric: u16,
+    pub full_path_rtt: f32,
+    /// the total price of the route in wei per byte
+    pub price: u32,
+    /// the total price of the route in wei per gigabyte
+    pub price_per_gb: u64,
+    /// the part of the price charged by the neighbor this route goes through
+    pub neighbor_fee: u32,
+    /// the part of the price charged by every hop past our neighbor
+    pub upstream_price: u32,
+}
+
+impl From<&Route> for RoutePrice {
+    fn from(route: &Route) -> RoutePrice {
+        RoutePrice {
+            neigh_ip: route.neigh_ip,
+            iface: route.iface.clone(),
+            installed: route.installed,
+            metric: route.metric,
+            full_path_rtt: route.full_path_rtt,
+            price: route.price,
+            price_per_gb: u64::from(route.price) * BYTES_PER_GB,
+            neighbor_fee: route.fee,
+            upstream_price: route.price.saturating_sub(route.fee),
+        }
+    }
+}
+
+/// All the routes Babel knows about to a destination
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoutePrices {
+    pub destination: IpAddr,
+    /// the route traffic is actually taking, None if Babel hasn't picked one
+    pub installed: Option<RoutePrice>,
+    /// every other route to the destination, cheapest first
+    pub alternatives: Vec<RoutePrice>,
+}
+
+/// Returns true if this route is a host route to the given address
+fn is_route_to(route: &Route, destination: IpAddr) -> bool {
+    match route.prefix {
+        IpNetwork::V6(ref net) => net.prefix() == 128 && IpAddr::V6(net.ip()) == destination,
+        IpNetwork::V4(ref net) => net.prefix() == 32 && IpAddr::V4(net.ip()) == destination,
+    }
+}
+
+/// Collects the prices of every route to the given destination
+pub fn get_route_prices(destination: IpAddr, routes: &[Route]) -> Result<RoutePrices, Error> {
+    let mut installed = None;
+    let mut alternatives = Vec::new();
+    for route in routes.iter().filter(|r| is_route_to(r, destination)) {
+        if route.installed && installed.is_none() {
+            installed = Some(RoutePrice::from(route));
+        } else {
+            alternatives.push(RoutePrice::from(route));
+        }
+    }
+    if installed.is_none() && alternatives.is_empty() {
+        bail!("No routes to {}", destination);
+    }
+    alternatives.sort_by_key(|r| r.price);
+
+    Ok(RoutePrices {
+        destination,
+        installed,
+        alternatives,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_routes_sync;
+
+    static TABLE: &str = "local fee 1024\n\
+add route 1 prefix fd00::2/128 from ::/0 installed yes id 00:00:00:00:00:00:00:02 metric 256 \
+price 3000 fee 1000 refmetric 0 full-path-rtt 10.5 via fe80::1 if wg0\n\
+add route 2 prefix fd00::2/128 from ::/0 installed no id 00:00:00:00:00:00:00:02 metric 512 \
+price 5000 fee 5000 refmetric 0 full-path-rtt 22.0 via fe80::2 if wg1\n\
+add route 3 prefix fd00::2/128 from ::/0 installed no id 00:00:00:00:00:00:00:02 metric 300 \
+price 2000 fee 500 refmetric 0 full-path-rtt 15.0 via fe80::3 if wg2\n\
+add route 4 prefix fd00::3/128 from ::/0 installed yes id 00:00:00:00:00:00:00:03 metric 256 \
+price 100 fee 100 refmetric 0 full-path-rtt 1.0 via fe80::1 if wg0\n\
+add route 5 prefix fd00::/64 from ::/0 installed yes id 00:00:00:00:00:00:00:04 metric 256 \
+price 1 fee 1 refmetric 0 full-path-rtt 1.0 via fe80::1 if wg0\n\
+ok\n";
+
+    #[test]
+    fn breaks_down_route_prices() {
+        let routes = parse_routes_sync(TABLE.to_string()).unwrap();
+        let prices = get_route_prices("fd00::2".parse().unwrap(), &routes).unwrap();
+
+        let installed = prices.installed.unwrap();
+        assert_eq!(installed.iface, "wg0");
+        assert_eq!(installed.price, 3000);
+        assert_eq!(installed.price_per_gb, 3000 * BYTES_PER_GB);
+        assert_eq!(installed.neighbor_fee, 1000);
+        assert_eq!(installed.upstream_price, 2000);
+
+        let alternatives: Vec<&str> = prices
+            .alternatives
+            .iter()
+            .map(|r| r.iface.as_str())
+            .collect();
+        assert_eq!(alternatives, vec!["wg2", "wg1"]);
+        assert_eq!(prices.alternatives[1].upstream_price, 0);
+    }
+
+    #[test]
+    fn no_routes() {
+        let routes = parse_routes_sync(TABLE.to_string()).unwrap();
+        // only covered by a subnet route, not a host route
+        assert!(get_route_prices("fd00::4".parse().unwrap(), &routes).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! Babel's `monitor` command dumps the full state of the daemon and then pushes every
+//! following change to routes, neighbours, and interfaces down the same connection as
+//! `add`, `change`, and `flush` lines. This module parses those updates and keeps a local
+//! copy of the route and neighbour tables so that callers don't have to run a full dump
+//! every time they want to look at them.
+
+use crate::parser::parse_babel_line;
+use crate::parser::BabelAction;
+use crate::parser::BabelEntry;
+use crate::parser::BabelLine;
+use crate::parser::BabelParseError;
+use crate::parser::BabelParseErrorKind;
+use crate::Neighbor;
+use crate::Route;
+use failure::Error;
+use std::collections::HashMap;
+use tokio::codec::FramedRead;
+use tokio::codec::LinesCodec;
+use tokio::io::AsyncRead;
+use tokio::io::ReadHalf;
+use tokio::io::WriteHalf;
+use tokio::net::TcpStream;
+
+/// A single line of Babel output that changes the tables
+#[derive(Debug, Clone)]
+pub enum BabelEvent {
+    /// a route was added or changed, either way the new state replaces the old
+    Route(Route),
+    /// the route with this id was removed
+    RouteFlush(String),
+    /// a neighbour was added or changed
+    Neighbor(Neighbor),
+    /// the neighbour with this id was removed
+    NeighborFlush(String),
+    /// the local fee as printed at the top of a dump
+    LocalFee(u32),
+}
+
+/// Parses a line of Babel output into an event, returns None for lines that don't
+/// describe table changes like interfaces, xroutes, and command terminators. Lines of a
+/// type we don't know about are also ignored so that newer versions of Babel can add them
+pub fn parse_monitor_line(line: &str) -> Result<Option<BabelEvent>, Error> {
+    let parsed = match parse_babel_line(line) {
+        Ok(parsed) => parsed,
+        Err(BabelParseError {
+            kind: BabelParseErrorKind::UnknownLine,
+            ..
+        })
+        | Err(BabelParseError {
+            kind: BabelParseErrorKind::Empty,
+            ..
+        }) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(match parsed {
+        BabelLine::Update {
+            action: BabelAction::Flush,
+            entry: BabelEntry::Route(route),
+        } => Some(BabelEvent::RouteFlush(route.id)),
+        BabelLine::Update {
+            entry: BabelEntry::Route(route),
+            ..
+        } => Some(BabelEvent::Route(route)),
+        BabelLine::Update {
+            action: BabelAction::Flush,
+            entry: BabelEntry::Neighbor(neigh),
+        } => Some(BabelEvent::NeighborFlush(neigh.id)),
+        BabelLine::Update {
+            entry: BabelEntry::Neighbor(neigh),
+            ..
+        } => Some(BabelEvent::Neighbor(neigh)),
+        BabelLine::LocalFee(fee) => Some(BabelEvent::LocalFee(fee)),
+        _ => None,
+    })
+}
+
+/// Returns true if this line ends the response to a command
+pub fn is_terminator(line: &str) -> bool {
+    matches!(line.trim(), "ok" | "bad" | "no")
+}
+
+/// Returns true if this line is an `add`, `change`, or `flush` update. In monitor mode Babel
+/// pushes these whenever its tables change, even in the middle of the response to a command
+pub fn is_update(line: &str) -> bool {
+    matches!(
+        line.split_whitespace().next(),
+        Some("add") | Some("change") | Some("flush")
+    )
+}
+
+/// The route and neighbour tables as last reported by Babel
+#[derive(Debug, Clone, Default)]
+pub struct BabelTables {
+    routes: HashMap<String, Route>,
+    neighbors: HashMap<String, Neighbor>,
+    local_fee: Option<u32>,
+}
+
+impl BabelTables {
+    pub fn apply(&mut self, event: BabelEvent) {
+        match event {
+            BabelEvent::Route(route) => {
+                self.routes.insert(route.id.clone(), route);
+            }
+            BabelEvent::RouteFlush(id) => {
+                self.routes.remove(&id);
+            }
+            BabelEvent::Neighbor(neigh) => {
+                self.neighbors.insert(neigh.id.clone(), neigh);
+            }
+            BabelEvent::NeighborFlush(id) => {
+                self.neighbors.remove(&id);
+            }
+            BabelEvent::LocalFee(fee) => self.local_fee = Some(fee),
+        }
+    }
+
+    /// Parses and applies a line of Babel output, lines that are not updates are ignored
+    pub fn apply_line(&mut self, line: &str) -> Result<(), Error> {
+        if let Some(event) = parse_monitor_line(line)? {
+            self.apply(event);
+        }
+        Ok(())
+    }
+
+    pub fn routes(&self) -> Vec<Route> {
+        self.routes.values().cloned().collect()
+    }
+
+    pub fn neighbors(&self) -> Vec<Neighbor> {
+        self.neighbors.values().cloned().collect()
+    }
+
+    pub fn local_fee(&self) -> Option<u32> {
+        self.local_fee
+    }
+
+    pub fn clear(&mut self) {
+        *self = BabelTables::default();
+    }
+}
+
+/// Splits a Babel management connection so that output can be consumed line by line as it
+/// arrives while commands are written from elsewhere
+pub fn split_babel_stream(
+    stream: TcpStream,
+) -> (
+    FramedRead<ReadHalf<TcpStream>, LinesCodec>,
+    WriteHalf<TcpStream>,
+) {
+    let (read, write) = stream.split();
+    (FramedRead::new(read, LinesCodec::new()), write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static ROUTE_LINE: &str =
+        "add route 14f06d8 prefix 10.28.20.151/32 from 0.0.0.0/0 installed yes id \
+         ba:27:eb:ff:fe:c1:2d:d5 metric 817 price 4008 fee 4008 refmetric 0 full-path-rtt 18.674 via \
+         fe80::e9d0:498f:6c61:be29 if wlan0";
+
+    static CHANGE_ROUTE_LINE: &str =
+        "change route 14f06d8 prefix 10.28.20.151/32 from 0.0.0.0/0 installed no id \
+         ba:27:eb:ff:fe:c1:2d:d5 metric 900 price 5000 fee 5000 refmetric 0 full-path-rtt 18.674 via \
+         fe80::e9d0:498f:6c61:be29 if wlan0";
+
+    static FLUSH_ROUTE_LINE: &str =
+        "flush route 14f06d8 prefix 10.28.20.151/32 from 0.0.0.0/0 installed no id \
+         ba:27:eb:ff:fe:c1:2d:d5 metric 65535 price 5000 fee 5000 refmetric 0 full-path-rtt 18.674 via \
+         fe80::e9d0:498f:6c61:be29 if wlan0";
+
+    static NEIGH_LINE: &str =
+        "add neighbour 14f05f0 address fe80::e9d0:498f:6c61:be29 if wlan0 reach ffff rxcost \
+         256 txcost 256 rtt 29.264 rttcost 1050 cost 1306";
+
+    static FLUSH_NEIGH_LINE: &str =
+        "flush neighbour 14f05f0 address fe80::e9d0:498f:6c61:be29 if wlan0 reach 0000 rxcost \
+         65535 txcost 256 rtt 29.264 rttcost 1050 cost 65535";
+
+    #[test]
+    fn route_updates() {
+        let mut tables = BabelTables::default();
+        tables.apply_line(ROUTE_LINE).unwrap();
+        assert_eq!(tables.routes().len(), 1);
+        assert!(tables.routes()[0].installed);
+
+        tables.apply_line(CHANGE_ROUTE_LINE).unwrap();
+        assert_eq!(tables.routes().len(), 1);
+        assert!(!tables.routes()[0].installed);
+        assert_eq!(tables.routes()[0].price, 5000);
+
+        tables.apply_line(FLUSH_ROUTE_LINE).unwrap();
+        assert!(tables.routes().is_empty());
+    }
+
+    #[test]
+    fn neighbor_updates() {
+        let mut tables = BabelTables::default();
+        tables.apply_line(NEIGH_LINE).unwrap();
+        assert_eq!(tables.neighbors().len(), 1);
+        tables.apply_line(FLUSH_NEIGH_LINE).unwrap();
+        assert!(tables.neighbors().is_empty());
+    }
+
+    #[test]
+    fn ignored_lines() {
+        let mut tables = BabelTables::default();
+        tables
+            .apply_line(
+                "add xroute 10.28.119.131/32-::/0 prefix 10.28.119.131/32 from ::/0 metric 0",
+            )
+            .unwrap();
+        tables.apply_line("add interface wlan0 up true").unwrap();
+        tables.apply_line("ok").unwrap();
+        tables.apply_line("local fee 1024").unwrap();
+        assert!(tables.routes().is_empty());
+        assert_eq!(tables.local_fee(), Some(1024));
+        assert!(is_terminator("ok"));
+        assert!(is_terminator("no"));
+        assert!(!is_terminator("local fee 1024"));
+        assert!(is_update(ROUTE_LINE));
+        assert!(is_update(FLUSH_NEIGH_LINE));
+        assert!(is_update("add interface wlan0 up true"));
+        assert!(!is_update("local fee 1024"));
+        assert!(!is_update("ok"));
+    }
+}
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE clients DROP COLUMN plan;
+DROP TABLE plans;
@@ -0,0 +1,10 @@
+CREATE TABLE plans
+(
+    name varchar(32) CONSTRAINT plankey PRIMARY KEY,
+    price_per_byte bigint NOT NULL,
+    guaranteed_kbit integer NOT NULL,
+    burst_kbit integer NOT NULL,
+    monthly_data_cap bigint DEFAULT 0 NOT NULL
+);
+
+ALTER TABLE clients ADD COLUMN plan varchar(32) DEFAULT '' NOT NULL;
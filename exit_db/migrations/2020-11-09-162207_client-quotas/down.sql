@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE clients DROP COLUMN period_start;
+ALTER TABLE clients DROP COLUMN period_usage;
+ALTER TABLE clients DROP COLUMN quota_warning_level;
@@ -0,0 +1,3 @@
+ALTER TABLE clients ADD COLUMN period_start bigint DEFAULT 0 NOT NULL;
+ALTER TABLE clients ADD COLUMN period_usage bigint DEFAULT 0 NOT NULL;
+ALTER TABLE clients ADD COLUMN quota_warning_level integer DEFAULT 0 NOT NULL;
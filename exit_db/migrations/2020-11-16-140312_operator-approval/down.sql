@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE clients DROP COLUMN denied;
+ALTER TABLE clients DROP COLUMN approval_message;
@@ -0,0 +1,2 @@
+ALTER TABLE clients ADD COLUMN denied boolean DEFAULT FALSE NOT NULL;
+ALTER TABLE clients ADD COLUMN approval_message varchar(512) DEFAULT '' NOT NULL;
@@ -1,4 +1,5 @@
 use crate::schema::clients;
+use crate::schema::plans;
 
 #[derive(Queryable, Serialize, Deserialize, Debug, Insertable, Clone, AsChangeset, Default)]
 #[table_name = "clients"]
@@ -18,4 +19,31 @@ pub struct Client {
     pub text_sent: i32,
     pub last_seen: i64,
     pub last_balance_warning_time: i64,
+    /// the name of the entry in the plans table this client is billed and shaped
+    /// according to, an empty string means the exit wide defaults apply
+    pub plan: String,
+    /// unix timestamp of the start of the client's current billing period
+    pub period_start: i64,
+    /// bytes used through the exit tunnel during the current billing period
+    pub period_usage: i64,
+    /// the highest quota warning percentage sent this billing period
+    pub quota_warning_level: i32,
+    /// true if the exit operator has denied this client, denied clients are never
+    /// added to the exit tunnel regardless of verification state
+    pub denied: bool,
+    /// the operator's reason for approving or denying this client, shown to the client
+    pub approval_message: String,
+}
+
+/// A service plan, clients are assigned a plan by name, a plan sets the price
+/// the client pays per byte as well as the bandwidth it is shaped to
+#[derive(Queryable, Serialize, Deserialize, Debug, Insertable, Clone, AsChangeset, Default)]
+#[table_name = "plans"]
+pub struct Plan {
+    pub name: String,
+    pub price_per_byte: i64,
+    pub guaranteed_kbit: i32,
+    pub burst_kbit: i32,
+    /// monthly data cap in bytes, zero means no cap
+    pub monthly_data_cap: i64,
 }
@@ -15,5 +15,23 @@ table! {
         text_sent -> Int4,
         last_seen -> Int8,
         last_balance_warning_time -> Int8,
+        plan -> Varchar,
+        period_start -> Int8,
+        period_usage -> Int8,
+        quota_warning_level -> Int4,
+        denied -> Bool,
+        approval_message -> Varchar,
     }
 }
+
+table! {
+    plans (name) {
+        name -> Varchar,
+        price_per_byte -> Int8,
+        guaranteed_kbit -> Int4,
+        burst_kbit -> Int4,
+        monthly_data_cap -> Int8,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(clients, plans,);
@@ -43,9 +43,6 @@ use std::time::{Duration, Instant};
 #[cfg(not(test))]
 use settings::FileWrite;
 
-#[cfg(test)]
-use std::sync::Mutex;
-
 mod middleware;
 mod rita_client;
 mod rita_common;
@@ -56,6 +53,7 @@ use crate::rita_client::rita_loop::start_rita_client_endpoints;
 use crate::rita_common::rita_loop::check_rita_common_actors;
 use crate::rita_common::rita_loop::start_core_rita_endpoints;
 
+use crate::rita_client::dashboard::backup::*;
 use crate::rita_client::dashboard::backup_created::*;
 use crate::rita_client::dashboard::eth_private_key::*;
 use crate::rita_client::dashboard::exits::*;
@@ -80,6 +78,7 @@ use crate::rita_common::dashboard::development::*;
 use crate::rita_common::dashboard::nickname::*;
 use crate::rita_common::dashboard::own_info::*;
 use crate::rita_common::dashboard::settings::*;
+use crate::rita_common::dashboard::statements::*;
 use crate::rita_common::dashboard::token_bridge::*;
 use crate::rita_common::dashboard::usage::*;
 use crate::rita_common::dashboard::wallet::*;
@@ -110,18 +109,15 @@ About:
 
 use althea_kernel_interface::KernelInterface;
 
-#[cfg(not(test))]
-use althea_kernel_interface::LinuxCommandRunner;
 #[cfg(test)]
-use althea_kernel_interface::TestCommandRunner;
+use althea_kernel_interface::fake::FakeKernel;
+#[cfg(not(test))]
+use althea_kernel_interface::LinuxNetlinkRunner;
 
 #[cfg(test)]
 lazy_static! {
-    pub static ref KI: Box<dyn KernelInterface> = Box::new(TestCommandRunner {
-        run_command: Arc::new(Mutex::new(Box::new(|_program, _args| {
-            panic!("kernel interface used before initialized");
-        })))
-    });
+    pub static ref FAKE_KERNEL: FakeKernel = FakeKernel::new();
+    pub static ref KI: Box<dyn KernelInterface> = Box::new(FAKE_KERNEL.clone());
 }
 
 #[cfg(test)]
@@ -131,7 +127,7 @@ lazy_static! {
 
 #[cfg(not(test))]
 lazy_static! {
-    pub static ref KI: Box<dyn KernelInterface> = Box::new(LinuxCommandRunner {});
+    pub static ref KI: Box<dyn KernelInterface> = Box::new(LinuxNetlinkRunner::default());
 }
 
 #[cfg(not(test))]
@@ -259,6 +255,14 @@ fn start_client_dashboard() {
         App::new()
             .middleware(middleware::Headers)
             .middleware(middleware::Auth)
+            .middleware(middleware::LastRequest)
+            .route("/backup", Method::POST, create_backup)
+            .resource("/restore", |r| {
+                r.method(Method::POST)
+                    .with_config(restore_backup_endpoint, |cfg| {
+                        cfg.limit(BACKUP_SIZE_LIMIT);
+                    })
+            })
             .route("/backup_created", Method::GET, get_backup_created)
             .route("/backup_created/{status}", Method::POST, set_backup_created)
             .route("/dao_list", Method::GET, get_dao_list)
@@ -280,6 +284,19 @@ fn start_client_dashboard() {
             .route("/local_fee/{fee}", Method::POST, set_local_fee)
             .route("/dao_fee", Method::GET, get_dao_fee)
             .route("/dao_fee/{fee}", Method::POST, set_dao_fee)
+            .route("/oracle/history", Method::GET, get_oracle_history)
+            .route("/oracle/pending", Method::GET, get_pending_oracle_update)
+            .route(
+                "/oracle/dry_run/{enabled}",
+                Method::POST,
+                set_oracle_dry_run,
+            )
+            .route(
+                "/oracle/rollback/{id}",
+                Method::POST,
+                rollback_oracle_update,
+            )
+            .route("/oracle/rejected", Method::GET, get_rejected_oracle_updates)
             .route("/metric_factor", Method::GET, get_metric_factor)
             .route("/metric_factor/{factor}", Method::POST, set_metric_factor)
             .route(
@@ -341,13 +358,32 @@ fn start_client_dashboard() {
                 Method::POST,
                 withdraw_eth,
             )
+            .route("/withdraw_queue", Method::GET, get_withdraw_queue)
+            .route(
+                "/withdraw_queue/{id}/approve",
+                Method::POST,
+                approve_held_withdraw,
+            )
+            .route(
+                "/withdraw_queue/{id}/cancel",
+                Method::POST,
+                cancel_held_withdraw,
+            )
             .route(
                 "/auto_price/enabled/{status}",
                 Method::POST,
                 set_auto_pricing,
             )
             .route("/auto_price/enabled", Method::GET, auto_pricing_status)
+            .route("/auto_price/engine", Method::GET, get_pricing_engine)
+            .route(
+                "/auto_price/engine/{engine}",
+                Method::POST,
+                set_pricing_engine,
+            )
+            .route("/auto_price/local", Method::GET, get_local_pricing_decision)
             .route("/prices", Method::GET, get_prices)
+            .route("/prices/route/{ip}", Method::GET, get_route_cost)
             .route(
                 "/blockchain/set/{chain_id}",
                 Method::POST,
@@ -369,6 +405,15 @@ fn start_client_dashboard() {
             .route("/usage/relay", Method::GET, get_relay_usage)
             .route("/usage/client", Method::GET, get_client_usage)
             .route("/usage/payments", Method::GET, get_payments)
+            .route("/usage/payments/query", Method::GET, query_payment_history)
+            .route("/usage/{kind}/query", Method::GET, query_usage_history)
+            .route("/usage/top/{kind}", Method::GET, get_top_peers)
+            .route("/statements/{year}/{month}", Method::GET, get_statement)
+            .route(
+                "/statements/{year}/{month}/csv",
+                Method::GET,
+                get_statement_csv,
+            )
             .route("/token_bridge/status", Method::GET, get_bridge_status)
             .route("/router/reboot", Method::POST, reboot_router)
             .route("/router/update", Method::POST, update_router)
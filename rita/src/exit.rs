@@ -46,9 +46,6 @@ use std::net::IpAddr;
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
-#[cfg(test)]
-use std::sync::Mutex;
-
 use settings::exit::{RitaExitSettings, RitaExitSettingsStruct};
 use settings::RitaCommonSettings;
 
@@ -73,11 +70,16 @@ use crate::rita_common::dashboard::development::*;
 use crate::rita_common::dashboard::nickname::*;
 use crate::rita_common::dashboard::own_info::*;
 use crate::rita_common::dashboard::settings::*;
+use crate::rita_common::dashboard::statements::*;
 use crate::rita_common::dashboard::token_bridge::*;
 use crate::rita_common::dashboard::usage::*;
 use crate::rita_common::dashboard::wallet::*;
 use crate::rita_common::dashboard::wg_key::*;
 use crate::rita_common::network_endpoints::*;
+use crate::rita_exit::database::geoip::geoip_db_loaded;
+use crate::rita_exit::database::geoip::load_geoip_cache;
+use crate::rita_exit::database::geoip::CachedCountry;
+use crate::rita_exit::database::sms_provider::check_sms_settings;
 use crate::rita_exit::network_endpoints::*;
 
 #[derive(Debug, Deserialize, Default)]
@@ -102,18 +104,15 @@ About:
 
 use althea_kernel_interface::KernelInterface;
 
-#[cfg(not(test))]
-use althea_kernel_interface::LinuxCommandRunner;
 #[cfg(test)]
-use althea_kernel_interface::TestCommandRunner;
+use althea_kernel_interface::fake::FakeKernel;
+#[cfg(not(test))]
+use althea_kernel_interface::LinuxNetlinkRunner;
 
 #[cfg(test)]
 lazy_static! {
-    pub static ref KI: Box<dyn KernelInterface> = Box::new(TestCommandRunner {
-        run_command: Arc::new(Mutex::new(Box::new(|_program, _args| {
-            panic!("kernel interface used before initialized");
-        })))
-    });
+    pub static ref FAKE_KERNEL: FakeKernel = FakeKernel::new();
+    pub static ref KI: Box<dyn KernelInterface> = Box::new(FAKE_KERNEL.clone());
 }
 
 #[cfg(test)]
@@ -123,7 +122,7 @@ lazy_static! {
 
 #[cfg(not(test))]
 lazy_static! {
-    pub static ref KI: Box<dyn KernelInterface> = Box::new(LinuxCommandRunner {});
+    pub static ref KI: Box<dyn KernelInterface> = Box::new(LinuxNetlinkRunner::default());
 }
 
 #[cfg(not(test))]
@@ -134,8 +133,8 @@ lazy_static! {
 }
 
 lazy_static! {
-    pub static ref GEOIP_CACHE: Arc<RwLock<HashMap<IpAddr, String>>> =
-        Arc::new(RwLock::new(HashMap::new()));
+    pub static ref GEOIP_CACHE: Arc<RwLock<HashMap<IpAddr, CachedCountry>>> =
+        Arc::new(RwLock::new(load_geoip_cache()));
 }
 
 // These are a set of vars that are never updated during runtime. This means we can have
@@ -217,10 +216,32 @@ lazy_static! {
 /// used to crash the exit on first startup if config does not make sense
 /// as is usually desirable for cloud infrastruture
 fn sanity_check_config() {
-    if !SETTING.get_allowed_countries().is_empty()
-        && SETTING.get_exit_network().geoip_api_key.is_none()
-    {
-        panic!("GEOIP enforcement configured but not api key provided!");
+    if !SETTING.get_allowed_countries().is_empty() {
+        let exit_network = SETTING.get_exit_network().clone();
+        match (exit_network.geoip_db_path, exit_network.geoip_api_key) {
+            (None, None) => {
+                panic!("GEOIP enforcement configured but no database or api key provided!")
+            }
+            (Some(path), None) => {
+                if !geoip_db_loaded() {
+                    panic!("GEOIP database {} could not be loaded!", path);
+                }
+            }
+            (Some(path), Some(_)) => {
+                if !geoip_db_loaded() {
+                    warn!(
+                        "GEOIP database {} could not be loaded, falling back to the api",
+                        path
+                    );
+                }
+            }
+            (None, Some(_)) => {}
+        }
+    }
+    if let Some(ExitVerifSettings::Phone(phone)) = SETTING.get_verif_settings() {
+        if let Err(e) = check_sms_settings(&phone) {
+            panic!("Invalid phone verification settings: {}", e);
+        }
     }
 }
 
@@ -276,6 +297,19 @@ fn start_rita_exit_dashboard() {
             .route("/local_fee/{fee}", Method::POST, set_local_fee)
             .route("/dao_fee", Method::GET, get_dao_fee)
             .route("/dao_fee/{fee}", Method::POST, set_dao_fee)
+            .route("/oracle/history", Method::GET, get_oracle_history)
+            .route("/oracle/pending", Method::GET, get_pending_oracle_update)
+            .route(
+                "/oracle/dry_run/{enabled}",
+                Method::POST,
+                set_oracle_dry_run,
+            )
+            .route(
+                "/oracle/rollback/{id}",
+                Method::POST,
+                rollback_oracle_update,
+            )
+            .route("/oracle/rejected", Method::GET, get_rejected_oracle_updates)
             .route("/metric_factor", Method::GET, get_metric_factor)
             .route("/metric_factor/{factor}", Method::POST, set_metric_factor)
             .route("/settings", Method::GET, get_settings)
@@ -300,12 +334,38 @@ fn start_rita_exit_dashboard() {
                 Method::POST,
                 withdraw_eth,
             )
+            .route("/withdraw_queue", Method::GET, get_withdraw_queue)
+            .route(
+                "/withdraw_queue/{id}/approve",
+                Method::POST,
+                approve_held_withdraw,
+            )
+            .route(
+                "/withdraw_queue/{id}/cancel",
+                Method::POST,
+                cancel_held_withdraw,
+            )
             .route("/nickname/get/", Method::GET, get_nickname)
             .route("/nickname/set/", Method::POST, set_nickname)
             .route("/router/password/", Method::POST, set_pass)
             .route("/crash_actors", Method::POST, crash_actors)
             .route("/usage/payments", Method::GET, get_payments)
+            .route("/usage/payments/query", Method::GET, query_payment_history)
+            .route("/usage/{kind}/query", Method::GET, query_usage_history)
+            .route("/usage/top/{kind}", Method::GET, get_top_peers)
+            .route("/statements/{year}/{month}", Method::GET, get_statement)
+            .route(
+                "/statements/{year}/{month}/csv",
+                Method::GET,
+                get_statement_csv,
+            )
             .route("/token_bridge/status", Method::GET, get_bridge_status)
+            .route("/plans", Method::GET, get_service_plans)
+            .route("/plans", Method::POST, set_service_plan)
+            .route("/plans/assign", Method::POST, assign_service_plan)
+            .route("/clients/pending", Method::GET, get_pending_clients_http)
+            .route("/clients/approve", Method::POST, approve_client)
+            .route("/clients/deny", Method::POST, deny_client)
     })
     .bind(format!(
         "[::0]:{}",
@@ -9,6 +9,8 @@ use actix_web_httpauth::extractors::basic::{BasicAuth, Config};
 use actix_web_httpauth::extractors::AuthenticationError;
 use regex::Regex;
 use settings::RitaCommonSettings;
+use std::sync::RwLock;
+use std::time::Instant;
 
 pub struct Headers;
 
@@ -81,3 +83,32 @@ impl<S> Middleware<S> for Auth {
         }
     }
 }
+
+lazy_static! {
+    static ref LAST_REMOTE_REQUEST: RwLock<Option<Instant>> = RwLock::new(None);
+}
+
+/// Records when the dashboard last answered a request from another device, one that
+/// comes in after a network change shows the change didn't lock the user out
+#[allow(dead_code)]
+pub struct LastRequest;
+
+impl<S> Middleware<S> for LastRequest {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        if let Some(addr) = req.peer_addr() {
+            if !addr.ip().is_loopback() {
+                *LAST_REMOTE_REQUEST.write().unwrap() = Some(Instant::now());
+            }
+        }
+        Ok(Started::Done)
+    }
+}
+
+/// Returns true if another device has used the dashboard since the given time
+#[allow(dead_code)]
+pub fn dashboard_used_since(since: Instant) -> bool {
+    match *LAST_REMOTE_REQUEST.read().unwrap() {
+        Some(last) => last >= since,
+        None => false,
+    }
+}
@@ -0,0 +1,556 @@
+//! Backup and restore of everything needed to move a router's identity and state onto a
+//! replacement device. The backup is a password encrypted bundle holding the settings (which
+//! include the keys and exit registrations), the debts and usage tracker files, the usage
+//! journal and the network and wireless UCI configs. Restoring writes all of it back and reboots
+//! into the restored state. A restore is checked fully before anything is written, and the files
+//! are then replaced all together or not at all, so a failed restore leaves the router as it was.
+
+use crate::rita_common::usage_tracker::usage_journal_file;
+use crate::rita_common::withdraw_policy::strip_protected_settings;
+use crate::ARGS;
+use crate::KI;
+use crate::SETTING;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, Json};
+use althea_kernel_interface::uci_transaction::UCI_CONFIG_DIR;
+use failure::Error;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use settings::client::RitaSettingsStruct;
+use settings::FileWrite;
+use settings::RitaCommonSettings;
+use sodiumoxide::crypto::pwhash::scryptsalsa208sha256 as pwhash;
+use sodiumoxide::crypto::secretbox;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// The bundle format version, bumped whenever a change means older code can't restore it
+pub const BACKUP_VERSION: u32 = 1;
+/// The largest bundle the restore endpoint accepts, the default json limit is too small
+/// for a long usage history
+pub const BACKUP_SIZE_LIMIT: usize = 8_000_000;
+/// The UCI configs carried in a backup
+const BACKUP_UCI_CONFIGS: [&str; 2] = ["network", "wireless"];
+/// Where OpenWrt keeps the board name, the wireless config of one board doesn't fit another
+const BOARD_NAME_FILE: &str = "/tmp/sysinfo/board_name";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BackupPassword {
+    pub password: String,
+}
+
+/// An encrypted backup as handed to and accepted from the dashboard
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BackupBundle {
+    pub version: u32,
+    pub salt: [u8; pwhash::SALTBYTES],
+    pub nonce: [u8; secretbox::NONCEBYTES],
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RestoreRequest {
+    pub password: String,
+    pub bundle: BackupBundle,
+}
+
+/// The decrypted contents of a backup
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct BackupContents {
+    /// the version of rita that made the backup
+    rita_version: String,
+    settings: serde_json::Value,
+    /// contents of the debts file, if there was one
+    debts: Option<Vec<u8>>,
+    /// contents of the usage tracker file, if there was one
+    usage: Option<Vec<u8>>,
+    /// contents of the usage journal, the usage since the last snapshot of the tracker file
+    #[serde(default)]
+    usage_journal: Option<Vec<u8>>,
+    /// UCI config name to the contents of its file
+    uci: HashMap<String, String>,
+    /// the board the backup was made on, None if it couldn't be read
+    #[serde(default)]
+    board: Option<String>,
+}
+
+fn derive_key(password: &str, salt: &pwhash::Salt) -> Result<secretbox::Key, Error> {
+    let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    if pwhash::derive_key(
+        &mut key.0,
+        password.as_bytes(),
+        salt,
+        pwhash::OPSLIMIT_INTERACTIVE,
+        pwhash::MEMLIMIT_INTERACTIVE,
+    )
+    .is_err()
+    {
+        bail!("Failed to derive backup key");
+    }
+    Ok(key)
+}
+
+fn seal_backup(contents: &BackupContents, password: &str) -> Result<BackupBundle, Error> {
+    if password.is_empty() {
+        bail!("A backup needs a password");
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&serde_json::to_vec(contents)?)?;
+    let plaintext = encoder.finish()?;
+
+    let salt = pwhash::gen_salt();
+    let nonce = secretbox::gen_nonce();
+    let key = derive_key(password, &salt)?;
+    Ok(BackupBundle {
+        version: BACKUP_VERSION,
+        salt: salt.0,
+        nonce: nonce.0,
+        ciphertext: secretbox::seal(&plaintext, &nonce, &key),
+    })
+}
+
+fn open_backup(bundle: &BackupBundle, password: &str) -> Result<BackupContents, Error> {
+    if bundle.version > BACKUP_VERSION {
+        bail!(
+            "Backup version {} is newer than this firmware supports, update first",
+            bundle.version
+        );
+    }
+    let key = derive_key(password, &pwhash::Salt(bundle.salt))?;
+    let plaintext = match secretbox::open(&bundle.ciphertext, &secretbox::Nonce(bundle.nonce), &key)
+    {
+        Ok(plaintext) => plaintext,
+        Err(_) => bail!("Could not decrypt backup, wrong password?"),
+    };
+    let mut contents = Vec::new();
+    ZlibDecoder::new(&plaintext[..]).read_to_end(&mut contents)?;
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    if path.exists() {
+        Ok(Some(fs::read(path)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn collect_backup() -> Result<BackupContents, Error> {
+    let debts_file = SETTING.get_payment().debts_file.clone();
+    let usage_file = SETTING.get_network().usage_tracker_file.clone();
+
+    let mut uci = HashMap::new();
+    for config in BACKUP_UCI_CONFIGS.iter() {
+        if let Some(contents) = read_if_exists(&Path::new(UCI_CONFIG_DIR).join(config))? {
+            uci.insert(config.to_string(), String::from_utf8(contents)?);
+        }
+    }
+
+    Ok(BackupContents {
+        rita_version: env!("CARGO_PKG_VERSION").to_string(),
+        settings: SETTING.get_all()?,
+        debts: read_if_exists(Path::new(&debts_file))?,
+        usage: read_if_exists(Path::new(&usage_file))?,
+        usage_journal: read_if_exists(Path::new(&usage_journal_file(&usage_file)))?,
+        uci,
+        board: board_name(),
+    })
+}
+
+fn board_name() -> Option<String> {
+    fs::read_to_string(BOARD_NAME_FILE)
+        .ok()
+        .map(|board| board.trim().to_string())
+}
+
+fn check_board(backup: &Option<String>, ours: &Option<String>) -> Result<(), Error> {
+    if let (Some(backup), Some(ours)) = (backup, ours) {
+        if backup != ours {
+            bail!(
+                "Backup was made on a {} and can't be restored onto this {}",
+                backup,
+                ours
+            );
+        }
+    }
+    Ok(())
+}
+
+fn staging_path(path: &Path, suffix: &str) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), suffix))
+}
+
+fn remove_staged(staged: &[(PathBuf, PathBuf)]) {
+    for (tmp, _) in staged {
+        let _ = fs::remove_file(tmp);
+    }
+}
+
+/// Writes every file next to its destination, returning (staged, destination) pairs. If any
+/// write fails the ones already staged are removed
+fn stage_files(files: &[(PathBuf, Vec<u8>)]) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    let mut staged = Vec::new();
+    for (path, contents) in files {
+        let tmp = staging_path(path, "restore");
+        if let Err(e) = fs::write(&tmp, contents) {
+            remove_staged(&staged);
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        staged.push((tmp, path.clone()));
+    }
+    Ok(staged)
+}
+
+/// Moves staged files into place, keeping the files they replace until all of them are in.
+/// If a move fails every file already replaced is put back and the staged files are removed
+fn commit_staged(staged: &[(PathBuf, PathBuf)]) -> Result<(), Error> {
+    let mut replaced: Vec<(&PathBuf, Option<PathBuf>)> = Vec::new();
+    for (tmp, path) in staged {
+        let old = if path.exists() {
+            let old = staging_path(path, "restore-old");
+            if let Err(e) = fs::rename(path, &old) {
+                undo_commit(replaced);
+                remove_staged(staged);
+                return Err(e.into());
+            }
+            Some(old)
+        } else {
+            None
+        };
+        if let Err(e) = fs::rename(tmp, path) {
+            // nothing was moved to path, only what was there before needs to go back
+            if old.is_some() {
+                replaced.push((path, old));
+            }
+            undo_commit(replaced);
+            remove_staged(staged);
+            return Err(e.into());
+        }
+        replaced.push((path, old));
+    }
+    for (_, old) in replaced {
+        if let Some(old) = old {
+            let _ = fs::remove_file(old);
+        }
+    }
+    Ok(())
+}
+
+fn undo_commit(replaced: Vec<(&PathBuf, Option<PathBuf>)>) {
+    for (path, old) in replaced.into_iter().rev() {
+        let res = match old {
+            Some(old) => fs::rename(old, path),
+            None => fs::remove_file(path),
+        };
+        if let Err(e) = res {
+            error!(
+                "Failed to put back {:?} after a failed restore {:?}",
+                path, e
+            );
+        }
+    }
+}
+
+/// The files to write for a restore, the debts and usage go to this router's own files. The
+/// restored usage always comes with a journal, an empty one if the backup had none, otherwise
+/// our own journal would be replayed on top of the restored history
+fn restore_files(
+    contents: &BackupContents,
+    debts_file: &str,
+    usage_file: &str,
+) -> Result<Vec<(PathBuf, Vec<u8>)>, Error> {
+    let mut files = Vec::new();
+    for (config, file) in contents.uci.iter() {
+        if !BACKUP_UCI_CONFIGS.contains(&config.as_str()) {
+            bail!("Backup contains unexpected UCI config {}", config);
+        }
+        files.push((
+            Path::new(UCI_CONFIG_DIR).join(config),
+            file.clone().into_bytes(),
+        ));
+    }
+    if let Some(debts) = &contents.debts {
+        files.push((PathBuf::from(debts_file), debts.clone()));
+    }
+    if let Some(usage) = &contents.usage {
+        files.push((PathBuf::from(usage_file), usage.clone()));
+        files.push((
+            PathBuf::from(usage_journal_file(usage_file)),
+            contents.usage_journal.clone().unwrap_or_default(),
+        ));
+    }
+    Ok(files)
+}
+
+/// Checks that a backup can be restored here and returns the settings to merge
+fn check_restore(contents: &BackupContents) -> Result<serde_json::Value, Error> {
+    check_board(&contents.board, &board_name())?;
+    // make sure the settings are complete client settings before anything is written
+    let settings: RitaSettingsStruct = serde_json::from_value(contents.settings.clone())?;
+    let mut settings = serde_json::to_value(settings)?;
+    // a restore is a settings change from the dashboard like any other, the withdraw policy
+    // and sweeps stay as they are and the key can't be swapped out from under the policy
+    strip_protected_settings(&mut settings);
+    // file locations are this router's own
+    if let Some(payment) = settings["payment"].as_object_mut() {
+        payment.remove("debts_file");
+    }
+    if let Some(network) = settings["network"].as_object_mut() {
+        network.remove("usage_tracker_file");
+    }
+    let policy_set = SETTING.get_payment().withdraw_policy.is_set();
+    if policy_set
+        && settings["payment"]["eth_private_key"]
+            != SETTING.get_all()?["payment"]["eth_private_key"]
+    {
+        bail!("A backup with another eth private key can't be restored while a withdraw policy is set");
+    }
+    Ok(settings)
+}
+
+fn restore_backup(contents: BackupContents, settings: serde_json::Value) -> Result<(), Error> {
+    info!("Restoring backup made by rita {}", contents.rita_version);
+    let debts_file = SETTING.get_payment().debts_file.clone();
+    let usage_file = SETTING.get_network().usage_tracker_file.clone();
+    let files = restore_files(&contents, &debts_file, &usage_file)?;
+
+    // merged into a copy so that our settings only change along with the files
+    let restored = Arc::new(RwLock::new(SETTING.read().unwrap().clone()));
+    restored.merge(settings)?;
+
+    let mut staged = stage_files(&files)?;
+    let config = PathBuf::from(&ARGS.flag_config);
+    let config_tmp = staging_path(&config, "restore");
+    if let Err(e) = restored
+        .read()
+        .unwrap()
+        .write(&config_tmp.to_string_lossy())
+    {
+        remove_staged(&staged);
+        let _ = fs::remove_file(&config_tmp);
+        return Err(e);
+    }
+    staged.push((config_tmp, config));
+    // held across the commit so the settings can't be saved over the restored file meanwhile
+    let mut setting = SETTING.write().unwrap();
+    commit_staged(&staged)?;
+    *setting = restored.read().unwrap().clone();
+    drop(setting);
+
+    // We edited disk contents, force global sync
+    KI.fs_sync()?;
+
+    // the running actors still hold the old debts and usage, reboot before they save them
+    trace!("Successfully restored backup, rebooting");
+    KI.run_command("reboot", &[])?;
+    Ok(())
+}
+
+pub fn create_backup(req: Json<BackupPassword>) -> Result<HttpResponse, Error> {
+    debug!("/backup POST hit");
+    // a backup carries the eth private key, which is locked while a policy is set
+    if SETTING.get_payment().withdraw_policy.is_set() {
+        return Ok(HttpResponse::new(StatusCode::FORBIDDEN)
+            .into_builder()
+            .json("A backup can't be made while a withdraw policy is set"));
+    }
+    let contents = collect_backup()?;
+    Ok(HttpResponse::Ok().json(seal_backup(&contents, &req.password)?))
+}
+
+pub fn restore_backup_endpoint(req: Json<RestoreRequest>) -> Result<HttpResponse, Error> {
+    debug!("/restore POST hit");
+    let req = req.into_inner();
+    let contents = match open_backup(&req.bundle, &req.password) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Failed to open backup {:?}", e);
+            return Ok(HttpResponse::new(StatusCode::BAD_REQUEST)
+                .into_builder()
+                .json(format!("{}", e)));
+        }
+    };
+    let settings = match check_restore(&contents) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Backup can't be restored here {:?}", e);
+            return Ok(HttpResponse::new(StatusCode::BAD_REQUEST)
+                .into_builder()
+                .json(format!("{}", e)));
+        }
+    };
+    restore_backup(contents, settings)?;
+    Ok(HttpResponse::Ok().json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_contents() -> BackupContents {
+        let mut uci = HashMap::new();
+        uci.insert(
+            "network".to_string(),
+            "config interface 'lan'\n".to_string(),
+        );
+        BackupContents {
+            rita_version: "0.5.38".to_string(),
+            settings: json!({"network": {"mesh_ip": "fd00::1"}}),
+            debts: Some(b"{}".to_vec()),
+            usage: None,
+            usage_journal: None,
+            uci,
+            board: Some("tplink,archer-c7-v2".to_string()),
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn dir_entries(dir: &Path) -> Vec<String> {
+        let mut entries: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_backup_round_trip() {
+        let contents = test_contents();
+        let bundle = seal_backup(&contents, "hunter2").unwrap();
+        assert_eq!(open_backup(&bundle, "hunter2").unwrap(), contents);
+        assert!(open_backup(&bundle, "hunter3").is_err());
+        assert!(seal_backup(&contents, "").is_err());
+    }
+
+    #[test]
+    fn test_backup_newer_version() {
+        let mut bundle = seal_backup(&test_contents(), "hunter2").unwrap();
+        bundle.version = BACKUP_VERSION + 1;
+        assert!(open_backup(&bundle, "hunter2").is_err());
+    }
+
+    #[test]
+    fn test_check_board() {
+        let archer = Some("tplink,archer-c7-v2".to_string());
+        let glb = Some("glinet,gl-b1300".to_string());
+        assert!(check_board(&archer, &archer).is_ok());
+        assert!(check_board(&archer, &glb).is_err());
+        // older backups and routers without sysinfo can't be checked
+        assert!(check_board(&None, &glb).is_ok());
+        assert!(check_board(&archer, &None).is_ok());
+    }
+
+    #[test]
+    fn test_restore_files() {
+        let mut contents = test_contents();
+        contents.usage = Some(b"[]".to_vec());
+        let mut files = restore_files(&contents, "/etc/rita-debts.json", "/etc/rita-usage.json")
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("/etc/config/network"),
+                PathBuf::from("/etc/rita-debts.json"),
+                PathBuf::from("/etc/rita-usage.json"),
+                PathBuf::from("/etc/rita-usage.json.journal"),
+            ]
+        );
+
+        contents
+            .uci
+            .insert("../shadow".to_string(), "root::0:0".to_string());
+        assert!(restore_files(&contents, "/etc/rita-debts.json", "/etc/rita-usage.json").is_err());
+    }
+
+    #[test]
+    fn test_restore_usage_journal() {
+        let journal_of = |contents: &BackupContents| {
+            restore_files(contents, "/etc/rita-debts.json", "/etc/rita-usage.json")
+                .unwrap()
+                .into_iter()
+                .find(|(path, _)| path == Path::new("/etc/rita-usage.json.journal"))
+                .map(|(_, journal)| journal)
+        };
+        // without usage our own history and journal stay
+        let mut contents = test_contents();
+        assert_eq!(journal_of(&contents), None);
+
+        // a backup without a journal still replaces ours with an empty one
+        contents.usage = Some(b"[]".to_vec());
+        assert_eq!(journal_of(&contents), Some(Vec::new()));
+
+        let line = b"{\"seq\":3,\"entry\":{}}\n".to_vec();
+        contents.usage_journal = Some(line.clone());
+        assert_eq!(journal_of(&contents), Some(line));
+
+        // bundles made before the journal was backed up still open
+        let mut old = serde_json::to_value(&test_contents()).unwrap();
+        old.as_object_mut().unwrap().remove("usage_journal");
+        let old: BackupContents = serde_json::from_value(old).unwrap();
+        assert_eq!(old.usage_journal, None);
+    }
+
+    #[test]
+    fn test_restore_commit() {
+        let dir = test_dir("rita_backup_commit_test");
+        fs::write(dir.join("network"), "old network").unwrap();
+        let files = vec![
+            (dir.join("network"), b"new network".to_vec()),
+            (dir.join("debts.json"), b"new debts".to_vec()),
+        ];
+        let staged = stage_files(&files).unwrap();
+        commit_staged(&staged).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("network")).unwrap(),
+            "new network"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("debts.json")).unwrap(),
+            "new debts"
+        );
+        assert_eq!(dir_entries(&dir), vec!["debts.json", "network"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore_commit_rollback() {
+        let dir = test_dir("rita_backup_rollback_test");
+        fs::write(dir.join("network"), "old network").unwrap();
+        fs::write(dir.join("usage.json"), "old usage").unwrap();
+        let files = vec![
+            (dir.join("network"), b"new network".to_vec()),
+            (dir.join("debts.json"), b"new debts".to_vec()),
+            (dir.join("usage.json"), b"new usage".to_vec()),
+        ];
+        let staged = stage_files(&files).unwrap();
+        // the last move fails after the others went through
+        fs::remove_file(dir.join("usage.json.restore")).unwrap();
+        assert!(commit_staged(&staged).is_err());
+        assert_eq!(
+            fs::read_to_string(dir.join("network")).unwrap(),
+            "old network"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("usage.json")).unwrap(),
+            "old usage"
+        );
+        assert_eq!(dir_entries(&dir), vec!["network", "usage.json"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 use crate::ARGS;
 use crate::KI;
 use crate::SETTING;
+use actix_web::http::StatusCode;
 use actix_web::{HttpRequest, HttpResponse, Json};
 use althea_types::ExitState;
 use clarity::PrivateKey;
@@ -15,8 +16,25 @@ pub struct EthPrivateKey {
     pub eth_private_key: String,
 }
 
+/// The key can move our funds without going through the withdraw policy, so while a policy is
+/// set it can't be exported or replaced from the dashboard
+fn key_locked() -> Option<HttpResponse> {
+    if SETTING.get_payment().withdraw_policy.is_set() {
+        Some(
+            HttpResponse::new(StatusCode::FORBIDDEN)
+                .into_builder()
+                .json("The eth private key is locked while a withdraw policy is set"),
+        )
+    } else {
+        None
+    }
+}
+
 pub fn get_eth_private_key(_req: HttpRequest) -> Result<HttpResponse, Error> {
     debug!("/eth_private_key GET hit");
+    if let Some(locked) = key_locked() {
+        return Ok(locked);
+    }
 
     let mut ret = HashMap::new();
 
@@ -36,6 +54,9 @@ pub fn get_eth_private_key(_req: HttpRequest) -> Result<HttpResponse, Error> {
 
 pub fn set_eth_private_key(data: Json<EthPrivateKey>) -> Result<HttpResponse, Error> {
     debug!("/eth_private_key POST hit");
+    if let Some(locked) = key_locked() {
+        return Ok(locked);
+    }
 
     let pk: PrivateKey = data.into_inner().eth_private_key.parse()?;
 
@@ -1,6 +1,7 @@
 //! The Exit info endpoint gathers infromation about exit status and presents it to the dashbaord.
 
 use crate::rita_client::exit_manager::exit_setup_request;
+use crate::rita_common::babel_manager;
 use crate::rita_common::dashboard::Dashboard;
 use crate::ARGS;
 use crate::KI;
@@ -15,15 +16,11 @@ use actix_web::Path;
 use actix_web::{HttpRequest, HttpResponse, Json};
 use althea_types::ExitState;
 use babel_monitor::do_we_have_route;
-use babel_monitor::open_babel_stream;
-use babel_monitor::parse_routes;
-use babel_monitor::start_connection;
 use bytes::Bytes;
 use failure::Error;
 use futures01::{future, Future};
 use settings::client::{ExitServer, RitaClientSettings};
 use settings::FileWrite;
-use settings::RitaCommonSettings;
 use std::boxed::Box;
 use std::collections::HashMap;
 use std::time::Duration;
@@ -73,52 +70,41 @@ impl Handler<GetExitInfo> for Dashboard {
     type Result = ResponseFuture<Vec<ExitInfo>, Error>;
 
     fn handle(&mut self, _msg: GetExitInfo, _ctx: &mut Self::Context) -> Self::Result {
-        let babel_port = SETTING.get_network().babel_port;
-
-        Box::new(
-            open_babel_stream(babel_port)
-                .from_err()
-                .and_then(move |stream| {
-                    start_connection(stream).and_then(move |stream| {
-                        parse_routes(stream).and_then(move |routes| {
-                            let route_table_sample = routes.1;
-                            let mut output = Vec::new();
-
-                            let exit_client = SETTING.get_exit_client();
-                            let current_exit = exit_client.get_current_exit();
-
-                            for exit in exit_client.exits.clone().into_iter() {
-                                let selected = is_selected(&exit.1, current_exit);
-                                let have_route =
-                                    do_we_have_route(&exit.1.id.mesh_ip, &route_table_sample)?;
-
-                                // failed pings block for one second, so we should be sure it's at least reasonable
-                                // to expect the pings to work before issuing them.
-                                let reachable = if have_route {
-                                    KI.ping_check(&exit.1.id.mesh_ip, EXIT_PING_TIMEOUT)?
-                                } else {
-                                    false
-                                };
-                                let tunnel_working = match (have_route, selected) {
-                                    (true, true) => is_tunnel_working(&exit.1, current_exit),
-                                    _ => false,
-                                };
-
-                                output.push(ExitInfo {
-                                    nickname: exit.0,
-                                    exit_settings: exit.1.clone(),
-                                    is_selected: selected,
-                                    have_route,
-                                    is_reachable: reachable,
-                                    is_tunnel_working: tunnel_working,
-                                })
-                            }
+        Box::new(babel_manager::get_routes().and_then(move |routes| {
+            let route_table_sample = routes;
+            let mut output = Vec::new();
+
+            let exit_client = SETTING.get_exit_client();
+            let current_exit = exit_client.get_current_exit();
+
+            for exit in exit_client.exits.clone().into_iter() {
+                let selected = is_selected(&exit.1, current_exit);
+                let have_route = do_we_have_route(&exit.1.id.mesh_ip, &route_table_sample)?;
+
+                // failed pings block for one second, so we should be sure it's at least reasonable
+                // to expect the pings to work before issuing them.
+                let reachable = if have_route {
+                    KI.ping_check(&exit.1.id.mesh_ip, EXIT_PING_TIMEOUT)?
+                } else {
+                    false
+                };
+                let tunnel_working = match (have_route, selected) {
+                    (true, true) => is_tunnel_working(&exit.1, current_exit),
+                    _ => false,
+                };
+
+                output.push(ExitInfo {
+                    nickname: exit.0,
+                    exit_settings: exit.1.clone(),
+                    is_selected: selected,
+                    have_route,
+                    is_reachable: reachable,
+                    is_tunnel_working: tunnel_working,
+                })
+            }
 
-                            Ok(output)
-                        })
-                    })
-                }),
-        )
+            Ok(output)
+        }))
     }
 }
 
@@ -1,4 +1,5 @@
 //! A generalized interface for modifying networking interface assignments using UCI
+use crate::middleware::dashboard_used_since;
 use crate::rita_common::peer_listener::PeerListener;
 use crate::rita_common::peer_listener::UnListen;
 use crate::ARGS;
@@ -10,8 +11,15 @@ use actix_web::{HttpRequest, HttpResponse, Json};
 use failure::Error;
 use settings::FileWrite;
 use settings::RitaCommonSettings;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long a changed interface config has to pass the connectivity check before it's rolled back
+const INTERFACE_CHANGE_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct InterfaceToSet {
@@ -170,27 +178,26 @@ pub fn ethernet_transform_mode(
         bail!("We can't change Unknown interfaces!");
     }
 
-    // if we have edited UCI and it fails we set this var to handle cleanup later
-    let mut return_codes = Vec::new();
-    // in case of failure we revert to here
+    // in case of failure or rollback we revert to here
     let old_network_settings = { SETTING.get_network().clone() };
     let filtered_ifname = format!("network.rita_{}", ifname.replace(".", ""));
+    // if we can see mesh neighbors before the change at least one of them must remain after
+    let neighbors_before = mesh_neighbor_count(ifname);
+    let mut transaction = KI.uci_transaction(&["network"]);
 
     match a {
         // Wan is very simple, just delete it
         InterfaceMode::WAN | InterfaceMode::StaticWAN { .. } => {
             SETTING.get_network_mut().external_nic = None;
 
-            let ret = KI.del_uci_var("network.backhaul");
-            return_codes.push(ret);
+            transaction.delete("network.backhaul");
         }
         // lan is a little more complicated, wifi interfaces
         // may depend on it so we only remove the ifname entry
         InterfaceMode::LAN => {
             let list = KI.get_uci_var("network.lan.ifname")?;
             let new_list = list_remove(&list, ifname);
-            let ret = KI.set_uci_var("network.lan.ifname", &new_list);
-            return_codes.push(ret);
+            transaction.set("network.lan.ifname", &new_list);
         }
         // for mesh we need to send an unlisten so that Rita stops
         // listening then we can remove the section, we also need to remove it
@@ -199,8 +206,7 @@ pub fn ethernet_transform_mode(
             PeerListener::from_registry().do_send(UnListen(ifname.to_string()));
             SETTING.get_network_mut().peer_interfaces.remove(ifname);
 
-            let ret = KI.del_uci_var(&filtered_ifname);
-            return_codes.push(ret);
+            transaction.delete(&filtered_ifname);
         }
         InterfaceMode::Unknown => unimplemented!(),
     }
@@ -210,12 +216,9 @@ pub fn ethernet_transform_mode(
         InterfaceMode::WAN => {
             SETTING.get_network_mut().external_nic = Some(ifname.to_string());
 
-            let ret = KI.set_uci_var("network.backhaul", "interface");
-            return_codes.push(ret);
-            let ret = KI.set_uci_var("network.backhaul.ifname", ifname);
-            return_codes.push(ret);
-            let ret = KI.set_uci_var("network.backhaul.proto", "dhcp");
-            return_codes.push(ret);
+            transaction.set("network.backhaul", "interface");
+            transaction.set("network.backhaul.ifname", ifname);
+            transaction.set("network.backhaul.proto", "dhcp");
         }
         InterfaceMode::StaticWAN {
             netmask,
@@ -224,39 +227,32 @@ pub fn ethernet_transform_mode(
         } => {
             SETTING.get_network_mut().external_nic = Some(ifname.to_string());
 
-            let ret = KI.set_uci_var("network.backhaul", "interface");
-            return_codes.push(ret);
-            let ret = KI.set_uci_var("network.backhaul.ifname", ifname);
-            return_codes.push(ret);
-            let ret = KI.set_uci_var("network.backhaul.proto", "static");
-            return_codes.push(ret);
-            let ret = KI.set_uci_var("network.backhaul.netmask", &format!("{}", netmask));
-            return_codes.push(ret);
-            let ret = KI.set_uci_var("network.backhaul.ipaddr", &format!("{}", ipaddr));
-            return_codes.push(ret);
-            let ret = KI.set_uci_var("network.backhaul.gateway", &format!("{}", gateway));
-            return_codes.push(ret);
+            transaction.set("network.backhaul", "interface");
+            transaction.set("network.backhaul.ifname", ifname);
+            transaction.set("network.backhaul.proto", "static");
+            transaction.set("network.backhaul.netmask", &format!("{}", netmask));
+            transaction.set("network.backhaul.ipaddr", &format!("{}", ipaddr));
+            transaction.set("network.backhaul.gateway", &format!("{}", gateway));
         }
         // since we left lan mostly unomidifed we just pop in the ifname
         InterfaceMode::LAN => {
             trace!("Converting interface to lan with ifname {:?}", ifname);
-            let ret = KI.get_uci_var("network.lan.ifname");
-            match ret {
+            match transaction.get("network.lan.ifname") {
                 Ok(list) => {
                     trace!("The existing LAN interfaces list is {:?}", list);
                     let new_list = list_add(&list, &ifname);
                     trace!("Setting the new list {:?}", new_list);
-                    let ret = KI.set_uci_var("network.lan.ifname", &new_list);
-                    return_codes.push(ret);
+                    transaction.set("network.lan.ifname", &new_list);
                 }
                 Err(e) => {
                     if e.to_string().contains("Entry not found") {
                         trace!("No LAN interfaces found, setting one now");
-                        let ret = KI.set_uci_var("network.lan.ifname", &ifname);
-                        return_codes.push(ret);
+                        transaction.set("network.lan.ifname", &ifname);
                     } else {
                         warn!("Trying to read lan ifname returned {:?}", e);
-                        return_codes.push(Err(e));
+                        let res = transaction.revert();
+                        *SETTING.get_network_mut() = old_network_settings;
+                        bail!("Error reading UCI! {:?} Revert attempted: {:?}", e, res);
                     }
                 }
             }
@@ -267,31 +263,61 @@ pub fn ethernet_transform_mode(
                 .peer_interfaces
                 .insert(ifname.to_string());
 
-            let ret = KI.set_uci_var(&filtered_ifname, "interface");
-            return_codes.push(ret);
-            let ret = KI.set_uci_var(&format!("{}.ifname", filtered_ifname), ifname);
-            return_codes.push(ret);
-            let ret = KI.set_uci_var(&format!("{}.proto", filtered_ifname), "static");
-            return_codes.push(ret);
+            transaction.set(&filtered_ifname, "interface");
+            transaction.set(&format!("{}.ifname", filtered_ifname), ifname);
+            transaction.set(&format!("{}.proto", filtered_ifname), "static");
         }
         InterfaceMode::Unknown => unimplemented!(),
     }
 
-    // check all of our return codes in order to handle any possible issue
-    let mut error_occured = false;
-    for ret in return_codes {
-        if ret.is_err() {
-            error_occured = true;
-        }
-    }
-    if error_occured {
-        let res = KI.uci_revert("network");
+    if let Some(e) = transaction.staging_error() {
+        let res = transaction.revert();
         *SETTING.get_network_mut() = old_network_settings;
-        bail!("Error running UCI commands! Revert attempted: {:?}", res);
+        bail!(
+            "Error running UCI commands! {:?} Revert attempted: {:?}",
+            e,
+            res
+        );
     }
 
-    KI.uci_commit(&"network")?;
-    KI.openwrt_reset_network()?;
+    // applying waits for the connectivity check so the request returns as soon as the
+    // changes are staged, the result only shows up in the logs
+    let ifname = ifname.to_string();
+    thread::spawn(move || {
+        // the first check runs once the changes are applied, only dashboard requests
+        // after that show the user can still reach us
+        let applied = Cell::new(None);
+        let res = transaction.apply(&["network"], INTERFACE_CHANGE_TIMEOUT, || {
+            let since = match applied.get() {
+                Some(since) => since,
+                None => {
+                    let now = Instant::now();
+                    applied.set(Some(now));
+                    now
+                }
+            };
+            dashboard_used_since(since)
+                && (neighbors_before == 0 || mesh_neighbor_count(&ifname) > 0)
+        });
+        match res {
+            Ok(()) => {
+                if let Err(e) = finish_interface_change() {
+                    error!("Failed to finish interface change {:?}", e);
+                }
+            }
+            Err(e) => {
+                // PeerListener picks mesh interfaces back up from the restored settings
+                error!("Interface change for {} rolled back {:?}", ifname, e);
+                *SETTING.get_network_mut() = old_network_settings;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Saves the settings matching a confirmed interface change and reboots into it
+fn finish_interface_change() -> Result<(), Error> {
     SETTING.write().unwrap().write(&ARGS.flag_config)?;
 
     // We edited disk contents, force global sync
@@ -303,6 +329,21 @@ pub fn ethernet_transform_mode(
     Ok(())
 }
 
+/// Counts the neighbors on mesh interfaces other than the one being changed
+fn mesh_neighbor_count(ifname: &str) -> usize {
+    let peer_interfaces = SETTING.get_network().peer_interfaces.clone();
+    match KI.get_neighbors() {
+        Ok(neighbors) => neighbors
+            .iter()
+            .filter(|(_, dev)| dev != ifname && peer_interfaces.contains(dev))
+            .count(),
+        Err(e) => {
+            warn!("Could not list neighbors {:?}", e);
+            0
+        }
+    }
+}
+
 /// Unlike physical ethernet interfaces you can run multiple SSID's on a single WIFI card
 /// so we don't provide options to 'change' wireless modes to match the users expectations
 /// instead we provide a toggle interface.
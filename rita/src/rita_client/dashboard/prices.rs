@@ -1,15 +1,32 @@
 use crate::rita_client::traffic_watcher::GetExitDestPrice;
 use crate::rita_client::traffic_watcher::TrafficWatcher;
+use crate::rita_common::babel_manager;
+use crate::rita_common::local_pricing::{get_last_decision, PricingDecision};
+use crate::rita_common::usage_tracker::average_hourly_usage;
+use crate::rita_common::usage_tracker::get_current_hour;
+use crate::rita_common::usage_tracker::GetUsage;
+use crate::rita_common::usage_tracker::UsageTracker;
+use crate::rita_common::usage_tracker::UsageType;
 use crate::ARGS;
 use crate::SETTING;
 use actix::SystemService;
+use actix_web::http::StatusCode;
 use actix_web::Path;
 use actix_web::{HttpRequest, HttpResponse, Json, Result};
+use babel_monitor::route_prices::get_route_prices;
+use babel_monitor::route_prices::RoutePrices;
 use failure::Error;
 use futures01::Future;
 use num256::Uint256;
+use settings::payment::AutoPricingEngine;
 use settings::FileWrite;
 use settings::RitaCommonSettings;
+use std::net::IpAddr;
+
+/// How far back to look when working out the recent usage rate, one week
+const USAGE_WINDOW_HOURS: u64 = 24 * 7;
+/// The average number of hours in a month
+const HOURS_PER_MONTH: u64 = 730;
 
 pub fn auto_pricing_status(_req: HttpRequest) -> Result<Json<bool>, Error> {
     debug!("Get Auto pricing enabled hit!");
@@ -28,6 +45,37 @@ pub fn set_auto_pricing(path: Path<bool>) -> Result<HttpResponse, Error> {
     Ok(HttpResponse::Ok().json(()))
 }
 
+pub fn get_pricing_engine(_req: HttpRequest) -> Result<Json<AutoPricingEngine>, Error> {
+    debug!("/auto_price/engine GET hit");
+    Ok(Json(SETTING.get_payment().local_pricing.engine))
+}
+
+pub fn set_pricing_engine(path: Path<String>) -> Result<HttpResponse, Error> {
+    debug!("/auto_price/engine/{{engine}} POST hit");
+    let engine = match path.into_inner().parse() {
+        Ok(engine) => engine,
+        Err(e) => {
+            return Ok(HttpResponse::new(StatusCode::BAD_REQUEST)
+                .into_builder()
+                .json(format!("{}", e)));
+        }
+    };
+    SETTING.get_payment_mut().local_pricing.engine = engine;
+
+    // try and save the config and fail if we can't
+    if let Err(e) = SETTING.write().unwrap().write(&ARGS.flag_config) {
+        return Err(e);
+    }
+    Ok(HttpResponse::Ok().json(()))
+}
+
+pub fn get_local_pricing_decision(
+    _req: HttpRequest,
+) -> Result<Json<Option<PricingDecision>>, Error> {
+    debug!("/auto_price/local GET hit");
+    Ok(Json(get_last_decision()))
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Prices {
     exit_dest_price: u128,
@@ -51,3 +99,44 @@ pub fn get_prices(_req: HttpRequest) -> Box<dyn Future<Item = Json<Prices>, Erro
     });
     Box::new(b)
 }
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RouteCostEstimate {
+    #[serde(flatten)]
+    routes: RoutePrices,
+    /// average bytes per hour of client traffic over the last week
+    hourly_usage: u64,
+    /// the cost in wei of a month of traffic at the recent usage rate over the
+    /// installed route, None if there is no installed route
+    estimated_monthly_cost: Option<u128>,
+}
+
+pub fn get_route_cost(
+    path: Path<IpAddr>,
+) -> Box<dyn Future<Item = Json<RouteCostEstimate>, Error = Error>> {
+    let destination = path.into_inner();
+    debug!("/prices/route/{} GET hit", destination);
+    let usage = UsageTracker::from_registry()
+        .send(GetUsage {
+            kind: UsageType::Client,
+        })
+        .from_err()
+        .and_then(|reply| reply);
+    Box::new(
+        babel_manager::get_routes()
+            .join(usage)
+            .and_then(move |(routes, usage)| {
+                let routes = get_route_prices(destination, &routes)?;
+                let hourly_usage =
+                    average_hourly_usage(&usage, get_current_hour()?, USAGE_WINDOW_HOURS);
+                let estimated_monthly_cost = routes.installed.as_ref().map(|route| {
+                    u128::from(hourly_usage) * u128::from(HOURS_PER_MONTH) * u128::from(route.price)
+                });
+                Ok(Json(RouteCostEstimate {
+                    routes,
+                    hourly_usage,
+                    estimated_monthly_cost,
+                }))
+            }),
+    )
+}
@@ -19,7 +19,9 @@
 use crate::rita_client::rita_loop::Tick;
 use crate::rita_client::rita_loop::CLIENT_LOOP_TIMEOUT;
 use crate::rita_client::traffic_watcher::{QueryExitDebts, TrafficWatcher};
+use crate::rita_common::babel_manager;
 use crate::rita_common::oracle::low_balance;
+use crate::rita_common::withdraw_policy::{mark_withdraws_notified, unnotified_withdraws};
 use crate::KI;
 use crate::SETTING;
 use ::actix::registry::SystemService;
@@ -31,9 +33,6 @@ use althea_types::ExitDetails;
 use althea_types::WgKey;
 use althea_types::{EncryptedExitClientIdentity, EncryptedExitState};
 use althea_types::{ExitClientIdentity, ExitState, ExitVerifMode};
-use babel_monitor::open_babel_stream;
-use babel_monitor::parse_routes;
-use babel_monitor::start_connection;
 use failure::Error;
 use futures01::future;
 use futures01::future::join_all;
@@ -56,7 +55,7 @@ fn linux_setup_exit_tunnel(
 ) -> Result<(), Error> {
     KI.update_settings_route(&mut SETTING.get_network_mut().default_route)?;
 
-    KI.setup_wg_if_named("wg_exit")?;
+    KI.create_wg_interface("wg_exit")?;
     KI.set_client_exit_tunnel_config(
         SocketAddr::new(current_exit.id.mesh_ip, general_details.wg_exit_port),
         current_exit.id.wg_public_key,
@@ -278,7 +277,13 @@ pub fn exit_setup_request(
         ExitVerifMode::Phone => {
             reg_details.phone_code = code;
         }
-        ExitVerifMode::Off => {}
+        ExitVerifMode::Approval | ExitVerifMode::Off => {}
+        ExitVerifMode::Unknown => {
+            return Box::new(future::err(format_err!(
+                "Exit {} uses a registration mode this version does not support",
+                exit
+            )))
+        }
     }
 
     let ident = ExitClientIdentity {
@@ -293,6 +298,7 @@ pub fn exit_setup_request(
         wg_port: SETTING.get_exit_client().wg_listen_port,
         reg_details,
         low_balance: None,
+        withdraw_held: None,
     };
 
     let endpoint = SocketAddr::new(exit_server, current_exit.registration_port);
@@ -337,6 +343,8 @@ fn exit_status_request(exit: String) -> impl Future<Item = (), Error = Error> {
     } else {
         false
     };
+    // held withdraws are always reported, they may be someone else spending our funds
+    let held_withdraws = unnotified_withdraws();
 
     let exit_server = current_exit.id.mesh_ip;
     let exit_pubkey = current_exit.id.wg_public_key;
@@ -352,6 +360,7 @@ fn exit_status_request(exit: String) -> impl Future<Item = (), Error = Error> {
         wg_port: SETTING.get_exit_client().wg_listen_port,
         reg_details: SETTING.get_exit_client().reg_details.clone().unwrap(),
         low_balance: Some(balance_notification),
+        withdraw_held: Some(!held_withdraws.is_empty()),
     };
 
     let endpoint = SocketAddr::new(exit_server, current_exit.registration_port);
@@ -364,6 +373,8 @@ fn exit_status_request(exit: String) -> impl Future<Item = (), Error = Error> {
 
     let r =
         send_exit_status_request(exit_pubkey, &endpoint, ident).and_then(move |exit_response| {
+            // the exit has our report, saving that takes the settings lock so it goes first
+            mark_withdraws_notified(&held_withdraws);
             let mut exits = SETTING.get_exits_mut();
 
             let current_exit = match exits.get_mut(&exit) {
@@ -487,29 +498,31 @@ impl Handler<Tick> for ExitManager {
 
                 // run billing at all times when an exit is setup
                 if signed_up_for_exit {
-                    let exit_price = general_details.exit_price;
+                    // a service plan assigned to us by the exit overrides the exit wide price
+                    let exit_price = match exit
+                        .info
+                        .our_details()
+                        .and_then(|details| details.service_plan.as_ref())
+                    {
+                        Some(plan) => plan.price_per_byte,
+                        None => general_details.exit_price,
+                    };
                     let exit_internal_addr = general_details.server_internal_ip;
                     let exit_port = exit.registration_port;
                     let exit_id = exit.id;
-                    let babel_port = SETTING.get_network().babel_port;
                     trace!("We are signed up for the selected exit!");
 
                     Arbiter::spawn(
-                        open_babel_stream(babel_port)
-                            .from_err()
-                            .and_then(move |stream| {
-                                start_connection(stream).and_then(move |stream| {
-                                    parse_routes(stream).and_then(move |routes| {
-                                        TrafficWatcher::from_registry().do_send(QueryExitDebts {
-                                            exit_id,
-                                            exit_price,
-                                            routes: routes.1,
-                                            exit_internal_addr,
-                                            exit_port,
-                                        });
-                                        Ok(())
-                                    })
-                                })
+                        babel_manager::get_routes()
+                            .and_then(move |routes| {
+                                TrafficWatcher::from_registry().do_send(QueryExitDebts {
+                                    exit_id,
+                                    exit_price,
+                                    routes,
+                                    exit_internal_addr,
+                                    exit_port,
+                                });
+                                Ok(())
                             })
                             .timeout(CLIENT_LOOP_TIMEOUT)
                             .then(|ret| {
@@ -551,7 +564,17 @@ impl Handler<Tick> for ExitManager {
                         },
                     )));
                 }
-                ExitState::Registered { .. } => {
+                // when waiting on the exit operator there's nothing for us to submit, so we
+                // poll until we are approved or denied
+                ExitState::Registered { .. }
+                | ExitState::Pending {
+                    general_details:
+                        ExitDetails {
+                            verif_mode: ExitVerifMode::Approval,
+                            ..
+                        },
+                    ..
+                } => {
                     futs.push(Box::new(exit_status_request(k.clone()).then(move |res| {
                         match res {
                             Ok(_) => {
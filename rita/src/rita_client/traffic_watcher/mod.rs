@@ -24,6 +24,9 @@
 use crate::rita_common::debt_keeper::{
     DebtKeeper, Traffic, TrafficReplace, TrafficUpdate, WgKeyInsensitiveTrafficUpdate,
 };
+use crate::rita_common::usage_tracker::PeerUsage;
+use crate::rita_common::usage_tracker::PeerUsageType;
+use crate::rita_common::usage_tracker::UpdatePeerUsage;
 use crate::rita_common::usage_tracker::UpdateUsage;
 use crate::rita_common::usage_tracker::UsageTracker;
 use crate::rita_common::usage_tracker::UsageType;
@@ -377,6 +380,15 @@ pub fn local_traffic_calculation(
             down: input,
             price: exit_dest_price as u32,
         });
+        UsageTracker::from_registry().do_send(UpdatePeerUsage {
+            usage: vec![PeerUsage {
+                kind: PeerUsageType::Destination,
+                id: *exit,
+                up: output,
+                down: input,
+                billed: owes_exit.into(),
+            }],
+        });
     } else {
         error!("no Exit bandwidth, no bill!");
     }
@@ -401,3 +413,72 @@ impl Handler<GetExitDestPrice> for TrafficWatcher {
         Ok(self.last_exit_dest_price)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rita_common::babel_manager::start_synced;
+    use crate::rita_common::babel_manager::wait_for_routes;
+    use crate::rita_common::babel_manager::GetRoutes;
+    use crate::FAKE_KERNEL;
+    use actix::System;
+    use althea_kernel_interface::KernelInterface;
+    use althea_kernel_interface::WgUsage;
+    use babel_monitor::fake::make_route;
+    use babel_monitor::fake::FakeBabel;
+    use clarity::Address;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_exit_billing_from_fake_babel() {
+        let exit = Identity::new(
+            "fd00::5".parse().unwrap(),
+            Address::from_str("ffffffffffffffffffffffffffffffffffffffff").unwrap(),
+            "8BeCExnthLe5ou0EYec5jNqJ/PduZ1x2o7lpXJOpgXk="
+                .parse()
+                .unwrap(),
+            None,
+        );
+        let babel = FakeBabel::start().unwrap();
+        babel.add_route(make_route(
+            "1",
+            "fd00::5/128".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+            "wg0",
+            100,
+        ));
+        FAKE_KERNEL.create_wg_interface("wg_exit").unwrap();
+        FAKE_KERNEL
+            .set_wg_usage(
+                "wg_exit",
+                exit.wg_public_key,
+                WgUsage {
+                    upload: 0,
+                    download: 0,
+                },
+            )
+            .unwrap();
+
+        // the routes the exit manager hands over with QueryExitDebts
+        let mut system = System::new("test_exit_billing_from_fake_babel");
+        let manager = start_synced(&mut system, babel.port());
+        let routes = system.block_on(manager.send(GetRoutes)).unwrap().unwrap();
+
+        // nothing has gone through the tunnel yet so nothing is owed, but the price is known
+        let mut watcher = TrafficWatcher::default();
+        assert_eq!(
+            local_traffic_calculation(&mut watcher, &exit, 50, routes).unwrap(),
+            0
+        );
+        let max_fee = SETTING.get_payment().max_fee;
+        assert_eq!(
+            watcher.last_exit_dest_price,
+            u128::from(100.min(max_fee)) + 50
+        );
+
+        // once Babel loses the route there's nothing to bill against
+        babel.remove_route("1");
+        let routes = wait_for_routes(&mut system, &manager, |routes| routes.is_empty());
+        assert!(local_traffic_calculation(&mut watcher, &exit, 50, routes).is_err());
+    }
+}
@@ -0,0 +1,502 @@
+//! BabelManager holds a single long lived connection to the Babel management socket. Instead of
+//! every caller opening a socket and running a full dump we put the connection into monitor mode,
+//! where Babel pushes every change to its route and neighbor tables as it happens, and keep a local
+//! copy of those tables up to date. Callers query the cached tables and send commands over the same
+//! connection, commands are answered in the order they where sent so responses can be matched up by
+//! keeping a queue of the callers waiting on them.
+//!
+//! If Babel restarts, sends a preamble we don't understand, or the connection drops for any other
+//! reason the tables are cleared, anyone waiting on a command gets an error, and we reconnect after
+//! a short delay. Updates Babel pushes while a command is running only change the tables, they are
+//! never part of the command's output.
+
+use crate::SETTING;
+use actix::fut::{ActorFuture, WrapFuture};
+use actix::io::{FramedWrite, WriteHandler};
+use actix::{
+    Actor, AsyncContext, Context, Handler, Message, ResponseFuture, Running, SpawnHandle,
+    StreamHandler, Supervised, SystemService,
+};
+use babel_monitor::open_babel_stream;
+use babel_monitor::tables::is_terminator;
+use babel_monitor::tables::is_update;
+use babel_monitor::tables::split_babel_stream;
+use babel_monitor::tables::BabelTables;
+use babel_monitor::BabelMonitorError;
+use babel_monitor::Neighbor as BabelNeighbor;
+use babel_monitor::Route as BabelRoute;
+use failure::Error;
+use futures01::sync::oneshot;
+use futures01::Future;
+use settings::RitaCommonSettings;
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+use tokio::io::WriteHalf;
+use tokio::net::TcpStream;
+use tokio_codec::LinesCodec;
+
+/// How long to wait before trying to connect to Babel again
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// How long to wait for Babel to answer a command before giving up on it
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Someone waiting on the output of a command, internal commands like the preamble
+/// and the initial monitor request have no one waiting on them
+struct PendingCommand {
+    command: String,
+    sender: Option<oneshot::Sender<Result<String, Error>>>,
+}
+
+pub struct BabelManager {
+    /// the port to connect to, if not the one in the settings
+    port: Option<u16>,
+    /// the stream of lines from the current connection
+    reader: Option<SpawnHandle>,
+    writer: Option<FramedWrite<WriteHalf<TcpStream>, LinesCodec>>,
+    /// commands we're waiting on responses for, oldest first
+    pending: VecDeque<PendingCommand>,
+    /// output collected for the command at the front of the queue
+    response: String,
+    tables: BabelTables,
+    /// true once the initial dump from the monitor command has been read, before that
+    /// the tables are incomplete
+    synced: bool,
+}
+
+impl Actor for BabelManager {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.connect(ctx);
+    }
+}
+
+impl Supervised for BabelManager {
+    fn restarting(&mut self, _ctx: &mut Context<BabelManager>) {
+        error!("BabelManager actor died! recovering!");
+        // the connection is gone, dropping the pending commands lets their callers know
+        *self = BabelManager {
+            port: self.port,
+            ..BabelManager::default()
+        };
+    }
+}
+
+impl SystemService for BabelManager {
+    fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        info!("Babel manager started");
+    }
+}
+
+impl Default for BabelManager {
+    fn default() -> BabelManager {
+        BabelManager {
+            port: None,
+            reader: None,
+            writer: None,
+            pending: VecDeque::new(),
+            response: String::new(),
+            tables: BabelTables::default(),
+            synced: false,
+        }
+    }
+}
+
+impl BabelManager {
+    /// A BabelManager that talks to Babel on the given port instead of the configured one
+    pub fn new(port: u16) -> BabelManager {
+        BabelManager {
+            port: Some(port),
+            ..BabelManager::default()
+        }
+    }
+
+    fn connect(&mut self, ctx: &mut Context<Self>) {
+        let babel_port = match self.port {
+            Some(port) => port,
+            None => SETTING.get_network().babel_port,
+        };
+        trace!("BabelManager connecting to Babel on port {}", babel_port);
+        ctx.spawn(
+            open_babel_stream(babel_port)
+                .into_actor(self)
+                .then(|res, act, ctx| {
+                    match res {
+                        Ok(stream) => {
+                            let (reader, writer) = split_babel_stream(stream);
+                            act.reader = Some(ctx.add_stream(reader));
+                            act.writer = Some(FramedWrite::new(writer, LinesCodec::new(), ctx));
+                            // Babel sends a preamble as soon as we connect, then we ask it
+                            // to dump everything and keep us informed of any changes
+                            act.pending.push_back(PendingCommand {
+                                command: "preamble".to_string(),
+                                sender: None,
+                            });
+                            act.send_command("monitor".to_string(), None);
+                        }
+                        Err(e) => {
+                            warn!("BabelManager failed to connect to Babel {:?}", e);
+                            ctx.run_later(RECONNECT_DELAY, |act, ctx| act.connect(ctx));
+                        }
+                    }
+                    actix::fut::ok(())
+                }),
+        );
+    }
+
+    /// Drops all the state associated with the connection and schedules a reconnect
+    fn disconnected(&mut self, ctx: &mut Context<Self>) {
+        warn!("BabelManager lost the connection to Babel, reconnecting");
+        // does nothing if the stream already ended, otherwise stops us reading from a
+        // connection we've given up on
+        if let Some(reader) = self.reader.take() {
+            ctx.cancel_future(reader);
+        }
+        self.writer = None;
+        self.synced = false;
+        self.tables.clear();
+        self.response.clear();
+        for command in self.pending.drain(..) {
+            if let Some(sender) = command.sender {
+                let _ = sender.send(Err(format_err!(
+                    "Lost connection to Babel while running {}",
+                    command.command
+                )));
+            }
+        }
+        ctx.run_later(RECONNECT_DELAY, |act, ctx| act.connect(ctx));
+    }
+
+    fn send_command(
+        &mut self,
+        command: String,
+        sender: Option<oneshot::Sender<Result<String, Error>>>,
+    ) {
+        match self.writer {
+            Some(ref mut writer) => {
+                trace!("Running babel command {}", command);
+                writer.write(command.clone());
+                self.pending.push_back(PendingCommand { command, sender });
+            }
+            None => {
+                if let Some(sender) = sender {
+                    let _ = sender.send(Err(format_err!("Not connected to Babel")));
+                }
+            }
+        }
+    }
+
+    /// Called when Babel finishes answering the command at the front of the queue
+    fn command_finished(&mut self, terminator: &str, ctx: &mut Context<Self>) {
+        let output = std::mem::replace(&mut self.response, String::new());
+        let command = match self.pending.pop_front() {
+            Some(command) => command,
+            None => {
+                warn!(
+                    "Babel sent {} when we where not waiting on a command",
+                    terminator
+                );
+                return;
+            }
+        };
+        let result = if terminator == "ok" {
+            Ok(output)
+        } else {
+            Err(BabelMonitorError::CommandFailed(command.command.clone(), output).into())
+        };
+
+        match (command.command.as_str(), command.sender) {
+            ("preamble", None) => {
+                let valid = match result {
+                    Ok(ref preamble) => preamble.contains("ALTHEA 0.1"),
+                    Err(_) => false,
+                };
+                if valid {
+                    trace!("Attached OK to Babel with preamble: {:?}", result);
+                } else {
+                    error!("Invalid Babel preamble {:?}", result);
+                    self.disconnected(ctx);
+                }
+            }
+            ("monitor", None) => match result {
+                Ok(_) => {
+                    info!("BabelManager synced with Babel");
+                    self.synced = true;
+                }
+                Err(e) => error!("Babel refused to enter monitor mode {:?}", e),
+            },
+            (_, Some(sender)) => {
+                let _ = sender.send(result);
+            }
+            (_, None) => {}
+        }
+    }
+
+    fn check_synced(&self) -> Result<(), Error> {
+        if self.synced {
+            Ok(())
+        } else {
+            bail!("Babel tables are not synced yet")
+        }
+    }
+}
+
+impl StreamHandler<String, io::Error> for BabelManager {
+    fn handle(&mut self, line: String, ctx: &mut Context<Self>) {
+        if let Err(e) = self.tables.apply_line(&line) {
+            warn!("Failed to parse babel line {} with {:?}", line, e);
+        }
+        let trimmed = line.trim();
+        if is_terminator(trimmed) {
+            self.command_finished(trimmed, ctx);
+        } else if !is_update(trimmed) && !self.pending.is_empty() {
+            self.response.push_str(&line);
+            self.response.push('\n');
+        }
+    }
+
+    fn error(&mut self, err: io::Error, _ctx: &mut Context<Self>) -> Running {
+        error!("Error reading from Babel {:?}", err);
+        Running::Stop
+    }
+
+    fn finished(&mut self, ctx: &mut Context<Self>) {
+        self.disconnected(ctx);
+    }
+}
+
+impl WriteHandler<io::Error> for BabelManager {
+    fn error(&mut self, err: io::Error, _ctx: &mut Context<Self>) -> Running {
+        error!("Error writing to Babel {:?}", err);
+        // the read side will notice the connection is gone and reconnect
+        Running::Continue
+    }
+
+    fn finished(&mut self, _ctx: &mut Context<Self>) {}
+}
+
+/// Returns all routes in the Babel route table
+pub struct GetRoutes;
+
+impl Message for GetRoutes {
+    type Result = Result<Vec<BabelRoute>, Error>;
+}
+
+impl Handler<GetRoutes> for BabelManager {
+    type Result = Result<Vec<BabelRoute>, Error>;
+    fn handle(&mut self, _: GetRoutes, _ctx: &mut Context<Self>) -> Self::Result {
+        self.check_synced()?;
+        Ok(self.tables.routes())
+    }
+}
+
+/// Returns all of Babel's neighbors, not to be confused with tunnel_manager::GetNeighbors
+/// which returns Rita's neighbors
+pub struct GetBabelNeighbors;
+
+impl Message for GetBabelNeighbors {
+    type Result = Result<Vec<BabelNeighbor>, Error>;
+}
+
+impl Handler<GetBabelNeighbors> for BabelManager {
+    type Result = Result<Vec<BabelNeighbor>, Error>;
+    fn handle(&mut self, _: GetBabelNeighbors, _ctx: &mut Context<Self>) -> Self::Result {
+        self.check_synced()?;
+        Ok(self.tables.neighbors())
+    }
+}
+
+/// Runs a command on the shared Babel connection and returns its output
+pub struct RunCommand(pub String);
+
+impl Message for RunCommand {
+    type Result = Result<String, Error>;
+}
+
+impl Handler<RunCommand> for BabelManager {
+    type Result = ResponseFuture<String, Error>;
+    fn handle(&mut self, msg: RunCommand, _ctx: &mut Context<Self>) -> Self::Result {
+        let (sender, receiver) = oneshot::channel();
+        self.send_command(msg.0, Some(sender));
+        Box::new(
+            receiver
+                .map_err(|_| format_err!("Babel command was dropped"))
+                .and_then(|res| res),
+        )
+    }
+}
+
+/// Flattens the mailbox and handler errors of a BabelManager request
+fn flatten<T>(res: Result<Result<T, Error>, actix::MailboxError>) -> Result<T, Error> {
+    match res {
+        Ok(res) => res,
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn get_routes() -> impl Future<Item = Vec<BabelRoute>, Error = Error> {
+    BabelManager::from_registry().send(GetRoutes).then(flatten)
+}
+
+pub fn get_neighbors() -> impl Future<Item = Vec<BabelNeighbor>, Error = Error> {
+    BabelManager::from_registry()
+        .send(GetBabelNeighbors)
+        .then(flatten)
+}
+
+pub fn run_command(command: String) -> impl Future<Item = String, Error = Error> {
+    BabelManager::from_registry()
+        .send(RunCommand(command))
+        .timeout(COMMAND_TIMEOUT)
+        .then(flatten)
+}
+
+pub fn set_local_fee(new_fee: u32) -> impl Future<Item = (), Error = Error> {
+    run_command(format!("fee {}", new_fee)).map(|_| ())
+}
+
+pub fn set_metric_factor(new_factor: u32) -> impl Future<Item = (), Error = Error> {
+    run_command(format!("metric-factor {}", new_factor)).map(|_| ())
+}
+
+/// Asks Babel to start running on an interface
+pub fn monitor(iface: &str) -> impl Future<Item = (), Error = Error> {
+    let iface_name = iface.to_string();
+    run_command(format!(
+        "interface {} max-rtt-penalty 500 enable-timestamps true",
+        iface
+    ))
+    .map(move |_| trace!("Babel started monitoring: {}", iface_name))
+}
+
+/// Asks Babel to stop running on an interface
+pub fn unmonitor(iface: &str) -> impl Future<Item = (), Error = Error> {
+    let iface_name = iface.to_string();
+    run_command(format!("flush interface {}", iface))
+        .map(move |_| trace!("Babel stopped monitoring: {}", iface_name))
+}
+
+/// Starts a BabelManager connected to a fake Babel on the given port and waits for it to sync
+#[cfg(test)]
+pub fn start_synced(system: &mut actix::SystemRunner, port: u16) -> actix::Addr<BabelManager> {
+    let manager = BabelManager::new(port).start();
+    wait_for_routes(system, &manager, |_| true);
+    manager
+}
+
+/// Polls the manager's route table until check passes, so tests can wait for the updates
+/// the fake Babel pushes
+#[cfg(test)]
+pub fn wait_for_routes(
+    system: &mut actix::SystemRunner,
+    manager: &actix::Addr<BabelManager>,
+    check: impl Fn(&[BabelRoute]) -> bool,
+) -> Vec<BabelRoute> {
+    use std::time::Instant;
+    use tokio::timer::Delay;
+
+    for _ in 0..200 {
+        let delay = Delay::new(Instant::now() + Duration::from_millis(10));
+        let request = manager.clone();
+        if let Ok(Ok(routes)) = system.block_on(delay.then(move |_| request.send(GetRoutes))) {
+            if check(&routes) {
+                return routes;
+            }
+        }
+    }
+    panic!("BabelManager never got the expected routes from the fake Babel");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::System;
+    use babel_monitor::fake::make_route;
+    use babel_monitor::fake::FakeBabel;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
+    use tokio::timer::Delay;
+
+    #[test]
+    fn test_commands_and_updates() {
+        let babel = FakeBabel::start().unwrap();
+        let mut system = System::new("test_commands_and_updates");
+        let manager = start_synced(&mut system, babel.port());
+        assert!(system
+            .block_on(manager.send(GetRoutes))
+            .unwrap()
+            .unwrap()
+            .is_empty());
+
+        // Babel pushes the new interface to us before it answers the command
+        let output = system
+            .block_on(manager.send(RunCommand(
+                "interface wg0 max-rtt-penalty 500 enable-timestamps true".to_string(),
+            )))
+            .unwrap()
+            .unwrap();
+        assert_eq!(output, "");
+        assert_eq!(babel.interfaces(), vec!["wg0"]);
+
+        let output = system
+            .block_on(manager.send(RunCommand("fee 25".to_string())))
+            .unwrap()
+            .unwrap();
+        assert_eq!(output, "");
+        assert_eq!(babel.local_fee(), 25);
+        assert!(system
+            .block_on(manager.send(RunCommand("bogus".to_string())))
+            .unwrap()
+            .is_err());
+
+        babel.add_route(make_route(
+            "1",
+            "fd00::1/128".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+            "wg0",
+            100,
+        ));
+        let routes = wait_for_routes(&mut system, &manager, |routes| !routes.is_empty());
+        assert_eq!(routes[0].price, 100);
+
+        // flushing the interface takes its routes with it, the updates don't end up in the output
+        let output = system
+            .block_on(manager.send(RunCommand("flush interface wg0".to_string())))
+            .unwrap()
+            .unwrap();
+        assert_eq!(output, "");
+        wait_for_routes(&mut system, &manager, |routes| routes.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_preamble() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        thread::spawn(move || {
+            // kept open so that only the preamble can be the reason to reconnect
+            let mut streams = Vec::new();
+            for stream in listener.incoming() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                if let Ok(mut stream) = stream {
+                    let _ = stream.write_all(b"NOT BABEL\nok\n");
+                    streams.push(stream);
+                }
+            }
+        });
+
+        let mut system = System::new("test_invalid_preamble");
+        let manager = BabelManager::new(port).start();
+        let wait = RECONNECT_DELAY + RECONNECT_DELAY / 2;
+        system.block_on(Delay::new(Instant::now() + wait)).unwrap();
+        // the first connection and one retry, not a retry as fast as we can connect
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert!(system.block_on(manager.send(GetRoutes)).unwrap().is_err());
+    }
+}
@@ -6,6 +6,7 @@
 //! the DAO fee amount and preventing the router from drastically making a large payment
 
 use crate::rita_common::payment_controller::TRANSACTION_SUBMISSON_TIMEOUT;
+use crate::rita_common::payment_token::payment_transaction;
 use crate::rita_common::rita_loop::get_web3_server;
 use crate::rita_common::simulated_txfee_manager::AddTxToTotal;
 use crate::rita_common::simulated_txfee_manager::SimulatedTxFeeManager;
@@ -15,7 +16,6 @@ use crate::SETTING;
 use ::actix::{Actor, Arbiter, Context, Handler, Message, Supervised, SystemService};
 use althea_types::Identity;
 use althea_types::PaymentTx;
-use clarity::Transaction;
 use futures01::future::Future;
 use num256::Int256;
 use num_traits::Signed;
@@ -93,6 +93,7 @@ impl Handler<Tick> for DAOManager {
         let should_pay =
             (Int256::from(self.last_payment_time.elapsed().as_secs()) * dao_fee) > pay_threshold;
         let net_version = payment_settings.net_version;
+        let payment_token = payment_settings.payment_token.clone();
         drop(payment_settings);
         trace!("We should pay the subnet dao {}", should_pay);
         trace!("We have a dao to pay {}", we_have_a_dao);
@@ -120,15 +121,13 @@ impl Handler<Tick> for DAOManager {
                 let full_node = get_web3_server();
                 let web3 = Web3::new(&full_node, TRANSACTION_SUBMISSON_TIMEOUT);
 
-                let tx = Transaction {
-                    nonce: nonce.clone(),
-                    gas_price: gas_price.clone(),
-                    gas_limit: "21000".parse().unwrap(),
-                    to: address,
-                    value: amount_to_pay.clone(),
-                    data: Vec::new(),
-                    signature: None,
-                };
+                let tx = payment_transaction(
+                    &payment_token,
+                    address,
+                    amount_to_pay.clone(),
+                    nonce.clone(),
+                    gas_price.clone(),
+                );
                 let transaction_signed = tx.sign(
                     &eth_private_key.expect("No private key configured!"),
                     net_version,
@@ -1,5 +1,11 @@
+use crate::rita_common::oracle::get_rejected_updates;
+use crate::rita_common::oracle::history::{
+    get_history, get_pending, rollback, OracleHistoryEntry, PendingOracleUpdate,
+};
+use crate::rita_common::oracle::RejectedOracleUpdate;
 use crate::ARGS;
 use crate::SETTING;
+use actix_web::http::StatusCode;
 use actix_web::Path;
 use actix_web::{HttpRequest, HttpResponse, Json, Result};
 use clarity::Address;
@@ -63,6 +69,60 @@ pub fn remove_from_dao_list(path: Path<Address>) -> Result<Json<()>, Error> {
     Ok(Json(()))
 }
 
+/// The oracle updates that were recently rejected, newest first
+pub fn get_rejected_oracle_updates(
+    _req: HttpRequest,
+) -> Result<Json<Vec<RejectedOracleUpdate>>, Error> {
+    trace!("get rejected oracle updates: Hit");
+    Ok(Json(get_rejected_updates()))
+}
+
+/// The oracle updates that were applied, newest first
+pub fn get_oracle_history(_req: HttpRequest) -> Result<Json<Vec<OracleHistoryEntry>>, Error> {
+    trace!("get oracle history: Hit");
+    Ok(Json(get_history()))
+}
+
+/// The update the oracle would have applied if dry run mode wasn't enabled
+pub fn get_pending_oracle_update(
+    _req: HttpRequest,
+) -> Result<Json<Option<PendingOracleUpdate>>, Error> {
+    trace!("get pending oracle update: Hit");
+    Ok(Json(get_pending()))
+}
+
+pub fn set_oracle_dry_run(path: Path<bool>) -> Result<Json<()>, Error> {
+    let enabled = path.into_inner();
+    debug!("/oracle/dry_run/{} POST hit", enabled);
+    SETTING.get_dao_mut().oracle_dry_run = enabled;
+
+    // try and save the config and fail if we can't
+    if let Err(e) = SETTING.write().unwrap().write(&ARGS.flag_config) {
+        return Err(e);
+    }
+    Ok(Json(()))
+}
+
+/// Undoes the settings changes made by the oracle update with the given id
+pub fn rollback_oracle_update(path: Path<u64>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    debug!("/oracle/rollback/{} POST hit", id);
+    let rollback_id = match rollback(id) {
+        Ok(rollback_id) => rollback_id,
+        Err(e) => {
+            return Ok(HttpResponse::new(StatusCode::BAD_REQUEST)
+                .into_builder()
+                .json(format!("{}", e)))
+        }
+    };
+
+    // try and save the config and fail if we can't
+    if let Err(e) = SETTING.write().unwrap().write(&ARGS.flag_config) {
+        return Err(e);
+    }
+    Ok(HttpResponse::Ok().json(rollback_id))
+}
+
 pub fn get_dao_fee(_req: HttpRequest) -> Result<HttpResponse, Error> {
     debug!("/dao_fee GET hit");
     let mut ret = HashMap::new();
@@ -13,6 +13,7 @@ pub mod development;
 pub mod nickname;
 pub mod own_info;
 pub mod settings;
+pub mod statements;
 pub mod token_bridge;
 pub mod usage;
 pub mod wallet;
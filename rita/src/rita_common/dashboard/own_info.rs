@@ -1,4 +1,5 @@
-use crate::rita_common::oracle::low_balance;
+use crate::rita_common::oracle::{low_balance, low_gas_balance};
+use crate::rita_common::withdraw_policy::pending_withdraws;
 use crate::SETTING;
 use actix_web::{HttpRequest, Json};
 use clarity::Address;
@@ -12,11 +13,15 @@ pub static READABLE_VERSION: &str = "Beta 12 RC1";
 pub struct OwnInfo {
     pub address: Address,
     pub balance: Uint256,
+    pub gas_balance: Uint256,
     pub local_fee: u32,
     pub metric_factor: u32,
     pub pay_threshold: Int256,
     pub close_threshold: Int256,
     pub low_balance: bool,
+    pub low_gas_balance: bool,
+    /// withdraws held by the withdraw policy, see /withdraw_queue
+    pub held_withdraws: usize,
     pub device: Option<String>,
     pub rita_version: String,
     pub version: String,
@@ -26,9 +31,12 @@ pub struct OwnInfo {
 
 pub fn get_own_info(_req: HttpRequest) -> Result<Json<OwnInfo>, Error> {
     debug!("Get own info endpoint hit!");
+    // the queue saves itself under the settings lock, so it's read before we take it
+    let held_withdraws = pending_withdraws().len();
     let payment_settings = SETTING.get_payment();
     let eth_address = payment_settings.eth_address.unwrap();
     let balance = payment_settings.balance.clone();
+    let gas_balance = payment_settings.gas_balance.clone();
     let pay_threshold = payment_settings.pay_threshold.clone();
     let close_threshold = payment_settings.close_threshold.clone();
     let local_fee = payment_settings.local_fee;
@@ -42,11 +50,14 @@ pub fn get_own_info(_req: HttpRequest) -> Result<Json<OwnInfo>, Error> {
     let reply = OwnInfo {
         address: eth_address,
         balance,
+        gas_balance,
         local_fee,
         metric_factor,
         pay_threshold,
         close_threshold,
         low_balance: low_balance(),
+        low_gas_balance: low_gas_balance(),
+        held_withdraws,
         device,
         rita_version: env!("CARGO_PKG_VERSION").to_string(),
         version: READABLE_VERSION.to_string(),
@@ -1,4 +1,5 @@
 use crate::rita_common::network_endpoints::JsonStatusResponse;
+use crate::rita_common::withdraw_policy::check_settings_change;
 use crate::SETTING;
 use ::actix_web::{HttpRequest, Json, Result};
 use ::settings::RitaCommonSettings;
@@ -14,7 +15,11 @@ pub fn set_settings(
     new_settings: Json<serde_json::Value>,
 ) -> Result<Json<JsonStatusResponse>, Error> {
     debug!("Set settings endpoint hit!");
-    SETTING.merge(new_settings.into_inner())?;
+    let new_settings = new_settings.into_inner();
+    if let Err(e) = check_settings_change(&new_settings) {
+        return JsonStatusResponse::new(Err(e));
+    }
+    SETTING.merge(new_settings)?;
 
     JsonStatusResponse::new(Ok("New settings applied".to_string()))
 }
@@ -0,0 +1,60 @@
+use crate::rita_common::usage_tracker::statements::month_bounds;
+use crate::rita_common::usage_tracker::statements::statement_to_csv;
+use crate::rita_common::usage_tracker::statements::GetStatement;
+use crate::rita_common::usage_tracker::UsageTracker;
+use ::actix::registry::SystemService;
+use ::actix_web::http::{header, StatusCode};
+use ::actix_web::{AsyncResponder, HttpResponse, Path};
+use failure::Error;
+use futures01::{future, Future};
+use std::boxed::Box;
+
+pub fn get_statement(
+    path: Path<(i64, u32)>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let (year, month) = path.into_inner();
+    trace!("/statements/{}/{} hit", year, month);
+    if let Err(e) = month_bounds(year, month) {
+        return bad_request(e);
+    }
+    UsageTracker::from_registry()
+        .send(GetStatement { year, month })
+        .from_err()
+        .and_then(|reply| Ok(HttpResponse::Ok().json(reply?)))
+        .responder()
+}
+
+pub fn get_statement_csv(
+    path: Path<(i64, u32)>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let (year, month) = path.into_inner();
+    trace!("/statements/{}/{}/csv hit", year, month);
+    if let Err(e) = month_bounds(year, month) {
+        return bad_request(e);
+    }
+    UsageTracker::from_registry()
+        .send(GetStatement { year, month })
+        .from_err()
+        .and_then(move |reply| {
+            let statement = reply?;
+            Ok(HttpResponse::Ok()
+                .content_type("text/csv")
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"statement-{}-{:02}.csv\"",
+                        year, month
+                    ),
+                )
+                .body(statement_to_csv(&statement)))
+        })
+        .responder()
+}
+
+fn bad_request(e: Error) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    Box::new(future::ok(
+        HttpResponse::new(StatusCode::BAD_REQUEST)
+            .into_builder()
+            .json(format!("{}", e)),
+    ))
+}
@@ -1,9 +1,23 @@
+use crate::rita_common::usage_tracker::get_current_hour;
+use crate::rita_common::usage_tracker::query_payments;
+use crate::rita_common::usage_tracker::query_usage;
+use crate::rita_common::usage_tracker::Counterparty;
 use crate::rita_common::usage_tracker::GetPayments;
+use crate::rita_common::usage_tracker::GetTopPeers;
+use crate::rita_common::usage_tracker::GetUsage;
 use crate::rita_common::usage_tracker::PaymentHour;
+use crate::rita_common::usage_tracker::PaymentQuery;
+use crate::rita_common::usage_tracker::PeerUsageType;
+use crate::rita_common::usage_tracker::TopPeersQuery;
+use crate::rita_common::usage_tracker::UsageBucket;
+use crate::rita_common::usage_tracker::UsageQuery;
 use crate::rita_common::usage_tracker::UsageTracker;
+use crate::rita_common::usage_tracker::UsageType;
 use ::actix::registry::SystemService;
-use ::actix_web::{AsyncResponder, HttpRequest, Json};
+use ::actix_web::http::StatusCode;
+use ::actix_web::{AsyncResponder, HttpRequest, HttpResponse, Json, Path, Query};
 use failure::Error;
+use futures01::future;
 use futures01::Future;
 use std::boxed::Box;
 use std::collections::VecDeque;
@@ -18,3 +32,110 @@ pub fn get_payments(
         .and_then(|reply| Ok(Json(reply?)))
         .responder()
 }
+
+fn bad_request(message: String) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    Box::new(future::ok(
+        HttpResponse::new(StatusCode::BAD_REQUEST)
+            .into_builder()
+            .json(message),
+    ))
+}
+
+/// The end of the current hour in seconds since the unix epoch, the default end of a query
+fn default_query_end() -> Result<u64, Error> {
+    Ok((get_current_hour()? + 1) * 3600)
+}
+
+pub fn query_usage_history(
+    req: (Path<String>, Query<UsageQuery>),
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let (kind, query) = req;
+    let query = query.into_inner();
+    trace!("/usage/{}/query hit with {:?}", kind, query);
+    let kind = match kind.as_str() {
+        "client" => UsageType::Client,
+        "relay" => UsageType::Relay,
+        "exit" => UsageType::Exit,
+        _ => return bad_request(format!("Unknown usage type {}", kind)),
+    };
+    Box::new(
+        UsageTracker::from_registry()
+            .send(GetUsage { kind })
+            .from_err()
+            .and_then(move |reply| {
+                let history = reply?;
+                let end = match query.end {
+                    Some(end) => end,
+                    None => default_query_end()?,
+                };
+                let buckets: Vec<UsageBucket> = query_usage(
+                    &history,
+                    query.start.unwrap_or(0),
+                    end,
+                    query.resolution,
+                    query.aggregation,
+                );
+                Ok(HttpResponse::Ok().json(buckets))
+            }),
+    )
+}
+
+pub fn query_payment_history(
+    query: Query<PaymentQuery>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let query = query.into_inner();
+    trace!("/usage/payments/query hit with {:?}", query);
+    let counterparty: Option<Counterparty> = match query.counterparty {
+        Some(ref counterparty) => match counterparty.parse() {
+            Ok(counterparty) => Some(counterparty),
+            Err(e) => return bad_request(format!("{}", e)),
+        },
+        None => None,
+    };
+    Box::new(
+        UsageTracker::from_registry()
+            .send(GetPayments {})
+            .from_err()
+            .and_then(move |reply| {
+                let payments = reply?;
+                let end = match query.end {
+                    Some(end) => end,
+                    None => default_query_end()?,
+                };
+                let payments =
+                    query_payments(&payments, query.start.unwrap_or(0), end, counterparty);
+                Ok(HttpResponse::Ok().json(payments))
+            }),
+    )
+}
+
+pub fn get_top_peers(
+    req: (Path<String>, Query<TopPeersQuery>),
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let (kind, query) = req;
+    let (kind, query) = (kind.into_inner(), query.into_inner());
+    trace!("/usage/top/{} hit with {:?}", kind, query);
+    let kind: PeerUsageType = match kind.parse() {
+        Ok(kind) => kind,
+        Err(e) => return bad_request(format!("{}", e)),
+    };
+    let end = match query.end {
+        Some(end) => end,
+        None => match default_query_end() {
+            Ok(end) => end,
+            Err(e) => return Box::new(future::err(e)),
+        },
+    };
+    Box::new(
+        UsageTracker::from_registry()
+            .send(GetTopPeers {
+                kind,
+                start: query.start.unwrap_or(0),
+                end,
+                limit: query.limit,
+                by: query.by,
+            })
+            .from_err()
+            .and_then(|reply| Ok(HttpResponse::Ok().json(reply?))),
+    )
+}
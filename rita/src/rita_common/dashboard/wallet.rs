@@ -1,29 +1,30 @@
-use crate::rita_common::oracle::trigger_update_nonce;
 use crate::rita_common::oracle::Oracle;
 use crate::rita_common::oracle::ZeroWindowStart;
-use crate::rita_common::rita_loop::get_web3_server;
+use crate::rita_common::payment_controller::send_withdraw;
+use crate::rita_common::token_bridge::dai_equal;
 use crate::rita_common::token_bridge::eth_equal;
 use crate::rita_common::token_bridge::GetBridge;
 use crate::rita_common::token_bridge::TokenBridge;
 use crate::rita_common::token_bridge::Withdraw;
 use crate::rita_common::token_bridge::DAI_WEI_CENT;
 use crate::rita_common::token_bridge::ETH_TRANSFER_TIMEOUT;
+use crate::rita_common::withdraw_policy::{
+    approve_withdraw, cancel_withdraw, finish_withdraw, hold_withdraw, pending_withdraws,
+    withdraw_all_amount, Checked, PendingWithdraw, WithdrawKind,
+};
 use crate::SETTING;
 use ::actix::SystemService;
 use ::actix_web::http::StatusCode;
 use ::actix_web::HttpResponse;
 use ::actix_web::Path;
+use ::actix_web::{HttpRequest, Json};
 use ::settings::RitaCommonSettings;
 use althea_types::SystemChain;
-use clarity::{Address, Transaction};
+use clarity::Address;
 use failure::Error;
 use futures01::{future, Future};
 use num256::Uint256;
 use std::boxed::Box;
-use std::time::Duration;
-use web30::client::Web3;
-
-pub const WITHDRAW_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub fn withdraw(
     path: Path<(Address, Uint256)>,
@@ -34,9 +35,24 @@ pub fn withdraw(
     let payment_settings = SETTING.get_payment();
     let system_chain = payment_settings.system_chain;
     let withdraw_chain = payment_settings.withdraw_chain;
+    let payment_token = payment_settings.payment_token.clone();
     drop(payment_settings);
 
-    match (system_chain, withdraw_chain) {
+    if payment_token.is_some() && system_chain != withdraw_chain {
+        return token_bridge_unsupported(system_chain, withdraw_chain);
+    }
+
+    let id = match hold_withdraw(
+        address,
+        amount.clone(),
+        amount.clone(),
+        WithdrawKind::Amount,
+    ) {
+        Checked::Send(id) => id,
+        Checked::Held(pending) => return held_withdraw(pending),
+    };
+
+    let res = match (system_chain, withdraw_chain) {
         (SystemChain::Ethereum, SystemChain::Ethereum) => eth_compatable_withdraw(address, amount),
         (SystemChain::Rinkeby, SystemChain::Rinkeby) => eth_compatable_withdraw(address, amount),
         (SystemChain::Xdai, SystemChain::Xdai) => eth_compatable_withdraw(address, amount),
@@ -49,7 +65,8 @@ pub fn withdraw(
                     system_chain, withdraw_chain
                 )),
         )),
-    }
+    };
+    finish_checked_withdraw(id, res)
 }
 
 pub fn withdraw_all(path: Path<Address>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
@@ -58,25 +75,23 @@ pub fn withdraw_all(path: Path<Address>) -> Box<dyn Future<Item = HttpResponse,
     let payment_settings = SETTING.get_payment();
     let system_chain = payment_settings.system_chain;
     let withdraw_chain = payment_settings.withdraw_chain;
-    let mut gas_price = payment_settings.gas_price.clone();
-    let balance = payment_settings.balance.clone();
+    let payment_token = payment_settings.payment_token.clone();
     drop(payment_settings);
 
+    if payment_token.is_some() && system_chain != withdraw_chain {
+        return token_bridge_unsupported(system_chain, withdraw_chain);
+    }
+
     Oracle::from_registry().do_send(ZeroWindowStart());
 
-    let tx_gas: Uint256 =
-        if (system_chain, withdraw_chain) == (SystemChain::Xdai, SystemChain::Ethereum) {
-            // this is the hardcoded gas price over in token bridge so we have to use it
-            gas_price = 10_000_000_000u128.into();
-            // this is a contract call
-            80000u32.into()
-        } else {
-            21000u32.into()
-        };
+    let amount = withdraw_all_amount();
 
-    let tx_cost = gas_price * tx_gas;
-    let amount = balance - tx_cost;
-    match (system_chain, withdraw_chain) {
+    let id = match hold_withdraw(address, amount.clone(), amount.clone(), WithdrawKind::All) {
+        Checked::Send(id) => id,
+        Checked::Held(pending) => return held_withdraw(pending),
+    };
+
+    let res = match (system_chain, withdraw_chain) {
         (SystemChain::Ethereum, SystemChain::Ethereum) => eth_compatable_withdraw(address, amount),
         (SystemChain::Rinkeby, SystemChain::Rinkeby) => eth_compatable_withdraw(address, amount),
         (SystemChain::Xdai, SystemChain::Xdai) => eth_compatable_withdraw(address, amount),
@@ -89,7 +104,8 @@ pub fn withdraw_all(path: Path<Address>) -> Box<dyn Future<Item = HttpResponse,
                     system_chain, withdraw_chain
                 )),
         )),
-    }
+    };
+    finish_checked_withdraw(id, res)
 }
 
 pub fn withdraw_eth(
@@ -100,6 +116,7 @@ pub fn withdraw_eth(
     debug!("/withdraw_eth/{:#x}/{} hit", to, withdraw_amount);
     let payment_settings = SETTING.get_payment();
     let our_address = payment_settings.eth_address.unwrap();
+    let system_chain = payment_settings.system_chain;
     drop(payment_settings);
 
     Box::new(
@@ -133,22 +150,42 @@ pub fn withdraw_eth(
                                     as Box<dyn Future<Item = HttpResponse, Error = Error>>;
                             }
                             let (our_eth_balance, wei_per_cent) = res.unwrap();
-                            let reserve_amount_eth = eth_equal(reserve_amount, wei_per_cent);
+                            let reserve_amount_eth =
+                                eth_equal(reserve_amount, wei_per_cent.clone());
                             if our_eth_balance - reserve_amount_eth > withdraw_amount {
-                                Box::new(
-                                    bridge
-                                        .eth_transfer(to, withdraw_amount, ETH_TRANSFER_TIMEOUT)
-                                        .then(|res| {
-                                            if let Err(e) = res {
-                                                Ok(HttpResponse::new(
-                                                    StatusCode::from_u16(500u16).unwrap(),
-                                                )
-                                                .into_builder()
-                                                .json(format!("Transfer error {:?}", e)))
-                                            } else {
-                                                Ok(HttpResponse::Ok().json("Success!".to_string()))
-                                            }
-                                        }),
+                                // the policy is in the system chain's coin, on xDai that's dai
+                                let value = if system_chain == SystemChain::Xdai {
+                                    dai_equal(withdraw_amount.clone(), wei_per_cent)
+                                } else {
+                                    withdraw_amount.clone()
+                                };
+                                let id = match hold_withdraw(
+                                    to,
+                                    withdraw_amount.clone(),
+                                    value,
+                                    WithdrawKind::Eth,
+                                ) {
+                                    Checked::Send(id) => id,
+                                    Checked::Held(pending) => return held_withdraw(pending),
+                                };
+                                finish_checked_withdraw(
+                                    id,
+                                    Box::new(
+                                        bridge
+                                            .eth_transfer(to, withdraw_amount, ETH_TRANSFER_TIMEOUT)
+                                            .then(|res| {
+                                                if let Err(e) = res {
+                                                    Ok(HttpResponse::new(
+                                                        StatusCode::from_u16(500u16).unwrap(),
+                                                    )
+                                                    .into_builder()
+                                                    .json(format!("Transfer error {:?}", e)))
+                                                } else {
+                                                    Ok(HttpResponse::Ok()
+                                                        .json("Success!".to_string()))
+                                                }
+                                            }),
+                                    ),
                                 )
                             } else {
                                 Box::new(future::ok(
@@ -163,76 +200,107 @@ pub fn withdraw_eth(
     )
 }
 
-/// Withdraw for eth compatible chains
-fn eth_compatable_withdraw(
-    address: Address,
-    amount: Uint256,
+/// The withdraws held by the withdraw policy
+pub fn get_withdraw_queue(_req: HttpRequest) -> Result<Json<Vec<PendingWithdraw>>, Error> {
+    debug!("/withdraw_queue GET hit");
+    Ok(Json(pending_withdraws()))
+}
+
+/// Approves a withdraw held for breaking the policy, it's sent once the delay has passed
+pub fn approve_held_withdraw(path: Path<u64>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    debug!("/withdraw_queue/{}/approve POST hit", id);
+    match approve_withdraw(id) {
+        Ok(pending) => Ok(HttpResponse::Ok().json(pending)),
+        Err(e) => Ok(HttpResponse::new(StatusCode::BAD_REQUEST)
+            .into_builder()
+            .json(format!("{}", e))),
+    }
+}
+
+pub fn cancel_held_withdraw(path: Path<u64>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    debug!("/withdraw_queue/{}/cancel POST hit", id);
+    match cancel_withdraw(id) {
+        Ok(pending) => Ok(HttpResponse::Ok().json(pending)),
+        Err(e) => Ok(HttpResponse::new(StatusCode::BAD_REQUEST)
+            .into_builder()
+            .json(format!("{}", e))),
+    }
+}
+
+/// The token bridge only moves xDai, so a payment token can only be withdrawn on its own chain
+fn token_bridge_unsupported(
+    system_chain: SystemChain,
+    withdraw_chain: SystemChain,
 ) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
-    let full_node = get_web3_server();
-    let web3 = Web3::new(&full_node, WITHDRAW_TIMEOUT);
-    let payment_settings = SETTING.get_payment();
-    let our_address = match payment_settings.eth_address {
-        Some(address) => address,
-        None => {
-            return Box::new(future::ok(
-                HttpResponse::new(StatusCode::from_u16(504u16).unwrap())
-                    .into_builder()
-                    .json("No Address configured, withdraw impossible!"),
-            ))
-        }
-    };
+    Box::new(future::ok(
+        HttpResponse::new(StatusCode::from_u16(400u16).unwrap())
+            .into_builder()
+            .json(format!(
+                "Payment token withdraws can't be bridged from {} to {}, withdraw impossible!",
+                system_chain, withdraw_chain
+            )),
+    ))
+}
 
-    let tx = Transaction {
-        nonce: payment_settings.nonce.clone(),
-        gas_price: payment_settings.gas_price.clone(),
-        gas_limit: 21_000u32.into(),
-        to: address,
-        value: amount,
-        data: Vec::new(),
-        signature: None,
-    };
-    let transaction_signed = tx.sign(
-        &payment_settings
-            .eth_private_key
-            .expect("No private key configured!"),
-        payment_settings.net_version,
-    );
+/// A withdraw that broke the withdraw policy, it's sent later or not at all
+fn held_withdraw(pending: PendingWithdraw) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    Box::new(future::ok(HttpResponse::Accepted().json(pending)))
+}
 
-    let transaction_bytes = match transaction_signed.to_bytes() {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            return Box::new(future::ok(
-                HttpResponse::new(StatusCode::from_u16(500u16).unwrap())
-                    .into_builder()
-                    .json(format!("Transaction to bytes failed! {:?}", e)),
-            ));
-        }
-    };
+/// Reports how a withdraw that passed the policy went once its response is ready, only one
+/// that went out counts against the daily limit
+fn finish_checked_withdraw(
+    id: u64,
+    res: Box<dyn Future<Item = HttpResponse, Error = Error>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    Box::new(res.then(move |res| {
+        let result = match &res {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(format!("Withdraw failed with {}", response.status())),
+            Err(e) => Err(format!("{}", e)),
+        };
+        finish_withdraw(id, result);
+        res
+    }))
+}
 
-    let transaction_status = web3.eth_send_raw_transaction(transaction_bytes);
+/// Withdraw for eth compatible chains, as a token transfer if a payment token is configured
+fn eth_compatable_withdraw(
+    address: Address,
+    amount: Uint256,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    if SETTING.get_payment().eth_address.is_none() {
+        return Box::new(future::ok(
+            HttpResponse::new(StatusCode::from_u16(504u16).unwrap())
+                .into_builder()
+                .json("No Address configured, withdraw impossible!"),
+        ));
+    }
 
-    Box::new(transaction_status.then(move |result| match result {
-        Ok(tx_id) => Box::new(future::ok({
-            SETTING.get_payment_mut().nonce += 1u64.into();
-            HttpResponse::Ok().json(format!("txid:{:#066x}", tx_id))
-        })),
-        Err(e) => {
-            trigger_update_nonce(our_address, &web3, full_node);
-            if e.to_string().contains("nonce") {
-                Box::new(future::ok(
-                    HttpResponse::new(StatusCode::from_u16(500u16).unwrap())
-                        .into_builder()
-                        .json(format!("The nonce was not updated, try again {:?}", e)),
-                ))
-            } else {
-                Box::new(future::ok(
-                    HttpResponse::new(StatusCode::from_u16(500u16).unwrap())
-                        .into_builder()
-                        .json(format!("Full node failed to send transaction! {:?}", e)),
-                ))
+    Box::new(
+        send_withdraw(address, amount).then(move |result| match result {
+            Ok(tx_id) => Box::new(future::ok(
+                HttpResponse::Ok().json(format!("txid:{:#066x}", tx_id)),
+            )),
+            Err(e) => {
+                if e.to_string().contains("nonce") {
+                    Box::new(future::ok(
+                        HttpResponse::new(StatusCode::from_u16(500u16).unwrap())
+                            .into_builder()
+                            .json(format!("The nonce was not updated, try again {:?}", e)),
+                    ))
+                } else {
+                    Box::new(future::ok(
+                        HttpResponse::new(StatusCode::from_u16(500u16).unwrap())
+                            .into_builder()
+                            .json(format!("Full node failed to send transaction! {:?}", e)),
+                    ))
+                }
             }
-        }
-    }))
+        }),
+    )
 }
 
 /// Cross chain bridge withdraw from Xdai -> ETH
@@ -0,0 +1,329 @@
+//! The local pricing engine sets local_fee from what this router observes rather than from the
+//! oracle. Every adjustment interval it looks at how relay demand compares to the weekly average,
+//! how busy the relay links were in the last hour and what our neighbors charge, judging a
+//! neighbor's price by the cheapest installed route through it. Each of those can move the fee up
+//! or down by one step, the result is kept within the configured floor and ceiling and pushed to
+//! Babel. Every decision is logged along with the reasons for it.
+
+use crate::rita_common::babel_manager;
+use crate::rita_common::usage_tracker::{
+    average_hourly_usage, get_current_hour, GetUsage, UsageTracker, UsageType,
+};
+use crate::SETTING;
+use actix::{Arbiter, SystemService};
+use babel_monitor::Neighbor as BabelNeighbor;
+use babel_monitor::Route as BabelRoute;
+use futures01::future::Either;
+use futures01::{future, Future};
+use settings::payment::{AutoPricingEngine, LocalPricingSettings};
+use settings::RitaCommonSettings;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::util::FutureExt;
+
+/// Relay links busier than this fraction of capacity raise the fee
+const HIGH_UTILIZATION: f64 = 0.8;
+/// Relay links quieter than this fraction of capacity lower the fee when demand is falling
+const LOW_UTILIZATION: f64 = 0.2;
+/// Relay demand over the last day compared to the weekly average that raises the fee
+const HIGH_DEMAND_RATIO: f64 = 1.5;
+/// Relay demand over the last day compared to the weekly average that lowers the fee
+const LOW_DEMAND_RATIO: f64 = 0.5;
+/// How far our fee may drift from the neighbor price, as a multiple, before it is pulled back
+const NEIGHBOR_PRICE_RATIO: f64 = 2.0;
+const DAY_HOURS: u64 = 24;
+const WEEK_HOURS: u64 = 24 * 7;
+const LOCAL_PRICING_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// What the engine looks at when deciding on a fee
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PricingInputs {
+    pub current_fee: u32,
+    /// average relay bytes per hour over the last day
+    pub recent_relay: u64,
+    /// average relay bytes per hour over the last week
+    pub average_relay: u64,
+    /// fraction of relay capacity used during the last full hour
+    pub utilization: f64,
+    /// the median of our neighbors' prices, if any are known
+    pub neighbor_price: Option<u32>,
+}
+
+/// The outcome of a pricing run
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PricingDecision {
+    /// seconds since the unix epoch
+    pub time: u64,
+    pub inputs: PricingInputs,
+    pub fee: u32,
+    pub reasons: Vec<String>,
+}
+
+lazy_static! {
+    static ref LAST_RUN: RwLock<Option<Instant>> = RwLock::new(None);
+    static ref LAST_DECISION: RwLock<Option<PricingDecision>> = RwLock::new(None);
+}
+
+/// The last decision made by the local pricing engine, if it has run
+pub fn get_last_decision() -> Option<PricingDecision> {
+    LAST_DECISION.read().unwrap().clone()
+}
+
+/// Decides on a new fee, returning it and the reasons it differs from the current fee.
+/// Every signal pointing up or down moves the fee by one step of max_step_percent, with
+/// the total move limited to a single step in either direction
+pub fn decide_local_fee(
+    inputs: &PricingInputs,
+    settings: &LocalPricingSettings,
+    max_fee: u32,
+) -> (u32, Vec<String>) {
+    let mut reasons = Vec::new();
+    let mut direction: i32 = 0;
+
+    if inputs.utilization > HIGH_UTILIZATION {
+        direction += 1;
+        reasons.push(format!(
+            "relay links were {:.0}% utilized",
+            inputs.utilization * 100.0
+        ));
+    } else if inputs.utilization < LOW_UTILIZATION && inputs.recent_relay < inputs.average_relay {
+        direction -= 1;
+        reasons.push(format!(
+            "relay links were only {:.0}% utilized and demand is falling",
+            inputs.utilization * 100.0
+        ));
+    }
+
+    if inputs.average_relay > 0 {
+        let ratio = inputs.recent_relay as f64 / inputs.average_relay as f64;
+        if ratio > HIGH_DEMAND_RATIO {
+            direction += 1;
+            reasons.push(format!(
+                "relay demand is {:.1} times the weekly average",
+                ratio
+            ));
+        } else if ratio < LOW_DEMAND_RATIO {
+            direction -= 1;
+            reasons.push(format!(
+                "relay demand is {:.1} times the weekly average",
+                ratio
+            ));
+        }
+    }
+
+    if let Some(neighbor_price) = inputs.neighbor_price {
+        let current = f64::from(inputs.current_fee);
+        let neighbor = f64::from(neighbor_price);
+        if current > neighbor * NEIGHBOR_PRICE_RATIO {
+            direction -= 1;
+            reasons.push(format!("neighbors charge {} wei/byte", neighbor_price));
+        } else if current * NEIGHBOR_PRICE_RATIO < neighbor {
+            direction += 1;
+            reasons.push(format!("neighbors charge {} wei/byte", neighbor_price));
+        }
+    }
+
+    let step = u64::from(settings.max_step_percent);
+    let mut fee = match direction.signum() {
+        1 => {
+            // a free router has nothing to scale, start from the cheapest sensible price
+            let base = u64::from(
+                inputs
+                    .current_fee
+                    .max(settings.floor)
+                    .max(inputs.neighbor_price.unwrap_or(0) / 2)
+                    .max(1),
+            );
+            let raised = base + (base * step / 100).max(1);
+            raised.min(u64::from(u32::max_value())) as u32
+        }
+        -1 => {
+            let current = u64::from(inputs.current_fee);
+            (current - (current * step / 100).min(current)) as u32
+        }
+        _ => inputs.current_fee,
+    };
+
+    let ceiling = settings.ceiling.min(max_fee);
+    if fee > ceiling {
+        fee = ceiling;
+        reasons.push(format!("limited to the ceiling of {} wei/byte", ceiling));
+    } else if fee < settings.floor {
+        fee = settings.floor;
+        reasons.push(format!(
+            "limited to the floor of {} wei/byte",
+            settings.floor
+        ));
+    }
+
+    (fee, reasons)
+}
+
+/// Our neighbors' prices judged by the cheapest installed route through each of them, the
+/// median is used so a single odd neighbor doesn't drag our price around
+pub fn neighbor_price(routes: &[BabelRoute], neighbors: &[BabelNeighbor]) -> Option<u32> {
+    let mut prices: Vec<u32> = neighbors
+        .iter()
+        .filter_map(|neigh| {
+            routes
+                .iter()
+                .filter(|route| route.installed && route.neigh_ip == neigh.address)
+                .map(|route| route.price)
+                .filter(|price| *price > 0)
+                .min()
+        })
+        .collect();
+    if prices.is_empty() {
+        return None;
+    }
+    prices.sort();
+    Some(prices[prices.len() / 2])
+}
+
+/// The fraction of the relay capacity used by the given number of bytes in an hour
+fn utilization(bytes_per_hour: u64, capacity_mbps: u32) -> f64 {
+    if capacity_mbps == 0 {
+        return 0.0;
+    }
+    let mbps = (bytes_per_hour as f64 * 8.0) / 3600.0 / 1_000_000.0;
+    mbps / f64::from(capacity_mbps)
+}
+
+/// Runs the engine if it's selected and an adjustment is due, called from the slow loop
+pub fn tick() {
+    let use_auto_price = SETTING.get_dao().use_oracle_price;
+    let payment = SETTING.get_payment();
+    let settings = payment.local_pricing.clone();
+    let max_fee = payment.max_fee;
+    let current_fee = payment.local_fee;
+    drop(payment);
+
+    if !use_auto_price || settings.engine != AutoPricingEngine::Local {
+        return;
+    }
+    {
+        let mut last_run = LAST_RUN.write().unwrap();
+        if let Some(last_run) = *last_run {
+            if Instant::now() - last_run < Duration::from_secs(settings.adjustment_interval) {
+                return;
+            }
+        }
+        *last_run = Some(Instant::now());
+    }
+
+    let usage = UsageTracker::from_registry()
+        .send(GetUsage {
+            kind: UsageType::Relay,
+        })
+        .from_err()
+        .and_then(|reply| reply);
+
+    Arbiter::spawn(
+        usage
+            .join3(babel_manager::get_routes(), babel_manager::get_neighbors())
+            .and_then(move |(usage, routes, neighbors)| {
+                let current_hour = get_current_hour()?;
+                let last_hour = average_hourly_usage(&usage, current_hour.saturating_sub(1), 1);
+                let inputs = PricingInputs {
+                    current_fee,
+                    recent_relay: average_hourly_usage(&usage, current_hour, DAY_HOURS),
+                    average_relay: average_hourly_usage(&usage, current_hour, WEEK_HOURS),
+                    utilization: utilization(last_hour, settings.relay_capacity_mbps),
+                    neighbor_price: neighbor_price(&routes, &neighbors),
+                };
+                let (fee, reasons) = decide_local_fee(&inputs, &settings, max_fee);
+                Ok((inputs, fee, reasons))
+            })
+            .and_then(move |(inputs, fee, reasons)| {
+                let res = if fee != current_fee {
+                    info!(
+                        "Local pricing engine changed our fee from {} to {} wei/byte: {}",
+                        current_fee,
+                        fee,
+                        reasons.join(", ")
+                    );
+                    SETTING.get_payment_mut().local_fee = fee;
+                    Either::A(babel_manager::set_local_fee(fee))
+                } else {
+                    info!(
+                        "Local pricing engine kept our fee at {} wei/byte {:?}",
+                        fee, reasons
+                    );
+                    Either::B(future::ok(()))
+                };
+                *LAST_DECISION.write().unwrap() = Some(PricingDecision {
+                    time: crate::rita_common::oracle::history::now(),
+                    inputs,
+                    fee,
+                    reasons,
+                });
+                res
+            })
+            .timeout(LOCAL_PRICING_TIMEOUT)
+            .then(|res| {
+                if let Err(e) = res {
+                    error!("Local pricing engine failed {:?}", e);
+                }
+                Ok(())
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(current_fee: u32) -> PricingInputs {
+        PricingInputs {
+            current_fee,
+            recent_relay: 1000,
+            average_relay: 1000,
+            utilization: 0.5,
+            neighbor_price: None,
+        }
+    }
+
+    #[test]
+    fn test_decide_local_fee() {
+        let settings = LocalPricingSettings::default();
+
+        // nothing out of the ordinary
+        assert_eq!(
+            decide_local_fee(&inputs(1000), &settings, 10_000),
+            (1000, Vec::new())
+        );
+
+        // busy links and rising demand move the fee by a single step
+        let mut busy = inputs(1000);
+        busy.utilization = 0.9;
+        busy.recent_relay = 2000;
+        let (fee, reasons) = decide_local_fee(&busy, &settings, 10_000);
+        assert_eq!(fee, 1100);
+        assert_eq!(reasons.len(), 2);
+
+        // an expensive router next to cheap neighbors comes down
+        let mut expensive = inputs(1000);
+        expensive.neighbor_price = Some(100);
+        assert_eq!(decide_local_fee(&expensive, &settings, 10_000).0, 900);
+
+        // bounds win over every signal
+        let floored = LocalPricingSettings {
+            floor: 950,
+            ..settings.clone()
+        };
+        assert_eq!(decide_local_fee(&expensive, &floored, 10_000).0, 950);
+        assert_eq!(decide_local_fee(&busy, &settings, 1050).0, 1050);
+
+        // a free router can start charging
+        let mut free = inputs(0);
+        free.utilization = 0.9;
+        assert_eq!(decide_local_fee(&free, &settings, 10_000).0, 2);
+    }
+
+    #[test]
+    fn test_utilization() {
+        // 45GB in an hour is 100mbps
+        assert!((utilization(45_000_000_000, 100) - 1.0).abs() < 0.001);
+        assert_eq!(utilization(1000, 0), 0.0);
+    }
+}
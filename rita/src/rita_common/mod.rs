@@ -1,17 +1,22 @@
+pub mod babel_manager;
 pub mod dao_manager;
 pub mod dashboard;
 pub mod debt_keeper;
 pub mod hello_handler;
+pub mod local_pricing;
 pub mod network_endpoints;
 pub mod network_monitor;
 pub mod oracle;
 pub mod payment_controller;
+pub mod payment_token;
 pub mod payment_validator;
 pub mod peer_listener;
 pub mod rita_loop;
 pub mod simulated_txfee_manager;
+pub mod sweep;
 pub mod token_bridge;
 pub mod traffic_watcher;
 pub mod tunnel_manager;
 pub mod usage_tracker;
 pub mod utils;
+pub mod withdraw_policy;
@@ -422,6 +422,59 @@ fn mean(data: &[f32]) -> Option<f32> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rita_common::babel_manager::start_synced;
+    use crate::rita_common::babel_manager::GetBabelNeighbors;
+    use crate::rita_common::babel_manager::GetRoutes;
+    use actix::System;
+    use babel_monitor::fake::make_neighbor;
+    use babel_monitor::fake::make_route;
+    use babel_monitor::fake::FakeBabel;
+
+    #[test]
+    fn test_network_monitor_from_fake_babel() {
+        let babel = FakeBabel::start().unwrap();
+        babel.add_neighbor(make_neighbor("1", "fe80::1".parse().unwrap(), "wg0"));
+        let mut lossy = make_neighbor("2", "fe80::2".parse().unwrap(), "wg1");
+        lossy.reach = 0;
+        babel.add_neighbor(lossy);
+        babel.add_route(make_route(
+            "1",
+            "fd00::1/128".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+            "wg0",
+            100,
+        ));
+
+        let mut system = System::new("test_network_monitor_from_fake_babel");
+        let manager = start_synced(&mut system, babel.port());
+        let monitor = NetworkMonitor::new().start();
+        // the latency stats need a few rounds before they have an average
+        for _ in 0..3 {
+            let routes = system.block_on(manager.send(GetRoutes)).unwrap().unwrap();
+            let neighbors = system
+                .block_on(manager.send(GetBabelNeighbors))
+                .unwrap()
+                .unwrap();
+            monitor.do_send(NetworkInfo {
+                babel_neighbors: neighbors,
+                babel_routes: routes,
+                rita_neighbors: Vec::new(),
+            });
+        }
+
+        let info = system
+            .block_on(monitor.send(GetNetworkInfo))
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.babel_routes.len(), 1);
+        assert_eq!(info.babel_neighbors.len(), 2);
+
+        let stats = system.block_on(monitor.send(GetStats {})).unwrap().unwrap();
+        assert_eq!(stats["wg0"].latency.avg, Some(10.0));
+        assert_eq!(stats["wg0"].packet_loss.avg, Some(0.0));
+        assert_eq!(stats["wg1"].packet_loss.avg, Some(1.0));
+    }
+
     #[test]
     fn test_get_first_n_set_bits() {
         let count = get_first_n_set_bits(0b1110_0000_0000_0000, 5);
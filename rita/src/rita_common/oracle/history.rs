@@ -0,0 +1,276 @@
+//! Keeps a record of every settings change made by the oracle as a diff against the settings it
+//! replaced, so that operators can see what an update did and undo it. The history is saved to
+//! the oracle_history_file after every change. Each applied update is identified by the hash of
+//! its signed json, once it has been rolled back the same update isn't applied again when the
+//! oracles keep serving it.
+
+use crate::SETTING;
+use althea_kernel_interface::opkg_feeds::set_release_feed;
+use failure::Error;
+use serde_json::Map;
+use serde_json::Value;
+use settings::RitaCommonSettings;
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many applied updates are kept in the history
+const MAX_HISTORY: usize = 100;
+/// The path used for the release feed, which lives in the opkg config rather than the settings
+pub const RELEASE_FEED_PATH: &str = "release_feed";
+
+/// A single value changed by an oracle update
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SettingChange {
+    /// the keys leading to the value in the settings json
+    pub path: Vec<String>,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// An oracle update that was applied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OracleHistoryEntry {
+    pub id: u64,
+    /// seconds since the unix epoch
+    pub time: u64,
+    /// the oracle urls that served the update, or the rollback that caused it
+    pub source: Vec<String>,
+    pub changes: Vec<SettingChange>,
+    /// hex keccak256 of the update json, None for rollbacks
+    #[serde(default)]
+    pub update_hash: Option<String>,
+    /// whether this entry has since been rolled back
+    #[serde(default)]
+    pub rolled_back: bool,
+}
+
+/// An oracle update that was fetched in dry run mode and not applied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingOracleUpdate {
+    /// seconds since the unix epoch
+    pub time: u64,
+    pub source: Vec<String>,
+    pub changes: Vec<SettingChange>,
+}
+
+lazy_static! {
+    static ref HISTORY: RwLock<VecDeque<OracleHistoryEntry>> = RwLock::new(load_history());
+    static ref PENDING: RwLock<Option<PendingOracleUpdate>> = RwLock::new(None);
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn load_history() -> VecDeque<OracleHistoryEntry> {
+    let file = SETTING.get_dao().oracle_history_file.clone();
+    if file.is_empty() {
+        return VecDeque::new();
+    }
+    match fs::read(&file) {
+        Ok(contents) => match serde_json::from_slice(&contents) {
+            Ok(history) => history,
+            Err(e) => {
+                error!("Failed to deserialize oracle history {:?}", e);
+                VecDeque::new()
+            }
+        },
+        Err(e) => {
+            info!("No oracle history loaded {:?}", e);
+            VecDeque::new()
+        }
+    }
+}
+
+fn save_history(history: &VecDeque<OracleHistoryEntry>) -> Result<(), Error> {
+    let file = SETTING.get_dao().oracle_history_file.clone();
+    if file.is_empty() {
+        return Ok(());
+    }
+    // written next to the history and moved into place so a crash can't leave it truncated
+    let tmp = format!("{}.tmp", file);
+    fs::write(&tmp, serde_json::to_vec(history)?)?;
+    fs::rename(&tmp, &file)?;
+    Ok(())
+}
+
+/// Lists every value that differs between two settings json objects
+pub fn diff_settings(old: &Value, new: &Value) -> Vec<SettingChange> {
+    let mut changes = Vec::new();
+    diff_values(&mut Vec::new(), old, new, &mut changes);
+    changes
+}
+
+fn diff_values(path: &mut Vec<String>, old: &Value, new: &Value, changes: &mut Vec<SettingChange>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            let mut keys: Vec<&String> = old_map.keys().chain(new_map.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                path.push(key.clone());
+                diff_values(
+                    path,
+                    old_map.get(key).unwrap_or(&Value::Null),
+                    new_map.get(key).unwrap_or(&Value::Null),
+                    changes,
+                );
+                path.pop();
+            }
+        }
+        (old, new) => {
+            if old != new {
+                changes.push(SettingChange {
+                    path: path.clone(),
+                    old: old.clone(),
+                    new: new.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Builds a patch that sets every changed setting back to its old value
+fn rollback_patch(changes: &[SettingChange]) -> Value {
+    let mut patch = Value::Object(Map::new());
+    for change in changes {
+        if change.path.is_empty() || change.path[0] == RELEASE_FEED_PATH {
+            continue;
+        }
+        let mut target = &mut patch;
+        for key in change.path.iter() {
+            if !target.is_object() {
+                *target = Value::Object(Map::new());
+            }
+            target = target
+                .as_object_mut()
+                .unwrap()
+                .entry(key.clone())
+                .or_insert(Value::Null);
+        }
+        *target = change.old.clone();
+    }
+    patch
+}
+
+/// Adds an applied update to the history, returning its id
+pub fn record_update(
+    source: Vec<String>,
+    changes: Vec<SettingChange>,
+    update_hash: Option<String>,
+) -> u64 {
+    let mut history = HISTORY.write().unwrap();
+    let id = history.front().map(|entry| entry.id + 1).unwrap_or(0);
+    history.push_front(OracleHistoryEntry {
+        id,
+        time: now(),
+        source,
+        changes,
+        update_hash,
+        rolled_back: false,
+    });
+    history.truncate(MAX_HISTORY);
+    if let Err(e) = save_history(&history) {
+        error!("Failed to save oracle history {:?}", e);
+    }
+    id
+}
+
+/// Whether the update with this hash was applied and then rolled back
+pub fn is_rolled_back(update_hash: &str) -> bool {
+    HISTORY.read().unwrap().iter().any(|entry| {
+        entry.rolled_back && entry.update_hash.as_ref().map(|h| h.as_str()) == Some(update_hash)
+    })
+}
+
+/// The applied oracle updates, newest first
+pub fn get_history() -> Vec<OracleHistoryEntry> {
+    HISTORY.read().unwrap().iter().cloned().collect()
+}
+
+pub fn set_pending(pending: PendingOracleUpdate) {
+    *PENDING.write().unwrap() = Some(pending);
+}
+
+/// The last update fetched in dry run mode, if any
+pub fn get_pending() -> Option<PendingOracleUpdate> {
+    PENDING.read().unwrap().clone()
+}
+
+/// Puts every setting changed by the given history entry back to the value it had before, the
+/// rollback itself is recorded in the history as well
+pub fn rollback(id: u64) -> Result<u64, Error> {
+    let entry = match HISTORY.read().unwrap().iter().find(|entry| entry.id == id) {
+        Some(entry) => entry.clone(),
+        None => bail!("No oracle update with id {}", id),
+    };
+    if entry.rolled_back {
+        bail!("Oracle update {} has already been rolled back", id);
+    }
+
+    let before = SETTING.get_all()?;
+    SETTING.merge(rollback_patch(&entry.changes))?;
+    let mut changes = diff_settings(&before, &SETTING.get_all()?);
+
+    for change in entry.changes.iter() {
+        if change.path.first().map(|p| p.as_str()) == Some(RELEASE_FEED_PATH) {
+            set_release_feed(serde_json::from_value(change.old.clone())?)?;
+            changes.push(SettingChange {
+                path: change.path.clone(),
+                old: change.new.clone(),
+                new: change.old.clone(),
+            });
+        }
+    }
+
+    // marked before the rollback is recorded so that both are saved together
+    if let Some(entry) = HISTORY
+        .write()
+        .unwrap()
+        .iter_mut()
+        .find(|entry| entry.id == id)
+    {
+        entry.rolled_back = true;
+    }
+    Ok(record_update(
+        vec![format!("rollback of {}", id)],
+        changes,
+        None,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_and_rollback_patch() {
+        let old = json!({"payment": {"local_fee": 10, "max_fee": 20}, "dao": {"dao_fee": "0"}});
+        let new = json!({"payment": {"local_fee": 15, "max_fee": 20}, "dao": {"dao_fee": "5"}});
+        let changes = diff_settings(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                SettingChange {
+                    path: vec!["dao".to_string(), "dao_fee".to_string()],
+                    old: json!("0"),
+                    new: json!("5"),
+                },
+                SettingChange {
+                    path: vec!["payment".to_string(), "local_fee".to_string()],
+                    old: json!(10),
+                    new: json!(15),
+                },
+            ]
+        );
+        assert_eq!(
+            rollback_patch(&changes),
+            json!({"payment": {"local_fee": 10}, "dao": {"dao_fee": "0"}})
+        );
+    }
+}
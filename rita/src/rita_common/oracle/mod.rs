@@ -7,27 +7,43 @@
 //! operates by simply grabbing a text file from a configured server and adjusting prices
 //! to match. More advanced pricing systems may be broken out into their own file some day
 
+pub mod history;
+
+use crate::rita_common::oracle::history::{
+    diff_settings, is_rolled_back, now, record_update, set_pending, PendingOracleUpdate,
+    SettingChange, RELEASE_FEED_PATH,
+};
+use crate::rita_common::payment_token::{get_payment_token, get_token_balance, transfer_gas};
 use crate::rita_common::rita_loop::fast_loop::FAST_LOOP_TIMEOUT;
 use crate::rita_common::rita_loop::get_web3_server;
 use crate::rita_common::token_bridge::ReloadAddresses;
 use crate::rita_common::token_bridge::TokenBridge;
 use crate::SETTING;
 use actix::{Actor, Arbiter, Context, Handler, Message, Supervised, SystemService};
-use actix_web::error::PayloadError;
-use actix_web::{client, Either, HttpMessage, Result};
+use actix_web::{client, HttpMessage, Result};
 use althea_kernel_interface::opkg_feeds::get_release_feed;
 use althea_kernel_interface::opkg_feeds::set_release_feed;
 use althea_types::OracleUpdate;
+use althea_types::ReleaseStatus;
+use althea_types::SignedOracleUpdate;
 use bytes::Bytes;
 use clarity::Address;
+use failure::Error;
+use futures01::future::Either;
 use futures01::{future, Future};
 use num256::Int256;
 use num256::Uint256;
 use num_traits::identities::Zero;
+use serde::{Deserialize, Serialize};
 use serde_json::Map;
 use serde_json::Value;
-use settings::payment::PaymentSettings;
+use settings::payment::{AutoPricingEngine, PaymentSettings};
 use settings::RitaCommonSettings;
+use sha3::{Digest, Keccak256};
+use std::cmp::max;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use std::time::Instant;
 use web30::client::Web3;
@@ -35,14 +51,42 @@ use web30::client::Web3;
 /// Things that you are not allowed to put into the merge json field of the oracle,
 /// this mostly includes dangerous local things like eth private keys (erase money)
 /// ports (destory all networking) etc etc
-const FORBIDDEN_MERGE_VALUES: [&str; 5] = [
+const FORBIDDEN_MERGE_VALUES: [&str; 14] = [
     "eth_private_key",
     "eth_address",
     "mesh_ip",
     "external_nic",
     "peer_interfaces",
+    "oracle_signers",
+    "oracle_quorum",
+    "oracle_dry_run",
+    "oracle_history_file",
+    "oracle_last_sequence",
+    // these decide where our funds go and what counts as a payment
+    "sweep",
+    "withdraw_policy",
+    "withdraw_queue_file",
+    "payment_token",
 ];
 
+/// How many rejected oracle updates are kept around for the dashboard
+const MAX_REJECTED_UPDATES: usize = 20;
+
+/// An oracle update that failed verification or didn't reach a quorum
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedOracleUpdate {
+    /// seconds since the unix epoch
+    pub time: u64,
+    /// the oracle that served it, None if it was rejected for lack of agreement
+    pub url: Option<String>,
+    pub reason: String,
+}
+
+lazy_static! {
+    static ref REJECTED_UPDATES: RwLock<VecDeque<RejectedOracleUpdate>> =
+        RwLock::new(VecDeque::new());
+}
+
 pub struct Oracle {
     /// An instant representing the start of a short period where the balance can
     /// actually go to zero. This is becuase full nodes (incluing Infura) have an infuriating
@@ -120,24 +164,34 @@ fn update_blockchain_info(
     zero_window: Option<Instant>,
 ) {
     let balance = web3.eth_get_balance(our_address);
+    // with a payment token our balance is the token balance and the native balance only pays gas
+    let token_balance = match get_payment_token() {
+        Some(token) => {
+            Either::A(get_token_balance(&web3, token.contract_address, our_address).map(Some))
+        }
+        None => Either::B(future::ok(None)),
+    };
     let nonce = web3.eth_get_transaction_count(our_address);
     let net_version = web3.net_version();
     let gas_price = web3.eth_gas_price();
     let res = balance
-        .join4(nonce, net_version, gas_price)
-        .and_then(move |(balance, nonce, net_version, gas_price)| {
-            let mut payment_settings = SETTING.get_payment_mut();
-            update_balance(
-                &full_node,
-                zero_window,
-                &mut payment_settings.balance,
-                balance,
-            );
-            update_gas_price(&full_node, gas_price, &mut payment_settings);
-            update_nonce(&full_node, nonce, &mut payment_settings.nonce);
-            get_net_version(&full_node, &mut payment_settings.net_version, net_version);
-            Ok(())
-        })
+        .join5(token_balance, nonce, net_version, gas_price)
+        .and_then(
+            move |(balance, token_balance, nonce, net_version, gas_price)| {
+                let mut payment_settings = SETTING.get_payment_mut();
+                update_gas_balance(&full_node, &mut payment_settings, balance.clone());
+                update_balance(
+                    &full_node,
+                    zero_window,
+                    &mut payment_settings.balance,
+                    token_balance.unwrap_or(balance),
+                );
+                update_gas_price(&full_node, gas_price, &mut payment_settings);
+                update_nonce(&full_node, nonce, &mut payment_settings.nonce);
+                get_net_version(&full_node, &mut payment_settings.net_version, net_version);
+                Ok(())
+            },
+        )
         .then(|res| {
             if let Err(e) = res {
                 warn!("Failed to update blockchain info with {:?}", e);
@@ -177,6 +231,28 @@ fn update_balance(
     }
 }
 
+/// Updates our balance of the native coin, which pays for gas, warning if it's running low
+/// while we pay in a token. Without a payment token this is the same as the balance
+fn update_gas_balance(
+    full_node: &str,
+    payment_settings: &mut PaymentSettings,
+    new_gas_balance: Uint256,
+) {
+    if let Some(token) = &payment_settings.payment_token {
+        info!(
+            "Got response from {} gas balance request {:?}",
+            full_node, new_gas_balance
+        );
+        if new_gas_balance < token.gas_warning_level {
+            warn!(
+                "Gas balance {} is low, we will soon be unable to pay in {}!",
+                new_gas_balance, token.symbol
+            );
+        }
+    }
+    payment_settings.gas_balance = new_gas_balance;
+}
+
 /// Updates the net_version in our global setting variable, this function
 /// specifically runs into some security issues, a hostile node could provide
 /// us with the wrong net_version, hoping to get a signed transaction good for
@@ -280,7 +356,7 @@ fn update_gas_price(
     };
 
     let dynamic_fee_factor: Int256 = payment_settings.dynamic_fee_multiplier.into();
-    let transaction_gas: Int256 = 21000.into();
+    let transaction_gas: Int256 = transfer_gas(&payment_settings.payment_token).into();
     let neg_one = -1i32;
     let sign_flip: Int256 = neg_one.into();
 
@@ -306,6 +382,10 @@ fn update_gas_price(
 /// will be able to adjust prices on their own and not require centralized input to know when it's best
 /// for the network to adjust bandwidth prices, that's not the case right now so the DAO suggested prices
 /// are taken at face value.
+///
+/// Updates must be signed by one of the configured oracle signers and when mirrors are configured
+/// at least oracle_quorum of the oracles have to serve the same update, so that a single compromised
+/// server can't reconfigure every router.
 fn update_oracle() {
     // check if the oracle is enabled
     if !SETTING.get_dao().oracle_enabled {
@@ -314,118 +394,350 @@ fn update_oracle() {
 
     // if there's no url the user has not configured a DAO yet and
     // we simply move on
-    let url = match SETTING.get_dao().oracle_url.clone() {
-        Some(url) => url,
-        None => return,
-    };
-    info!("Starting oracle updating using {}", url);
-
-    let is_gateway = SETTING.get_network().is_gateway;
-
-    if !url.starts_with("https://") {
-        error!("Unsafe price update url, your must use https!");
+    let dao = SETTING.get_dao();
+    let mut urls: Vec<String> = dao.oracle_url.iter().cloned().collect();
+    urls.extend(dao.oracle_mirror_urls.iter().cloned());
+    let quorum = max(dao.oracle_quorum, 1);
+    let unsigned = dao.oracle_signers.is_empty();
+    drop(dao);
+    // the same oracle listed twice must not count twice towards the quorum
+    urls.sort();
+    urls.dedup();
+    if urls.is_empty() {
         return;
     }
+    if unsigned {
+        error!(
+            "No oracle_signers configured! Oracle updates are not authenticated, whoever serves {:?} can change our settings",
+            urls
+        );
+    }
+    info!("Starting oracle updating using {:?}", urls);
+
+    if quorum > urls.len() {
+        reject_update(
+            None,
+            format!(
+                "A quorum of {} can't be reached with {} oracles",
+                quorum,
+                urls.len()
+            ),
+        );
+        return;
+    }
+    let total = urls.len();
+    let mut requests = Vec::new();
+    for url in urls {
+        if url.starts_with("https://") {
+            requests.push(fetch_oracle_update(url));
+        } else {
+            reject_update(
+                Some(&url),
+                "Unsafe price update url, your must use https!".to_string(),
+            );
+        }
+    }
+
+    let res = future::join_all(requests).then(move |responses| {
+        if let Ok(responses) = responses {
+            handle_oracle_responses(responses, total, quorum);
+        }
+        Ok(())
+    });
 
-    let res = client::get(url)
+    Arbiter::spawn(res);
+}
+
+/// Downloads the update served by a single oracle. This never fails so that one unreachable
+/// oracle doesn't stop the others from being counted, None means there was no response
+fn fetch_oracle_update(
+    url: String,
+) -> impl Future<Item = (String, Option<Result<SignedOracleUpdate, Error>>), Error = ()> {
+    client::get(&url)
         .header("User-Agent", "Actix-web")
         .finish()
         .unwrap()
         .send()
         .timeout(ORACLE_TIMEOUT)
-        .then(move |response| {
-            match response {
-                Ok(response) => {
-                    Either::A(response.body().then(
-                        move |message_body: Result<Bytes, PayloadError>| {
-                            match message_body {
-                                Ok(new_prices) => {
-                                    // .json() only works on application/json content types unlike reqwest which handles bytes
-                                    // transparently actix requests need to get the body and deserialize using serde_json in
-                                    // an explicit fashion
-                                    match serde_json::from_slice::<OracleUpdate>(&new_prices) {
-                                        Ok(new_settings) => {
-                                            let dao = SETTING.get_dao();
-                                            let use_oracle_price = dao.use_oracle_price;
-                                            drop(dao);
-
-                                            let mut payment = SETTING.get_payment_mut();
-                                            let starting_token_bridge_core =
-                                                payment.bridge_addresses.clone();
-
-                                            if use_oracle_price {
-                                                // This will be true on devices that have integrated switches
-                                                // and a wan port configured. Mostly not a problem since we stopped
-                                                // shipping wan ports by default
-                                                if is_gateway {
-                                                    payment.local_fee = new_settings.gateway;
-                                                } else {
-                                                    payment.local_fee = new_settings.client;
-                                                }
-                                            } else {
-                                                info!("User has disabled the Oracle!");
-                                            }
-
-                                            payment.max_fee = new_settings.max;
-                                            payment.balance_warning_level =
-                                                new_settings.warning.into();
-                                            if let Some(new_chain) = new_settings.system_chain {
-                                                payment.system_chain = new_chain;
-                                            }
-                                            if let Some(new_chain) = new_settings.withdraw_chain {
-                                                payment.withdraw_chain = new_chain;
-                                            }
-                                            drop(payment);
-
-                                            let new_dao_fee = Uint256::from(new_settings.dao_fee);
-                                            let current_dao_fee = SETTING.get_dao().dao_fee.clone();
-                                            if new_dao_fee > current_dao_fee {
-                                                let mut dao = SETTING.get_dao_mut();
-                                                dao.dao_fee = new_dao_fee;
-                                            }
-
-                                            merge_settings_safely(new_settings.merge_json);
-
-                                            // update the release feed to the provided release
-                                            // gated on "None" to prevent reading a file if there is
-                                            // no update. Maybe someday match will be smart enough to
-                                            // avoid that on it's own
-                                            if new_settings.release_feed.is_some() {
-                                                handle_release_feed_update(
-                                                    new_settings.release_feed,
-                                                );
-                                            }
-                                            // Sends a message to reload bridge addresses live if needed
-                                            if SETTING.get_payment().bridge_addresses
-                                                != starting_token_bridge_core
-                                            {
-                                                TokenBridge::from_registry()
-                                                    .do_send(ReloadAddresses());
-                                            }
-
-                                            trace!("Successfully updated oracle");
-                                        }
-                                        Err(e) => warn!(
-                                            "Failed to deserialize oracle update message with {:?}",
-                                            e
-                                        ),
-                                    }
-                                }
-                                Err(e) => warn!("Failed to decode message body {:?}", e),
-                            }
-                            Ok(()) as Result<(), ()>
-                        },
-                    ))
+        .from_err::<Error>()
+        .and_then(|response| response.body().from_err())
+        .then(move |message_body: Result<Bytes, Error>| -> Result<_, ()> {
+            match message_body {
+                // .json() only works on application/json content types unlike reqwest which handles bytes
+                // transparently actix requests need to get the body and deserialize using serde_json in
+                // an explicit fashion
+                Ok(body) => Ok((url, Some(parse_oracle_update(&body)))),
+                Err(e) => {
+                    trace!(
+                        "Failed to make oracle update request to {} with {:?}",
+                        url,
+                        e
+                    );
+                    Ok((url, None))
                 }
-                Err(e) => Either::B({
-                    trace!("Failed to make oracle update request with {:?}", e);
-                    // don't ask me why these types agree
-                    future::ok(())
-                }),
             }
+        })
+}
+
+/// Parses a signed update, or a bare unsigned update as served by older oracles
+fn parse_oracle_update(body: &[u8]) -> Result<SignedOracleUpdate, Error> {
+    if let Ok(signed) = serde_json::from_slice::<SignedOracleUpdate>(body) {
+        return Ok(signed);
+    }
+    let update = String::from_utf8(body.to_vec())?;
+    if let Err(e) = serde_json::from_str::<OracleUpdate>(&update) {
+        bail!("Failed to deserialize oracle update message with {:?}", e);
+    }
+    Ok(SignedOracleUpdate {
+        update,
+        signatures: Vec::new(),
+    })
+}
+
+/// Checks that an update is signed by one of the given signers, or when there are no signers
+/// configured accepts it as is
+fn verify_oracle_update(
+    signed: &SignedOracleUpdate,
+    signers: &[Address],
+) -> Result<OracleUpdate, Error> {
+    let update: OracleUpdate = serde_json::from_str(&signed.update)?;
+    if signers.is_empty() {
+        return Ok(update);
+    }
+    if update.sequence == 0 {
+        bail!("Signed oracle update has no sequence");
+    }
+    let hash = Keccak256::digest(signed.update.as_bytes());
+    for signature in signed.signatures.iter() {
+        if let Ok(signer) = signature.recover(&hash) {
+            if signers.contains(&signer) {
+                return Ok(update);
+            }
+        }
+    }
+    bail!("Oracle update is not signed by any of {:?}", signers)
+}
+
+/// Checks an update against the sequence of the last one applied. An older update is an error,
+/// the one already applied is Ok(false) since oracles keep serving it until there's a new one
+fn is_newer(update: &OracleUpdate, last_sequence: u64) -> Result<bool, Error> {
+    if update.sequence < last_sequence {
+        bail!(
+            "Oracle update sequence {} is older than {}, the last one applied",
+            update.sequence,
+            last_sequence
+        );
+    }
+    Ok(update.sequence > last_sequence || last_sequence == 0)
+}
+
+/// Verifies every response and applies the update if enough oracles served the same valid one
+fn handle_oracle_responses(
+    responses: Vec<(String, Option<Result<SignedOracleUpdate, Error>>)>,
+    total: usize,
+    quorum: usize,
+) {
+    let dao = SETTING.get_dao();
+    let signers = dao.oracle_signers.clone();
+    let last_sequence = dao.oracle_last_sequence;
+    drop(dao);
+    // keyed by the exact signed json so only identical updates count as agreeing
+    let mut agreeing: HashMap<String, (OracleUpdate, Vec<String>)> = HashMap::new();
+    for (url, response) in responses {
+        let signed = match response {
+            Some(Ok(signed)) => signed,
+            Some(Err(e)) => {
+                reject_update(Some(&url), format!("{}", e));
+                continue;
+            }
+            None => continue,
+        };
+        match verify_oracle_update(&signed, &signers)
+            .and_then(|update| is_newer(&update, last_sequence).map(|newer| (update, newer)))
+        {
+            Ok((_, false)) => trace!("Oracle update from {} is already applied", url),
+            Ok((update, true)) => agreeing
+                .entry(signed.update)
+                .or_insert_with(|| (update, Vec::new()))
+                .1
+                .push(url),
+            Err(e) => reject_update(Some(&url), format!("{}", e)),
+        }
+    }
+
+    match agreeing.into_iter().max_by_key(|(_, (_, urls))| urls.len()) {
+        Some((json, (update, urls))) => {
+            let update_hash = update_hash(&json);
+            if urls.len() >= quorum && is_rolled_back(&update_hash) {
+                info!(
+                    "Oracle update {} was rolled back, not applying it again",
+                    update_hash
+                );
+            } else if urls.len() >= quorum {
+                apply_oracle_update(update, urls.clone(), update_hash);
+                trace!("Successfully updated oracle from {:?}", urls);
+            } else {
+                reject_update(
+                    None,
+                    format!(
+                        "Only {} of {} oracles served the same update, {} needed",
+                        urls.len(),
+                        total,
+                        quorum
+                    ),
+                );
+            }
+        }
+        None => trace!("No valid oracle update this round"),
+    }
+}
+
+/// Identifies an update by its exact json, so that a rolled back update can be recognized
+fn update_hash(update: &str) -> String {
+    format!("0x{:x}", Keccak256::digest(update.as_bytes()))
+}
+
+/// Applies a verified oracle update to the local settings and records what it changed, or in
+/// dry run mode only records the change it would make
+fn apply_oracle_update(new_settings: OracleUpdate, source: Vec<String>, update_hash: String) {
+    let before = match SETTING.get_all() {
+        Ok(before) => before,
+        Err(e) => {
+            error!("Failed to read settings for the oracle update {:?}", e);
+            return;
+        }
+    };
+    let release_change = release_feed_change(&new_settings.release_feed);
+
+    if SETTING.get_dao().oracle_dry_run {
+        let preview = Arc::new(RwLock::new(SETTING.read().unwrap().clone()));
+        update_settings(&preview, &new_settings);
+        let mut changes = match preview.get_all() {
+            Ok(after) => diff_settings(&before, &after),
+            Err(e) => {
+                error!("Failed to preview the oracle update {:?}", e);
+                return;
+            }
+        };
+        changes.extend(release_change);
+        info!("Oracle dry run, not applying {:?}", changes);
+        set_pending(PendingOracleUpdate {
+            time: now(),
+            source,
+            changes,
         });
+        return;
+    }
 
-    Arbiter::spawn(res);
+    let starting_token_bridge_core = SETTING.get_payment().bridge_addresses.clone();
+    update_settings(&*SETTING, &new_settings);
+    SETTING.get_dao_mut().oracle_last_sequence = new_settings.sequence;
+
+    // update the release feed to the provided release
+    // gated on "None" to prevent reading a file if there is
+    // no update. Maybe someday match will be smart enough to
+    // avoid that on it's own
+    if new_settings.release_feed.is_some() {
+        handle_release_feed_update(new_settings.release_feed);
+    }
+    // Sends a message to reload bridge addresses live if needed
+    if SETTING.get_payment().bridge_addresses != starting_token_bridge_core {
+        TokenBridge::from_registry().do_send(ReloadAddresses());
+    }
+
+    let mut changes = match SETTING.get_all() {
+        Ok(after) => diff_settings(&before, &after),
+        Err(e) => {
+            error!("Failed to read settings after the oracle update {:?}", e);
+            return;
+        }
+    };
+    changes.extend(release_change);
+    if !changes.is_empty() {
+        let id = record_update(source, changes, Some(update_hash));
+        info!("Applied oracle update {}", id);
+    }
+}
+
+/// Makes the settings changes described by an oracle update, this is generic so that dry runs
+/// can apply it to a copy of the settings
+fn update_settings<T>(settings: &Arc<RwLock<T>>, new_settings: &OracleUpdate)
+where
+    T: Serialize + Deserialize<'static>,
+    Arc<RwLock<T>>: RitaCommonSettings<T>,
+{
+    let is_gateway = settings.get_network().is_gateway;
+    let dao = settings.get_dao();
+    let use_oracle_price = dao.use_oracle_price;
+    drop(dao);
+
+    let mut payment = settings.get_payment_mut();
+    let local_engine = payment.local_pricing.engine == AutoPricingEngine::Local;
+
+    if use_oracle_price && local_engine {
+        info!("The local pricing engine sets our price, ignoring the Oracle price");
+    } else if use_oracle_price {
+        // This will be true on devices that have integrated switches
+        // and a wan port configured. Mostly not a problem since we stopped
+        // shipping wan ports by default
+        if is_gateway {
+            payment.local_fee = new_settings.gateway;
+        } else {
+            payment.local_fee = new_settings.client;
+        }
+    } else {
+        info!("User has disabled the Oracle!");
+    }
+
+    payment.max_fee = new_settings.max;
+    payment.balance_warning_level = new_settings.warning.into();
+    if let Some(new_chain) = new_settings.system_chain {
+        payment.system_chain = new_chain;
+    }
+    if let Some(new_chain) = new_settings.withdraw_chain {
+        payment.withdraw_chain = new_chain;
+    }
+    drop(payment);
+
+    let new_dao_fee = Uint256::from(new_settings.dao_fee);
+    let current_dao_fee = settings.get_dao().dao_fee.clone();
+    if new_dao_fee > current_dao_fee {
+        let mut dao = settings.get_dao_mut();
+        dao.dao_fee = new_dao_fee;
+    }
+
+    merge_settings_safely(settings, new_settings.merge_json.clone());
+}
+
+/// The change a release feed update would make, if any
+fn release_feed_change(val: &Option<String>) -> Option<SettingChange> {
+    let new_feed: ReleaseStatus = val.as_ref()?.parse().ok()?;
+    let old_feed = get_release_feed().ok()?;
+    if new_feed == old_feed {
+        return None;
+    }
+    Some(SettingChange {
+        path: vec![RELEASE_FEED_PATH.to_string()],
+        old: serde_json::to_value(old_feed).ok()?,
+        new: serde_json::to_value(new_feed).ok()?,
+    })
+}
+
+/// Records an oracle update that was not applied so it can be shown on the dashboard
+fn reject_update(url: Option<&str>, reason: String) {
+    error!("Rejected oracle update from {:?}: {}", url, reason);
+    let mut rejected = REJECTED_UPDATES.write().unwrap();
+    rejected.push_front(RejectedOracleUpdate {
+        time: now(),
+        url: url.map(|url| url.to_string()),
+        reason,
+    });
+    rejected.truncate(MAX_REJECTED_UPDATES);
+}
+
+/// The most recently rejected oracle updates, newest first
+pub fn get_rejected_updates() -> Vec<RejectedOracleUpdate> {
+    REJECTED_UPDATES.read().unwrap().iter().cloned().collect()
 }
 
 /// A very simple function placed here for convinence that indicates
@@ -438,6 +750,16 @@ pub fn low_balance() -> bool {
     balance < balance_warning_level
 }
 
+/// Indicates if we pay in a token and our native balance is too low to keep paying gas, this
+/// is separate from low_balance because topping it up takes a different coin
+pub fn low_gas_balance() -> bool {
+    let payment_settings = SETTING.get_payment();
+    match &payment_settings.payment_token {
+        Some(token) => payment_settings.gas_balance < token.gas_warning_level,
+        None => false,
+    }
+}
+
 /// Allows for online updating of the release feed, note that this not run
 /// on every device startup meaning just editing it the config is not sufficient
 fn handle_release_feed_update(val: Option<String>) {
@@ -464,13 +786,17 @@ fn handle_release_feed_update(val: Option<String>) {
 
 /// Merges an arbitrary settings string, after first filtering for several
 /// forbidden values
-fn merge_settings_safely(new_settings: Value) {
+fn merge_settings_safely<T>(settings: &Arc<RwLock<T>>, new_settings: Value)
+where
+    T: Serialize + Deserialize<'static>,
+    Arc<RwLock<T>>: RitaCommonSettings<T>,
+{
     // merge in arbitrary setting change string if it's not blank
     if new_settings != "" {
         if let Value::Object(map) = new_settings.clone() {
             let contains_forbidden_key = contains_forbidden_key(map, &FORBIDDEN_MERGE_VALUES);
             if !contains_forbidden_key {
-                match SETTING.merge(new_settings.clone()) {
+                match settings.merge(new_settings.clone()) {
                     Ok(_) => trace!("Merged new settings successfully {:?}", new_settings),
                     Err(e) => error!("Failed to merge oracle settings {:?} {:?}", new_settings, e),
                 }
@@ -511,6 +837,7 @@ fn contains_forbidden_key(map: Map<String, Value>, forbidden_values: &[&str]) ->
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clarity::PrivateKey;
 
     const FORBIDDEN_MERGE_VALUES: [&str; 2] = ["test_key", "other_test_key"];
 
@@ -532,4 +859,126 @@ mod tests {
             panic!("Not a json map!");
         }
     }
+
+    #[test]
+    fn test_fund_settings_are_forbidden() {
+        // an oracle that could set up a sweep could send every router's balance to itself
+        let object = json!({"payment": {"sweep": {"enabled": true, "destination": "0x0000000000000000000000000000000000000001", "interval": 1}}});
+        if let Value::Object(map) = object {
+            assert!(contains_forbidden_key(map, &super::FORBIDDEN_MERGE_VALUES));
+        } else {
+            panic!("Not a json map!");
+        }
+
+        for key in &["withdraw_policy", "withdraw_queue_file", "payment_token"] {
+            let object = json!({ "payment": { *key: {} } });
+            if let Value::Object(map) = object {
+                assert!(contains_forbidden_key(map, &super::FORBIDDEN_MERGE_VALUES));
+            } else {
+                panic!("Not a json map!");
+            }
+        }
+    }
+
+    #[test]
+    fn test_oracle_settings_are_forbidden() {
+        // an oracle that could point the history elsewhere could make a rollback forgotten
+        for key in &["oracle_signers", "oracle_dry_run", "oracle_history_file"] {
+            let object = json!({ "dao": { *key: {} } });
+            if let Value::Object(map) = object {
+                assert!(contains_forbidden_key(map, &super::FORBIDDEN_MERGE_VALUES));
+            } else {
+                panic!("Not a json map!");
+            }
+        }
+    }
+
+    #[test]
+    fn test_update_hash() {
+        let update = test_update();
+        assert_eq!(update_hash(&update), update_hash(&update.clone()));
+        assert_ne!(
+            update_hash(&update),
+            update_hash(&update.replace("\"client\":1", "\"client\":100"))
+        );
+        assert!(update_hash(&update).starts_with("0x"));
+        assert_eq!(update_hash(&update).len(), 66);
+    }
+
+    fn test_update() -> String {
+        json!({
+            "client": 1,
+            "gateway": 2,
+            "max": 3,
+            "dao_fee": 4,
+            "warning": 5,
+            "system_chain": null,
+            "withdraw_chain": null,
+            "release_feed": null,
+            "merge_json": {},
+            "sequence": 7
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_verify_oracle_update() {
+        let key: PrivateKey = "0x0101010101010101010101010101010101010101010101010101010101010101"
+            .parse()
+            .unwrap();
+        let other: PrivateKey =
+            "0x0202020202020202020202020202020202020202020202020202020202020202"
+                .parse()
+                .unwrap();
+        let update = test_update();
+        let hash = Keccak256::digest(update.as_bytes());
+        let signed = SignedOracleUpdate {
+            update: update.clone(),
+            signatures: vec![key.sign_hash(&hash)],
+        };
+        let signers = vec![key.to_public_key().unwrap()];
+        let other_signers = vec![other.to_public_key().unwrap()];
+
+        assert_eq!(verify_oracle_update(&signed, &signers).unwrap().client, 1);
+        assert!(verify_oracle_update(&signed, &other_signers).is_err());
+
+        // the signature doesn't carry over to a modified update
+        let tampered = SignedOracleUpdate {
+            update: update.replace("\"client\":1", "\"client\":100"),
+            signatures: signed.signatures.clone(),
+        };
+        assert!(verify_oracle_update(&tampered, &signers).is_err());
+
+        // legacy unsigned updates only pass when no signers are configured
+        let unsigned = parse_oracle_update(update.as_bytes()).unwrap();
+        assert!(unsigned.signatures.is_empty());
+        assert!(verify_oracle_update(&unsigned, &signers).is_err());
+        assert!(verify_oracle_update(&unsigned, &[]).is_ok());
+
+        // a signed update has to carry a sequence to be checked against
+        let update = test_update().replace("\"sequence\":7", "\"sequence\":0");
+        let hash = Keccak256::digest(update.as_bytes());
+        let unsequenced = SignedOracleUpdate {
+            update,
+            signatures: vec![key.sign_hash(&hash)],
+        };
+        assert!(verify_oracle_update(&unsequenced, &signers).is_err());
+    }
+
+    #[test]
+    fn test_is_newer() {
+        let update: OracleUpdate = serde_json::from_str(&test_update()).unwrap();
+        assert!(is_newer(&update, 0).unwrap());
+        assert!(is_newer(&update, 6).unwrap());
+        // served again after it was applied
+        assert!(!is_newer(&update, 7).unwrap());
+        // an old update replayed after a newer one
+        assert!(is_newer(&update, 8).is_err());
+
+        // unsigned updates from older oracles have no sequence and are always applied
+        let legacy: OracleUpdate =
+            serde_json::from_str(&test_update().replace(",\"sequence\":7", "")).unwrap();
+        assert_eq!(legacy.sequence, 0);
+        assert!(is_newer(&legacy, 0).unwrap());
+    }
 }
@@ -6,6 +6,7 @@
 use crate::rita_common::debt_keeper::DebtKeeper;
 use crate::rita_common::debt_keeper::PaymentFailed;
 use crate::rita_common::oracle::trigger_update_nonce;
+use crate::rita_common::payment_token::{payment_transaction, transfer_gas};
 use crate::rita_common::payment_validator::{PaymentValidator, ToValidate, ValidateLater};
 use crate::rita_common::rita_loop::get_web3_server;
 use crate::SETTING;
@@ -13,7 +14,7 @@ use actix::prelude::{Actor, Arbiter, Context, Handler, Message, Supervised, Syst
 use actix_web::client;
 use actix_web::client::Connection;
 use althea_types::PaymentTx;
-use clarity::Transaction;
+use clarity::Address;
 use failure::Error;
 use futures01::future::Either;
 use futures01::{future, Future};
@@ -27,6 +28,7 @@ use web30::client::Web3;
 
 pub const TRANSACTION_SUBMISSON_TIMEOUT: Duration = Duration::from_secs(15);
 pub const MAX_TXID_RETRIES: u8 = 15u8;
+pub const WITHDRAW_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct PaymentController();
 
@@ -71,15 +73,21 @@ fn make_payment(mut pmt: PaymentTx) -> Result<(), Error> {
     let payment_settings = SETTING.get_payment();
     let balance = payment_settings.balance.clone();
     let nonce = payment_settings.nonce.clone();
+    let gas_balance = payment_settings.gas_balance.clone();
     let gas_price = payment_settings.gas_price.clone();
+    let token = payment_settings.payment_token.clone();
     let our_address = payment_settings.eth_address.unwrap();
     info!(
         "current balance: {:?}, payment of {:?}, from address {} to address {} with nonce {}",
         balance, pmt.amount, our_address, pmt.to.eth_address, nonce
     );
+    let gas_cost = gas_price.clone() * Uint256::from(transfer_gas(&token));
     if balance < pmt.amount {
         warn!("Not enough money to pay debts! Cutoff immenient");
         bail!("Not enough money!")
+    } else if token.is_some() && gas_balance < gas_cost {
+        warn!("Not enough gas to pay debts in tokens! Cutoff immenient");
+        bail!("Not enough gas!")
     } else if pmt.amount == 0u32.into() {
         error!("Trying to pay nothing!");
         bail!("Zero payment!");
@@ -113,15 +121,13 @@ fn make_payment(mut pmt: PaymentTx) -> Result<(), Error> {
     let full_node = get_web3_server();
     let web3 = Web3::new(&full_node, TRANSACTION_SUBMISSON_TIMEOUT);
 
-    let tx = Transaction {
+    let tx = payment_transaction(
+        &token,
+        pmt.to.eth_address,
+        pmt.amount.clone(),
         nonce,
         gas_price,
-        gas_limit: "21000".parse().unwrap(),
-        to: pmt.to.eth_address,
-        value: pmt.amount.clone(),
-        data: Vec::new(),
-        signature: None,
-    };
+    );
     let transaction_signed = tx.sign(
         &payment_settings
             .eth_private_key
@@ -232,6 +238,62 @@ fn make_payment(mut pmt: PaymentTx) -> Result<(), Error> {
     Ok(())
 }
 
+/// Sends `amount` to `to` on the system chain, in the payment token if one is configured,
+/// returning the txid. Used for withdraws, which unlike payments have no neighbor to notify
+pub fn send_withdraw(
+    to: Address,
+    amount: Uint256,
+) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    let payment_settings = SETTING.get_payment();
+    let our_address = match payment_settings.eth_address {
+        Some(address) => address,
+        None => {
+            return Box::new(future::err(format_err!(
+                "No Address configured, withdraw impossible!"
+            )))
+        }
+    };
+    let tx = payment_transaction(
+        &payment_settings.payment_token,
+        to,
+        amount,
+        payment_settings.nonce.clone(),
+        payment_settings.gas_price.clone(),
+    );
+    let transaction_signed = tx.sign(
+        &payment_settings
+            .eth_private_key
+            .expect("No private key configured!"),
+        payment_settings.net_version,
+    );
+    drop(payment_settings);
+
+    let transaction_bytes = match transaction_signed.to_bytes() {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Box::new(future::err(format_err!(
+                "Transaction to bytes failed! {:?}",
+                e
+            )))
+        }
+    };
+
+    let full_node = get_web3_server();
+    let web3 = Web3::new(&full_node, WITHDRAW_TIMEOUT);
+    let transaction_status = web3.eth_send_raw_transaction(transaction_bytes);
+
+    Box::new(transaction_status.then(move |result| match result {
+        Ok(tx_id) => {
+            SETTING.get_payment_mut().nonce += 1u64.into();
+            Ok(tx_id)
+        }
+        Err(e) => {
+            trigger_update_nonce(our_address, &web3, full_node);
+            Err(e)
+        }
+    }))
+}
+
 struct ResendInfo {
     txid: Uint256,
     contact_socket: SocketAddr,
@@ -0,0 +1,225 @@
+//! Payments in an ERC-20 token rather than the native coin of the system chain. When a payment
+//! token is configured every outgoing payment (bandwidth, DAO fees, simulated tx fees and
+//! withdraws) becomes a call to the token's transfer function, `balance` holds our token balance
+//! and gas is still paid in the native coin, which is tracked separately as `gas_balance`.
+//!
+//! A token transfer carries no value of its own, so incoming payments are validated against the
+//! Transfer event the token contract emits rather than the value of the transaction.
+
+use crate::SETTING;
+use clarity::{Address, Transaction};
+use failure::Error;
+use futures01::Future;
+use num256::Uint256;
+use settings::payment::PaymentToken;
+use settings::RitaCommonSettings;
+use web30::client::Web3;
+use web30::types::{NewFilter, TransactionResponse};
+
+/// The function selector of transfer(address,uint256)
+const TRANSFER_SELECTOR: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb];
+/// The topic of Transfer(address,address,uint256), the keccak256 hash of the signature
+const TRANSFER_EVENT_TOPIC: [u8; 32] = [
+    0xdd, 0xf2, 0x52, 0xad, 0x1b, 0xe2, 0xc8, 0x9b, 0x69, 0xc2, 0xb0, 0x68, 0xfc, 0x37, 0x8d, 0xaa,
+    0x95, 0x2b, 0xa7, 0xf1, 0x63, 0xc4, 0xa1, 0x16, 0x28, 0xf5, 0x5a, 0x4d, 0xf5, 0x23, 0xb3, 0xef,
+];
+const BALANCE_OF_SIG: &str = "balanceOf(address)";
+/// The gas used by a plain transfer of the native coin
+pub const NATIVE_TRANSFER_GAS: u64 = 21_000;
+
+/// A movement of funds, either the value of a native coin transaction or a token Transfer event
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub from: Address,
+    pub to: Address,
+    pub amount: Uint256,
+}
+
+impl Transfer {
+    pub fn from_transaction(transaction: &TransactionResponse) -> Transfer {
+        Transfer {
+            from: transaction.from,
+            to: transaction.to,
+            amount: transaction.value.clone(),
+        }
+    }
+}
+
+/// The configured payment token, if any
+pub fn get_payment_token() -> Option<PaymentToken> {
+    SETTING.get_payment().payment_token.clone()
+}
+
+/// The gas limit of a single payment, a token transfer costs more than a native one
+pub fn transfer_gas(token: &Option<PaymentToken>) -> u64 {
+    match token {
+        Some(token) => token.transfer_gas,
+        None => NATIVE_TRANSFER_GAS,
+    }
+}
+
+/// Builds an unsigned payment of `amount` to `to`, a call to the token's transfer function
+/// if a payment token is configured and a plain transfer of the native coin otherwise
+pub fn payment_transaction(
+    token: &Option<PaymentToken>,
+    to: Address,
+    amount: Uint256,
+    nonce: Uint256,
+    gas_price: Uint256,
+) -> Transaction {
+    match token {
+        Some(token) => Transaction {
+            nonce,
+            gas_price,
+            gas_limit: token.transfer_gas.into(),
+            to: token.contract_address,
+            value: 0u32.into(),
+            data: encode_transfer(to, &amount),
+            signature: None,
+        },
+        None => Transaction {
+            nonce,
+            gas_price,
+            gas_limit: NATIVE_TRANSFER_GAS.into(),
+            to,
+            value: amount,
+            data: Vec::new(),
+            signature: None,
+        },
+    }
+}
+
+/// The calldata of transfer(to, amount)
+pub fn encode_transfer(to: Address, amount: &Uint256) -> Vec<u8> {
+    let mut data = TRANSFER_SELECTOR.to_vec();
+    data.extend_from_slice(&pad_left(to.as_bytes()));
+    data.extend_from_slice(&pad_left(&amount.to_bytes_be()));
+    data
+}
+
+/// Left pads a value to an ABI word, values longer than a word can't occur for addresses
+/// or Uint256 values
+fn pad_left(bytes: &[u8]) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[32 - bytes.len()..].copy_from_slice(bytes);
+    word
+}
+
+/// Decodes a Transfer event from its topics and data, None if the log is something else
+pub fn decode_transfer_event(topics: &[Vec<u8>], data: &[u8]) -> Option<Transfer> {
+    if topics.len() != 3 || topics[0][..] != TRANSFER_EVENT_TOPIC[..] || data.len() != 32 {
+        return None;
+    }
+    let address = |topic: &Vec<u8>| {
+        if topic.len() != 32 || topic[..12].iter().any(|byte| *byte != 0) {
+            return None;
+        }
+        Address::from_slice(&topic[12..]).ok()
+    };
+    Some(Transfer {
+        from: address(&topics[1])?,
+        to: address(&topics[2])?,
+        amount: Uint256::from_bytes_be(data),
+    })
+}
+
+/// Gets the token balance of `owner`
+pub fn get_token_balance(
+    web3: &Web3,
+    token: Address,
+    owner: Address,
+) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    Box::new(
+        web3.contract_call(token, BALANCE_OF_SIG, &[owner.into()], owner)
+            .and_then(|bytes| {
+                if bytes.len() != 32 {
+                    bail!("Bad balanceOf response {:?}", bytes);
+                }
+                Ok(Uint256::from_bytes_be(&bytes))
+            }),
+    )
+}
+
+/// Gets the token transfers made by the transaction `txid`, which was included in `block`
+pub fn get_token_transfers(
+    web3: &Web3,
+    token: Address,
+    txid: Uint256,
+    block: Uint256,
+) -> Box<dyn Future<Item = Vec<Transfer>, Error = Error>> {
+    let block = format!("{:#x}", block);
+    let topic = format!("0x{}", hex_encode(&TRANSFER_EVENT_TOPIC));
+    Box::new(
+        web3.eth_get_logs(NewFilter {
+            from_block: Some(block.clone()),
+            to_block: Some(block),
+            address: vec![token],
+            topics: Some(vec![Some(vec![Some(topic)])]),
+        })
+        .and_then(move |logs| {
+            Ok(logs
+                .iter()
+                .filter(|log| match &log.transaction_hash {
+                    Some(hash) => Uint256::from_bytes_be(&hash.0) == txid,
+                    None => false,
+                })
+                .filter_map(|log| {
+                    let topics: Vec<Vec<u8>> =
+                        log.topics.iter().map(|topic| topic.0.clone()).collect();
+                    decode_transfer_event(&topics, &log.data.0)
+                })
+                .collect())
+        }),
+    )
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(last: u8) -> Address {
+        let mut bytes = [0u8; 20];
+        bytes[19] = last;
+        Address::from_slice(&bytes).unwrap()
+    }
+
+    fn word(bytes: &[u8]) -> Vec<u8> {
+        pad_left(bytes).to_vec()
+    }
+
+    #[test]
+    fn test_encode_transfer() {
+        let data = encode_transfer(address(7), &1_000_000u32.into());
+        assert_eq!(data.len(), 4 + 32 + 32);
+        assert_eq!(data[..4], TRANSFER_SELECTOR[..]);
+        assert_eq!(data[4..36], word(&[7])[..]);
+        assert_eq!(data[36..], word(&[0x0f, 0x42, 0x40])[..]);
+    }
+
+    #[test]
+    fn test_decode_transfer_event() {
+        let topics = vec![TRANSFER_EVENT_TOPIC.to_vec(), word(&[1]), word(&[2])];
+        let data = word(&[0x01, 0x00]);
+        assert_eq!(
+            decode_transfer_event(&topics, &data),
+            Some(Transfer {
+                from: address(1),
+                to: address(2),
+                amount: 256u32.into(),
+            })
+        );
+
+        // an Approval event has the same shape but a different topic
+        let mut approval = topics.clone();
+        approval[0] = word(&[9]);
+        assert_eq!(decode_transfer_event(&approval, &data), None);
+        // an ERC-721 Transfer shares the topic but indexes the token id
+        let mut nft = topics.clone();
+        nft.push(word(&[5]));
+        assert_eq!(decode_transfer_event(&nft, &[]), None);
+    }
+}
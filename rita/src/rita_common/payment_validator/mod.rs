@@ -10,6 +10,7 @@
 use crate::rita_common::debt_keeper::DebtKeeper;
 use crate::rita_common::debt_keeper::PaymentReceived;
 use crate::rita_common::debt_keeper::PaymentSucceeded;
+use crate::rita_common::payment_token::{get_payment_token, get_token_transfers, Transfer};
 use crate::rita_common::rita_loop::fast_loop::FAST_LOOP_TIMEOUT;
 use crate::rita_common::rita_loop::get_web3_server;
 use crate::rita_common::usage_tracker::UpdatePayments;
@@ -17,16 +18,18 @@ use crate::rita_common::usage_tracker::UsageTracker;
 use crate::SETTING;
 use actix::{Actor, Arbiter, Context, Handler, Message, Supervised, SystemService};
 use althea_types::PaymentTx;
+use clarity::Address;
 use failure::Error;
-use futures01::Future;
+use futures01::future::Either;
+use futures01::{future, Future};
 use num256::Uint256;
+use settings::payment::PaymentToken;
 use settings::RitaCommonSettings;
 use std::collections::HashSet;
 use std::fmt;
 use std::time::{Duration, Instant};
 use tokio::util::FutureExt;
 use web30::client::Web3;
-use web30::types::TransactionResponse;
 
 pub const TRANSACTION_VERIFICATION_TIMEOUT: Duration = FAST_LOOP_TIMEOUT;
 
@@ -239,10 +242,55 @@ pub fn validate_transaction(ts: &ToValidate) {
                 });
             }
 
-            if let Some(transaction) = tx_status {
-                handle_tx_messaging(txid, transaction, long_life_ts, block_num);
+            match (tx_status, get_payment_token()) {
+                (Some(transaction), None) => {
+                    let transfer = Transfer::from_transaction(&transaction);
+                    handle_tx_messaging(
+                        txid,
+                        transfer,
+                        transaction.block_number,
+                        long_life_ts,
+                        block_num,
+                    );
+                    Either::A(future::ok(()))
+                }
+                (Some(transaction), Some(token)) => {
+                    if !is_token_call(transaction.to, &token) {
+                        error!("Transaction is not a call to the payment token!");
+                        PaymentValidator::from_registry().do_send(Remove {
+                            tx: long_life_ts,
+                            success: false,
+                        });
+                        return Either::A(future::ok(()));
+                    }
+                    // the Transfer event only exists once the transaction is in a block
+                    let tx_block = match transaction.block_number {
+                        Some(tx_block) => tx_block,
+                        None => return Either::A(future::ok(())),
+                    };
+                    let web3 = Web3::new(&full_node, TRANSACTION_VERIFICATION_TIMEOUT);
+                    Either::B(
+                        get_token_transfers(
+                            &web3,
+                            token.contract_address,
+                            txid.clone(),
+                            tx_block.clone(),
+                        )
+                        .and_then(move |transfers| {
+                            handle_token_transfers(
+                                txid,
+                                transfers,
+                                tx_block,
+                                long_life_ts,
+                                block_num,
+                            );
+                            Ok(())
+                        })
+                        .timeout(TRANSACTION_VERIFICATION_TIMEOUT),
+                    )
+                }
+                (None, _) => Either::A(future::ok(())),
             }
-            Ok(())
         })
         .then(|res| {
             if let Err(e) = res {
@@ -257,11 +305,89 @@ pub fn validate_transaction(ts: &ToValidate) {
     Arbiter::spawn(res);
 }
 
+/// Only the payment token's own Transfer events count, any other contract can emit one
+fn is_token_call(called: Address, token: &PaymentToken) -> bool {
+    called == token.contract_address
+}
+
+/// Picks the Transfer event that pays for a payment out of those emitted by a token transaction
+fn find_transfer(transfers: Vec<Transfer>, from: Address, to: Address) -> Option<Transfer> {
+    transfers
+        .into_iter()
+        .find(|transfer| transfer.from == from && transfer.to == to)
+}
+
+/// Validates a token transaction by the Transfer event that pays for this payment. A
+/// transaction that made it into a block without one most likely reverted
+fn handle_token_transfers(
+    txid: Uint256,
+    transfers: Vec<Transfer>,
+    tx_block: Uint256,
+    ts: ToValidate,
+    current_block: Uint256,
+) {
+    let from = ts.payment.from.eth_address;
+    let to = ts.payment.to.eth_address;
+    match find_transfer(transfers, from, to) {
+        Some(transfer) => handle_tx_messaging(txid, transfer, Some(tx_block), ts, current_block),
+        None => {
+            error!(
+                "Token transaction {:#066x} has no matching Transfer event!",
+                txid
+            );
+            PaymentValidator::from_registry().do_send(Remove {
+                tx: ts,
+                success: false,
+            });
+        }
+    }
+}
+
+/// What a transfer found on chain means for the payment it's supposed to carry
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TransferCheck {
+    /// we where successfully paid
+    Received,
+    /// we successfully paid someone
+    Sent,
+    /// not confirmed yet, check again later
+    Waiting,
+    /// the transfer can't be this payment
+    Invalid(&'static str),
+}
+
+fn check_transfer(
+    transfer: &Transfer,
+    amount: &Uint256,
+    our_address: Address,
+    tx_block: Option<Uint256>,
+    current_block: Uint256,
+) -> TransferCheck {
+    let to_us = transfer.to == our_address;
+    let from_us = transfer.from == our_address;
+    let is_in_chain = payment_in_chain(current_block.clone(), tx_block.clone());
+
+    if transfer.amount != *amount {
+        return TransferCheck::Invalid("Transaction with invalid amount!");
+    }
+    if payment_is_old(current_block, tx_block) {
+        return TransferCheck::Invalid("Transaction is more than 6 hours old!");
+    }
+    match (to_us, from_us, is_in_chain) {
+        (true, false, true) => TransferCheck::Received,
+        (false, true, true) => TransferCheck::Sent,
+        (true, true, _) => TransferCheck::Invalid("Transaction to ourselves!"),
+        (false, false, _) => TransferCheck::Invalid("Transaction has nothing to do with us?"),
+        (_, _, false) => TransferCheck::Waiting,
+    }
+}
+
 /// Handles the tx response from the full node and it's various cases
 /// pulled out of validate_transaction purely for cosmetic reasons
 fn handle_tx_messaging(
     txid: Uint256,
-    transaction: TransactionResponse,
+    transfer: Transfer,
+    tx_block: Option<Uint256>,
     ts: ToValidate,
     current_block: Uint256,
 ) {
@@ -270,33 +396,8 @@ fn handle_tx_messaging(
     let pmt = ts.payment.clone();
     let our_address = SETTING.get_payment().eth_address.expect("No Address!");
 
-    let to_us = transaction.to == our_address;
-    let from_us = transaction.from == our_address;
-    let value_correct = transaction.value == amount;
-    let is_in_chain = payment_in_chain(current_block.clone(), transaction.block_number.clone());
-    let is_old = payment_is_old(current_block, transaction.block_number);
-
-    if !value_correct {
-        error!("Transaction with invalid amount!");
-        PaymentValidator::from_registry().do_send(Remove {
-            tx: ts,
-            success: false,
-        });
-        return;
-    }
-
-    if is_old {
-        error!("Transaction is more than 6 hours old! {:#066x}", txid);
-        PaymentValidator::from_registry().do_send(Remove {
-            tx: ts,
-            success: false,
-        });
-        return;
-    }
-
-    match (to_us, from_us, is_in_chain) {
-        // we where successfully paid
-        (true, false, true) => {
+    match check_transfer(&transfer, &amount, our_address, tx_block, current_block) {
+        TransferCheck::Received => {
             let res = PaymentValidator::from_registry()
                 .send(Remove {
                     tx: ts,
@@ -326,8 +427,7 @@ fn handle_tx_messaging(
                 .then(|_| Ok(()));
             Arbiter::spawn(res);
         }
-        // we suceessfully paid someone
-        (false, true, true) => {
+        TransferCheck::Sent => {
             info!(
                 "payment {:#066x} from {} for {} wei successfully sent!",
                 txid, from_address, amount
@@ -351,21 +451,14 @@ fn handle_tx_messaging(
                 .then(|_| Ok(()));
             Arbiter::spawn(res);
         }
-        (true, true, _) => {
-            error!("Transaction to ourselves!");
-            PaymentValidator::from_registry().do_send(Remove {
-                tx: ts,
-                success: false,
-            });
-        }
-        (false, false, _) => {
-            error!("Transaction has nothing to do with us?");
+        TransferCheck::Invalid(reason) => {
+            error!("{} {:#066x}", reason, txid);
             PaymentValidator::from_registry().do_send(Remove {
                 tx: ts,
                 success: false,
             });
         }
-        (_, _, false) => {
+        TransferCheck::Waiting => {
             //transaction waiting for validation, do nothing
         }
     }
@@ -408,3 +501,109 @@ fn print_txids(list: &HashSet<ToValidate>) -> String {
     }
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(last: u8) -> Address {
+        let mut bytes = [0u8; 20];
+        bytes[19] = last;
+        Address::from_slice(&bytes).unwrap()
+    }
+
+    fn token() -> PaymentToken {
+        PaymentToken {
+            contract_address: address(9),
+            symbol: "DAI".to_string(),
+            transfer_gas: 60_000,
+            gas_warning_level: 0u32.into(),
+        }
+    }
+
+    fn transfer(from: u8, to: u8, amount: u32) -> Transfer {
+        Transfer {
+            from: address(from),
+            to: address(to),
+            amount: amount.into(),
+        }
+    }
+
+    #[test]
+    fn test_token_call() {
+        assert!(is_token_call(address(9), &token()));
+        // a contract of the payer's choosing can emit whatever Transfer events it likes
+        assert!(!is_token_call(address(8), &token()));
+    }
+
+    #[test]
+    fn test_find_transfer() {
+        let transfers = vec![transfer(1, 3, 100), transfer(1, 2, 100)];
+        assert_eq!(
+            find_transfer(transfers.clone(), address(1), address(2)),
+            Some(transfer(1, 2, 100))
+        );
+        // the transaction moved tokens, but not from the payer to the payee
+        assert_eq!(find_transfer(transfers, address(2), address(1)), None);
+        // a reverted transaction emits nothing
+        assert_eq!(find_transfer(Vec::new(), address(1), address(2)), None);
+    }
+
+    #[test]
+    fn test_check_transfer() {
+        let us = address(2);
+        let confirmed = Some(100u32.into());
+        let current: Uint256 = 110u32.into();
+
+        assert_eq!(
+            check_transfer(
+                &transfer(1, 2, 100),
+                &100u32.into(),
+                us,
+                confirmed.clone(),
+                current.clone()
+            ),
+            TransferCheck::Received
+        );
+        assert_eq!(
+            check_transfer(
+                &transfer(2, 1, 100),
+                &100u32.into(),
+                us,
+                confirmed.clone(),
+                current.clone()
+            ),
+            TransferCheck::Sent
+        );
+        match check_transfer(
+            &transfer(1, 2, 99),
+            &100u32.into(),
+            us,
+            confirmed.clone(),
+            current.clone(),
+        ) {
+            TransferCheck::Invalid(_) => {}
+            check => panic!("unexpected check {:?}", check),
+        }
+        match check_transfer(
+            &transfer(1, 3, 100),
+            &100u32.into(),
+            us,
+            confirmed,
+            current.clone(),
+        ) {
+            TransferCheck::Invalid(_) => {}
+            check => panic!("unexpected check {:?}", check),
+        }
+        assert_eq!(
+            check_transfer(
+                &transfer(1, 2, 100),
+                &100u32.into(),
+                us,
+                Some(108u32.into()),
+                current
+            ),
+            TransferCheck::Waiting
+        );
+    }
+}
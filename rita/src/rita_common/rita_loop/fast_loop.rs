@@ -1,3 +1,4 @@
+use crate::rita_common::babel_manager;
 use crate::rita_common::debt_keeper::{DebtKeeper, SendUpdate};
 use crate::rita_common::network_monitor::NetworkInfo as NetworkMonitorTick;
 use crate::rita_common::network_monitor::NetworkMonitor;
@@ -14,10 +15,6 @@ use actix::{
     Actor, ActorContext, Addr, Arbiter, AsyncContext, Context, Handler, Message, Supervised,
     SystemService,
 };
-use babel_monitor::open_babel_stream;
-use babel_monitor::parse_neighs;
-use babel_monitor::parse_routes;
-use babel_monitor::start_connection;
 use failure::Error;
 use futures01::Future;
 use settings::RitaCommonSettings;
@@ -79,7 +76,6 @@ impl Message for Tick {
 impl Handler<Tick> for RitaFastLoop {
     type Result = Result<(), Error>;
     fn handle(&mut self, _: Tick, _ctx: &mut Context<Self>) -> Self::Result {
-        let babel_port = SETTING.get_network().babel_port;
         trace!("Common tick!");
 
         manage_gateway();
@@ -111,24 +107,19 @@ impl Handler<Tick> for RitaFastLoop {
                         start.elapsed().subsec_millis()
                     );
 
-                    open_babel_stream(babel_port)
-                        .from_err()
-                        .and_then(move |stream| {
-                            start_connection(stream).and_then(move |stream| {
-                                parse_routes(stream).and_then(move |routes| {
-                                    TrafficWatcher::from_registry()
-                                        .send(Watch::new(neighbors, routes.1))
-                                        .timeout(FAST_LOOP_TIMEOUT)
-                                        .then(move |_res| {
-                                            info!(
-                                                "TrafficWatcher completed in {}s {}ms",
-                                                neigh.elapsed().as_secs(),
-                                                neigh.elapsed().subsec_millis()
-                                            );
-                                            Ok(())
-                                        })
+                    babel_manager::get_routes()
+                        .and_then(move |routes| {
+                            TrafficWatcher::from_registry()
+                                .send(Watch::new(neighbors, routes))
+                                .timeout(FAST_LOOP_TIMEOUT)
+                                .then(move |_res| {
+                                    info!(
+                                        "TrafficWatcher completed in {}s {}ms",
+                                        neigh.elapsed().as_secs(),
+                                        neigh.elapsed().subsec_millis()
+                                    );
+                                    Ok(())
                                 })
-                            })
                         })
                         .then(|ret| {
                             if let Err(e) = ret {
@@ -143,21 +134,15 @@ impl Handler<Tick> for RitaFastLoop {
         Arbiter::spawn(TunnelManager::from_registry().send(GetNeighbors).then(
             move |rita_neighbors| {
                 let rita_neighbors = rita_neighbors.unwrap().unwrap();
-                open_babel_stream(babel_port)
-                    .from_err()
-                    .and_then(move |stream| {
-                        start_connection(stream).and_then(move |stream| {
-                            parse_routes(stream).and_then(move |(stream, babel_routes)| {
-                                parse_neighs(stream).and_then(move |(_stream, babel_neighbors)| {
-                                    NetworkMonitor::from_registry().do_send(NetworkMonitorTick {
-                                        rita_neighbors,
-                                        babel_routes,
-                                        babel_neighbors,
-                                    });
-                                    Ok(())
-                                })
-                            })
-                        })
+                babel_manager::get_routes()
+                    .join(babel_manager::get_neighbors())
+                    .and_then(move |(babel_routes, babel_neighbors)| {
+                        NetworkMonitor::from_registry().do_send(NetworkMonitorTick {
+                            rita_neighbors,
+                            babel_routes,
+                            babel_neighbors,
+                        });
+                        Ok(())
                     })
                     .then(|ret| {
                         if let Err(e) = ret {
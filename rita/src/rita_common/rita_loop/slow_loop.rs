@@ -1,19 +1,19 @@
+use crate::rita_common::babel_manager;
 use crate::rita_common::dao_manager::DAOManager;
 use crate::rita_common::dao_manager::Tick as DAOTick;
+use crate::rita_common::local_pricing;
 use crate::rita_common::simulated_txfee_manager::SimulatedTxFeeManager;
 use crate::rita_common::simulated_txfee_manager::Tick as TxFeeTick;
+use crate::rita_common::sweep;
 use crate::rita_common::token_bridge::Tick as TokenBridgeTick;
 use crate::rita_common::token_bridge::TokenBridge;
 use crate::rita_common::tunnel_manager::{TriggerGC, TunnelManager};
+use crate::rita_common::withdraw_policy;
 use crate::SETTING;
 use actix::{
     Actor, ActorContext, Addr, Arbiter, AsyncContext, Context, Handler, Message, Supervised,
     SystemService,
 };
-use babel_monitor::open_babel_stream;
-use babel_monitor::set_local_fee;
-use babel_monitor::set_metric_factor;
-use babel_monitor::start_connection;
 use failure::Error;
 use futures01::future::Future;
 use settings::RitaCommonSettings;
@@ -93,23 +93,22 @@ impl Handler<Tick> for RitaSlowLoop {
         // could catch the edge case where babel is restarted under us
         set_babel_price();
 
+        local_pricing::tick();
+
+        sweep::tick();
+
+        withdraw_policy::tick();
+
         Ok(())
     }
 }
 
 fn set_babel_price() {
-    let babel_port = SETTING.get_network().babel_port;
     let local_fee = SETTING.get_payment().local_fee;
     let metric_factor = SETTING.get_network().metric_factor;
     Arbiter::spawn(
-        open_babel_stream(babel_port)
-            .from_err()
-            .and_then(move |stream| {
-                start_connection(stream).and_then(move |stream| {
-                    set_local_fee(stream, local_fee)
-                        .and_then(move |stream| Ok(set_metric_factor(stream, metric_factor)))
-                })
-            })
+        babel_manager::set_local_fee(local_fee)
+            .and_then(move |_| babel_manager::set_metric_factor(metric_factor))
             .timeout(SLOW_LOOP_TIMEOUT)
             .then(|res| {
                 if let Err(e) = res {
@@ -1,6 +1,7 @@
 //! The maintainer fee is a fraction of all payments that is sent to the firmware maintainer
 
 use crate::rita_common::payment_controller::TRANSACTION_SUBMISSON_TIMEOUT;
+use crate::rita_common::payment_token::payment_transaction;
 use crate::rita_common::rita_loop::get_web3_server;
 use crate::rita_common::usage_tracker::UpdatePayments;
 use crate::rita_common::usage_tracker::UsageTracker;
@@ -8,7 +9,6 @@ use crate::SETTING;
 use actix::{Actor, Arbiter, Context, Handler, Message, Supervised, SystemService};
 use althea_types::Identity;
 use althea_types::PaymentTx;
-use clarity::Transaction;
 use futures01::future::Future;
 use num256::Uint256;
 use num_traits::Signed;
@@ -110,6 +110,7 @@ impl Handler<Tick> for SimulatedTxFeeManager {
         let amount_to_pay = self.amount_owed.clone();
         let should_pay = amount_to_pay > pay_threshold.abs().to_uint256().unwrap();
         let net_version = payment_settings.net_version;
+        let payment_token = payment_settings.payment_token.clone();
         drop(payment_settings);
         trace!(
             "We should pay the simulated tx fee {} of 1/{} % to {}",
@@ -135,15 +136,13 @@ impl Handler<Tick> for SimulatedTxFeeManager {
         let full_node = get_web3_server();
         let web3 = Web3::new(&full_node, TRANSACTION_SUBMISSON_TIMEOUT);
 
-        let tx = Transaction {
+        let tx = payment_transaction(
+            &payment_token,
+            simulated_transaction_fee_address,
+            amount_to_pay.clone(),
             nonce,
             gas_price,
-            gas_limit: "21000".parse().unwrap(),
-            to: simulated_transaction_fee_address,
-            value: amount_to_pay.clone(),
-            data: Vec::new(),
-            signature: None,
-        };
+        );
         let transaction_signed = tx.sign(
             &eth_private_key.expect("No private key configured!"),
             net_version,
@@ -0,0 +1,205 @@
+//! Sweeps move our earnings to another address automatically, so that exit and relay operators
+//! don't have to withdraw by hand. Each slow loop tick checks whether a sweep is due, either the
+//! configured interval has passed since the last one or the balance has reached the threshold,
+//! and if so withdraws everything above the reserve. Withdraws on the system chain are sent
+//! directly, an xDai router withdrawing to Ethereum goes through the token bridge. Sweeps go to
+//! a destination that can't be changed from the dashboard so they skip the withdraw policy.
+//! Every sweep is recorded in the usage tracker's payment history.
+
+use crate::rita_common::oracle::history::now;
+use crate::rita_common::usage_tracker::{UpdatePayments, UsageTracker};
+use crate::rita_common::withdraw_policy::{dispatch_withdraw, withdraw_gas_cost, WithdrawKind};
+use crate::SETTING;
+use actix::{Arbiter, SystemService};
+use althea_types::{Identity, PaymentTx};
+use clarity::Address;
+use futures01::Future;
+use num256::Uint256;
+use settings::payment::{SweepReserve, SweepSettings};
+use settings::RitaCommonSettings;
+
+/// The least time between threshold sweeps, a sweep can take this long to show up in our
+/// balance when it goes through the bridge and we don't want to send it twice
+const THRESHOLD_SWEEP_COOLDOWN: u64 = 3600;
+
+/// How much of the balance a sweep leaves behind
+pub fn reserve_amount(reserve: &SweepReserve, balance_warning_level: &Uint256) -> Uint256 {
+    match reserve {
+        SweepReserve::WarningLevelMultiple(multiple) => {
+            balance_warning_level.clone() * Uint256::from(*multiple)
+        }
+        SweepReserve::Fixed(amount) => amount.clone(),
+    }
+}
+
+/// Whether the schedule or threshold calls for a sweep at `time`
+pub fn sweep_due(settings: &SweepSettings, balance: &Uint256, time: u64) -> bool {
+    let since_last = settings
+        .last_sweep
+        .map(|last| time.saturating_sub(last))
+        .unwrap_or(u64::max_value());
+    let scheduled = match settings.interval {
+        Some(interval) => since_last >= interval,
+        None => false,
+    };
+    let over_threshold = match &settings.threshold {
+        Some(threshold) => balance >= threshold && since_last >= THRESHOLD_SWEEP_COOLDOWN,
+        None => false,
+    };
+    scheduled || over_threshold
+}
+
+/// The amount to sweep, what's left of the balance after the reserve and the gas for the
+/// sweep itself, None if that's less than the minimum sweep
+pub fn sweep_amount(
+    balance: &Uint256,
+    reserve: &Uint256,
+    gas_cost: &Uint256,
+    minimum: &Uint256,
+) -> Option<Uint256> {
+    let keep = reserve.clone() + gas_cost.clone();
+    if *balance <= keep {
+        return None;
+    }
+    let amount = balance.clone() - keep;
+    if amount < *minimum {
+        None
+    } else {
+        Some(amount)
+    }
+}
+
+/// Checks if a sweep is due and starts it, called from the slow loop
+pub fn tick() {
+    let payment = SETTING.get_payment();
+    let settings = payment.sweep.clone();
+    let balance = payment.balance.clone();
+    let balance_warning_level = payment.balance_warning_level.clone();
+    drop(payment);
+
+    if !settings.enabled {
+        return;
+    }
+    let destination = match settings.destination {
+        Some(destination) => destination,
+        None => {
+            warn!("Sweeps are enabled without a destination!");
+            return;
+        }
+    };
+    let time = now();
+    if !sweep_due(&settings, &balance, time) {
+        return;
+    }
+
+    let gas_cost = withdraw_gas_cost();
+    let reserve = reserve_amount(&settings.reserve, &balance_warning_level);
+    let amount = match sweep_amount(&balance, &reserve, &gas_cost, &settings.minimum) {
+        Some(amount) => amount,
+        None => {
+            trace!("Sweep is due but {} is too little to sweep", balance);
+            return;
+        }
+    };
+
+    // recorded before the sweep goes out so that a slow or failed sweep isn't retried every tick
+    SETTING.get_payment_mut().sweep.last_sweep = Some(time);
+    info!("Sweeping {} to {}", amount, destination);
+    Arbiter::spawn(
+        dispatch_withdraw(destination, amount.clone(), WithdrawKind::Amount).then(move |res| {
+            match res {
+                Ok(txid) => {
+                    info!("Sweep of {} to {} is underway", amount, destination);
+                    record_sweep(destination, amount, txid);
+                }
+                Err(e) => error!("Failed to sweep {} to {} {:?}", amount, destination, e),
+            }
+            Ok(())
+        }),
+    )
+}
+
+/// Adds a sweep to the payment history, a bridged sweep has no txid of its own, its bridge
+/// transfer is in the token bridge history
+fn record_sweep(destination: Address, amount: Uint256, txid: Option<Uint256>) {
+    let our_id = match SETTING.get_identity() {
+        Some(id) => id,
+        None => return,
+    };
+    let destination = Identity {
+        eth_address: destination,
+        // this key has no meaning, it's here so that we don't have to change
+        // the identity indexing
+        wg_public_key: "YJhxFPv+NVeU5e+eBmwIXFd/pVdgk61jUHojuSt8IU0="
+            .parse()
+            .unwrap(),
+        mesh_ip: "::1".parse().unwrap(),
+        nickname: None,
+    };
+    UsageTracker::from_registry().do_send(UpdatePayments {
+        payment: PaymentTx {
+            to: destination,
+            from: our_id,
+            amount,
+            txid,
+        },
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_due() {
+        let mut settings = SweepSettings {
+            interval: Some(86400),
+            ..SweepSettings::default()
+        };
+        let balance: Uint256 = 100u32.into();
+
+        // a schedule that has never run is due right away
+        assert!(sweep_due(&settings, &balance, 1000));
+        settings.last_sweep = Some(1000);
+        assert!(!sweep_due(&settings, &balance, 2000));
+        assert!(sweep_due(&settings, &balance, 1000 + 86400));
+
+        // the threshold sweeps early, but not twice in a row
+        settings.threshold = Some(50u32.into());
+        assert!(!sweep_due(&settings, &balance, 2000));
+        assert!(sweep_due(
+            &settings,
+            &balance,
+            1000 + THRESHOLD_SWEEP_COOLDOWN
+        ));
+        assert!(!sweep_due(
+            &settings,
+            &10u32.into(),
+            1000 + THRESHOLD_SWEEP_COOLDOWN
+        ));
+
+        // nothing configured, nothing due
+        let settings = SweepSettings::default();
+        assert!(!sweep_due(&settings, &balance, 1000));
+    }
+
+    #[test]
+    fn test_sweep_amount() {
+        let reserve = reserve_amount(&SweepReserve::WarningLevelMultiple(2), &100u32.into());
+        assert_eq!(reserve, 200u32.into());
+        assert_eq!(
+            reserve_amount(&SweepReserve::Fixed(7u32.into()), &100u32.into()),
+            7u32.into()
+        );
+
+        let gas: Uint256 = 10u32.into();
+        let minimum: Uint256 = 50u32.into();
+        assert_eq!(
+            sweep_amount(&1000u32.into(), &reserve, &gas, &minimum),
+            Some(790u32.into())
+        );
+        // too little over the reserve to be worth the gas
+        assert_eq!(sweep_amount(&250u32.into(), &reserve, &gas, &minimum), None);
+        assert_eq!(sweep_amount(&100u32.into(), &reserve, &gas, &minimum), None);
+    }
+}
@@ -0,0 +1,407 @@
+//! A durable record of the deposits and withdraws made by the token bridge. Each operation is
+//! written to the bridge_history_file as it starts and again at every step, with the transaction
+//! hash of every leg we send ourselves: the xDai transfer into the bridge and the final eth
+//! transfer of a withdraw. The uniswap swaps and the Dai transfer into the bridge are made inside
+//! auto_bridge, which doesn't hand back their hashes, so those legs are recorded by amount.
+//!
+//! On startup a withdraw that was in progress is resumed from its last recorded step and a
+//! deposit is closed out, since the conveyor belt will pick up whatever it left behind. A withdraw
+//! that had already started its final transfer is never resumed, that transfer may have gone out.
+
+use super::DetailedBridgeState;
+use crate::SETTING;
+use clarity::Address;
+use failure::Error;
+use num256::Uint256;
+use settings::RitaCommonSettings;
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// How many operations are kept in the history
+const MAX_OPERATIONS: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BridgeOperationKind {
+    /// Eth, or Dai we found lying around, moving over to xDai
+    Deposit,
+    /// xDai moving back to Eth and out to `to`
+    Withdraw {
+        to: Address,
+        amount: Uint256,
+        withdraw_all: bool,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BridgeOperationStatus {
+    InProgress,
+    Complete,
+    Failed {
+        reason: String,
+    },
+    /// Given up on without an error, any funds left along the way are moved back into xDai by
+    /// the normal deposit process
+    Aborted {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BridgeStep {
+    /// seconds since the unix epoch
+    pub time: u64,
+    pub state: DetailedBridgeState,
+    pub txid: Option<Uint256>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BridgeOperation {
+    pub id: u64,
+    pub kind: BridgeOperationKind,
+    /// seconds since the unix epoch
+    pub started: u64,
+    pub finished: Option<u64>,
+    pub status: BridgeOperationStatus,
+    pub steps: Vec<BridgeStep>,
+}
+
+/// A withdraw that was in progress when we stopped and should be picked up again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumedWithdraw {
+    pub to: Address,
+    pub amount: Uint256,
+    pub withdraw_all: bool,
+    /// how long the withdraw has been running for
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BridgeHistory {
+    next_id: u64,
+    /// newest first, only the first operation may be in progress
+    operations: VecDeque<BridgeOperation>,
+}
+
+impl BridgeHistory {
+    pub fn operations(&self) -> Vec<BridgeOperation> {
+        self.operations.iter().cloned().collect()
+    }
+
+    /// The operation in progress, if there is one
+    pub fn current(&self) -> Option<&BridgeOperation> {
+        self.operations
+            .front()
+            .filter(|op| op.status == BridgeOperationStatus::InProgress)
+    }
+
+    /// The latest step of the operation in progress
+    pub fn last_step(&self) -> Option<&BridgeStep> {
+        self.current().and_then(|op| op.steps.last())
+    }
+
+    fn current_mut(&mut self) -> Option<&mut BridgeOperation> {
+        self.operations
+            .front_mut()
+            .filter(|op| op.status == BridgeOperationStatus::InProgress)
+    }
+
+    /// Starts a new operation, aborting the one in progress if there is one
+    pub fn start(&mut self, kind: BridgeOperationKind, time: u64) {
+        self.finish(
+            BridgeOperationStatus::Aborted {
+                reason: "Superseded by another operation".to_string(),
+            },
+            time,
+        );
+        self.operations.push_front(BridgeOperation {
+            id: self.next_id,
+            kind,
+            started: time,
+            finished: None,
+            status: BridgeOperationStatus::InProgress,
+            steps: Vec::new(),
+        });
+        self.next_id += 1;
+        self.operations.truncate(MAX_OPERATIONS);
+    }
+
+    /// Records a step of the operation in progress, returns false if nothing was recorded
+    /// because there is no operation or it's already at this step
+    pub fn add_step(&mut self, state: DetailedBridgeState, time: u64) -> bool {
+        match self.current_mut() {
+            Some(op) => {
+                if op.steps.last().map(|step| &step.state) == Some(&state) {
+                    return false;
+                }
+                op.steps.push(BridgeStep {
+                    time,
+                    state,
+                    txid: None,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Attaches a transaction hash to the latest step of the operation in progress
+    pub fn set_txid(&mut self, txid: Uint256) -> bool {
+        match self.current_mut().and_then(|op| op.steps.last_mut()) {
+            Some(step) => {
+                step.txid = Some(txid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ends the operation in progress. A deposit that never got past checking our balances
+    /// is the common case on every tick, so it's dropped rather than filling the history.
+    /// Returns false if there was nothing worth recording
+    pub fn finish(&mut self, status: BridgeOperationStatus, time: u64) -> bool {
+        let drop = match self.current_mut() {
+            Some(op) => {
+                op.status = status;
+                op.finished = Some(time);
+                op.kind == BridgeOperationKind::Deposit && op.steps.is_empty()
+            }
+            None => return false,
+        };
+        if drop {
+            self.operations.pop_front();
+        }
+        !drop
+    }
+
+    /// Decides what to do with the operation that was in progress when we stopped. Withdraws
+    /// that haven't timed out are handed back to be resumed from their last step, everything
+    /// else is ended. A withdraw whose final transfer was started is complete if the transfer
+    /// got a hash and aborted otherwise, either way the transfer is never sent twice
+    pub fn resume(&mut self, time: u64, timeout: Duration) -> Option<ResumedWithdraw> {
+        let (kind, started) = match self.current() {
+            Some(op) => (op.kind.clone(), op.started),
+            None => return None,
+        };
+        if let Some(BridgeStep {
+            state: DetailedBridgeState::EthToDest { .. },
+            txid,
+            ..
+        }) = self.last_step()
+        {
+            let status = match txid {
+                Some(_) => BridgeOperationStatus::Complete,
+                None => BridgeOperationStatus::Aborted {
+                    reason: "Interrupted while sending the final transfer, check the destination \
+                             before withdrawing again"
+                        .to_string(),
+                },
+            };
+            self.finish(status, time);
+            return None;
+        }
+        let elapsed = Duration::from_secs(time.saturating_sub(started));
+        match kind {
+            BridgeOperationKind::Withdraw {
+                to,
+                amount,
+                withdraw_all,
+            } if elapsed <= timeout => Some(ResumedWithdraw {
+                to,
+                amount,
+                withdraw_all,
+                elapsed,
+            }),
+            BridgeOperationKind::Withdraw { .. } => {
+                self.finish(
+                    BridgeOperationStatus::Aborted {
+                        reason: "Timed out while we were stopped".to_string(),
+                    },
+                    time,
+                );
+                None
+            }
+            BridgeOperationKind::Deposit => {
+                self.finish(
+                    BridgeOperationStatus::Aborted {
+                        reason: "Interrupted by a restart".to_string(),
+                    },
+                    time,
+                );
+                None
+            }
+        }
+    }
+}
+
+pub fn load_history() -> BridgeHistory {
+    let file = SETTING.get_payment().bridge_history_file.clone();
+    if file.is_empty() {
+        return BridgeHistory::default();
+    }
+    match fs::read(&file) {
+        Ok(contents) => match serde_json::from_slice(&contents) {
+            Ok(history) => history,
+            Err(e) => {
+                error!("Failed to deserialize token bridge history {:?}", e);
+                BridgeHistory::default()
+            }
+        },
+        Err(e) => {
+            info!("No token bridge history loaded {:?}", e);
+            BridgeHistory::default()
+        }
+    }
+}
+
+/// Writes the history out, through a synced temporary file so that a crash or power loss
+/// while saving can't lose the record of a withdraw in progress
+pub fn save_history(history: &BridgeHistory) -> Result<(), Error> {
+    let file = SETTING.get_payment().bridge_history_file.clone();
+    if file.is_empty() {
+        return Ok(());
+    }
+    let tmp = format!("{}.tmp", file);
+    let mut tmp_file = File::create(&tmp)?;
+    tmp_file.write_all(&serde_json::to_vec(history)?)?;
+    tmp_file.sync_all()?;
+    fs::rename(&tmp, &file)?;
+    // the rename itself only survives a power loss once the directory is synced
+    if let Some(dir) = Path::new(&file).parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn withdraw() -> BridgeOperationKind {
+        BridgeOperationKind::Withdraw {
+            to: "0x5aee3dff733f56cfe7e5390b9cc3a46a90ca1cfa"
+                .parse()
+                .unwrap(),
+            amount: 1000u32.into(),
+            withdraw_all: false,
+        }
+    }
+
+    #[test]
+    fn test_operation_steps() {
+        let mut history = BridgeHistory::default();
+
+        // a deposit with nothing to deposit leaves no trace
+        history.start(BridgeOperationKind::Deposit, 10);
+        assert!(!history.finish(BridgeOperationStatus::Complete, 11));
+        assert!(history.operations().is_empty());
+
+        history.start(withdraw(), 20);
+        let step = DetailedBridgeState::XdaiToDai {
+            amount: 1000u32.into(),
+        };
+        assert!(history.add_step(step.clone(), 21));
+        assert!(!history.add_step(step, 22));
+        assert!(history.set_txid(7u32.into()));
+        assert!(history.finish(BridgeOperationStatus::Complete, 30));
+        assert!(history.current().is_none());
+
+        let ops = history.operations();
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].id, 1);
+        assert_eq!(ops[0].steps.len(), 1);
+        assert_eq!(ops[0].steps[0].txid, Some(7u32.into()));
+        assert_eq!(ops[0].finished, Some(30));
+
+        // nothing is in progress so there is nothing to step or finish
+        assert!(!history.add_step(
+            DetailedBridgeState::DaiToXdai {
+                amount: 1u32.into()
+            },
+            31
+        ));
+        assert!(!history.finish(BridgeOperationStatus::Complete, 31));
+    }
+
+    #[test]
+    fn test_resume() {
+        let timeout = Duration::from_secs(3600);
+
+        let mut history = BridgeHistory::default();
+        history.start(withdraw(), 100);
+        let resumed = history.resume(700, timeout).unwrap();
+        assert_eq!(resumed.elapsed, Duration::from_secs(600));
+        assert_eq!(resumed.amount, 1000u32.into());
+        assert!(history.current().is_some());
+
+        // a withdraw that ran out of time while we were down is given up on
+        assert_eq!(history.resume(10_000, timeout), None);
+        assert!(history.current().is_none());
+        match &history.operations()[0].status {
+            BridgeOperationStatus::Aborted { .. } => {}
+            status => panic!("unexpected status {:?}", status),
+        }
+
+        // as is any deposit, the conveyor belt picks up where it left off
+        history.start(BridgeOperationKind::Deposit, 20_000);
+        history.add_step(
+            DetailedBridgeState::DaiToXdai {
+                amount: 1u32.into(),
+            },
+            20_001,
+        );
+        assert_eq!(history.resume(20_002, timeout), None);
+        assert!(history.current().is_none());
+        assert_eq!(history.operations().len(), 2);
+    }
+
+    #[test]
+    fn test_resume_from_step() {
+        let timeout = Duration::from_secs(3600);
+        let swap = DetailedBridgeState::DaiToEth {
+            amount_of_dai: 1000u32.into(),
+            wei_per_dollar: 5u32.into(),
+        };
+        let transfer = DetailedBridgeState::EthToDest {
+            amount_of_eth: 50u32.into(),
+            wei_per_dollar: 5u32.into(),
+            dest_address: "0x5aee3dff733f56cfe7e5390b9cc3a46a90ca1cfa"
+                .parse()
+                .unwrap(),
+        };
+
+        // stopped after the swap, the withdraw carries on from there
+        let mut history = BridgeHistory::default();
+        history.start(withdraw(), 100);
+        history.add_step(swap.clone(), 110);
+        assert!(history.resume(200, timeout).is_some());
+        assert_eq!(history.last_step().unwrap().state, swap);
+
+        // stopped after the final transfer went out, nothing is left to do
+        history.add_step(transfer.clone(), 120);
+        history.set_txid(9u32.into());
+        assert_eq!(history.resume(200, timeout), None);
+        assert_eq!(
+            history.operations()[0].status,
+            BridgeOperationStatus::Complete
+        );
+
+        // stopped while sending it, we can't know if it went out so it's not sent again
+        history.start(withdraw(), 300);
+        history.add_step(swap, 310);
+        history.add_step(transfer, 320);
+        assert_eq!(history.resume(400, timeout), None);
+        match &history.operations()[0].status {
+            BridgeOperationStatus::Aborted { .. } => {}
+            status => panic!("unexpected status {:?}", status),
+        }
+    }
+}
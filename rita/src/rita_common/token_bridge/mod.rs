@@ -49,7 +49,20 @@
 //!
 //!     State::Withdrawing { to, amount, timestamp}:
 //!         Nothing happens
-
+//!
+//! Every deposit and withdraw is recorded step by step in the bridge history, see history.rs, which
+//! is how an interrupted withdraw picks up where it left off after a restart.
+
+pub mod history;
+
+use crate::rita_common::oracle::history::now;
+use crate::rita_common::payment_token::payment_transaction;
+use crate::rita_common::token_bridge::history::{
+    load_history, save_history, BridgeHistory, BridgeOperation, BridgeOperationKind,
+    BridgeOperationStatus,
+};
+use crate::rita_common::usage_tracker::RecordDaiRate;
+use crate::rita_common::usage_tracker::UsageTracker;
 use crate::SETTING;
 use actix::Actor;
 use actix::Arbiter;
@@ -73,6 +86,8 @@ use std::time::Duration;
 use std::time::Instant;
 
 const BRIDGE_TIMEOUT: Duration = Duration::from_secs(3600);
+/// How often the dai rate is recorded for statements, the usage tracker keeps one per hour
+const DAI_RATE_INTERVAL: Duration = Duration::from_secs(600);
 const UNISWAP_TIMEOUT: u64 = 600u64;
 pub const ETH_TRANSFER_TIMEOUT: u64 = 600u64;
 /// 1c in of dai in wei
@@ -96,6 +111,11 @@ pub fn eth_equal(dai_in_wei: Uint256, wei_per_cent: Uint256) -> Uint256 {
     wei_dai_to_dai_cents(dai_in_wei) * wei_per_cent
 }
 
+/// Provided an amount in wei (ETH) returns the equal amount in DAI wei, the inverse of eth_equal
+pub fn dai_equal(wei: Uint256, wei_per_cent: Uint256) -> Uint256 {
+    wei / wei_per_cent * DAI_WEI_CENT.into()
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum State {
     Ready {
@@ -147,6 +167,9 @@ pub struct TokenBridge {
     // when no withdraw is in progress.
     minimum_stranded_dai_transfer: u32,
     detailed_state: DetailedBridgeState,
+    history: BridgeHistory,
+    /// when the dai rate was last recorded
+    last_dai_rate: Option<Instant>,
 }
 
 impl Actor for TokenBridge {
@@ -178,16 +201,53 @@ fn token_bridge_core_from_settings() -> TokenBridgeCore {
 
 impl Default for TokenBridge {
     fn default() -> TokenBridge {
+        let mut history = load_history();
+        let mut state = State::Ready { former_state: None };
+        let mut detailed_state = DetailedBridgeState::NoOp {
+            eth_balance: Uint256::zero(),
+            wei_per_dollar: Uint256::zero(),
+        };
+        if let Some(withdraw) = history.resume(now(), BRIDGE_TIMEOUT) {
+            info!(
+                "Resuming withdraw of {} to {} started {:?} ago",
+                withdraw.amount, withdraw.to, withdraw.elapsed
+            );
+            detailed_state = match history.last_step() {
+                Some(step) => step.state.clone(),
+                None => DetailedBridgeState::XdaiToDai {
+                    amount: withdraw.amount.clone(),
+                },
+            };
+            state = State::Withdrawing {
+                to: withdraw.to,
+                amount: withdraw.amount,
+                timestamp: Instant::now()
+                    .checked_sub(withdraw.elapsed)
+                    .unwrap_or_else(Instant::now),
+                withdraw_all: withdraw.withdraw_all,
+            };
+        }
+        if let Err(e) = save_history(&history) {
+            error!("Failed to save token bridge history {:?}", e);
+        }
+
         TokenBridge {
             bridge: token_bridge_core_from_settings(),
-            state: State::Ready { former_state: None },
+            state,
             minimum_to_exchange: 2,
             reserve_amount: 1,
             minimum_stranded_dai_transfer: 1,
-            detailed_state: DetailedBridgeState::NoOp {
-                eth_balance: Uint256::zero(),
-                wei_per_dollar: Uint256::zero(),
-            },
+            detailed_state,
+            history,
+            last_dai_rate: None,
+        }
+    }
+}
+
+impl TokenBridge {
+    fn save_history(&self) {
+        if let Err(e) = save_history(&self.history) {
+            error!("Failed to save token bridge history {:?}", e);
         }
     }
 }
@@ -233,11 +293,22 @@ impl Handler<Tick> for TokenBridge {
     fn handle(&mut self, _msg: Tick, _ctx: &mut Context<Self>) -> Self::Result {
         let payment_settings = SETTING.get_payment();
         let system_chain = payment_settings.system_chain;
+        let bridge_enabled = payment_settings.bridge_enabled;
+        drop(payment_settings);
 
-        if !payment_settings.bridge_enabled {
+        // statements need the rate on every chain that isn't dai already, bridge or not
+        let rate_due = match self.last_dai_rate {
+            Some(last) => Instant::now() - last >= DAI_RATE_INTERVAL,
+            None => true,
+        };
+        if system_chain != SystemChain::Xdai && rate_due {
+            self.last_dai_rate = Some(Instant::now());
+            record_dai_rate(&self.bridge);
+        }
+
+        if !bridge_enabled {
             return;
         }
-        drop(payment_settings);
 
         match system_chain {
             SystemChain::Xdai => xdai_bridge(self.state.clone(), &self),
@@ -247,6 +318,20 @@ impl Handler<Tick> for TokenBridge {
     }
 }
 
+/// Records the current price of eth in dai so statements can show what payments were worth
+/// at the time
+fn record_dai_rate(bridge: &TokenBridgeCore) {
+    Arbiter::spawn(bridge.dai_to_eth_price(eth_to_wei(1u8.into())).then(|res| {
+        match res {
+            Ok(wei_per_dollar) => {
+                UsageTracker::from_registry().do_send(RecordDaiRate { wei_per_dollar })
+            }
+            Err(e) => warn!("Failed to get the dai rate {:?}", e),
+        }
+        Ok(())
+    }))
+}
+
 /// simplified logic for bringing xdai back over to Eth if the user has xdai and then
 /// selects Eth as their blockchain it will brin gthe full balance back into Eth
 fn eth_bridge(_state: State, bridge: &TokenBridge) {
@@ -318,6 +403,8 @@ fn xdai_bridge(state: State, bridge: &TokenBridge) {
     let minimum_stranded_dai_transfer = bridge.minimum_stranded_dai_transfer;
     let reserve_amount = bridge.reserve_amount;
     let minimum_to_exchange = bridge.minimum_to_exchange;
+    // the step a withdraw got to, so one resumed after a restart carries on from there
+    let last_step = bridge.history.last_step().map(|step| step.state.clone());
     let bridge = bridge.bridge.clone();
     match state {
         State::Ready { .. } => {
@@ -398,12 +485,19 @@ fn xdai_bridge(state: State, bridge: &TokenBridge) {
                     // is in the bridge or if failed. This prevents multiple simultaneous
                     // attempts to bridge the same Dai.
 
-                    if res.is_err() {
-                        error!("Error in State::Deposit Tick handler: {:?}", res);
-                    }
-                    TokenBridge::from_registry().do_send(StateChange(State::Ready {
-                        former_state: Some(Box::new(State::Depositing {})),
-                    }));
+                    let status = match res {
+                        Ok(_) => BridgeOperationStatus::Complete,
+                        Err(e) => {
+                            error!("Error in State::Deposit Tick handler: {:?}", e);
+                            BridgeOperationStatus::Failed {
+                                reason: format!("{:?}", e),
+                            }
+                        }
+                    };
+                    TokenBridge::from_registry().do_send(Finish {
+                        former_state: State::Depositing {},
+                        status,
+                    });
                     Ok(())
                 }),
             )
@@ -415,7 +509,9 @@ fn xdai_bridge(state: State, bridge: &TokenBridge) {
             timestamp,
             withdraw_all,
         } => {
-            if is_timed_out(timestamp) {
+            if let Some(DetailedBridgeState::EthToDest { .. }) = last_step {
+                trace!("Withdraw is waiting on its final transfer");
+            } else if is_timed_out(timestamp) {
                 error!("Withdraw timed out!");
                 TokenBridge::from_registry().do_send(DetailedStateChange(
                     DetailedBridgeState::NoOp {
@@ -423,84 +519,187 @@ fn xdai_bridge(state: State, bridge: &TokenBridge) {
                         wei_per_dollar: Uint256::zero(),
                     },
                 ));
-                TokenBridge::from_registry().do_send(StateChange(State::Ready {
-                    former_state: Some(Box::new(State::Withdrawing {
+                TokenBridge::from_registry().do_send(Finish {
+                    former_state: State::Withdrawing {
                         to,
                         amount,
                         timestamp,
                         withdraw_all,
-                    })),
-                }));
+                    },
+                    status: BridgeOperationStatus::Aborted {
+                        reason: "Timed out".to_string(),
+                    },
+                });
             } else {
+                let swapped = match last_step {
+                    Some(DetailedBridgeState::DaiToEth { .. }) => true,
+                    _ => false,
+                };
+                let former_state = State::Withdrawing {
+                    to,
+                    amount: amount.clone(),
+                    timestamp,
+                    withdraw_all,
+                };
                 let amount_a = amount.clone();
                 Arbiter::spawn(
-                            bridge
-                                .get_dai_balance(bridge.own_address)
-                                .join5(bridge.eth_web3.eth_get_balance(bridge.own_address),
-                                bridge.dai_to_eth_price(eth_to_wei(1u8.into())),
-                                bridge.dai_to_eth_price(DAI_WEI_CENT.into()),
-                                bridge.eth_web3.eth_gas_price())
-                                .and_then(move |(our_dai_balance, our_eth_balance, wei_per_dollar, wei_per_cent, eth_gas_price)| {
-                                    info!("withdraw state is {} dai {} eth {} wei per dollar", our_dai_balance, our_eth_balance, wei_per_dollar);
-                                    let transferred_eth = eth_equal(amount_a.clone(), wei_per_cent);
-                                    // Money has come over the bridge
-                                    if our_dai_balance >= amount {
-                                        TokenBridge::from_registry().do_send(DetailedStateChange(DetailedBridgeState::DaiToEth{
+                    bridge
+                        .get_dai_balance(bridge.own_address)
+                        .join5(
+                            bridge.eth_web3.eth_get_balance(bridge.own_address),
+                            bridge.dai_to_eth_price(eth_to_wei(1u8.into())),
+                            bridge.dai_to_eth_price(DAI_WEI_CENT.into()),
+                            bridge.eth_web3.eth_gas_price(),
+                        )
+                        .and_then(
+                            move |(
+                                our_dai_balance,
+                                our_eth_balance,
+                                wei_per_dollar,
+                                wei_per_cent,
+                                eth_gas_price,
+                            )| {
+                                info!(
+                                    "withdraw state is {} dai {} eth {} wei per dollar",
+                                    our_dai_balance, our_eth_balance, wei_per_dollar
+                                );
+                                let transferred_eth = eth_equal(amount_a.clone(), wei_per_cent);
+                                // this only works because the gas price is hardcoded in auto_bridge
+                                // that should be fixed someday and this should use dynamic gas
+                                let tx_gas: Uint256 = 21_000u32.into();
+                                let tx_cost = eth_gas_price * tx_gas;
+                                // Money has come over the bridge
+                                if our_dai_balance >= amount {
+                                    TokenBridge::from_registry().do_send(DetailedStateChange(
+                                        DetailedBridgeState::DaiToEth {
                                             amount_of_dai: amount_a.clone(),
-                                            wei_per_dollar
-                                        }));
-                                        Box::new(
-                                            bridge
-                                                // Then it converts to eth
-                                                .dai_to_eth_swap(amount, UNISWAP_TIMEOUT).and_then(|_| Ok(()))
-                                        )
-                                            as Box<dyn Future<Item = (), Error = Error>>
-                                    // all other steps are done and the eth is sitting and waiting
-                                    } else if our_eth_balance >= transferred_eth {
-                                        info!("Converted dai back to eth!");
-                                        let withdraw_amount = if withdraw_all {
-                                            // this only works because the gas price is hardcoded in auto_bridge
-                                            // that should be fixed someday and this should use dynamic gas
-                                            let gas_price = eth_gas_price;
-                                            let tx_gas: Uint256 = 21_000u32.into();
-                                            let tx_cost = gas_price * tx_gas;
-                                            our_eth_balance - tx_cost
-                                            } else { transferred_eth };
-
-                                        TokenBridge::from_registry().do_send(DetailedStateChange(DetailedBridgeState::EthToDest{
-                                            amount_of_eth: withdraw_amount.clone(),
                                             wei_per_dollar,
-                                            dest_address: to
-                                        }));
-                                        Box::new(bridge.eth_transfer(
-                                            to,
-                                            withdraw_amount,
-                                            ETH_TRANSFER_TIMEOUT,
-                                        )
-                                        .and_then(move |_| {
-                                            info!("Issued an eth transfer for withdraw! Now complete!");
-                                            // we only exit the withdraw state on success or timeout
-                                            TokenBridge::from_registry().do_send(StateChange(State::Ready {former_state: Some(Box::new(State::Withdrawing{to, amount: amount_a, timestamp, withdraw_all}))}));
-                                            Ok(())}))
-                                    } else {
-                                        info!("withdraw is waiting on bridge");
-                                        TokenBridge::from_registry().do_send(DetailedStateChange(DetailedBridgeState::XdaiToDai{amount}));
-                                        Box::new(futures01::future::ok(()))
-                                            as Box<dyn Future<Item = (), Error = Error>>
-                                    }
-                                })
-                                .then(|res| {
-                                    if res.is_err() {
-                                        error!("Error in State::Withdraw Tick handler: {:?}", res);
-                                    }
-                                    Ok(())
-                                }),
+                                        },
+                                    ));
+                                    Box::new(
+                                        bridge
+                                            // Then it converts to eth
+                                            .dai_to_eth_swap(amount, UNISWAP_TIMEOUT)
+                                            .and_then(|_| Ok(())),
+                                    )
+                                        as Box<dyn Future<Item = (), Error = Error>>
+                                // all other steps are done and the eth is sitting and waiting, once
+                                // the swap went through what it bought is sent even if the price
+                                // moved and it's a little short of the amount
+                                } else if our_eth_balance > tx_cost
+                                    && (our_eth_balance >= transferred_eth || swapped)
+                                {
+                                    info!("Converted dai back to eth!");
+                                    let withdraw_amount =
+                                        if withdraw_all || our_eth_balance < transferred_eth {
+                                            our_eth_balance - tx_cost
+                                        } else {
+                                            transferred_eth
+                                        };
+                                    send_final_transfer(
+                                        bridge,
+                                        former_state,
+                                        withdraw_amount,
+                                        wei_per_dollar,
+                                    )
+                                } else {
+                                    info!("withdraw is waiting on bridge");
+                                    TokenBridge::from_registry().do_send(DetailedStateChange(
+                                        DetailedBridgeState::XdaiToDai { amount },
+                                    ));
+                                    Box::new(futures01::future::ok(()))
+                                        as Box<dyn Future<Item = (), Error = Error>>
+                                }
+                            },
                         )
+                        .then(|res| {
+                            if res.is_err() {
+                                error!("Error in State::Withdraw Tick handler: {:?}", res);
+                            }
+                            Ok(())
+                        }),
+                )
             }
         }
     }
 }
 
+/// Sends the eth of a withdraw on to its destination. The step is saved before the transfer
+/// goes out so that a restart can never send it twice, and the hash is recorded once we have it
+fn send_final_transfer(
+    bridge: TokenBridgeCore,
+    former_state: State,
+    amount: Uint256,
+    wei_per_dollar: Uint256,
+) -> Box<dyn Future<Item = (), Error = Error>> {
+    let to = match former_state {
+        State::Withdrawing { to, .. } => to,
+        _ => return Box::new(future::err(format_err!("Not withdrawing"))),
+    };
+    Box::new(
+        TokenBridge::from_registry()
+            .send(DetailedStateChange(DetailedBridgeState::EthToDest {
+                amount_of_eth: amount.clone(),
+                wei_per_dollar,
+                dest_address: to,
+            }))
+            .from_err()
+            .and_then(move |_| send_eth(bridge, to, amount))
+            .then(move |res| -> Result<(), Error> {
+                let status = match res {
+                    Ok(txid) => {
+                        info!("Issued an eth transfer for withdraw! Now complete!");
+                        TokenBridge::from_registry().do_send(RecordTxid(txid));
+                        BridgeOperationStatus::Complete
+                    }
+                    // we can't tell if a failed send went out, so it's not retried, if it
+                    // didn't the eth goes back into xDai with the next deposit
+                    Err(e) => BridgeOperationStatus::Failed {
+                        reason: format!("Failed to send the final transfer {:?}", e),
+                    },
+                };
+                // we only exit the withdraw state on success, failure or timeout
+                TokenBridge::from_registry().do_send(Finish {
+                    former_state,
+                    status,
+                });
+                Ok(())
+            }),
+    )
+}
+
+/// Sends eth from our address on Ethereum. Unlike the transfers auto_bridge makes this hands
+/// back the transaction hash, so it can be kept in the history
+fn send_eth(
+    bridge: TokenBridgeCore,
+    to: Address,
+    amount: Uint256,
+) -> Box<dyn Future<Item = Uint256, Error = Error>> {
+    let key = match SETTING.get_payment().eth_private_key {
+        Some(key) => key,
+        None => return Box::new(future::err(format_err!("No private key configured!"))),
+    };
+    Box::new(
+        bridge
+            .eth_web3
+            .eth_get_transaction_count(bridge.own_address)
+            .join3(
+                bridge.eth_web3.net_version(),
+                bridge.eth_web3.eth_gas_price(),
+            )
+            .and_then(move |(nonce, net_version, gas_price)| {
+                let net_version: u64 = net_version.parse()?;
+                let transaction = payment_transaction(&None, to, amount, nonce, gas_price)
+                    .sign(&key, Some(net_version));
+                match transaction.to_bytes() {
+                    Ok(bytes) => Ok(bytes),
+                    Err(e) => bail!("Transaction to bytes failed! {:?}", e),
+                }
+            })
+            .and_then(move |bytes| bridge.eth_web3.eth_send_raw_transaction(bytes)),
+    )
+}
+
 /// Withdraw state struct for the bridge, if withdraw_all is true, the eth will be
 /// cleaned up on the way out as well
 pub struct Withdraw {
@@ -540,22 +739,43 @@ impl Handler<Withdraw> for TokenBridge {
                     bail!("Cannot start a withdraw when one is in progress")
                 }
                 _ => {
+                    // recorded before anything is sent, if we restart before the bridge transfer
+                    // goes out the resumed withdraw simply times out with the funds still in xDai
+                    self.history.start(
+                        BridgeOperationKind::Withdraw {
+                            to,
+                            amount: amount.clone(),
+                            withdraw_all,
+                        },
+                        now(),
+                    );
+                    self.save_history();
                     Arbiter::spawn(bridge.xdai_to_dai_bridge(amount.clone()).then(move |res| {
-                        if res.is_err() {
-                            error!("Error in State::Deposit Withdraw handler: {:?}", res);
-                        } else {
-                            // Only change to Withdraw if there was no error
-                            TokenBridge::from_registry().do_send(DetailedStateChange(
-                                DetailedBridgeState::XdaiToDai {
-                                    amount: amount.clone(),
-                                },
-                            ));
-                            TokenBridge::from_registry().do_send(StateChange(State::Withdrawing {
-                                to,
-                                amount,
-                                timestamp: Instant::now(),
-                                withdraw_all,
-                            }));
+                        match res {
+                            Ok(txid) => {
+                                // Only change to Withdraw if there was no error
+                                TokenBridge::from_registry().do_send(DetailedStateChange(
+                                    DetailedBridgeState::XdaiToDai {
+                                        amount: amount.clone(),
+                                    },
+                                ));
+                                TokenBridge::from_registry().do_send(RecordTxid(txid));
+                                TokenBridge::from_registry().do_send(StateChange(
+                                    State::Withdrawing {
+                                        to,
+                                        amount,
+                                        timestamp: Instant::now(),
+                                        withdraw_all,
+                                    },
+                                ));
+                            }
+                            Err(e) => {
+                                error!("Error in State::Deposit Withdraw handler: {:?}", e);
+                                TokenBridge::from_registry().do_send(WithdrawFailed(format!(
+                                    "Failed to send xDai into the bridge {:?}",
+                                    e
+                                )));
+                            }
                         }
                         Ok(())
                     }));
@@ -593,10 +813,77 @@ impl Handler<StateChange> for TokenBridge {
                 return;
             }
         }
+        // a withdraw already has its operation, started when it was requested, and one waiting
+        // on its bridge transfer is never superseded by a deposit
+        if new_state == (State::Depositing {})
+            && self.state != (State::Depositing {})
+            && self.history.current().is_none()
+        {
+            self.history.start(BridgeOperationKind::Deposit, now());
+        }
         self.state = new_state;
     }
 }
 
+/// Ends the operation started when the bridge entered `former_state`, returning it to Ready.
+/// Like Ready in StateChange this is ignored if the bridge has moved on to another state
+#[derive(Message)]
+struct Finish {
+    former_state: State,
+    status: BridgeOperationStatus,
+}
+
+impl Handler<Finish> for TokenBridge {
+    type Result = ();
+    fn handle(&mut self, msg: Finish, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.state != msg.former_state {
+            trace!("{} != {}", self.state, msg.former_state);
+            return;
+        }
+        let matches = match (self.history.current().map(|op| &op.kind), &msg.former_state) {
+            (Some(BridgeOperationKind::Deposit), State::Depositing {}) => true,
+            (Some(BridgeOperationKind::Withdraw { .. }), State::Withdrawing { .. }) => true,
+            _ => false,
+        };
+        if matches && self.history.finish(msg.status, now()) {
+            self.save_history();
+        }
+        self.state = State::Ready {
+            former_state: Some(Box::new(msg.former_state)),
+        };
+    }
+}
+
+/// The bridge transfer of a withdraw failed, so it never got underway
+#[derive(Message)]
+struct WithdrawFailed(String);
+
+impl Handler<WithdrawFailed> for TokenBridge {
+    type Result = ();
+    fn handle(&mut self, msg: WithdrawFailed, _ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(BridgeOperationKind::Withdraw { .. }) =
+            self.history.current().map(|op| &op.kind)
+        {
+            self.history
+                .finish(BridgeOperationStatus::Failed { reason: msg.0 }, now());
+            self.save_history();
+        }
+    }
+}
+
+/// The transaction hash of the step the bridge just took
+#[derive(Message)]
+struct RecordTxid(Uint256);
+
+impl Handler<RecordTxid> for TokenBridge {
+    type Result = ();
+    fn handle(&mut self, msg: RecordTxid, _ctx: &mut Context<Self>) -> Self::Result {
+        if self.history.set_txid(msg.0) {
+            self.save_history();
+        }
+    }
+}
+
 #[derive(Message)]
 struct DetailedStateChange(DetailedBridgeState);
 
@@ -605,6 +892,13 @@ impl Handler<DetailedStateChange> for TokenBridge {
     fn handle(&mut self, msg: DetailedStateChange, _ctx: &mut Context<Self>) -> Self::Result {
         trace!("Changing detailed state to {:?}", msg.0);
         let new_state = msg.0;
+        let is_step = match new_state {
+            DetailedBridgeState::NoOp { .. } => false,
+            _ => true,
+        };
+        if is_step && self.history.add_step(new_state.clone(), now()) {
+            self.save_history();
+        }
         self.detailed_state = new_state;
     }
 }
@@ -612,7 +906,7 @@ impl Handler<DetailedStateChange> for TokenBridge {
 /// Used to display the state of the bridge to the user, has a higher
 /// resolution than the actual bridge state object in exchange for possibly
 /// being inaccurate or going backwards
-#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum DetailedBridgeState {
     /// Converting Eth to Dai
     EthToDai {
@@ -649,6 +943,8 @@ pub struct BridgeStatus {
     minimum_deposit: u32,
     withdraw_chain: SystemChain,
     state: DetailedBridgeState,
+    /// every deposit and withdraw we've recorded, newest first
+    operations: Vec<BridgeOperation>,
 }
 
 pub struct GetBridgeStatus;
@@ -667,6 +963,7 @@ impl Handler<GetBridgeStatus> for TokenBridge {
             minimum_deposit: self.minimum_to_exchange,
             withdraw_chain,
             state: self.detailed_state.clone(),
+            operations: self.history.operations(),
         };
         Ok(ret)
     }
@@ -1,11 +1,14 @@
 //! Traffic watcher monitors system traffic by interfacing with KernelInterface to create and check
-//! iptables and ipset counters on each per hop tunnel (the WireGuard tunnel between two devices). These counts
+//! iptables and ipset (or nftables or eBPF) counters on each per hop tunnel (the WireGuard tunnel between two devices). These counts
 //! are then stored and used to compute amounts for bills.
 
 use crate::rita_common::debt_keeper;
 use crate::rita_common::debt_keeper::DebtKeeper;
 use crate::rita_common::debt_keeper::Traffic;
 use crate::rita_common::tunnel_manager::Neighbor;
+use crate::rita_common::usage_tracker::PeerUsage;
+use crate::rita_common::usage_tracker::PeerUsageType;
+use crate::rita_common::usage_tracker::UpdatePeerUsage;
 use crate::rita_common::usage_tracker::UpdateUsage;
 use crate::rita_common::usage_tracker::UsageTracker;
 use crate::rita_common::usage_tracker::UsageType;
@@ -32,6 +35,7 @@ impl Supervised for TrafficWatcher {}
 
 impl SystemService for TrafficWatcher {
     fn service_started(&mut self, _ctx: &mut Context<Self>) {
+        KI.set_ebpf_counters(SETTING.get_network().ebpf_counters);
         KI.init_counter(&FilterTarget::Input).unwrap();
         KI.init_counter(&FilterTarget::Output).unwrap();
         KI.init_counter(&FilterTarget::ForwardInput).unwrap();
@@ -87,13 +91,29 @@ pub fn prepare_helper_maps(
 }
 
 pub fn get_babel_info(routes: Vec<Route>) -> Result<(HashMap<IpAddr, i128>, u32), Error> {
-    trace!("Got {} routes: {:?}", routes.len(), routes);
-    let mut destinations = HashMap::new();
     // we assume this matches what is actually set it babel becuase we
     // panic on startup if it does not get set correctly
     let local_fee = SETTING.get_payment().local_fee;
-
     let max_fee = SETTING.get_payment().max_fee;
+    let mesh_ip = match SETTING.get_network().mesh_ip {
+        Some(ip) => ip,
+        None => bail!("No mesh IP configured yet"),
+    };
+    Ok((
+        get_destinations(routes, mesh_ip, local_fee, max_fee),
+        local_fee,
+    ))
+}
+
+/// Prices every destination we have an installed host route to, plus ourselves for free
+fn get_destinations(
+    routes: Vec<Route>,
+    mesh_ip: IpAddr,
+    local_fee: u32,
+    max_fee: u32,
+) -> HashMap<IpAddr, i128> {
+    trace!("Got {} routes: {:?}", routes.len(), routes);
+    let mut destinations = HashMap::new();
     for route in &routes {
         // Only ip6
         if let IpNetwork::V6(ref ip) = route.prefix {
@@ -114,17 +134,11 @@ pub fn get_babel_info(routes: Vec<Route>) -> Result<(HashMap<IpAddr, i128>, u32)
         }
     }
 
-    destinations.insert(
-        match SETTING.get_network().mesh_ip {
-            Some(ip) => ip,
-            None => bail!("No mesh IP configured yet"),
-        },
-        i128::from(0),
-    );
+    destinations.insert(mesh_ip, i128::from(0));
 
     trace!("{} destinations setup", destinations.len());
 
-    Ok((destinations, local_fee))
+    destinations
 }
 
 pub fn get_input_counters() -> Result<HashMap<(IpAddr, String), u64>, Error> {
@@ -283,6 +297,8 @@ pub fn watch(routes: Vec<Route>, neighbors: &[Neighbor]) -> Result<(), Error> {
     // Destination counters should credit your neighbor which you sent the packet to
 
     let mut debts = HashMap::new();
+    // bytes sent to and received from each neighbor
+    let mut neighbor_bytes: HashMap<Identity, (u64, u64)> = HashMap::new();
 
     // Setup the debts table
     for (_, ident) in identities.clone() {
@@ -299,6 +315,7 @@ pub fn watch(routes: Vec<Route>, neighbors: &[Neighbor]) -> Result<(), Error> {
                 match debts.get_mut(&id_from_if) {
                     Some(debt) => {
                         *debt -= dest * i128::from(bytes);
+                        neighbor_bytes.entry(*id_from_if).or_insert((0, 0)).1 += bytes;
                     }
                     // debts is generated from identities, this should be impossible
                     None => warn!("No debts entry for input entry id {:?}", id_from_if),
@@ -328,6 +345,7 @@ pub fn watch(routes: Vec<Route>, neighbors: &[Neighbor]) -> Result<(), Error> {
             (Some(dest), Some(id_from_if)) => match debts.get_mut(&id_from_if) {
                 Some(debt) => {
                     *debt += (dest - i128::from(local_fee)) * i128::from(bytes);
+                    neighbor_bytes.entry(*id_from_if).or_insert((0, 0)).0 += bytes;
                 }
                 // debts is generated from identities, this should be impossible
                 None => warn!("No debts entry for input entry id {:?}", id_from_if),
@@ -357,6 +375,18 @@ pub fn watch(routes: Vec<Route>, neighbors: &[Neighbor]) -> Result<(), Error> {
         total_income
     );
 
+    let mut peer_usage = Vec::new();
+    for (id, (up, down)) in neighbor_bytes {
+        peer_usage.push(PeerUsage {
+            kind: PeerUsageType::Neighbor,
+            id,
+            up,
+            down,
+            billed: debts.get(&id).cloned().unwrap_or(0).into(),
+        });
+    }
+    UsageTracker::from_registry().do_send(UpdatePeerUsage { usage: peer_usage });
+
     let mut traffic_vec = Vec::new();
     for (from, amount) in debts {
         trace!("collated debt for {} is {}", from.mesh_ip, amount);
@@ -375,9 +405,17 @@ pub fn watch(routes: Vec<Route>, neighbors: &[Neighbor]) -> Result<(), Error> {
 
 #[cfg(test)]
 mod tests {
+    use super::get_destinations;
+    use crate::rita_common::babel_manager::start_synced;
+    use crate::rita_common::babel_manager::wait_for_routes;
+    use crate::rita_common::babel_manager::GetRoutes;
+    use actix::System;
+    use babel_monitor::fake::make_route;
+    use babel_monitor::fake::FakeBabel;
     use std::collections::HashMap;
     use std::net::IpAddr;
     use std::net::Ipv6Addr;
+
     #[test]
     fn test_ip_lookup() {
         let ip_a: IpAddr = "fd00::1337:e8f".parse().unwrap();
@@ -388,4 +426,53 @@ mod tests {
         map.insert(ip_b, "test");
         assert!(map.get(&ip_a) != None);
     }
+
+    #[test]
+    fn test_destinations_from_fake_babel() {
+        let babel = FakeBabel::start().unwrap();
+        let neigh_ip: IpAddr = "fe80::1".parse().unwrap();
+        babel.add_route(make_route(
+            "1",
+            "fd00::1/128".parse().unwrap(),
+            neigh_ip,
+            "wg0",
+            100,
+        ));
+        // not a host route to a mesh ip, so it can't be billed for
+        babel.add_route(make_route(
+            "2",
+            "10.0.0.1/32".parse().unwrap(),
+            neigh_ip,
+            "wg0",
+            100,
+        ));
+        let mut uninstalled = make_route("3", "fd00::3/128".parse().unwrap(), neigh_ip, "wg0", 100);
+        uninstalled.installed = false;
+        babel.add_route(uninstalled);
+
+        let mut system = System::new("test_destinations_from_fake_babel");
+        let manager = start_synced(&mut system, babel.port());
+        let routes = system.block_on(manager.send(GetRoutes)).unwrap().unwrap();
+        assert_eq!(routes.len(), 3);
+
+        let mesh_ip: IpAddr = "fd00::2".parse().unwrap();
+        let destinations = get_destinations(routes, mesh_ip, 10, 1000);
+        assert_eq!(destinations.len(), 2);
+        assert_eq!(destinations[&"fd00::1".parse::<IpAddr>().unwrap()], 110);
+        assert_eq!(destinations[&mesh_ip], 0);
+
+        // a route that changes after the sync is pushed to the manager, and capped at max_fee
+        babel.add_route(make_route(
+            "1",
+            "fd00::1/128".parse().unwrap(),
+            neigh_ip,
+            "wg0",
+            5000,
+        ));
+        let routes = wait_for_routes(&mut system, &manager, |routes| {
+            routes.iter().any(|r| r.price == 5000)
+        });
+        let destinations = get_destinations(routes, mesh_ip, 10, 1000);
+        assert_eq!(destinations[&"fd00::1".parse::<IpAddr>().unwrap()], 1010);
+    }
 }
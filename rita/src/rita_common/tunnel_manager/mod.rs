@@ -7,6 +7,7 @@
 pub mod id_callback;
 
 use crate::rita_common;
+use crate::rita_common::babel_manager;
 use crate::rita_common::hello_handler::Hello;
 use crate::rita_common::peer_listener::Peer;
 use crate::KI;
@@ -17,10 +18,6 @@ use actix::actors::resolver;
 use actix::{Actor, Arbiter, Context, Handler, Message, Supervised, SystemService};
 use althea_types::Identity;
 use althea_types::LocalIdentity;
-use babel_monitor::monitor;
-use babel_monitor::open_babel_stream;
-use babel_monitor::start_connection;
-use babel_monitor::unmonitor;
 use failure::Error;
 use futures01::Future;
 use rand::thread_rng;
@@ -204,80 +201,62 @@ impl Tunnel {
     /// Register this tunnel into Babel monitor
     pub fn monitor(&self, retry_count: u8) {
         info!("Monitoring tunnel {}", self.iface_name);
-        let iface_name = self.iface_name.clone();
-        let babel_port = SETTING.get_network().babel_port;
         let tunnel = self.clone();
 
-        Arbiter::spawn(
-            open_babel_stream(babel_port)
-                .from_err()
-                .and_then(move |stream| {
-                    start_connection(stream).and_then(move |stream| monitor(stream, &iface_name))
-                })
-                .then(move |res| {
-                    // Errors here seem very very rare, I've only ever seen it happen
-                    // twice myself and I couldn't reproduce it, nontheless it's a pretty
-                    // bad situation so we will retry
-                    if let Err(e) = res {
-                        warn!("Tunnel monitor failed with {:?}, retrying in 1 second", e);
-                        let when = Instant::now() + Duration::from_secs(1);
-                        let fut = Delay::new(when)
-                            .map_err(move |e| panic!("timer failed; err={:?}", e))
-                            .and_then(move |_| {
-                                TunnelManager::from_registry().do_send(TunnelMonitorFailure {
-                                    tunnel_to_retry: tunnel,
-                                    retry_count,
-                                });
-                                Ok(())
-                            });
-                        Arbiter::spawn(fut);
-                    }
-                    Ok(())
-                }),
-        )
+        Arbiter::spawn(babel_manager::monitor(&self.iface_name).then(move |res| {
+            // Errors here seem very very rare, I've only ever seen it happen
+            // twice myself and I couldn't reproduce it, nontheless it's a pretty
+            // bad situation so we will retry
+            if let Err(e) = res {
+                warn!("Tunnel monitor failed with {:?}, retrying in 1 second", e);
+                let when = Instant::now() + Duration::from_secs(1);
+                let fut = Delay::new(when)
+                    .map_err(move |e| panic!("timer failed; err={:?}", e))
+                    .and_then(move |_| {
+                        TunnelManager::from_registry().do_send(TunnelMonitorFailure {
+                            tunnel_to_retry: tunnel,
+                            retry_count,
+                        });
+                        Ok(())
+                    });
+                Arbiter::spawn(fut);
+            }
+            Ok(())
+        }))
     }
 
     pub fn unmonitor(&self, retry_count: u8) {
         warn!("Unmonitoring tunnel {}", self.iface_name);
-        let iface_name = self.iface_name.clone();
-        let babel_port = SETTING.get_network().babel_port;
         let tunnel = self.clone();
 
-        Arbiter::spawn(
-            open_babel_stream(babel_port)
-                .from_err()
-                .and_then(move |stream| {
-                    start_connection(stream).and_then(move |stream| unmonitor(stream, &iface_name))
-                })
-                .then(move |res| {
-                    // Errors here seem very very rare, I've only ever seen it happen
-                    // twice myself and I couldn't reproduce it, nontheless it's a pretty
-                    // bad situation so we will retry
-                    if let Err(e) = res {
-                        warn!("Tunnel unmonitor failed with {:?}, retrying in 1 second", e);
-                        let when = Instant::now() + Duration::from_secs(1);
-                        let fut = Delay::new(when)
-                            .map_err(move |e| panic!("timer failed; err={:?}", e))
-                            .and_then(move |_| {
-                                TunnelManager::from_registry().do_send(TunnelUnMonitorFailure {
-                                    tunnel_to_retry: tunnel,
-                                    retry_count,
-                                });
-                                Ok(())
-                            });
-                        Arbiter::spawn(fut);
-                    } else {
-                        // We must wait until we have flushed the interface before deleting it
-                        // otherwise we will experience this error
-                        // https://github.com/sudomesh/bugs/issues/24
-                        if let Err(e) = KI.del_interface(&tunnel.iface_name) {
-                            error!("Failed to delete wg interface! {:?}", e);
-                        }
-                        TunnelManager::from_registry().do_send(PortCallback(tunnel.listen_port));
-                    }
-                    Ok(())
-                }),
-        )
+        Arbiter::spawn(babel_manager::unmonitor(&self.iface_name).then(move |res| {
+            // Errors here seem very very rare, I've only ever seen it happen
+            // twice myself and I couldn't reproduce it, nontheless it's a pretty
+            // bad situation so we will retry
+            if let Err(e) = res {
+                warn!("Tunnel unmonitor failed with {:?}, retrying in 1 second", e);
+                let when = Instant::now() + Duration::from_secs(1);
+                let fut = Delay::new(when)
+                    .map_err(move |e| panic!("timer failed; err={:?}", e))
+                    .and_then(move |_| {
+                        TunnelManager::from_registry().do_send(TunnelUnMonitorFailure {
+                            tunnel_to_retry: tunnel,
+                            retry_count,
+                        });
+                        Ok(())
+                    });
+                Arbiter::spawn(fut);
+            } else {
+                // We must wait until we have flushed the interface before deleting it
+                // otherwise we will experience this error
+                // https://github.com/sudomesh/bugs/issues/24
+                if let Err(e) = KI.del_interface(&tunnel.iface_name) {
+                    error!("Failed to delete wg interface! {:?}", e);
+                }
+                TunnelManager::from_registry().do_send(PortCallback(tunnel.listen_port));
+            }
+            Ok(())
+        }))
     }
 
     pub fn close_light_client_tunnel(&self) {
@@ -1186,11 +1165,18 @@ fn tunnel_bw_limit_update(tunnels: &HashMap<Identity, Vec<Tunnel>>) -> Result<()
 
 #[cfg(test)]
 mod tests {
+    use super::tunnel_bw_limit_update;
+    use crate::rita_common::tunnel_manager::PaymentState;
     use crate::rita_common::tunnel_manager::RegistrationState;
     use crate::rita_common::tunnel_manager::Tunnel;
     use crate::rita_common::tunnel_manager::TunnelManager;
+    use crate::FAKE_KERNEL;
+    use crate::SETTING;
+    use althea_kernel_interface::fake::Qdisc;
     use althea_types::Identity;
     use althea_types::LocalIdentity;
+    use settings::RitaCommonSettings;
+    use std::collections::HashMap;
 
     /// gets a mutable reference tunnel from the list with the given index
     fn get_mut_tunnel_by_ifidx(ifidx: u32, tunnels: &mut Vec<Tunnel>) -> Option<&mut Tunnel> {
@@ -1266,4 +1252,71 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    pub fn test_tunnel_bw_limit_update() {
+        use clarity::Address;
+        use std::str::FromStr;
+
+        let id = Identity::new(
+            "0.0.0.0".parse().unwrap(),
+            Address::from_str("ffffffffffffffffffffffffffffffffffffffff").unwrap(),
+            "8BeCExnthLe5ou0EYec5jNqJ/PduZ1x2o7lpXJOpgXk="
+                .parse()
+                .unwrap(),
+            None,
+        );
+        let make_tunnel = |iface: &str, payment_state| {
+            FAKE_KERNEL.add_interface(iface);
+            let mut tunnel = Tunnel::new(
+                "0.0.0.0".parse().unwrap(),
+                iface.into(),
+                65535,
+                0,
+                LocalIdentity {
+                    wg_port: 65535,
+                    have_tunnel: Some(true),
+                    global: id,
+                },
+                None,
+            );
+            tunnel.state.payment_state = payment_state;
+            tunnel
+        };
+        let mut tunnels = HashMap::new();
+        tunnels.insert(
+            id,
+            vec![
+                make_tunnel("wg_limit_test0", PaymentState::Overdue),
+                make_tunnel("wg_limit_test1", PaymentState::Overdue),
+                make_tunnel("wg_limit_test2", PaymentState::Paid),
+            ],
+        );
+
+        tunnel_bw_limit_update(&tunnels).unwrap();
+        let free_tier = SETTING.get_payment().free_tier_throughput;
+        for iface in &["wg_limit_test0", "wg_limit_test1"] {
+            assert_eq!(
+                FAKE_KERNEL.qdisc(iface),
+                Some(Qdisc::Tbf {
+                    rate_kbit: free_tier / 2
+                })
+            );
+        }
+        assert_eq!(FAKE_KERNEL.qdisc("wg_limit_test2"), None);
+
+        // once paid the limit is replaced with regular shaping
+        tunnels.get_mut(&id).unwrap()[0].state.payment_state = PaymentState::Paid;
+        tunnel_bw_limit_update(&tunnels).unwrap();
+        assert_eq!(
+            FAKE_KERNEL.qdisc("wg_limit_test0"),
+            Some(Qdisc::Codel { bandwidth: None })
+        );
+        assert_eq!(
+            FAKE_KERNEL.qdisc("wg_limit_test1"),
+            Some(Qdisc::Tbf {
+                rate_kbit: free_tier
+            })
+        );
+    }
 }
@@ -4,8 +4,14 @@
 //! the handler updates the storage to reflect the new total. When a user would like to inspect
 //! or graph usage they query an endpoint which will request the data from this module.
 //!
-//! Persistant storage is planned but not currently implemented.
+//! The full history is saved as a compressed snapshot every few hours. Every change made since
+//! the last snapshot is also appended to a journal file next to it, usage at most once a minute
+//! and payments right away, so a crash or power loss between snapshots only loses the last
+//! minute of usage. On startup the snapshot is loaded and the journal replayed on top of it.
 
+pub mod statements;
+
+use self::statements::{PaymentCategory, PaymentParties};
 use crate::SETTING;
 use actix::Actor;
 use actix::Context;
@@ -15,22 +21,31 @@ use actix::Supervised;
 use actix::SystemService;
 use althea_types::Identity;
 use althea_types::PaymentTx;
+use althea_types::WgKey;
+use clarity::Address;
 use failure::Error;
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
-use num256::Uint256;
+use num256::{Int256, Uint256};
+use num_traits::Signed;
 use serde::{Deserialize, Serialize};
 use serde_json::Error as SerdeError;
 use settings::RitaCommonSettings;
 use std::collections::VecDeque;
+use std::fs;
 use std::fs::File;
+use std::fs::OpenOptions;
 use std::io;
 use std::io::Error as IOError;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::Instant;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
@@ -38,10 +53,16 @@ use std::time::UNIX_EPOCH;
 const MAX_ENTRIES: usize = 8760;
 /// Save every 4 hours
 const SAVE_FREQENCY: u64 = 4;
+/// The per node breakdown grows with the number of nodes, so only a month of it is kept
+const MAX_PEER_ENTRIES: usize = 24 * 31;
+/// How often buffered usage is appended to the journal
+const JOURNAL_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+const SECONDS_PER_HOUR: u64 = 3600;
+const SECONDS_PER_DAY: u64 = 86400;
 
 /// In an effort to converge this module between the three possible bw tracking
 /// use cases this enum is used to identify which sort of usage we are tracking
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum UsageType {
     Client,
@@ -68,6 +89,10 @@ pub struct FormattedPaymentTx {
     pub amount: Uint256,
     // should always be populated in this case
     pub txid: String,
+    /// what the payment was for, decided when it was made since the DAO addresses change.
+    /// None for payments recorded before this was kept
+    #[serde(default)]
+    pub category: Option<PaymentCategory>,
 }
 
 fn to_formatted_payment_tx(input: PaymentTx) -> FormattedPaymentTx {
@@ -77,12 +102,14 @@ fn to_formatted_payment_tx(input: PaymentTx) -> FormattedPaymentTx {
             from: input.from,
             amount: input.amount,
             txid: format!("{:#066x}", txid),
+            category: None,
         },
         None => FormattedPaymentTx {
             to: input.to,
             from: input.from,
             amount: input.amount,
             txid: String::new(),
+            category: None,
         },
     }
 }
@@ -94,6 +121,57 @@ pub struct PaymentHour {
     payments: Vec<FormattedPaymentTx>,
 }
 
+/// What another node is to us in the per node usage breakdown
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerUsageType {
+    /// a neighbor, counting traffic we relay for it and traffic we send over it
+    Neighbor,
+    /// an exit our own traffic goes to
+    Destination,
+    /// a client of this exit
+    ExitClient,
+}
+
+impl FromStr for PeerUsageType {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<PeerUsageType, Error> {
+        match s {
+            "neighbor" => Ok(PeerUsageType::Neighbor),
+            "destination" => Ok(PeerUsageType::Destination),
+            "exit_client" => Ok(PeerUsageType::ExitClient),
+            _ => bail!("Unknown peer usage type {}", s),
+        }
+    }
+}
+
+/// Bytes moved between us and one other node and what was billed for them
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PeerUsage {
+    pub kind: PeerUsageType,
+    pub id: Identity,
+    /// bytes we sent towards this node
+    pub up: u64,
+    /// bytes we received from this node
+    pub down: u64,
+    /// uses the debt keeper's sign, positive is what we owe them and negative what they owe us
+    pub billed: Int256,
+}
+
+/// The per node usage of one hour, indexed in hours since unix epoch
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PeerUsageHour {
+    index: u64,
+    usage: Vec<PeerUsage>,
+}
+
+/// The wei per dollar rate seen during an hour, indexed in hours since unix epoch
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DaiRateHour {
+    index: u64,
+    wei_per_dollar: Uint256,
+}
+
 /// The main actor that holds the usage state for the duration of operations
 /// at some point loading and saving will be defined in service started
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -105,96 +183,341 @@ pub struct UsageTracker {
     exit_bandwith: VecDeque<UsageHour>,
     /// A history of payments
     payments: VecDeque<PaymentHour>,
+    /// A history of usage broken down by the node it was to or from
+    #[serde(default)]
+    peer_usage: VecDeque<PeerUsageHour>,
+    /// The price of eth in wei per dollar, recorded once an hour while it's being checked
+    #[serde(default)]
+    dai_rates: VecDeque<DaiRateHour>,
+    /// The sequence number of the last journal entry applied, journal entries up to this
+    /// one are already part of a saved snapshot
+    #[serde(default)]
+    journal_seq: u64,
+    /// Journal entries not yet written to disk
+    #[serde(skip)]
+    unflushed: Vec<JournalLine>,
+    #[serde(skip)]
+    last_flush: Option<Instant>,
+}
+
+/// A single change to the usage tracker as recorded in the journal
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum JournalEntry {
+    Usage {
+        kind: UsageType,
+        hour: u64,
+        up: u64,
+        down: u64,
+        price: u32,
+    },
+    Payment {
+        hour: u64,
+        payment: FormattedPaymentTx,
+    },
+    Peers {
+        hour: u64,
+        usage: Vec<PeerUsage>,
+    },
+    DaiRate {
+        hour: u64,
+        wei_per_dollar: Uint256,
+    },
+}
+
+/// One line of the journal file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct JournalLine {
+    seq: u64,
+    entry: JournalEntry,
+}
+
+/// The journal kept next to the given usage tracker file
+pub fn usage_journal_file(usage_file: &str) -> String {
+    format!("{}.journal", usage_file)
+}
+
+fn journal_path() -> String {
+    usage_journal_file(&SETTING.get_network().usage_tracker_file)
 }
 
 impl Default for UsageTracker {
     fn default() -> UsageTracker {
-        let file = File::open(SETTING.get_network().usage_tracker_file.clone());
-        // if the loading process goes wrong for any reason, we just start again
-        let blank_usage_tracker = UsageTracker {
-            last_save_hour: 0,
-            client_bandwith: VecDeque::new(),
-            relay_bandwith: VecDeque::new(),
-            exit_bandwith: VecDeque::new(),
-            payments: VecDeque::new(),
-        };
+        let mut tracker = load_snapshot();
+        match fs::read(journal_path()) {
+            Ok(journal) => {
+                tracker.replay_journal(&journal);
+                // otherwise the next append would be glued onto the cut short line
+                if let Err(e) = truncate_journal(&journal) {
+                    error!("Failed to truncate usage journal {:?}", e);
+                }
+            }
+            Err(e) => info!("No usage journal to replay {:?}", e),
+        }
+        tracker
+    }
+}
 
-        match file {
-            Ok(mut file) => {
-                let mut byte_contents = Vec::new();
-                // try compressed
-                match file.read_to_end(&mut byte_contents) {
-                    Ok(_bytes_read) => {
-                        let mut decoder = ZlibDecoder::new(&byte_contents[..]);
-                        let mut contents = Vec::new();
-                        let mut contents_str = String::new();
-                        // Extract data from decoder
-                        trace!("attempting to unzip or read bw history");
-                        match io::copy(&mut decoder, &mut contents) {
-                            Ok(_bytes) => {
-                                trace!("found a compressed json stream");
-                                let deserialized: Result<UsageTracker, SerdeError> =
-                                    serde_json::from_slice(&contents);
-                                match deserialized {
-                                    Ok(value) => value,
-                                    Err(e) => {
-                                        error!("Failed to deserialize bytes in compressed bw history {:?}", e);
-                                        blank_usage_tracker
-                                    }
+/// The length of the journal up to the end of its last complete line
+fn complete_lines_len(journal: &[u8]) -> usize {
+    match journal.iter().rposition(|b| *b == b'\n') {
+        Some(i) => i + 1,
+        None => 0,
+    }
+}
+
+/// Cuts off a line left partly written by a crash, if there is one
+fn truncate_journal(journal: &[u8]) -> Result<(), Error> {
+    let len = complete_lines_len(journal);
+    if len < journal.len() {
+        warn!(
+            "Truncating usage journal from {} to {} bytes",
+            journal.len(),
+            len
+        );
+        let file = OpenOptions::new().write(true).open(journal_path())?;
+        file.set_len(len as u64)?;
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+fn load_snapshot() -> UsageTracker {
+    let file = File::open(SETTING.get_network().usage_tracker_file.clone());
+    // if the loading process goes wrong for any reason, we just start again
+    let blank_usage_tracker = UsageTracker {
+        last_save_hour: 0,
+        client_bandwith: VecDeque::new(),
+        relay_bandwith: VecDeque::new(),
+        exit_bandwith: VecDeque::new(),
+        payments: VecDeque::new(),
+        peer_usage: VecDeque::new(),
+        dai_rates: VecDeque::new(),
+        journal_seq: 0,
+        unflushed: Vec::new(),
+        last_flush: None,
+    };
+
+    match file {
+        Ok(mut file) => {
+            let mut byte_contents = Vec::new();
+            // try compressed
+            match file.read_to_end(&mut byte_contents) {
+                Ok(_bytes_read) => {
+                    let mut decoder = ZlibDecoder::new(&byte_contents[..]);
+                    let mut contents = Vec::new();
+                    let mut contents_str = String::new();
+                    // Extract data from decoder
+                    trace!("attempting to unzip or read bw history");
+                    match io::copy(&mut decoder, &mut contents) {
+                        Ok(_bytes) => {
+                            trace!("found a compressed json stream");
+                            let deserialized: Result<UsageTracker, SerdeError> =
+                                serde_json::from_slice(&contents);
+                            match deserialized {
+                                Ok(value) => value,
+                                Err(e) => {
+                                    error!(
+                                        "Failed to deserialize bytes in compressed bw history {:?}",
+                                        e
+                                    );
+                                    blank_usage_tracker
                                 }
                             }
-                            Err(e) => {
-                                info!("Failed to decompress with, trying flatfile {:?}", e);
-                                file.seek(SeekFrom::Start(0))
-                                    .expect("Failed to return to start of file!");
-                                match file.read_to_string(&mut contents_str) {
-                                    Ok(_bytes_read) => {
-                                        trace!("failed to inflate, trying raw string");
-                                        let deserialized: Result<UsageTracker, SerdeError> =
-                                            serde_json::from_str(&contents_str);
-
-                                        match deserialized {
-                                            Ok(value) => value,
-                                            Err(e) => {
-                                                error!("Failed to deserialize usage tracker from flatfile {:?}", e);
-                                                blank_usage_tracker
-                                            }
+                        }
+                        Err(e) => {
+                            info!("Failed to decompress with, trying flatfile {:?}", e);
+                            file.seek(SeekFrom::Start(0))
+                                .expect("Failed to return to start of file!");
+                            match file.read_to_string(&mut contents_str) {
+                                Ok(_bytes_read) => {
+                                    trace!("failed to inflate, trying raw string");
+                                    let deserialized: Result<UsageTracker, SerdeError> =
+                                        serde_json::from_str(&contents_str);
+
+                                    match deserialized {
+                                        Ok(value) => value,
+                                        Err(e) => {
+                                            error!("Failed to deserialize usage tracker from flatfile {:?}", e);
+                                            blank_usage_tracker
                                         }
                                     }
-                                    Err(e) => {
-                                        error!(
-                                            "Failed to read usage tracker file to string! {:?}",
-                                            e
-                                        );
-                                        blank_usage_tracker
-                                    }
+                                }
+                                Err(e) => {
+                                    error!("Failed to read usage tracker file to string! {:?}", e);
+                                    blank_usage_tracker
                                 }
                             }
                         }
                     }
-                    Err(e) => {
-                        error!("Failed to read usage tracker file! {:?}", e);
-                        blank_usage_tracker
-                    }
+                }
+                Err(e) => {
+                    error!("Failed to read usage tracker file! {:?}", e);
+                    blank_usage_tracker
                 }
             }
-            Err(e) => {
-                error!("Failed to open usage tracker file! {:?}", e);
-                blank_usage_tracker
-            }
+        }
+        Err(e) => {
+            error!("Failed to open usage tracker file! {:?}", e);
+            blank_usage_tracker
         }
     }
 }
 
 impl UsageTracker {
+    /// Writes a snapshot of the whole history next to the old one and moves it into place,
+    /// once it's there the journal entries it includes are no longer needed
     fn save(&mut self) -> Result<(), IOError> {
         let serialized = serde_json::to_vec(self)?;
-        let mut file = File::create(SETTING.get_network().usage_tracker_file.clone())?;
+        let path = SETTING.get_network().usage_tracker_file.clone();
+        let tmp = format!("{}.tmp", path);
+        let mut file = File::create(&tmp)?;
         let buffer: Vec<u8> = Vec::new();
         let mut encoder = ZlibEncoder::new(buffer, Compression::fast());
         encoder.write_all(&serialized)?;
         let compressed_bytes = encoder.finish()?;
-        file.write_all(&compressed_bytes)
+        file.write_all(&compressed_bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+
+        self.unflushed.clear();
+        File::create(journal_path())?;
+        Ok(())
+    }
+
+    fn history_mut(&mut self, kind: UsageType) -> &mut VecDeque<UsageHour> {
+        match kind {
+            UsageType::Client => &mut self.client_bandwith,
+            UsageType::Relay => &mut self.relay_bandwith,
+            UsageType::Exit => &mut self.exit_bandwith,
+        }
+    }
+
+    fn apply(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::Usage {
+                kind,
+                hour,
+                up,
+                down,
+                price,
+            } => add_usage(self.history_mut(kind), hour, up, down, price),
+            JournalEntry::Payment { hour, payment } => {
+                add_payment(&mut self.payments, hour, payment)
+            }
+            JournalEntry::Peers { hour, usage } => {
+                add_peer_usage(&mut self.peer_usage, hour, &usage)
+            }
+            JournalEntry::DaiRate {
+                hour,
+                wei_per_dollar,
+            } => {
+                self.dai_rates.push_front(DaiRateHour {
+                    index: hour,
+                    wei_per_dollar,
+                });
+                while self.dai_rates.len() > MAX_ENTRIES {
+                    let _discarded_entry = self.dai_rates.pop_back();
+                }
+            }
+        }
+    }
+
+    /// Queues an entry for the journal, usage in the same hour as an entry that hasn't been
+    /// written yet is added to it rather than making the journal longer
+    fn journal(&mut self, entry: JournalEntry) {
+        for line in self.unflushed.iter_mut() {
+            match (&mut line.entry, &entry) {
+                (
+                    JournalEntry::Usage {
+                        kind: line_kind,
+                        hour: line_hour,
+                        up: line_up,
+                        down: line_down,
+                        ..
+                    },
+                    JournalEntry::Usage {
+                        kind,
+                        hour,
+                        up,
+                        down,
+                        ..
+                    },
+                ) => {
+                    if line_kind == kind && line_hour == hour {
+                        *line_up += up;
+                        *line_down += down;
+                        return;
+                    }
+                }
+                (
+                    JournalEntry::Peers {
+                        hour: line_hour,
+                        usage: line_usage,
+                    },
+                    JournalEntry::Peers { hour, usage },
+                ) => {
+                    if line_hour == hour {
+                        merge_peer_usage(line_usage, usage);
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.journal_seq += 1;
+        self.unflushed.push(JournalLine {
+            seq: self.journal_seq,
+            entry,
+        });
+    }
+
+    /// Appends the queued entries to the journal, unless the last flush was too recent and
+    /// force is not set
+    fn flush_journal(&mut self, force: bool) -> Result<(), Error> {
+        if let Some(last_flush) = self.last_flush {
+            if !force && Instant::now() - last_flush < JOURNAL_FLUSH_INTERVAL {
+                return Ok(());
+            }
+        }
+        self.last_flush = Some(Instant::now());
+        if self.unflushed.is_empty() {
+            return Ok(());
+        }
+        let mut lines = Vec::new();
+        for line in self.unflushed.iter() {
+            serde_json::to_writer(&mut lines, line)?;
+            lines.push(b'\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(journal_path())?;
+        file.write_all(&lines)?;
+        file.sync_data()?;
+        self.unflushed.clear();
+        Ok(())
+    }
+
+    /// Applies every journal entry newer than the loaded snapshot, a line cut short by a
+    /// crash is skipped
+    fn replay_journal(&mut self, journal: &[u8]) {
+        let mut replayed = 0;
+        for line in journal.split(|b| *b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_slice::<JournalLine>(line) {
+                Ok(line) => {
+                    if line.seq > self.journal_seq {
+                        self.journal_seq = line.seq;
+                        self.apply(line.entry);
+                        replayed += 1;
+                    }
+                }
+                Err(e) => warn!("Skipping damaged usage journal line {:?}", e),
+            }
+        }
+        info!("Replayed {} usage journal entries", replayed);
     }
 }
 
@@ -210,11 +533,27 @@ impl SystemService for UsageTracker {
 }
 
 /// Gets the current hour since the unix epoch
-fn get_current_hour() -> Result<u64, Error> {
+pub fn get_current_hour() -> Result<u64, Error> {
     let seconds = SystemTime::now().duration_since(UNIX_EPOCH)?;
     Ok(seconds.as_secs() / (60 * 60))
 }
 
+/// Averages the bytes up and down per hour over the given number of hours leading up to
+/// current_hour. Hours with no traffic don't have an entry so they count as zero.
+pub fn average_hourly_usage(history: &VecDeque<UsageHour>, current_hour: u64, hours: u64) -> u64 {
+    if hours == 0 {
+        return 0;
+    }
+    let start = current_hour.saturating_sub(hours);
+    let total: u64 = history
+        .iter()
+        .take_while(|entry| entry.index > start)
+        .filter(|entry| entry.index <= current_hour)
+        .map(|entry| entry.up + entry.down)
+        .sum();
+    total / hours
+}
+
 /// The messauge used to update the current usage hour from each traffic
 /// watcher module
 #[derive(Clone, Copy, Debug)]
@@ -246,32 +585,51 @@ impl Handler<UpdateUsage> for UsageTracker {
 }
 
 fn process_usage_update(current_hour: u64, msg: UpdateUsage, data: &mut UsageTracker) {
-    // history contains a reference to whatever the correct storage array is
-    let history = match msg.kind {
-        UsageType::Client => &mut data.client_bandwith,
-        UsageType::Relay => &mut data.relay_bandwith,
-        UsageType::Exit => &mut data.exit_bandwith,
+    let entry = JournalEntry::Usage {
+        kind: msg.kind,
+        hour: current_hour,
+        up: msg.up,
+        down: msg.down,
+        price: msg.price,
     };
+    data.apply(entry.clone());
+    data.journal(entry);
+    save_or_flush(current_hour, data, false);
+}
+
+/// Saves a snapshot if one is due, otherwise writes out the journal
+fn save_or_flush(current_hour: u64, data: &mut UsageTracker, force_flush: bool) {
+    if (current_hour - SAVE_FREQENCY) > data.last_save_hour {
+        data.last_save_hour = current_hour;
+        let res = data.save();
+        info!("Saving usage data: {:?}", res);
+    } else if let Err(e) = data.flush_journal(force_flush) {
+        error!("Failed to write usage journal {:?}", e);
+    }
+}
+
+/// Adds usage to the given hour of the history
+fn add_usage(history: &mut VecDeque<UsageHour>, hour: u64, up: u64, down: u64, price: u32) {
     // we grab the front entry from the VecDeque, if there is an entry one we check if it's
     // up to date, if it is we add to it, if it's not or there is no entry we create one.
     // note that price is only sampled once per hour.
     match history.front_mut() {
         None => history.push_front(UsageHour {
-            index: current_hour,
-            up: msg.up,
-            down: msg.down,
-            price: msg.price,
+            index: hour,
+            up,
+            down,
+            price,
         }),
         Some(entry) => {
-            if entry.index == current_hour {
-                entry.up += msg.up;
-                entry.down += msg.down;
+            if entry.index == hour {
+                entry.up += up;
+                entry.down += down;
             } else {
                 history.push_front(UsageHour {
-                    index: current_hour,
-                    up: msg.up,
-                    down: msg.down,
-                    price: msg.price,
+                    index: hour,
+                    up,
+                    down,
+                    price,
                 })
             }
         }
@@ -279,10 +637,57 @@ fn process_usage_update(current_hour: u64, msg: UpdateUsage, data: &mut UsageTra
     while history.len() > MAX_ENTRIES {
         let _discarded_entry = history.pop_back();
     }
-    if (current_hour - SAVE_FREQENCY) > data.last_save_hour {
-        data.last_save_hour = current_hour;
-        let res = data.save();
-        info!("Saving usage data: {:?}", res);
+}
+
+fn add_payment(payments: &mut VecDeque<PaymentHour>, hour: u64, payment: FormattedPaymentTx) {
+    match payments.front_mut() {
+        None => payments.push_front(PaymentHour {
+            index: hour,
+            payments: vec![payment],
+        }),
+        Some(entry) => {
+            if entry.index == hour {
+                entry.payments.push(payment);
+            } else {
+                payments.push_front(PaymentHour {
+                    index: hour,
+                    payments: vec![payment],
+                })
+            }
+        }
+    }
+    while payments.len() > MAX_ENTRIES {
+        let _discarded_entry = payments.pop_back();
+    }
+}
+
+/// Adds each node's usage to the entry for the same node in into, or a new one
+fn merge_peer_usage(into: &mut Vec<PeerUsage>, usage: &[PeerUsage]) {
+    for peer in usage {
+        match into
+            .iter_mut()
+            .find(|entry| entry.kind == peer.kind && entry.id == peer.id)
+        {
+            Some(entry) => {
+                entry.up += peer.up;
+                entry.down += peer.down;
+                entry.billed += peer.billed.clone();
+            }
+            None => into.push(peer.clone()),
+        }
+    }
+}
+
+fn add_peer_usage(history: &mut VecDeque<PeerUsageHour>, hour: u64, usage: &[PeerUsage]) {
+    match history.front_mut() {
+        Some(entry) if entry.index == hour => merge_peer_usage(&mut entry.usage, usage),
+        _ => history.push_front(PeerUsageHour {
+            index: hour,
+            usage: usage.to_vec(),
+        }),
+    }
+    while history.len() > MAX_PEER_ENTRIES {
+        let _discarded_entry = history.pop_back();
     }
 }
 
@@ -304,31 +709,16 @@ impl Handler<UpdatePayments> for UsageTracker {
                 return Ok(());
             }
         };
-        let formatted_payment = to_formatted_payment_tx(msg.payment);
-        match self.payments.front_mut() {
-            None => self.payments.push_front(PaymentHour {
-                index: current_hour,
-                payments: vec![formatted_payment],
-            }),
-            Some(entry) => {
-                if entry.index == current_hour {
-                    entry.payments.push(formatted_payment);
-                } else {
-                    self.payments.push_front(PaymentHour {
-                        index: current_hour,
-                        payments: vec![formatted_payment],
-                    })
-                }
-            }
-        }
-        while self.payments.len() > MAX_ENTRIES {
-            let _discarded_entry = self.payments.pop_back();
-        }
-        if (current_hour - SAVE_FREQENCY) > self.last_save_hour {
-            self.last_save_hour = current_hour;
-            let res = self.save();
-            info!("Saving usage data: {:?}", res);
-        }
+        let mut payment = to_formatted_payment_tx(msg.payment);
+        payment.category = Some(PaymentParties::from_settings().categorize(&payment));
+        let entry = JournalEntry::Payment {
+            hour: current_hour,
+            payment,
+        };
+        self.apply(entry.clone());
+        self.journal(entry);
+        // payments are rare and worth more than usage, write them out right away
+        save_or_flush(current_hour, self, true);
         Ok(())
     }
 }
@@ -364,3 +754,621 @@ impl Handler<GetPayments> for UsageTracker {
         Ok(self.payments.clone())
     }
 }
+
+/// The usage of each node we moved traffic to or from this round, sent by the traffic watchers
+pub struct UpdatePeerUsage {
+    pub usage: Vec<PeerUsage>,
+}
+
+impl Message for UpdatePeerUsage {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<UpdatePeerUsage> for UsageTracker {
+    type Result = Result<(), Error>;
+    fn handle(&mut self, msg: UpdatePeerUsage, _: &mut Context<Self>) -> Self::Result {
+        if msg.usage.is_empty() {
+            return Ok(());
+        }
+        let current_hour = match get_current_hour() {
+            Ok(hour) => hour,
+            Err(e) => {
+                error!("System time is set earlier than unix epoch! {:?}", e);
+                return Ok(());
+            }
+        };
+        let entry = JournalEntry::Peers {
+            hour: current_hour,
+            usage: msg.usage,
+        };
+        self.apply(entry.clone());
+        self.journal(entry);
+        save_or_flush(current_hour, self, false);
+        Ok(())
+    }
+}
+
+/// Records the current wei per dollar rate, only the first rate seen each hour is kept
+pub struct RecordDaiRate {
+    pub wei_per_dollar: Uint256,
+}
+
+impl Message for RecordDaiRate {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<RecordDaiRate> for UsageTracker {
+    type Result = Result<(), Error>;
+    fn handle(&mut self, msg: RecordDaiRate, _: &mut Context<Self>) -> Self::Result {
+        let current_hour = get_current_hour()?;
+        if self.dai_rates.front().map(|rate| rate.index) == Some(current_hour)
+            || msg.wei_per_dollar == Uint256::from(0u8)
+        {
+            return Ok(());
+        }
+        let entry = JournalEntry::DaiRate {
+            hour: current_hour,
+            wei_per_dollar: msg.wei_per_dollar,
+        };
+        self.apply(entry.clone());
+        self.journal(entry);
+        save_or_flush(current_hour, self, false);
+        Ok(())
+    }
+}
+
+/// What the top nodes are ranked by
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerRanking {
+    /// bytes moved in both directions
+    Bytes,
+    /// the size of the amount billed, whichever way it went
+    Billed,
+}
+
+impl Default for PeerRanking {
+    fn default() -> Self {
+        PeerRanking::Bytes
+    }
+}
+
+fn default_top_peers_limit() -> usize {
+    10
+}
+
+/// Query parameters for the top nodes, times are in seconds since the unix epoch
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TopPeersQuery {
+    pub start: Option<u64>,
+    /// exclusive
+    pub end: Option<u64>,
+    #[serde(default = "default_top_peers_limit")]
+    pub limit: usize,
+    #[serde(default)]
+    pub by: PeerRanking,
+}
+
+/// Gets the nodes of the given kind that moved the most traffic or were billed the most
+/// between start and end, with their usage totalled over that time
+pub struct GetTopPeers {
+    pub kind: PeerUsageType,
+    pub start: u64,
+    pub end: u64,
+    pub limit: usize,
+    pub by: PeerRanking,
+}
+
+impl Message for GetTopPeers {
+    type Result = Result<Vec<PeerUsage>, Error>;
+}
+
+impl Handler<GetTopPeers> for UsageTracker {
+    type Result = Result<Vec<PeerUsage>, Error>;
+    fn handle(&mut self, msg: GetTopPeers, _: &mut Context<Self>) -> Self::Result {
+        Ok(top_peers(&self.peer_usage, &msg))
+    }
+}
+
+fn top_peers(history: &VecDeque<PeerUsageHour>, query: &GetTopPeers) -> Vec<PeerUsage> {
+    let mut totals = Vec::new();
+    for hour in history.iter() {
+        let time = hour.index * SECONDS_PER_HOUR;
+        if time < query.start || time >= query.end {
+            continue;
+        }
+        let usage: Vec<PeerUsage> = hour
+            .usage
+            .iter()
+            .filter(|peer| peer.kind == query.kind)
+            .cloned()
+            .collect();
+        merge_peer_usage(&mut totals, &usage);
+    }
+    match query.by {
+        PeerRanking::Bytes => totals.sort_by(|a, b| (b.up + b.down).cmp(&(a.up + a.down))),
+        PeerRanking::Billed => totals.sort_by(|a, b| b.billed.abs().cmp(&a.billed.abs())),
+    }
+    totals.truncate(query.limit);
+    totals
+}
+
+/// The size of the buckets usage is grouped into by query_usage
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    Hour,
+    Day,
+    /// calendar months in UTC
+    Month,
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Resolution::Hour
+    }
+}
+
+/// How the hours in a bucket are combined by query_usage
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    /// the total bytes in the bucket
+    Sum,
+    /// bytes per hour, hours without traffic count as zero
+    Average,
+    /// the busiest hour in the bucket
+    Max,
+}
+
+impl Default for Aggregation {
+    fn default() -> Self {
+        Aggregation::Sum
+    }
+}
+
+/// Query parameters for the usage history, times are in seconds since the unix epoch
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default)]
+pub struct UsageQuery {
+    /// defaults to the start of the history
+    pub start: Option<u64>,
+    /// exclusive, defaults to the end of the current hour
+    pub end: Option<u64>,
+    #[serde(default)]
+    pub resolution: Resolution,
+    #[serde(default)]
+    pub aggregation: Aggregation,
+}
+
+/// Usage over one bucket of a query, newest first like the history itself
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UsageBucket {
+    /// seconds since the unix epoch
+    pub start: u64,
+    pub up: u64,
+    pub down: u64,
+    /// the average price over the hours with traffic
+    pub price: u32,
+}
+
+/// Days since the unix epoch to a year, month and day, see
+/// http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The inverse of civil_from_days
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The start and end of the bucket containing the given time, in seconds since the epoch
+fn bucket_bounds(time: u64, resolution: Resolution) -> (u64, u64) {
+    match resolution {
+        Resolution::Hour => {
+            let start = time - time % SECONDS_PER_HOUR;
+            (start, start + SECONDS_PER_HOUR)
+        }
+        Resolution::Day => {
+            let start = time - time % SECONDS_PER_DAY;
+            (start, start + SECONDS_PER_DAY)
+        }
+        Resolution::Month => {
+            let (year, month, _) = civil_from_days((time / SECONDS_PER_DAY) as i64);
+            let (next_year, next_month) = if month == 12 {
+                (year + 1, 1)
+            } else {
+                (year, month + 1)
+            };
+            let start = days_from_civil(year, month, 1) as u64 * SECONDS_PER_DAY;
+            let end = days_from_civil(next_year, next_month, 1) as u64 * SECONDS_PER_DAY;
+            (start, end)
+        }
+    }
+}
+
+/// Groups the hours of a usage history between start and end into buckets of the requested
+/// resolution and aggregates each of them
+pub fn query_usage(
+    history: &VecDeque<UsageHour>,
+    start: u64,
+    end: u64,
+    resolution: Resolution,
+    aggregation: Aggregation,
+) -> Vec<UsageBucket> {
+    let mut buckets: Vec<UsageBucket> = Vec::new();
+    // hours with traffic in the current bucket, used for the price average
+    let mut priced_hours = 0u64;
+    let mut price_total = 0u64;
+
+    let finish = |bucket: &mut UsageBucket, priced_hours: u64, price_total: u64| {
+        if priced_hours > 0 {
+            bucket.price = (price_total / priced_hours) as u32;
+        }
+        if aggregation == Aggregation::Average {
+            let (bucket_start, bucket_end) = bucket_bounds(bucket.start, resolution);
+            let span = bucket_end.min(end) - bucket_start.max(start);
+            let hours = ((span + SECONDS_PER_HOUR - 1) / SECONDS_PER_HOUR).max(1);
+            bucket.up /= hours;
+            bucket.down /= hours;
+        }
+    };
+
+    for hour in history.iter() {
+        let time = hour.index * SECONDS_PER_HOUR;
+        if time < start || time >= end {
+            continue;
+        }
+        let (bucket_start, _) = bucket_bounds(time, resolution);
+        if buckets.last().map(|b| b.start) != Some(bucket_start) {
+            if let Some(bucket) = buckets.last_mut() {
+                finish(bucket, priced_hours, price_total);
+            }
+            priced_hours = 0;
+            price_total = 0;
+            buckets.push(UsageBucket {
+                start: bucket_start,
+                up: 0,
+                down: 0,
+                price: 0,
+            });
+        }
+        let bucket = buckets.last_mut().unwrap();
+        match aggregation {
+            Aggregation::Sum | Aggregation::Average => {
+                bucket.up += hour.up;
+                bucket.down += hour.down;
+            }
+            Aggregation::Max => {
+                bucket.up = bucket.up.max(hour.up);
+                bucket.down = bucket.down.max(hour.down);
+            }
+        }
+        priced_hours += 1;
+        price_total += u64::from(hour.price);
+    }
+    if let Some(bucket) = buckets.last_mut() {
+        finish(bucket, priced_hours, price_total);
+    }
+    buckets
+}
+
+/// Identifies the other side of a payment by any one of its identifiers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counterparty {
+    MeshIp(IpAddr),
+    EthAddress(Address),
+    WgKey(WgKey),
+}
+
+impl FromStr for Counterparty {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Counterparty, Error> {
+        if let Ok(ip) = s.parse() {
+            Ok(Counterparty::MeshIp(ip))
+        } else if let Ok(address) = s.parse() {
+            Ok(Counterparty::EthAddress(address))
+        } else if let Ok(key) = s.parse() {
+            Ok(Counterparty::WgKey(key))
+        } else {
+            bail!("{} is not a mesh ip, eth address or wireguard key", s)
+        }
+    }
+}
+
+impl Counterparty {
+    pub fn matches(&self, id: &Identity) -> bool {
+        match self {
+            Counterparty::MeshIp(ip) => id.mesh_ip == *ip,
+            Counterparty::EthAddress(address) => id.eth_address == *address,
+            Counterparty::WgKey(key) => id.wg_public_key == *key,
+        }
+    }
+}
+
+/// Query parameters for the payment history, times are in seconds since the unix epoch
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct PaymentQuery {
+    pub start: Option<u64>,
+    /// exclusive
+    pub end: Option<u64>,
+    /// a mesh ip, eth address or wireguard key, only payments to or from it are returned
+    pub counterparty: Option<String>,
+}
+
+/// The hours of the payment history between start and end, keeping only the payments to or
+/// from the counterparty if one is given. Hours left without payments are dropped
+pub fn query_payments(
+    payments: &VecDeque<PaymentHour>,
+    start: u64,
+    end: u64,
+    counterparty: Option<Counterparty>,
+) -> Vec<PaymentHour> {
+    payments
+        .iter()
+        .filter(|hour| {
+            let time = hour.index * SECONDS_PER_HOUR;
+            time >= start && time < end
+        })
+        .filter_map(|hour| {
+            let payments: Vec<FormattedPaymentTx> = hour
+                .payments
+                .iter()
+                .filter(|payment| match counterparty {
+                    Some(c) => c.matches(&payment.to) || c.matches(&payment.from),
+                    None => true,
+                })
+                .cloned()
+                .collect();
+            if payments.is_empty() {
+                None
+            } else {
+                Some(PaymentHour {
+                    index: hour.index,
+                    payments,
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hour(index: u64, up: u64, down: u64) -> UsageHour {
+        UsageHour {
+            index,
+            up,
+            down,
+            price: 0,
+        }
+    }
+
+    #[test]
+    fn test_average_hourly_usage() {
+        // newest first, the same order process_usage_update keeps them in
+        let history: VecDeque<UsageHour> =
+            vec![hour(100, 10, 20), hour(99, 30, 40), hour(90, 1000, 1000)]
+                .into_iter()
+                .collect();
+        assert_eq!(average_hourly_usage(&history, 100, 2), 50);
+        assert_eq!(average_hourly_usage(&history, 100, 20), 105);
+        assert_eq!(average_hourly_usage(&history, 100, 0), 0);
+        assert_eq!(average_hourly_usage(&VecDeque::new(), 100, 24), 0);
+    }
+
+    #[test]
+    fn test_query_usage() {
+        // 2019-01-31 22:00 and 23:00 and 2019-02-01 00:00 UTC
+        let feb = 1_548_979_200 / 3600;
+        let history: VecDeque<UsageHour> = vec![
+            UsageHour {
+                index: feb,
+                up: 10,
+                down: 20,
+                price: 30,
+            },
+            hour(feb - 1, 5, 5),
+            hour(feb - 2, 1, 1),
+        ]
+        .into_iter()
+        .collect();
+
+        let months = query_usage(
+            &history,
+            0,
+            u64::max_value(),
+            Resolution::Month,
+            Aggregation::Sum,
+        );
+        assert_eq!(
+            months,
+            vec![
+                UsageBucket {
+                    start: 1_548_979_200,
+                    up: 10,
+                    down: 20,
+                    price: 30
+                },
+                UsageBucket {
+                    start: 1_546_300_800,
+                    up: 6,
+                    down: 6,
+                    price: 0
+                },
+            ]
+        );
+
+        // the range cuts off 22:00 so the average is over the one hour of the 31st left
+        let days = query_usage(
+            &history,
+            (feb - 1) * 3600,
+            (feb + 1) * 3600,
+            Resolution::Day,
+            Aggregation::Average,
+        );
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[1].up, 5);
+        assert_eq!(days[1].down, 5);
+
+        let max = query_usage(
+            &history,
+            0,
+            u64::max_value(),
+            Resolution::Day,
+            Aggregation::Max,
+        );
+        assert_eq!(max[1].up, 5);
+    }
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(17_928), (2019, 2, 1));
+        assert_eq!(days_from_civil(2019, 2, 1), 17_928);
+        assert_eq!(
+            days_from_civil(2020, 3, 1) - days_from_civil(2020, 2, 1),
+            29
+        );
+    }
+
+    #[test]
+    fn test_replay_journal() {
+        let mut tracker = UsageTracker {
+            last_save_hour: 0,
+            client_bandwith: VecDeque::new(),
+            relay_bandwith: VecDeque::new(),
+            exit_bandwith: VecDeque::new(),
+            payments: VecDeque::new(),
+            peer_usage: VecDeque::new(),
+            dai_rates: VecDeque::new(),
+            journal_seq: 1,
+            unflushed: Vec::new(),
+            last_flush: None,
+        };
+        let usage = |up| JournalEntry::Usage {
+            kind: UsageType::Relay,
+            hour: 10,
+            up,
+            down: 0,
+            price: 1,
+        };
+        tracker.journal(usage(1));
+        // merged into the unwritten entry
+        tracker.journal(usage(2));
+        assert_eq!(tracker.unflushed.len(), 1);
+
+        let mut journal = Vec::new();
+        for (seq, up) in [(1, 100), (2, 3), (3, 4)].iter() {
+            serde_json::to_writer(
+                &mut journal,
+                &JournalLine {
+                    seq: *seq,
+                    entry: usage(*up),
+                },
+            )
+            .unwrap();
+            journal.push(b'\n');
+        }
+        // a line cut short by a crash
+        journal.extend_from_slice(b"{\"seq\":4,\"ent");
+
+        tracker.journal_seq = 1;
+        tracker.replay_journal(&journal);
+        // the first entry is already in the snapshot
+        assert_eq!(tracker.relay_bandwith[0].up, 7);
+        assert_eq!(tracker.journal_seq, 3);
+
+        // the cut short line is dropped so the next append starts on a line of its own
+        let len = complete_lines_len(&journal);
+        assert!(journal[..len].ends_with(b"\n"));
+        assert_eq!(&journal[len..], b"{\"seq\":4,\"ent");
+        assert_eq!(complete_lines_len(&journal[..len]), len);
+        assert_eq!(complete_lines_len(b"{\"seq\":1"), 0);
+    }
+
+    fn peer(mesh_ip: &str, kind: PeerUsageType, up: u64, billed: i64) -> PeerUsage {
+        PeerUsage {
+            kind,
+            id: Identity::new(
+                mesh_ip.parse().unwrap(),
+                "0x0000000000000000000000000000000000000001"
+                    .parse()
+                    .unwrap(),
+                "8BeCExnthLe5ou0EYec5jNqJ/PduZ1x2o7lpXJOpgXk="
+                    .parse()
+                    .unwrap(),
+                None,
+            ),
+            up,
+            down: 0,
+            billed: Int256::from(billed),
+        }
+    }
+
+    #[test]
+    fn test_top_peers() {
+        let mut history = VecDeque::new();
+        add_peer_usage(
+            &mut history,
+            10,
+            &[
+                peer("fd00::1", PeerUsageType::Neighbor, 100, 5),
+                peer("fd00::2", PeerUsageType::Neighbor, 50, -500),
+            ],
+        );
+        // the same hour is merged
+        add_peer_usage(
+            &mut history,
+            10,
+            &[peer("fd00::2", PeerUsageType::Neighbor, 10, -10)],
+        );
+        add_peer_usage(
+            &mut history,
+            11,
+            &[
+                peer("fd00::2", PeerUsageType::Neighbor, 60, 0),
+                peer("fd00::3", PeerUsageType::Destination, 1000, 1000),
+            ],
+        );
+        assert_eq!(history.len(), 2);
+
+        let mut query = GetTopPeers {
+            kind: PeerUsageType::Neighbor,
+            start: 0,
+            end: u64::max_value(),
+            limit: 10,
+            by: PeerRanking::Bytes,
+        };
+        let top = top_peers(&history, &query);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].up, 120);
+        assert_eq!(top[0].billed, Int256::from(-510));
+
+        // only hour 10, ranked by how much was billed
+        query.end = 11 * 3600;
+        query.by = PeerRanking::Billed;
+        query.limit = 1;
+        let top = top_peers(&history, &query);
+        assert_eq!(
+            top,
+            vec![peer("fd00::2", PeerUsageType::Neighbor, 60, -510)]
+        );
+    }
+}
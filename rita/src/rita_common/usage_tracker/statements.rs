@@ -0,0 +1,651 @@
+//! Monthly statements built from the usage tracker's history. A statement answers "what did I
+//! pay, to whom, for how much data" for one calendar month in UTC. It totals the bandwidth used
+//! of each kind along with its cost at the price recorded for each hour, then lists every
+//! payment sent or received, with DAO fees and simulated transaction fees split out from
+//! ordinary bandwidth payments. Amounts are in wei, and also in dollars when a DAI rate is known.
+//!
+//! Each amount is converted at the rate recorded for its own hour, or the closest one recorded
+//! before it, so a statement shows what the payments were worth when they were made. Payments
+//! are sorted by the category they were given when they were made, older payments recorded
+//! without one are sorted using the current DAO addresses.
+
+use super::{
+    bucket_bounds, civil_from_days, days_from_civil, DaiRateHour, FormattedPaymentTx, Resolution,
+    UsageTracker, UsageType, SECONDS_PER_DAY, SECONDS_PER_HOUR,
+};
+use crate::SETTING;
+use actix::{Context, Handler, Message};
+use althea_types::{Identity, SystemChain};
+use clarity::Address;
+use failure::Error;
+use num256::Uint256;
+use settings::RitaCommonSettings;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+/// One dai, 1*10^18 wei dai to the dollar
+const WEI_PER_DAI: u128 = 1_000_000_000_000_000_000;
+
+/// Bandwidth of one kind used over the month
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StatementUsage {
+    pub kind: UsageType,
+    pub up: u64,
+    pub down: u64,
+    /// the bytes of each hour times the price recorded for that hour
+    pub cost: Uint256,
+    /// the cost of each hour at that hour's rate, none if any hour has no rate
+    pub cost_usd: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentCategory {
+    /// bandwidth payments we made
+    Sent,
+    /// bandwidth payments made to us
+    Received,
+    DaoFee,
+    SimulatedTxFee,
+}
+
+impl PaymentCategory {
+    fn name(self) -> &'static str {
+        match self {
+            PaymentCategory::Sent => "sent",
+            PaymentCategory::Received => "received",
+            PaymentCategory::DaoFee => "dao_fee",
+            PaymentCategory::SimulatedTxFee => "simulated_tx_fee",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StatementPayment {
+    /// the start of the hour the payment was made in, seconds since the unix epoch
+    pub time: u64,
+    pub category: PaymentCategory,
+    /// who the payment was to or from
+    pub counterparty: Identity,
+    pub amount: Uint256,
+    pub amount_usd: Option<String>,
+    /// the rate amount_usd was converted at
+    pub wei_per_dollar: Option<Uint256>,
+    pub txid: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StatementTotal {
+    pub category: PaymentCategory,
+    pub amount: Uint256,
+    /// the sum of the payments' dollar amounts, none if any of them has none
+    pub amount_usd: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub year: i64,
+    pub month: u32,
+    /// the rate at the end of the month, none if no rate was recorded by then. Each amount is
+    /// converted at the rate of its own hour
+    pub wei_per_dollar: Option<Uint256>,
+    pub usage: Vec<StatementUsage>,
+    pub payments: Vec<StatementPayment>,
+    pub totals: Vec<StatementTotal>,
+}
+
+/// Who we are and who our fees go to, what sorting payments into categories needs
+pub struct PaymentParties {
+    pub our_address: Option<Address>,
+    pub dao_addresses: Vec<Address>,
+    pub simulated_tx_fee_address: Address,
+}
+
+impl PaymentParties {
+    pub fn from_settings() -> PaymentParties {
+        let payment = SETTING.get_payment();
+        let our_address = payment.eth_address;
+        let simulated_tx_fee_address = payment.simulated_transaction_fee_address;
+        drop(payment);
+        PaymentParties {
+            our_address,
+            dao_addresses: SETTING.get_dao().dao_addresses.clone(),
+            simulated_tx_fee_address,
+        }
+    }
+
+    pub fn categorize(&self, payment: &FormattedPaymentTx) -> PaymentCategory {
+        if Some(payment.from.eth_address) != self.our_address {
+            PaymentCategory::Received
+        } else if payment.to.eth_address == self.simulated_tx_fee_address {
+            PaymentCategory::SimulatedTxFee
+        } else if self.dao_addresses.contains(&payment.to.eth_address) {
+            PaymentCategory::DaoFee
+        } else {
+            PaymentCategory::Sent
+        }
+    }
+}
+
+/// How amounts are converted to dollars
+pub enum DollarRates {
+    /// the coin is pegged to the dollar, like xDai
+    Fixed(Uint256),
+    /// the rate recorded for each hour, newest first like the tracker's history
+    Recorded(Vec<(u64, Uint256)>),
+    /// amounts can't be converted, like those of a payment token we know no rate for
+    Unknown,
+}
+
+impl DollarRates {
+    /// The rate of the given hour, or the closest one recorded before it
+    pub fn at(&self, hour: u64) -> Option<Uint256> {
+        match self {
+            DollarRates::Fixed(rate) => Some(rate.clone()),
+            DollarRates::Recorded(rates) => rates
+                .iter()
+                .find(|(index, _)| *index <= hour)
+                .map(|(_, rate)| rate.clone()),
+            DollarRates::Unknown => None,
+        }
+    }
+}
+
+/// What a statement needs to know besides the history
+pub struct StatementContext {
+    /// sorts the payments recorded before payments carried their category
+    pub parties: PaymentParties,
+    pub rates: DollarRates,
+}
+
+/// Converts a wei amount to whole cents, rounding down
+fn to_cents(wei: &Uint256, wei_per_dollar: &Option<Uint256>) -> Option<Uint256> {
+    match wei_per_dollar {
+        Some(rate) if *rate > Uint256::from(0u8) => {
+            Some(wei.clone() * Uint256::from(100u8) / rate.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Formats cents as dollars with two decimal places
+fn format_cents(cents: &Uint256) -> String {
+    let cents = format!("{:0>3}", cents.to_string());
+    let (dollars, cents) = cents.split_at(cents.len() - 2);
+    format!("{}.{}", dollars, cents)
+}
+
+/// Formats a wei amount as dollars with two decimal places, rounding down to the cent
+fn to_dollars(wei: &Uint256, wei_per_dollar: &Option<Uint256>) -> Option<String> {
+    to_cents(wei, wei_per_dollar).map(|cents| format_cents(&cents))
+}
+
+/// Adds up amounts that were each converted on their own, none if any couldn't be
+fn sum_cents(cents: impl Iterator<Item = Option<Uint256>>) -> Option<String> {
+    let mut total = Uint256::from(0u8);
+    for amount in cents {
+        total = total + amount?;
+    }
+    Some(format_cents(&total))
+}
+
+/// The UTC calendar month as seconds since the unix epoch, start inclusive and end exclusive
+pub fn month_bounds(year: i64, month: u32) -> Result<(u64, u64), Error> {
+    if month < 1 || month > 12 {
+        bail!("{} is not a month", month);
+    }
+    let days = days_from_civil(year, month, 1);
+    if days < 0 {
+        bail!("Statements start at 1970");
+    }
+    Ok(bucket_bounds(
+        days as u64 * SECONDS_PER_DAY,
+        Resolution::Month,
+    ))
+}
+
+pub fn build_statement(
+    tracker: &UsageTracker,
+    year: i64,
+    month: u32,
+    ctx: &StatementContext,
+) -> Result<Statement, Error> {
+    let (start, end) = month_bounds(year, month)?;
+    let in_month = |index: u64| {
+        let time = index * SECONDS_PER_HOUR;
+        time >= start && time < end
+    };
+
+    let mut usage = Vec::new();
+    for (kind, history) in [
+        (UsageType::Client, &tracker.client_bandwith),
+        (UsageType::Relay, &tracker.relay_bandwith),
+        (UsageType::Exit, &tracker.exit_bandwith),
+    ]
+    .iter()
+    {
+        let mut line = StatementUsage {
+            kind: *kind,
+            up: 0,
+            down: 0,
+            cost: Uint256::from(0u8),
+            cost_usd: None,
+        };
+        let mut cents = Vec::new();
+        for hour in history.iter().filter(|hour| in_month(hour.index)) {
+            let cost = Uint256::from(hour.up + hour.down) * Uint256::from(hour.price);
+            line.up += hour.up;
+            line.down += hour.down;
+            cents.push(to_cents(&cost, &ctx.rates.at(hour.index)));
+            line.cost = line.cost + cost;
+        }
+        if line.up + line.down > 0 {
+            line.cost_usd = sum_cents(cents.into_iter());
+            usage.push(line);
+        }
+    }
+
+    let mut payments = Vec::new();
+    // the history is newest first, statements read oldest first
+    for hour in tracker
+        .payments
+        .iter()
+        .rev()
+        .filter(|hour| in_month(hour.index))
+    {
+        let wei_per_dollar = ctx.rates.at(hour.index);
+        for payment in hour.payments.iter() {
+            let category = payment
+                .category
+                .unwrap_or_else(|| ctx.parties.categorize(payment));
+            let counterparty = if category == PaymentCategory::Received {
+                payment.from
+            } else {
+                payment.to
+            };
+            payments.push(StatementPayment {
+                time: hour.index * SECONDS_PER_HOUR,
+                category,
+                counterparty,
+                amount: payment.amount.clone(),
+                amount_usd: to_dollars(&payment.amount, &wei_per_dollar),
+                wei_per_dollar: wei_per_dollar.clone(),
+                txid: payment.txid.clone(),
+            });
+        }
+    }
+
+    let mut totals = Vec::new();
+    for category in [
+        PaymentCategory::Sent,
+        PaymentCategory::Received,
+        PaymentCategory::DaoFee,
+        PaymentCategory::SimulatedTxFee,
+    ]
+    .iter()
+    {
+        let mut amount = Uint256::from(0u8);
+        let mut cents = Vec::new();
+        for payment in payments.iter().filter(|p| p.category == *category) {
+            amount = amount + payment.amount.clone();
+            cents.push(to_cents(&payment.amount, &payment.wei_per_dollar));
+        }
+        totals.push(StatementTotal {
+            category: *category,
+            amount_usd: sum_cents(cents.into_iter()),
+            amount,
+        });
+    }
+
+    Ok(Statement {
+        year,
+        month,
+        wei_per_dollar: ctx.rates.at((end - 1) / SECONDS_PER_HOUR),
+        usage,
+        payments,
+        totals,
+    })
+}
+
+/// Formats seconds since the unix epoch as an ISO 8601 UTC time
+fn format_time(time: u64) -> String {
+    let (year, month, day) = civil_from_days((time / SECONDS_PER_DAY) as i64);
+    let seconds = time % SECONDS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
+
+/// Renders a statement as CSV, one row for each kind of usage, then each payment, then the
+/// totals. None of the fields can contain a comma so nothing needs quoting
+pub fn statement_to_csv(statement: &Statement) -> String {
+    let mut csv = String::from(
+        "time,category,counterparty_mesh_ip,counterparty_eth_address,up_bytes,down_bytes,amount_wei,amount_usd,txid\n",
+    );
+    let (start, _) = month_bounds(statement.year, statement.month).unwrap_or((0, 0));
+    let month_start = format_time(start);
+    let usd = |amount: &Option<String>| amount.clone().unwrap_or_default();
+
+    for usage in statement.usage.iter() {
+        let category = match usage.kind {
+            UsageType::Client => "client_usage",
+            UsageType::Relay => "relay_usage",
+            UsageType::Exit => "exit_usage",
+        };
+        let _ = writeln!(
+            csv,
+            "{},{},,,{},{},{},{},",
+            month_start,
+            category,
+            usage.up,
+            usage.down,
+            usage.cost,
+            usd(&usage.cost_usd)
+        );
+    }
+    for payment in statement.payments.iter() {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},,,{},{},{}",
+            format_time(payment.time),
+            payment.category.name(),
+            payment.counterparty.mesh_ip,
+            payment.counterparty.eth_address,
+            payment.amount,
+            usd(&payment.amount_usd),
+            payment.txid
+        );
+    }
+    for total in statement.totals.iter() {
+        let _ = writeln!(
+            csv,
+            "{},total_{},,,,,{},{},",
+            month_start,
+            total.category.name(),
+            total.amount,
+            usd(&total.amount_usd)
+        );
+    }
+    csv
+}
+
+/// xDai is dai. We know no rate for a payment token, it may not be a dollar or have 18 decimals,
+/// on the other chains the rates the token bridge recorded are used
+fn dollar_rates(
+    system_chain: SystemChain,
+    payment_token: bool,
+    dai_rates: &VecDeque<DaiRateHour>,
+) -> DollarRates {
+    match (system_chain, payment_token) {
+        (_, true) => DollarRates::Unknown,
+        (SystemChain::Xdai, false) => DollarRates::Fixed(Uint256::from(WEI_PER_DAI)),
+        (_, false) => DollarRates::Recorded(
+            dai_rates
+                .iter()
+                .map(|rate| (rate.index, rate.wei_per_dollar.clone()))
+                .collect(),
+        ),
+    }
+}
+
+/// Builds the statement for a calendar month
+pub struct GetStatement {
+    pub year: i64,
+    pub month: u32,
+}
+
+impl Message for GetStatement {
+    type Result = Result<Statement, Error>;
+}
+
+impl Handler<GetStatement> for UsageTracker {
+    type Result = Result<Statement, Error>;
+    fn handle(&mut self, msg: GetStatement, _: &mut Context<Self>) -> Self::Result {
+        let payment = SETTING.get_payment();
+        let system_chain = payment.system_chain;
+        let token = payment.payment_token.is_some();
+        drop(payment);
+
+        let ctx = StatementContext {
+            parties: PaymentParties::from_settings(),
+            rates: dollar_rates(system_chain, token, &self.dai_rates),
+        };
+        build_statement(self, msg.year, msg.month, &ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{PaymentHour, UsageHour};
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn identity(address: &str) -> Identity {
+        Identity::new(
+            "fd00::1".parse().unwrap(),
+            address.parse().unwrap(),
+            "8BeCExnthLe5ou0EYec5jNqJ/PduZ1x2o7lpXJOpgXk="
+                .parse()
+                .unwrap(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_to_dollars() {
+        let rate = Some(Uint256::from(WEI_PER_DAI));
+        assert_eq!(
+            to_dollars(&Uint256::from(1_500_000_000_000_000_000u128), &rate),
+            Some("1.50".to_string())
+        );
+        assert_eq!(
+            to_dollars(&Uint256::from(10_000_000_000_000_000u128), &rate),
+            Some("0.01".to_string())
+        );
+        assert_eq!(to_dollars(&Uint256::from(1u8), &None), None);
+    }
+
+    #[test]
+    fn test_build_statement() {
+        let us = "0x0000000000000000000000000000000000000001";
+        let neighbor = "0x0000000000000000000000000000000000000002";
+        let dao = "0x0000000000000000000000000000000000000003";
+        let txfee = "0x0000000000000000000000000000000000000004";
+        // 2019-10-01T00:00:00Z
+        let october = 1_569_888_000 / 3600;
+        let payment = |to: &str, from: &str, amount: u64| FormattedPaymentTx {
+            to: identity(to),
+            from: identity(from),
+            amount: Uint256::from(amount),
+            txid: String::new(),
+            category: None,
+        };
+
+        let mut tracker = UsageTracker {
+            last_save_hour: 0,
+            client_bandwith: VecDeque::new(),
+            relay_bandwith: VecDeque::new(),
+            exit_bandwith: VecDeque::new(),
+            payments: VecDeque::new(),
+            peer_usage: VecDeque::new(),
+            dai_rates: VecDeque::new(),
+            journal_seq: 0,
+            unflushed: Vec::new(),
+            last_flush: None,
+        };
+        tracker.client_bandwith.push_front(UsageHour {
+            index: october,
+            up: 10,
+            down: 20,
+            price: 2,
+        });
+        // september
+        tracker.client_bandwith.push_front(UsageHour {
+            index: october - 1,
+            up: 1000,
+            down: 1000,
+            price: 2,
+        });
+        tracker.payments.push_front(PaymentHour {
+            index: october + 1,
+            payments: vec![
+                payment(neighbor, us, 100),
+                payment(dao, us, 10),
+                payment(txfee, us, 5),
+                payment(us, neighbor, 40),
+            ],
+        });
+
+        let ctx = StatementContext {
+            parties: PaymentParties {
+                our_address: Some(us.parse().unwrap()),
+                dao_addresses: vec![dao.parse().unwrap()],
+                simulated_tx_fee_address: txfee.parse().unwrap(),
+            },
+            rates: DollarRates::Unknown,
+        };
+        let statement = build_statement(&tracker, 2019, 10, &ctx).unwrap();
+        assert_eq!(statement.usage.len(), 1);
+        assert_eq!(statement.usage[0].cost, Uint256::from(60u8));
+        let categories: Vec<PaymentCategory> =
+            statement.payments.iter().map(|p| p.category).collect();
+        assert_eq!(
+            categories,
+            vec![
+                PaymentCategory::Sent,
+                PaymentCategory::DaoFee,
+                PaymentCategory::SimulatedTxFee,
+                PaymentCategory::Received
+            ]
+        );
+        assert_eq!(statement.payments[3].counterparty, identity(neighbor));
+        assert_eq!(statement.totals[1].amount, Uint256::from(40u8));
+
+        let csv = statement_to_csv(&statement);
+        assert_eq!(csv.lines().count(), 1 + 1 + 4 + 4);
+        assert!(csv.contains("2019-10-01T00:00:00Z,client_usage,,,10,20,60,,"));
+
+        assert!(build_statement(&tracker, 2019, 13, &ctx).is_err());
+
+        // a payment keeps the category it was given when it was made, even once the DAO it
+        // paid is no longer on the list
+        let mut old_fee = payment(neighbor, us, 7);
+        old_fee.category = Some(PaymentCategory::DaoFee);
+        tracker.payments.push_front(PaymentHour {
+            index: october + 2,
+            payments: vec![old_fee],
+        });
+        let statement = build_statement(&tracker, 2019, 10, &ctx).unwrap();
+        assert_eq!(statement.payments[4].category, PaymentCategory::DaoFee);
+        assert_eq!(statement.totals[2].amount, Uint256::from(17u8));
+    }
+
+    #[test]
+    fn test_statement_rates() {
+        let us = "0x0000000000000000000000000000000000000001";
+        let neighbor = "0x0000000000000000000000000000000000000002";
+        let october = 1_569_888_000 / 3600;
+        let dollar = 1_000_000_000_000_000u128;
+        let payment = |amount: u128| FormattedPaymentTx {
+            to: identity(neighbor),
+            from: identity(us),
+            amount: Uint256::from(amount),
+            txid: String::new(),
+            category: None,
+        };
+
+        let mut tracker = UsageTracker {
+            last_save_hour: 0,
+            client_bandwith: VecDeque::new(),
+            relay_bandwith: VecDeque::new(),
+            exit_bandwith: VecDeque::new(),
+            payments: VecDeque::new(),
+            peer_usage: VecDeque::new(),
+            dai_rates: VecDeque::new(),
+            journal_seq: 0,
+            unflushed: Vec::new(),
+            last_flush: None,
+        };
+        for index in [october + 1, october + 5, october + 10].iter() {
+            tracker.payments.push_front(PaymentHour {
+                index: *index,
+                payments: vec![payment(dollar)],
+            });
+        }
+        // eth doubles in price between the first two payments
+        let rates = vec![
+            (october + 4, Uint256::from(dollar / 2)),
+            (october, Uint256::from(dollar)),
+        ];
+        let ctx = StatementContext {
+            parties: PaymentParties {
+                our_address: Some(us.parse().unwrap()),
+                dao_addresses: Vec::new(),
+                simulated_tx_fee_address: neighbor.parse().unwrap(),
+            },
+            rates: DollarRates::Recorded(rates),
+        };
+        let statement = build_statement(&tracker, 2019, 10, &ctx).unwrap();
+        let usd: Vec<Option<String>> = statement
+            .payments
+            .iter()
+            .map(|p| p.amount_usd.clone())
+            .collect();
+        // the later payments use the closest rate recorded before them
+        assert_eq!(
+            usd,
+            vec![
+                Some("1.00".to_string()),
+                Some("2.00".to_string()),
+                Some("2.00".to_string())
+            ]
+        );
+        assert_eq!(statement.totals[0].amount_usd, Some("5.00".to_string()));
+        assert_eq!(statement.wei_per_dollar, Some(Uint256::from(dollar / 2)));
+
+        // nothing was recorded before this payment, so the total can't be given either
+        tracker.payments.push_back(PaymentHour {
+            index: october,
+            payments: vec![payment(dollar)],
+        });
+        let ctx = StatementContext {
+            rates: DollarRates::Recorded(vec![(october + 4, Uint256::from(dollar))]),
+            ..ctx
+        };
+        let statement = build_statement(&tracker, 2019, 10, &ctx).unwrap();
+        assert_eq!(statement.payments[0].amount_usd, None);
+        assert_eq!(statement.totals[0].amount_usd, None);
+    }
+
+    #[test]
+    fn test_dollar_rates() {
+        let mut dai_rates = VecDeque::new();
+        dai_rates.push_front(DaiRateHour {
+            index: 10,
+            wei_per_dollar: 5u32.into(),
+        });
+        assert_eq!(
+            dollar_rates(SystemChain::Xdai, false, &dai_rates).at(1),
+            Some(Uint256::from(WEI_PER_DAI))
+        );
+        assert_eq!(
+            dollar_rates(SystemChain::Rinkeby, false, &dai_rates).at(11),
+            Some(5u32.into())
+        );
+        assert_eq!(
+            dollar_rates(SystemChain::Ethereum, false, &dai_rates).at(9),
+            None
+        );
+        // a payment token isn't converted as if it were dai
+        assert_eq!(
+            dollar_rates(SystemChain::Xdai, true, &dai_rates).at(11),
+            None
+        );
+    }
+}
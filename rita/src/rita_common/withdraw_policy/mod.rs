@@ -0,0 +1,845 @@
+//! Enforces the withdraw_policy on withdraws made from the dashboard, so that someone who has
+//! the dashboard password can't simply empty the router. A withdraw to an address that isn't on
+//! the whitelist, or one that would go over the daily limit, is held until it's approved and
+//! then waits out the delay. A withdraw over the delay threshold only waits. Anything held can
+//! be cancelled until it's sent, and the owner is told about it through the exit's phone or
+//! email notifications as well as the dashboard.
+//!
+//! Approving a withdraw takes the same dashboard password as making one, so the whitelist and
+//! daily limit can't stop someone who has it. What they add is time, a breach is never sent
+//! before the delay has passed since it was approved and the owner has been told about it. To
+//! keep that delay meaningful the policy and sweep settings can't be changed from the dashboard
+//! and while a policy is set the eth private key can't be exported or replaced through it.
+//!
+//! A withdraw only counts against the daily limit once it has been sent, while it's on its way
+//! it counts as well so that a second one can't slip under the limit meanwhile. A held withdraw
+//! that fails to send stays in the queue as failed, and the owner is told about it, until it's
+//! cancelled.
+//!
+//! The queue is saved to the withdraw_queue_file so that a restart neither drops a held
+//! withdraw nor resets the daily limit.
+
+use crate::rita_common::oracle::history::now;
+use crate::rita_common::oracle::{Oracle, ZeroWindowStart};
+use crate::rita_common::payment_controller::send_withdraw;
+use crate::rita_common::payment_token::NATIVE_TRANSFER_GAS;
+use crate::rita_common::token_bridge::{GetBridge, TokenBridge, Withdraw, ETH_TRANSFER_TIMEOUT};
+use crate::SETTING;
+use actix::{Arbiter, SystemService};
+use althea_types::SystemChain;
+use clarity::Address;
+use failure::Error;
+use futures01::{future, Future};
+use num256::Uint256;
+use serde_json::Value;
+use settings::payment::WithdrawPolicy;
+use settings::RitaCommonSettings;
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::RwLock;
+
+/// The window the daily limit applies to, in seconds
+const DAY: u64 = 86400;
+/// The payment settings that decide where our funds can go, these can only be changed in the
+/// config file
+pub const PROTECTED_PAYMENT_SETTINGS: [&str; 3] =
+    ["withdraw_policy", "sweep", "withdraw_queue_file"];
+/// The gas price hardcoded in the token bridge for the xDai leg of a withdraw
+const BRIDGE_GAS_PRICE: u64 = 10_000_000_000;
+/// The gas used sending xDai into the token bridge
+const BRIDGE_GAS: u64 = 80_000;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WithdrawKind {
+    /// the given amount of our balance
+    Amount,
+    /// our whole balance, worked out again when a held withdraw is sent
+    All,
+    /// eth left on the Ethereum side of the token bridge, sent from there directly
+    Eth,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HoldReason {
+    /// the destination isn't on the whitelist
+    NotWhitelisted,
+    /// the withdraw would take us over the daily limit, spent is what went out in the last day
+    OverDailyLimit { spent: Uint256, limit: Uint256 },
+    /// the amount is at least the delay threshold
+    OverDelayThreshold,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WithdrawStatus {
+    /// waiting to be approved or for its release time
+    Held,
+    /// released and on its way, waiting to hear how it went
+    Sending,
+    /// sending failed for the given reason, it stays until it's cancelled
+    Failed(String),
+}
+
+impl Default for WithdrawStatus {
+    fn default() -> WithdrawStatus {
+        WithdrawStatus::Held
+    }
+}
+
+/// What became of a withdraw checked against the policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checked {
+    /// it can be sent now, how that went is reported with finish_withdraw under this id
+    Send(u64),
+    /// it's held in the queue
+    Held(PendingWithdraw),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingWithdraw {
+    pub id: u64,
+    pub to: Address,
+    pub amount: Uint256,
+    /// what the withdraw counts for against the daily limit and delay threshold, the amount
+    /// itself except for eth, which is valued in the system chain's coin
+    pub value: Uint256,
+    pub kind: WithdrawKind,
+    /// seconds since the unix epoch
+    pub requested: u64,
+    pub reasons: Vec<HoldReason>,
+    /// when the withdraw will be sent, None until a policy breach is approved
+    pub release: Option<u64>,
+    /// whether the owner has been notified through the exit
+    pub notified: bool,
+    #[serde(default)]
+    pub status: WithdrawStatus,
+}
+
+impl PendingWithdraw {
+    /// Breaking the whitelist or daily limit needs approval, a large withdraw only waits
+    pub fn needs_approval(&self) -> bool {
+        self.reasons
+            .iter()
+            .any(|reason| *reason != HoldReason::OverDelayThreshold)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WithdrawQueue {
+    next_id: u64,
+    pending: Vec<PendingWithdraw>,
+    /// when and how much was sent over the last day, oldest first
+    sent: VecDeque<(u64, Uint256)>,
+    /// the id and value of each withdraw that passed the policy and is being sent
+    #[serde(default)]
+    sending: Vec<(u64, Uint256)>,
+}
+
+impl WithdrawQueue {
+    pub fn pending(&self) -> Vec<PendingWithdraw> {
+        self.pending.clone()
+    }
+
+    /// How much has been sent since `time`
+    fn spent_since(&self, time: u64) -> Uint256 {
+        self.sent
+            .iter()
+            .filter(|(sent, _)| *sent > time)
+            .fold(0u32.into(), |total: Uint256, (_, amount)| {
+                total + amount.clone()
+            })
+    }
+
+    /// The value of the withdraws being sent, these count against the daily limit until we
+    /// know whether they went out
+    fn in_flight(&self) -> Uint256 {
+        let held = self
+            .pending
+            .iter()
+            .filter(|pending| pending.status == WithdrawStatus::Sending)
+            .map(|pending| pending.value.clone());
+        self.sending
+            .iter()
+            .map(|(_, value)| value.clone())
+            .chain(held)
+            .fold(0u32.into(), |total: Uint256, value| total + value)
+    }
+
+    fn record_sent(&mut self, time: u64, amount: Uint256) {
+        self.sent.push_back((time, amount));
+        while let Some((sent, _)) = self.sent.front() {
+            if *sent + DAY > time {
+                break;
+            }
+            self.sent.pop_front();
+        }
+    }
+
+    /// Lists the ways a withdraw of `value` breaks the policy, empty if it can go out now
+    pub fn check(
+        &self,
+        policy: &WithdrawPolicy,
+        to: Address,
+        value: &Uint256,
+        time: u64,
+    ) -> Vec<HoldReason> {
+        let mut reasons = Vec::new();
+        if !policy.whitelist.is_empty() && !policy.whitelist.contains(&to) {
+            reasons.push(HoldReason::NotWhitelisted);
+        }
+        if let Some(limit) = &policy.daily_limit {
+            let spent = self.spent_since(time.saturating_sub(DAY)) + self.in_flight();
+            if spent.clone() + value.clone() > *limit {
+                reasons.push(HoldReason::OverDailyLimit {
+                    spent,
+                    limit: limit.clone(),
+                });
+            }
+        }
+        if let Some(threshold) = &policy.delay_threshold {
+            if value >= threshold {
+                reasons.push(HoldReason::OverDelayThreshold);
+            }
+        }
+        reasons
+    }
+
+    /// Checks a withdraw against the policy, if it passes it can be sent and counts as in flight
+    /// until it's reported as sent or failed, otherwise it's held
+    pub fn request(
+        &mut self,
+        policy: &WithdrawPolicy,
+        to: Address,
+        amount: Uint256,
+        value: Uint256,
+        kind: WithdrawKind,
+        time: u64,
+    ) -> Checked {
+        let reasons = self.check(policy, to, &value, time);
+        if reasons.is_empty() {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.sending.push((id, value));
+            return Checked::Send(id);
+        }
+        let mut pending = PendingWithdraw {
+            id: self.next_id,
+            to,
+            amount,
+            value,
+            kind,
+            requested: time,
+            reasons,
+            release: None,
+            notified: false,
+            status: WithdrawStatus::Held,
+        };
+        if !pending.needs_approval() {
+            pending.release = Some(time + policy.delay);
+        }
+        self.next_id += 1;
+        self.pending.push(pending.clone());
+        Checked::Held(pending)
+    }
+
+    /// Approves a held withdraw, it's sent once the delay has passed
+    pub fn approve(&mut self, id: u64, delay: u64, time: u64) -> Result<PendingWithdraw, Error> {
+        let pending = match self.pending.iter_mut().find(|pending| pending.id == id) {
+            Some(pending) => pending,
+            None => bail!("No pending withdraw {}", id),
+        };
+        if let Some(release) = pending.release {
+            bail!("Withdraw {} is already scheduled for {}", id, release);
+        }
+        pending.release = Some(time + delay);
+        Ok(pending.clone())
+    }
+
+    /// Removes a held or failed withdraw, one that's being sent can't be stopped anymore
+    pub fn cancel(&mut self, id: u64) -> Result<PendingWithdraw, Error> {
+        match self.pending.iter().position(|pending| pending.id == id) {
+            Some(index) => {
+                if self.pending[index].status == WithdrawStatus::Sending {
+                    bail!("Withdraw {} is already being sent", id);
+                }
+                Ok(self.pending.remove(index))
+            }
+            None => bail!("No pending withdraw {}", id),
+        }
+    }
+
+    /// Marks the held withdraws that are due as being sent and returns them, they stay in the
+    /// queue until they're reported as sent or failed
+    pub fn take_due(&mut self, time: u64) -> Vec<PendingWithdraw> {
+        let mut due = Vec::new();
+        for pending in self.pending.iter_mut() {
+            let released = match pending.release {
+                Some(release) => release <= time,
+                None => false,
+            };
+            if released && pending.status == WithdrawStatus::Held {
+                pending.status = WithdrawStatus::Sending;
+                due.push(pending.clone());
+            }
+        }
+        due
+    }
+
+    /// A withdraw went out, it's removed and counted against the daily limit
+    pub fn sent(&mut self, id: u64, time: u64) {
+        let value = if let Some(index) = self.sending.iter().position(|(sending, _)| *sending == id)
+        {
+            self.sending.remove(index).1
+        } else if let Some(index) = self.pending.iter().position(|pending| pending.id == id) {
+            self.pending.remove(index).value
+        } else {
+            return;
+        };
+        self.record_sent(time, value);
+    }
+
+    /// A withdraw failed to go out so it doesn't count against the daily limit. One that was
+    /// held stays in the queue as failed and the owner is told, the caller of one that wasn't
+    /// gets the error directly
+    pub fn failed(&mut self, id: u64, error: String) {
+        if let Some(index) = self.sending.iter().position(|(sending, _)| *sending == id) {
+            self.sending.remove(index);
+        } else if let Some(pending) = self.pending.iter_mut().find(|pending| pending.id == id) {
+            pending.status = WithdrawStatus::Failed(error);
+            pending.notified = false;
+        }
+    }
+
+    /// After a restart we can't know how the withdraws that were being sent went. They count
+    /// against the daily limit in case they went out, and a held one is marked failed so the
+    /// owner can check on it
+    fn interrupted(&mut self, time: u64) {
+        for (_, value) in std::mem::replace(&mut self.sending, Vec::new()) {
+            self.record_sent(time, value);
+        }
+        let mut interrupted = Vec::new();
+        for pending in self.pending.iter_mut() {
+            if pending.status == WithdrawStatus::Sending {
+                pending.status = WithdrawStatus::Failed(
+                    "Interrupted by a restart, it may have been sent".into(),
+                );
+                pending.notified = false;
+                interrupted.push(pending.value.clone());
+            }
+        }
+        for value in interrupted {
+            self.record_sent(time, value);
+        }
+    }
+
+    /// The held withdraws the owner hasn't been told about yet, or about their failing
+    pub fn unnotified(&self) -> Vec<u64> {
+        self.pending
+            .iter()
+            .filter(|pending| !pending.notified)
+            .map(|pending| pending.id)
+            .collect()
+    }
+
+    pub fn mark_notified(&mut self, ids: &[u64]) {
+        for pending in self.pending.iter_mut() {
+            if ids.contains(&pending.id) {
+                pending.notified = true;
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref QUEUE: RwLock<WithdrawQueue> = RwLock::new(load_queue());
+}
+
+fn load_queue() -> WithdrawQueue {
+    let file = queue_file();
+    if file.is_empty() {
+        return WithdrawQueue::default();
+    }
+    match fs::read(&file) {
+        Ok(contents) => match serde_json::from_slice::<WithdrawQueue>(&contents) {
+            Ok(mut queue) => {
+                queue.interrupted(now());
+                queue
+            }
+            Err(e) => {
+                error!("Failed to deserialize withdraw queue {:?}", e);
+                WithdrawQueue::default()
+            }
+        },
+        Err(e) => {
+            info!("No withdraw queue loaded {:?}", e);
+            WithdrawQueue::default()
+        }
+    }
+}
+
+fn queue_file() -> String {
+    SETTING.get_payment().withdraw_queue_file.clone()
+}
+
+/// Writes the queue out through a temporary file, losing it would release every held withdraw.
+/// Takes the file rather than reading the settings so that callers never hold the queue lock
+/// while waiting on the settings lock
+fn save_queue(file: &str, queue: &WithdrawQueue) {
+    if let Err(e) = write_queue(file, queue) {
+        error!("Failed to save withdraw queue {:?}", e);
+    }
+}
+
+fn write_queue(file: &str, queue: &WithdrawQueue) -> Result<(), Error> {
+    if file.is_empty() {
+        return Ok(());
+    }
+    let tmp = format!("{}.tmp", file);
+    fs::write(&tmp, serde_json::to_vec(queue)?)?;
+    fs::rename(&tmp, file)?;
+    Ok(())
+}
+
+/// Checks a withdraw against the policy, one that can be sent now has to be reported with
+/// finish_withdraw once it's known how that went
+pub fn hold_withdraw(to: Address, amount: Uint256, value: Uint256, kind: WithdrawKind) -> Checked {
+    let policy = SETTING.get_payment().withdraw_policy.clone();
+    let file = queue_file();
+    let mut queue = QUEUE.write().unwrap();
+    let checked = queue.request(&policy, to, amount, value, kind, now());
+    save_queue(&file, &queue);
+    if let Checked::Held(pending) = &checked {
+        warn!(
+            "Holding withdraw {} of {} to {:#x} for {:?}",
+            pending.id, pending.amount, pending.to, pending.reasons
+        );
+    }
+    checked
+}
+
+/// Records how sending a withdraw went, only one that went out counts against the daily limit
+pub fn finish_withdraw(id: u64, result: Result<(), String>) {
+    let file = queue_file();
+    let mut queue = QUEUE.write().unwrap();
+    match result {
+        Ok(()) => queue.sent(id, now()),
+        Err(e) => queue.failed(id, e),
+    }
+    save_queue(&file, &queue);
+}
+
+pub fn pending_withdraws() -> Vec<PendingWithdraw> {
+    QUEUE.read().unwrap().pending()
+}
+
+pub fn approve_withdraw(id: u64) -> Result<PendingWithdraw, Error> {
+    let delay = SETTING.get_payment().withdraw_policy.delay;
+    let file = queue_file();
+    let mut queue = QUEUE.write().unwrap();
+    let pending = queue.approve(id, delay, now())?;
+    save_queue(&file, &queue);
+    info!("Approved withdraw {}, sending at {:?}", id, pending.release);
+    Ok(pending)
+}
+
+pub fn cancel_withdraw(id: u64) -> Result<PendingWithdraw, Error> {
+    let file = queue_file();
+    let mut queue = QUEUE.write().unwrap();
+    let pending = queue.cancel(id)?;
+    save_queue(&file, &queue);
+    info!("Cancelled withdraw {}", id);
+    Ok(pending)
+}
+
+/// The held withdraws to report to the exit on our next check in
+pub fn unnotified_withdraws() -> Vec<u64> {
+    QUEUE.read().unwrap().unnotified()
+}
+
+pub fn mark_withdraws_notified(ids: &[u64]) {
+    if ids.is_empty() {
+        return;
+    }
+    let file = queue_file();
+    let mut queue = QUEUE.write().unwrap();
+    queue.mark_notified(ids);
+    save_queue(&file, &queue);
+}
+
+/// Refuses a settings change from the dashboard that would get around the withdraw policy
+pub fn check_settings_change(new_settings: &Value) -> Result<(), Error> {
+    for protected in PROTECTED_PAYMENT_SETTINGS.iter() {
+        if new_settings["payment"].get(protected).is_some() {
+            bail!("payment.{} can't be changed from the dashboard", protected);
+        }
+    }
+    if new_settings["payment"].get("eth_private_key").is_some()
+        && SETTING.get_payment().withdraw_policy.is_set()
+    {
+        bail!("The eth private key can't be changed while a withdraw policy is set");
+    }
+    Ok(())
+}
+
+/// Removes the protected payment settings from a full set of settings, so that merging them
+/// keeps ours
+pub fn strip_protected_settings(settings: &mut Value) {
+    if let Some(payment) = settings["payment"].as_object_mut() {
+        for protected in PROTECTED_PAYMENT_SETTINGS.iter() {
+            payment.remove(*protected);
+        }
+    }
+}
+
+/// What a withdraw costs in gas out of our balance
+pub fn withdraw_gas_cost() -> Uint256 {
+    let payment = SETTING.get_payment();
+    let bridged = (payment.system_chain, payment.withdraw_chain)
+        == (SystemChain::Xdai, SystemChain::Ethereum);
+    if payment.payment_token.is_some() {
+        // gas for a token transfer comes out of the native balance, not the tokens
+        0u32.into()
+    } else if bridged {
+        // this is the hardcoded gas price over in token bridge so we have to use it
+        Uint256::from(BRIDGE_GAS_PRICE) * Uint256::from(BRIDGE_GAS)
+    } else {
+        payment.gas_price.clone() * Uint256::from(NATIVE_TRANSFER_GAS)
+    }
+}
+
+/// The most we can withdraw, the balance less the gas to send it
+pub fn withdraw_all_amount() -> Uint256 {
+    let tx_cost = withdraw_gas_cost();
+    let balance = SETTING.get_payment().balance.clone();
+    if balance > tx_cost {
+        balance - tx_cost
+    } else {
+        0u32.into()
+    }
+}
+
+/// Sends a withdraw, directly on the system chain or through the token bridge for an xDai
+/// router withdrawing to Ethereum, eth is sent from the Ethereum side of the bridge. The txid
+/// is only known for a direct withdraw
+pub fn dispatch_withdraw(
+    to: Address,
+    amount: Uint256,
+    kind: WithdrawKind,
+) -> Box<dyn Future<Item = Option<Uint256>, Error = Error>> {
+    let payment = SETTING.get_payment();
+    let system_chain = payment.system_chain;
+    let withdraw_chain = payment.withdraw_chain;
+    let token = payment.payment_token.is_some();
+    drop(payment);
+
+    if kind == WithdrawKind::Eth {
+        Box::new(
+            TokenBridge::from_registry()
+                .send(GetBridge())
+                .from_err()
+                .and_then(|res| res)
+                .and_then(move |(bridge, _reserve)| {
+                    bridge
+                        .eth_transfer(to, amount, ETH_TRANSFER_TIMEOUT)
+                        .map(|_| None)
+                }),
+        )
+    } else if system_chain == withdraw_chain {
+        Box::new(send_withdraw(to, amount).map(Some))
+    } else if (system_chain, withdraw_chain) == (SystemChain::Xdai, SystemChain::Ethereum) && !token
+    {
+        Box::new(
+            TokenBridge::from_registry()
+                .send(Withdraw {
+                    to,
+                    amount,
+                    withdraw_all: kind == WithdrawKind::All,
+                })
+                .from_err()
+                .and_then(|res: Result<(), Error>| res)
+                .map(|_| None),
+        )
+    } else {
+        Box::new(future::err(format_err!(
+            "Can't withdraw from {} to {}, check the withdraw chain!",
+            system_chain,
+            withdraw_chain
+        )))
+    }
+}
+
+/// Sends the held withdraws whose delay has passed, called from the slow loop
+pub fn tick() {
+    let file = queue_file();
+    let due = {
+        let mut queue = QUEUE.write().unwrap();
+        let due = queue.take_due(now());
+        if !due.is_empty() {
+            save_queue(&file, &queue);
+        }
+        due
+    };
+    for withdraw in due {
+        // the balance has moved on since the request, send what's there now
+        let amount = if withdraw.kind == WithdrawKind::All {
+            Oracle::from_registry().do_send(ZeroWindowStart());
+            withdraw_all_amount()
+        } else {
+            withdraw.amount.clone()
+        };
+        info!(
+            "Sending held withdraw {} of {} to {:#x}",
+            withdraw.id, amount, withdraw.to
+        );
+        Arbiter::spawn(
+            dispatch_withdraw(withdraw.to, amount, withdraw.kind).then(move |res| {
+                match res {
+                    Ok(txid) => {
+                        info!("Held withdraw {} sent {:?}", withdraw.id, txid);
+                        finish_withdraw(withdraw.id, Ok(()));
+                    }
+                    Err(e) => {
+                        error!("Failed to send held withdraw {} {:?}", withdraw.id, e);
+                        finish_withdraw(withdraw.id, Err(format!("{}", e)));
+                    }
+                }
+                Ok(())
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(last: u8) -> Address {
+        let mut bytes = [0u8; 20];
+        bytes[19] = last;
+        Address::from_slice(&bytes).unwrap()
+    }
+
+    fn held(checked: Checked) -> PendingWithdraw {
+        match checked {
+            Checked::Held(pending) => pending,
+            Checked::Send(id) => panic!("Withdraw {} wasn't held", id),
+        }
+    }
+
+    fn send(checked: Checked) -> u64 {
+        match checked {
+            Checked::Send(id) => id,
+            Checked::Held(pending) => panic!("Withdraw {:?} was held", pending),
+        }
+    }
+
+    #[test]
+    fn test_daily_limit_and_whitelist() {
+        let policy = WithdrawPolicy {
+            daily_limit: Some(100u32.into()),
+            whitelist: vec![address(1)],
+            ..WithdrawPolicy::default()
+        };
+        let mut queue = WithdrawQueue::default();
+
+        let id = send(queue.request(
+            &policy,
+            address(1),
+            60u32.into(),
+            60u32.into(),
+            WithdrawKind::Amount,
+            1000,
+        ));
+        queue.sent(id, 1000);
+        // the second withdraw goes over the limit and to the wrong address
+        let held = held(queue.request(
+            &policy,
+            address(2),
+            50u32.into(),
+            50u32.into(),
+            WithdrawKind::Amount,
+            2000,
+        ));
+        assert_eq!(
+            held.reasons,
+            vec![
+                HoldReason::NotWhitelisted,
+                HoldReason::OverDailyLimit {
+                    spent: 60u32.into(),
+                    limit: 100u32.into()
+                }
+            ]
+        );
+        assert!(held.needs_approval());
+        assert_eq!(held.release, None);
+
+        // a day later the first withdraw no longer counts
+        let id = send(queue.request(
+            &policy,
+            address(1),
+            50u32.into(),
+            50u32.into(),
+            WithdrawKind::Amount,
+            1000 + DAY,
+        ));
+        queue.sent(id, 1000 + DAY);
+
+        // nothing is sent until it's approved, then only after the delay
+        assert!(queue.take_due(1_000_000).is_empty());
+        let approved = queue.approve(held.id, policy.delay, 5000).unwrap();
+        assert_eq!(approved.release, Some(5000 + policy.delay));
+        assert!(queue.approve(held.id, policy.delay, 5000).is_err());
+        assert!(queue.take_due(5000).is_empty());
+        let due = queue.take_due(5000 + policy.delay);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, approved.id);
+        assert_eq!(due[0].status, WithdrawStatus::Sending);
+        // it stays until it's known to be sent, and isn't sent twice meanwhile
+        assert!(queue.take_due(5000 + policy.delay).is_empty());
+        queue.sent(approved.id, 5000 + policy.delay);
+        assert!(queue.pending().is_empty());
+    }
+
+    #[test]
+    fn test_delay_and_cancel() {
+        let policy = WithdrawPolicy {
+            delay_threshold: Some(1000u32.into()),
+            delay: 600,
+            ..WithdrawPolicy::default()
+        };
+        let mut queue = WithdrawQueue::default();
+
+        let held = held(queue.request(
+            &policy,
+            address(1),
+            1000u32.into(),
+            1000u32.into(),
+            WithdrawKind::All,
+            100,
+        ));
+        assert_eq!(held.reasons, vec![HoldReason::OverDelayThreshold]);
+        assert!(!held.needs_approval());
+        assert_eq!(held.release, Some(700));
+        assert_eq!(queue.unnotified(), vec![held.id]);
+        queue.mark_notified(&[held.id]);
+        assert!(queue.unnotified().is_empty());
+
+        assert_eq!(queue.cancel(held.id).unwrap().id, held.id);
+        assert!(queue.cancel(held.id).is_err());
+        assert!(queue.take_due(700).is_empty());
+    }
+
+    #[test]
+    fn test_eth_withdraw_counts_its_value() {
+        let policy = WithdrawPolicy {
+            daily_limit: Some(100u32.into()),
+            ..WithdrawPolicy::default()
+        };
+        let mut queue = WithdrawQueue::default();
+
+        // a small amount of eth can be worth more than the limit in the system chain's coin
+        let pending = held(queue.request(
+            &policy,
+            address(1),
+            1u32.into(),
+            500u32.into(),
+            WithdrawKind::Eth,
+            1000,
+        ));
+        assert_eq!(pending.kind, WithdrawKind::Eth);
+        assert_eq!(pending.amount, 1u32.into());
+        assert_eq!(pending.value, 500u32.into());
+        assert!(pending.needs_approval());
+
+        // what is being sent counts at its value too
+        send(queue.request(
+            &policy,
+            address(1),
+            2u32.into(),
+            90u32.into(),
+            WithdrawKind::Eth,
+            1000,
+        ));
+        held(queue.request(
+            &policy,
+            address(1),
+            1u32.into(),
+            20u32.into(),
+            WithdrawKind::Amount,
+            1000,
+        ));
+    }
+
+    #[test]
+    fn test_failed_withdraws() {
+        let policy = WithdrawPolicy {
+            daily_limit: Some(100u32.into()),
+            delay_threshold: Some(50u32.into()),
+            delay: 10,
+            ..WithdrawPolicy::default()
+        };
+        let mut queue = WithdrawQueue::default();
+        let request = |queue: &mut WithdrawQueue, value: u32, time: u64| {
+            queue.request(
+                &policy,
+                address(1),
+                value.into(),
+                value.into(),
+                WithdrawKind::Amount,
+                time,
+            )
+        };
+
+        // an immediate withdraw that fails doesn't count against the limit
+        let id = send(request(&mut queue, 40, 1000));
+        queue.failed(id, "nonce".to_string());
+        let id = send(request(&mut queue, 40, 1000));
+        queue.sent(id, 1000);
+        assert_eq!(queue.spent_since(0), 40u32.into());
+        assert!(queue.pending().is_empty());
+
+        let pending = held(request(&mut queue, 50, 1005));
+        assert_eq!(pending.reasons, vec![HoldReason::OverDelayThreshold]);
+        queue.mark_notified(&[pending.id]);
+        assert_eq!(queue.take_due(1015).len(), 1);
+        // while it's being sent it can't be cancelled and counts against the limit
+        assert!(queue.cancel(pending.id).is_err());
+        assert!(!queue
+            .check(&policy, address(1), &20u32.into(), 1016)
+            .is_empty());
+
+        // a failed send stays in the queue, the owner is told and it no longer counts
+        queue.failed(pending.id, "reverted".to_string());
+        assert_eq!(
+            queue.pending()[0].status,
+            WithdrawStatus::Failed("reverted".to_string())
+        );
+        assert_eq!(queue.unnotified(), vec![pending.id]);
+        assert!(queue.take_due(1020).is_empty());
+        assert!(queue
+            .check(&policy, address(1), &20u32.into(), 1016)
+            .is_empty());
+        assert_eq!(queue.cancel(pending.id).unwrap().id, pending.id);
+
+        // withdraws interrupted by a restart count in case they went out
+        send(request(&mut queue, 10, 1020));
+        let pending = held(request(&mut queue, 50, 1020));
+        assert_eq!(queue.take_due(1030).len(), 1);
+        queue.interrupted(1030);
+        assert_eq!(queue.spent_since(0), 100u32.into());
+        assert!(queue.sending.is_empty());
+        assert_eq!(queue.unnotified(), vec![pending.id]);
+    }
+
+    #[test]
+    fn test_protected_settings() {
+        for key in PROTECTED_PAYMENT_SETTINGS.iter() {
+            assert!(check_settings_change(&json!({"payment": { *key: {} }})).is_err());
+        }
+        assert!(check_settings_change(&json!({"payment": {"balance_warning_level": "1"}})).is_ok());
+
+        let mut settings =
+            json!({"payment": {"sweep": {}, "withdraw_policy": {}, "debts_file": "a"}});
+        strip_protected_settings(&mut settings);
+        assert_eq!(settings, json!({"payment": {"debts_file": "a"}}));
+    }
+}
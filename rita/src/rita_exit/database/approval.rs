@@ -0,0 +1,112 @@
+//! Manual registration mode where the exit operator approves or denies each new client. Clients
+//! sit in the pending state, which is simply neither verified nor denied in the database, until
+//! the operator makes a decision using the exit dashboard. The operator's message is stored and
+//! shown to the client either way.
+
+use crate::rita_exit::database::database_tools::get_client_details;
+use crate::rita_exit::database::get_exit_info;
+use crate::rita_exit::database::struct_tools::verif_done;
+use althea_types::ExitState;
+use diesel::prelude::PgConnection;
+use exit_db::models;
+use failure::Error;
+use settings::exit::ApprovalVerifSettings;
+
+/// The message for a client still waiting on the operator, an operator may leave a note
+/// on a pending client without deciding yet
+pub fn pending_approval_message(
+    their_record: &models::Client,
+    settings: &ApprovalVerifSettings,
+) -> String {
+    if their_record.approval_message.is_empty() {
+        settings.pending_message.clone()
+    } else {
+        format!(
+            "{} {}",
+            settings.pending_message, their_record.approval_message
+        )
+    }
+}
+
+/// Where the operator stands on a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Pending,
+    Approved,
+    Denied,
+}
+
+pub fn operator_decision(their_record: &models::Client) -> Decision {
+    if their_record.denied {
+        Decision::Denied
+    } else if verif_done(their_record) {
+        Decision::Approved
+    } else {
+        Decision::Pending
+    }
+}
+
+/// Returns the registration state of a client under approval mode, there's nothing for
+/// the client to submit so this only reports the operator's decision
+pub fn handle_approval_registration(
+    their_record: &models::Client,
+    settings: &ApprovalVerifSettings,
+    conn: &PgConnection,
+) -> Result<ExitState, Error> {
+    match operator_decision(their_record) {
+        Decision::Denied => Ok(ExitState::Denied {
+            message: their_record.approval_message.clone(),
+        }),
+        Decision::Approved => {
+            info!("{} was approved by the operator", their_record.wg_pubkey);
+            Ok(ExitState::Registered {
+                our_details: get_client_details(their_record, conn)?,
+                general_details: get_exit_info(),
+                message: if their_record.approval_message.is_empty() {
+                    "Registration OK".to_string()
+                } else {
+                    their_record.approval_message.clone()
+                },
+            })
+        }
+        Decision::Pending => Ok(ExitState::Pending {
+            general_details: get_exit_info(),
+            message: pending_approval_message(their_record, settings),
+            email_code: None,
+            phone_code: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operator_decision() {
+        let mut client = models::Client::default();
+        assert_eq!(operator_decision(&client), Decision::Pending);
+        client.verified = true;
+        assert_eq!(operator_decision(&client), Decision::Approved);
+        // a denial wins even if the client was approved before
+        client.denied = true;
+        assert_eq!(operator_decision(&client), Decision::Denied);
+    }
+
+    #[test]
+    fn test_pending_approval_message() {
+        let settings = ApprovalVerifSettings {
+            pending_message: "Waiting on the operator.".to_string(),
+        };
+        let mut client = models::Client::default();
+        assert_eq!(
+            pending_approval_message(&client, &settings),
+            "Waiting on the operator."
+        );
+        client.approval_message = "Send us your address.".to_string();
+        assert_eq!(
+            pending_approval_message(&client, &settings),
+            "Waiting on the operator. Send us your address."
+        );
+    }
+}
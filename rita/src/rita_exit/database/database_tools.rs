@@ -1,15 +1,21 @@
 use crate::rita_common::utils::ip_increment::increment;
+use crate::rita_exit::database::quota::rolled_over_period_start;
 use crate::rita_exit::database::secs_since_unix_epoch;
 use crate::rita_exit::database::struct_tools::client_to_new_db_client;
+use crate::rita_exit::database::struct_tools::to_service_plan;
 use crate::rita_exit::database::ONE_DAY;
 use crate::DB_POOL;
 use crate::SETTING;
 use actix_web::Result;
 use althea_kernel_interface::ExitClient;
+use althea_types::ExitClientDetails;
 use althea_types::ExitClientIdentity;
+use althea_types::WgKey;
 use diesel;
 use diesel::dsl::{delete, exists};
+use diesel::pg::Pg;
 use diesel::prelude::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::query_builder::{QueryFragment, QueryId};
 use diesel::r2d2::ConnectionManager;
 use diesel::r2d2::PooledConnection;
 use diesel::select;
@@ -18,6 +24,7 @@ use failure::Error;
 use futures01::future;
 use futures01::future::Future;
 use settings::exit::RitaExitSettings;
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::time::Duration;
@@ -372,3 +379,204 @@ pub fn create_or_update_user_record(
         Ok(c)
     }
 }
+
+/// Loads every service plan in the database, keyed by plan name
+pub fn get_plans(conn: &PgConnection) -> Result<HashMap<String, models::Plan>, Error> {
+    use self::schema::plans::dsl::plans;
+    let mut ret = HashMap::new();
+    for plan in plans.load::<models::Plan>(conn)? {
+        ret.insert(plan.name.clone(), plan);
+    }
+    Ok(ret)
+}
+
+pub fn get_plan(plan_name: &str, conn: &PgConnection) -> Result<Option<models::Plan>, Error> {
+    use self::schema::plans::dsl::{name, plans};
+    let mut entry = plans
+        .filter(name.eq(plan_name))
+        .load::<models::Plan>(conn)?;
+    Ok(entry.pop())
+}
+
+/// Creates a new plan or overwrites the existing plan with the same name
+pub fn create_or_update_plan(plan: &models::Plan, conn: &PgConnection) -> Result<(), Error> {
+    use self::schema::plans::dsl::plans;
+    if plan.name.is_empty() {
+        bail!("Plans must have a name!");
+    }
+    if plan.price_per_byte < 0 || plan.guaranteed_kbit < 0 || plan.burst_kbit < 0 {
+        bail!("Plan values can not be negative!");
+    }
+    if get_plan(&plan.name, conn)?.is_some() {
+        diesel::update(plans.find(&plan.name))
+            .set(plan)
+            .execute(conn)?;
+    } else {
+        diesel::insert_into(plans).values(plan).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Assigns a client to a plan by wg key, an empty plan name returns the client to
+/// the exit wide defaults
+pub fn set_client_plan(
+    client_wg_pubkey: &str,
+    plan_name: &str,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    use self::schema::clients::dsl::{clients, plan, wg_pubkey};
+    if !plan_name.is_empty() && get_plan(plan_name, conn)?.is_none() {
+        bail!("No plan named {}", plan_name);
+    }
+    info!("Setting plan for {} to {}", client_wg_pubkey, plan_name);
+    let changed = diesel::update(clients.filter(wg_pubkey.eq(client_wg_pubkey)))
+        .set(plan.eq(plan_name))
+        .execute(conn)?;
+    if changed == 0 {
+        bail!("No client with wg key {}", client_wg_pubkey);
+    }
+    Ok(())
+}
+
+/// Builds the client specific details we hand back to a registered client, including
+/// the service plan they are on if any
+pub fn get_client_details(
+    their_record: &models::Client,
+    conn: &PgConnection,
+) -> Result<ExitClientDetails, Error> {
+    let service_plan = if their_record.plan.is_empty() {
+        None
+    } else {
+        match get_plan(&their_record.plan, conn)? {
+            Some(plan) => Some(to_service_plan(&plan)),
+            None => {
+                warn!(
+                    "Client {} is assigned to missing plan {}",
+                    their_record.wg_pubkey, their_record.plan
+                );
+                None
+            }
+        }
+    };
+    Ok(ExitClientDetails {
+        client_internal_ip: their_record.internal_ip.parse()?,
+        service_plan,
+    })
+}
+
+/// Adds usage in bytes for this billing period to each client, starting a new billing
+/// period for any client whose current period has ended
+pub fn add_client_usage(
+    usage: &HashMap<WgKey, u64>,
+    billing_period: i64,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    use self::schema::clients::dsl::{
+        clients, period_start, period_usage, quota_warning_level, wg_pubkey,
+    };
+    let now = secs_since_unix_epoch();
+
+    // only the clients whose period has ended, usually none of them
+    let ended = clients
+        .filter(period_start.lt(now - billing_period))
+        .load::<models::Client>(conn)?;
+    for client in ended.iter() {
+        let start = rolled_over_period_start(client.period_start, now, billing_period);
+        diesel::update(clients.find(&client.mesh_ip))
+            .set((
+                period_start.eq(start),
+                period_usage.eq(0),
+                quota_warning_level.eq(0),
+            ))
+            .execute(conn)?;
+    }
+    if !ended.is_empty() {
+        info!("Started a new billing period for {} clients", ended.len());
+    }
+
+    for (key, bytes) in usage.iter() {
+        if *bytes == 0 {
+            continue;
+        }
+        diesel::update(clients.filter(wg_pubkey.eq(key.to_string())))
+            .set(period_usage.eq(period_usage + *bytes as i64))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+pub fn set_quota_warning_level(
+    client: &models::Client,
+    level: u8,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    use self::schema::clients::dsl::{clients, quota_warning_level};
+    diesel::update(clients.find(&client.mesh_ip))
+        .set(quota_warning_level.eq(i32::from(level)))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Records the operator's decision on a client, approved clients are marked verified
+/// and denied clients are removed from the exit tunnel on the next loop
+pub fn set_client_approval(
+    client_wg_pubkey: &str,
+    approved: bool,
+    message: &str,
+    conn: &PgConnection,
+) -> Result<(), Error> {
+    info!(
+        "Operator has {} client {} with message {}",
+        if approved { "approved" } else { "denied" },
+        client_wg_pubkey,
+        message
+    );
+    let changed = approval_update(client_wg_pubkey, approved, message).execute(conn)?;
+    if changed == 0 {
+        bail!("No client with wg key {}", client_wg_pubkey);
+    }
+    Ok(())
+}
+
+/// The update that records an operator's decision on a client
+fn approval_update<'a>(
+    client_wg_pubkey: &'a str,
+    approved: bool,
+    message: &'a str,
+) -> impl QueryFragment<Pg> + QueryId + RunQueryDsl<PgConnection> + 'a {
+    use self::schema::clients::dsl::{approval_message, clients, denied, verified, wg_pubkey};
+    diesel::update(clients.filter(wg_pubkey.eq(client_wg_pubkey))).set((
+        verified.eq(approved),
+        denied.eq(!approved),
+        approval_message.eq(message),
+    ))
+}
+
+/// Gets all clients that are neither verified nor denied, in approval mode this is
+/// the list of clients waiting on the operator
+pub fn get_pending_clients(conn: &PgConnection) -> Result<Vec<models::Client>, Error> {
+    use self::schema::clients::dsl::{clients, denied, verified};
+    Ok(clients
+        .filter(verified.eq(false))
+        .filter(denied.eq(false))
+        .load::<models::Client>(conn)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::debug_query;
+
+    #[test]
+    fn test_approval_update() {
+        let approve = approval_update("key", true, "Welcome!");
+        let sql = debug_query::<Pg, _>(&approve).to_string();
+        assert!(sql.contains(r#"SET "verified" = $1, "denied" = $2, "approval_message" = $3"#));
+        assert!(sql.contains(r#"WHERE "clients"."wg_pubkey" = $4"#));
+        assert!(sql.ends_with(r#"-- binds: [true, false, "Welcome!", "key"]"#));
+
+        let deny = approval_update("key", false, "");
+        let sql = debug_query::<Pg, _>(&deny).to_string();
+        assert!(sql.ends_with(r#"-- binds: [false, true, "", "key"]"#));
+    }
+}
@@ -1,10 +1,11 @@
+use crate::rita_exit::database::database_tools::get_client_details;
 use crate::rita_exit::database::database_tools::update_mail_sent_time;
 use crate::rita_exit::database::database_tools::verify_client;
 use crate::rita_exit::database::get_exit_info;
 use crate::rita_exit::database::secs_since_unix_epoch;
 use crate::rita_exit::database::struct_tools::verif_done;
 use crate::SETTING;
-use althea_types::{ExitClientDetails, ExitClientIdentity, ExitState};
+use althea_types::{ExitClientIdentity, ExitState};
 use diesel;
 use diesel::prelude::PgConnection;
 use exit_db::models;
@@ -19,6 +20,7 @@ use lettre::smtp::ConnectionReuseParameters;
 use lettre::{SmtpClient, Transport};
 use lettre_email::EmailBuilder;
 use settings::exit::EmailVerifSettings;
+use settings::exit::ExitQuotaSettings;
 use settings::exit::ExitVerifSettings;
 use settings::exit::RitaExitSettings;
 
@@ -83,12 +85,12 @@ pub fn handle_email_registration(
     if verif_done(&their_record) {
         info!("{:?} is now registered", client);
 
-        let client_internal_ip = match their_record.internal_ip.parse() {
-            Ok(ip) => ip,
-            Err(e) => return future::err(format_err!("{:?}", e)),
+        let our_details = match get_client_details(&their_record, &conn) {
+            Ok(details) => details,
+            Err(e) => return future::err(e),
         };
         future::ok(ExitState::Registered {
-            our_details: ExitClientDetails { client_internal_ip },
+            our_details,
             general_details: get_exit_info(),
             message: "Registration OK".to_string(),
         })
@@ -125,12 +127,42 @@ pub fn handle_email_registration(
 
 pub fn send_low_balance_email(email: &str, mailer: EmailVerifSettings) -> Result<(), Error> {
     info!("Sending low balance email to {}", email);
+    let subject = mailer.balance_notification_subject.clone();
+    let body = mailer.balance_notification_body.clone();
+    send_notification_email(email, mailer, subject, body)
+}
+
+pub fn send_withdraw_held_email(email: &str, mailer: EmailVerifSettings) -> Result<(), Error> {
+    info!("Sending withdraw held email to {}", email);
+    let subject = mailer.withdraw_held_subject.clone();
+    let body = mailer.withdraw_held_body.clone();
+    send_notification_email(email, mailer, subject, body)
+}
+
+/// Sends a data quota warning, the body is templated with the percentage of the quota used
+pub fn send_quota_warning_email(
+    email: &str,
+    mailer: EmailVerifSettings,
+    quota: &ExitQuotaSettings,
+    percent: u8,
+) -> Result<(), Error> {
+    info!("Sending {}% quota warning email to {}", percent, email);
+    let reg = Handlebars::new();
+    let body = reg.render_template(&quota.warning_body, &json!({ "percent": percent }))?;
+    send_notification_email(email, mailer, quota.warning_subject.clone(), body)
+}
 
+fn send_notification_email(
+    email: &str,
+    mailer: EmailVerifSettings,
+    subject: String,
+    body: String,
+) -> Result<(), Error> {
     let email = EmailBuilder::new()
         .to(email)
         .from(mailer.from_address)
-        .subject(mailer.balance_notification_subject)
-        .text(mailer.balance_notification_body)
+        .subject(subject)
+        .text(body)
         .build()?;
 
     if mailer.test {
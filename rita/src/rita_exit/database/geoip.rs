@@ -1,53 +1,45 @@
+use crate::rita_common::babel_manager;
+use crate::rita_exit::database::secs_since_unix_epoch;
 use crate::GEOIP_CACHE;
 use crate::KI;
 use crate::SETTING;
 use actix_web::client as actix_client;
 use actix_web::HttpMessage;
-use babel_monitor::open_babel_stream;
-use babel_monitor::parse_routes;
-use babel_monitor::start_connection;
 use failure::Error;
 use future::Either;
 use futures01::future;
 use futures01::future::Future;
 use ipnetwork::IpNetwork;
+use maxminddb::geoip2;
+use maxminddb::Reader;
 use settings::exit::RitaExitSettings;
-use settings::RitaCommonSettings;
 use std::collections::HashMap;
+use std::fs::rename;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Write;
 use std::net::IpAddr;
+use std::time::Duration;
 
 /// gets the gateway ip for a given mesh IP
 pub fn get_gateway_ip_single(mesh_ip: IpAddr) -> Box<dyn Future<Item = IpAddr, Error = Error>> {
-    let babel_port = SETTING.get_network().babel_port;
-
-    Box::new(
-        open_babel_stream(babel_port)
-            .from_err()
-            .and_then(move |stream| {
-                start_connection(stream).and_then(move |stream| {
-                    parse_routes(stream).and_then(move |routes| {
-                        let mut route_to_des = None;
-                        for route in routes.1.iter() {
-                            // Only ip6
-                            if let IpNetwork::V6(ref ip) = route.prefix {
-                                // Only host addresses and installed routes
-                                if ip.prefix() == 128
-                                    && route.installed
-                                    && IpAddr::V6(ip.ip()) == mesh_ip
-                                {
-                                    route_to_des = Some(route.clone());
-                                }
-                            }
-                        }
+    Box::new(babel_manager::get_routes().and_then(move |routes| {
+        let mut route_to_des = None;
+        for route in routes.iter() {
+            // Only ip6
+            if let IpNetwork::V6(ref ip) = route.prefix {
+                // Only host addresses and installed routes
+                if ip.prefix() == 128 && route.installed && IpAddr::V6(ip.ip()) == mesh_ip {
+                    route_to_des = Some(route.clone());
+                }
+            }
+        }
 
-                        match route_to_des {
-                            Some(route) => Ok(KI.get_wg_remote_ip(&route.iface)?),
-                            None => bail!("No route found for mesh ip: {:?}", mesh_ip),
-                        }
-                    })
-                })
-            }),
-    )
+        match route_to_des {
+            Some(route) => Ok(KI.get_wg_remote_ip(&route.iface)?),
+            None => bail!("No route found for mesh ip: {:?}", mesh_ip),
+        }
+    }))
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -61,51 +53,43 @@ pub struct IpPair {
 pub fn get_gateway_ip_bulk(
     mesh_ip_list: Vec<IpAddr>,
 ) -> Box<dyn Future<Item = Vec<IpPair>, Error = Error>> {
-    let babel_port = SETTING.get_network().babel_port;
     trace!("getting gateway ip bulk");
 
-    Box::new(open_babel_stream(babel_port).from_err().and_then(|stream| {
-        start_connection(stream).and_then(|stream| {
-            parse_routes(stream).and_then(|routes| {
-                trace!("done talking to babel for gateway ip bulk");
-                let mut remote_ip_cache: HashMap<String, IpAddr> = HashMap::new();
-                let mut results = Vec::new();
-                for mesh_ip in mesh_ip_list {
-                    for route in routes.1.iter() {
-                        // Only ip6
-                        if let IpNetwork::V6(ref ip) = route.prefix {
-                            // Only host addresses and installed routes
-                            if ip.prefix() == 128
-                                && route.installed
-                                && IpAddr::V6(ip.ip()) == mesh_ip
-                            {
-                                // check if we've already looked up this interface this round, since gateways
-                                // have many clients this will often be the case
-                                if let Some(remote_ip) = remote_ip_cache.get(&route.iface) {
+    Box::new(babel_manager::get_routes().and_then(|routes| {
+        trace!("done talking to babel for gateway ip bulk");
+        let mut remote_ip_cache: HashMap<String, IpAddr> = HashMap::new();
+        let mut results = Vec::new();
+        for mesh_ip in mesh_ip_list {
+            for route in routes.iter() {
+                // Only ip6
+                if let IpNetwork::V6(ref ip) = route.prefix {
+                    // Only host addresses and installed routes
+                    if ip.prefix() == 128 && route.installed && IpAddr::V6(ip.ip()) == mesh_ip {
+                        // check if we've already looked up this interface this round, since gateways
+                        // have many clients this will often be the case
+                        if let Some(remote_ip) = remote_ip_cache.get(&route.iface) {
+                            results.push(IpPair {
+                                mesh_ip,
+                                gateway_ip: *remote_ip,
+                            });
+                        } else {
+                            match KI.get_wg_remote_ip(&route.iface) {
+                                Ok(remote_ip) => {
+                                    remote_ip_cache.insert(route.iface.clone(), remote_ip);
                                     results.push(IpPair {
                                         mesh_ip,
-                                        gateway_ip: *remote_ip,
-                                    });
-                                } else {
-                                    match KI.get_wg_remote_ip(&route.iface) {
-                                        Ok(remote_ip) => {
-                                            remote_ip_cache.insert(route.iface.clone(), remote_ip);
-                                            results.push(IpPair {
-                                                mesh_ip,
-                                                gateway_ip: remote_ip,
-                                            })
-                                        }
-                                        Err(e) => error!("Failure looking up remote ip {:?}", e),
-                                    }
+                                        gateway_ip: remote_ip,
+                                    })
                                 }
+                                Err(e) => error!("Failure looking up remote ip {:?}", e),
                             }
                         }
                     }
                 }
+            }
+        }
 
-                Ok(results)
-            })
-        })
+        Ok(results)
     }))
 }
 
@@ -119,7 +103,131 @@ struct CountryDetails {
     iso_code: String,
 }
 
-/// get ISO country code from ip, consults a in memory cache
+/// How long a country from the api is trusted before we ask again, addresses move between
+/// countries rarely but they do move
+const GEOIP_CACHE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// A country looked up with the api, the local database is fast enough that its answers
+/// aren't cached, that way they change as soon as the database is updated
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CachedCountry {
+    pub code: String,
+    /// when the lookup was made, in seconds since the unix epoch
+    pub fetched: i64,
+}
+
+impl CachedCountry {
+    fn new(code: String) -> CachedCountry {
+        CachedCountry {
+            code,
+            fetched: secs_since_unix_epoch(),
+        }
+    }
+
+    fn expired(&self, now: i64) -> bool {
+        now - self.fetched > GEOIP_CACHE_TTL.as_secs() as i64
+    }
+}
+
+lazy_static! {
+    /// The local MaxMind format country database, if one is configured
+    static ref GEOIP_DB: Option<Reader<Vec<u8>>> = open_geoip_db();
+}
+
+fn open_geoip_db() -> Option<Reader<Vec<u8>>> {
+    let path = SETTING.get_exit_network().geoip_db_path.clone()?;
+    match Reader::open_readfile(&path) {
+        Ok(reader) => {
+            info!("Loaded GeoIP database {}", path);
+            Some(reader)
+        }
+        Err(e) => {
+            error!("Failed to open GeoIP database {} with {:?}", path, e);
+            None
+        }
+    }
+}
+
+/// True if a local geoip database is configured and was loaded successfully
+pub fn geoip_db_loaded() -> bool {
+    GEOIP_DB.is_some()
+}
+
+/// Looks up the ISO country code for an ip in the local database, returns None if there is
+/// no local database or the ip can't be found in it
+fn get_country_local(ip: IpAddr) -> Option<String> {
+    let reader = GEOIP_DB.as_ref()?;
+    match reader.lookup::<geoip2::Country>(ip) {
+        Ok(result) => result
+            .country
+            .and_then(|country| country.iso_code)
+            .map(|code| code.to_string()),
+        Err(e) => {
+            trace!("{} not found in local GeoIP database {:?}", ip, e);
+            None
+        }
+    }
+}
+
+/// Loads the persisted geoip cache, used to initialize GEOIP_CACHE
+pub fn load_geoip_cache() -> HashMap<IpAddr, CachedCountry> {
+    match SETTING.get_exit_network().geoip_cache_path.clone() {
+        Some(path) => read_geoip_cache(&path, secs_since_unix_epoch()),
+        None => HashMap::new(),
+    }
+}
+
+/// Reads a persisted cache leaving out the entries that have expired, a cache that
+/// can't be read is logged and started over
+fn read_geoip_cache(path: &str, now: i64) -> HashMap<IpAddr, CachedCountry> {
+    let res = File::open(path)
+        .map_err(Error::from)
+        .and_then(|file| Ok(serde_json::from_reader(BufReader::new(file))?));
+    match res {
+        Ok(cache) => {
+            let mut cache: HashMap<IpAddr, CachedCountry> = cache;
+            cache.retain(|_, country| !country.expired(now));
+            cache
+        }
+        Err(e) => {
+            warn!("Could not load GeoIP cache from {} {:?}", path, e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Writes the geoip cache to disk
+fn save_geoip_cache() -> Result<(), Error> {
+    let path = match SETTING.get_exit_network().geoip_cache_path.clone() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let cache = GEOIP_CACHE.read().unwrap().clone();
+    write_geoip_cache(&path, &cache)
+}
+
+/// Writes to a temporary file first so that a crash mid write does not leave us with a
+/// corrupt cache
+fn write_geoip_cache(path: &str, cache: &HashMap<IpAddr, CachedCountry>) -> Result<(), Error> {
+    let serialized = serde_json::to_vec(cache)?;
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&serialized)?;
+    file.sync_all()?;
+    rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Returns the cached country for an ip, if we have one that hasn't expired
+fn get_country_cached(ip: IpAddr, now: i64) -> Option<String> {
+    match GEOIP_CACHE.read().unwrap().get(&ip) {
+        Some(country) if !country.expired(now) => Some(country.code.clone()),
+        _ => None,
+    }
+}
+
+/// get ISO country code from ip, consults the local database if configured, then a cache
+/// of earlier api lookups, then the Maxmind web api if configured
 pub fn get_country(ip: IpAddr) -> impl Future<Item = String, Error = Error> {
     trace!("get GeoIP country for {}", ip.to_string());
 
@@ -129,46 +237,57 @@ pub fn get_country(ip: IpAddr) -> impl Future<Item = String, Error = Error> {
         return Either::A(future::ok(String::new()));
     }
 
-    // on the other hand if there is a configured list of allowed countries
-    // but no configured api details, we panic
-    let api_user = SETTING
-        .get_exit_network()
-        .geoip_api_user
-        .clone()
-        .expect("No api key configured!");
-    let api_key = SETTING
-        .get_exit_network()
-        .geoip_api_key
-        .clone()
-        .expect("No api key configured!");
+    if let Some(code) = get_country_local(ip) {
+        return Either::A(future::ok(code));
+    }
 
-    match GEOIP_CACHE.read().unwrap().get(&ip) {
-        Some(code) => Either::A(future::ok(code.clone())),
-        None => {
-            let geo_ip_url = format!("https://geoip.maxmind.com/geoip/v2.1/country/{}", ip);
-            info!(
-                "making GeoIP request to {} for {}",
-                geo_ip_url,
-                ip.to_string()
-            );
-            Either::B(
-                actix_client::get(&geo_ip_url)
-                    .basic_auth(api_user, Some(api_key))
-                    .finish()
-                    .unwrap()
-                    .send()
-                    .from_err()
-                    .and_then(move |response| {
-                        response.json().from_err().and_then(move |result| {
-                            let value: GeoIPRet = result;
-                            let code = value.country.iso_code;
-                            GEOIP_CACHE.write().unwrap().insert(ip, code.clone());
-                            Ok(code)
-                        })
-                    }),
-            )
-        }
+    if let Some(code) = get_country_cached(ip, secs_since_unix_epoch()) {
+        return Either::A(future::ok(code));
     }
+
+    let exit_network = SETTING.get_exit_network();
+    let (api_user, api_key) = match (
+        exit_network.geoip_api_user.clone(),
+        exit_network.geoip_api_key.clone(),
+    ) {
+        (Some(user), Some(key)) => (user, key),
+        _ => {
+            return Either::A(future::err(format_err!(
+                "No GeoIP data for {} and no api key configured!",
+                ip
+            )))
+        }
+    };
+    drop(exit_network);
+
+    let geo_ip_url = format!("https://geoip.maxmind.com/geoip/v2.1/country/{}", ip);
+    info!(
+        "making GeoIP request to {} for {}",
+        geo_ip_url,
+        ip.to_string()
+    );
+    Either::B(
+        actix_client::get(&geo_ip_url)
+            .basic_auth(api_user, Some(api_key))
+            .finish()
+            .unwrap()
+            .send()
+            .from_err()
+            .and_then(move |response| {
+                response.json().from_err().and_then(move |result| {
+                    let value: GeoIPRet = result;
+                    let code = value.country.iso_code;
+                    GEOIP_CACHE
+                        .write()
+                        .unwrap()
+                        .insert(ip, CachedCountry::new(code.clone()));
+                    if let Err(e) = save_geoip_cache() {
+                        warn!("Failed to save GeoIP cache {:?}", e);
+                    }
+                    Ok(code)
+                })
+            }),
+    )
 }
 
 /// Returns true or false if an ip is confirmed to be inside or outside the region and error
@@ -194,3 +313,39 @@ pub fn verify_ip(request_ip: IpAddr) -> impl Future<Item = bool, Error = Error>
 fn test_get_country() {
     get_country("8.8.8.8".parse().unwrap()).wait().unwrap();
 }
+
+#[test]
+fn test_geoip_cache_persistence() {
+    let path = std::env::temp_dir().join("rita_geoip_cache_test.json");
+    let path = path.to_str().unwrap();
+    let now = secs_since_unix_epoch();
+    let ttl = GEOIP_CACHE_TTL.as_secs() as i64;
+
+    let mut cache = HashMap::new();
+    let fresh = CachedCountry {
+        code: "US".to_string(),
+        fetched: now,
+    };
+    cache.insert("1.1.1.1".parse().unwrap(), fresh.clone());
+    cache.insert(
+        "2.2.2.2".parse().unwrap(),
+        CachedCountry {
+            code: "FR".to_string(),
+            fetched: now - ttl - 1,
+        },
+    );
+    write_geoip_cache(path, &cache).unwrap();
+
+    // the expired entry is dropped on load
+    let loaded = read_geoip_cache(path, now);
+    assert_eq!(loaded.len(), 1);
+    assert_eq!(loaded[&"1.1.1.1".parse().unwrap()], fresh);
+    // and so is the other one once it's old enough
+    assert!(read_geoip_cache(path, now + ttl + 1).is_empty());
+
+    // a corrupt or missing cache starts over
+    std::fs::write(path, b"{\"1.1.1.1\": ").unwrap();
+    assert!(read_geoip_cache(path, now).is_empty());
+    std::fs::remove_file(path).unwrap();
+    assert!(read_geoip_cache(path, now).is_empty());
+}
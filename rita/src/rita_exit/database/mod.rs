@@ -5,11 +5,15 @@
 use crate::rita_common::debt_keeper::DebtAction;
 use crate::rita_common::debt_keeper::DebtKeeper;
 use crate::rita_common::debt_keeper::GetDebtsList;
+use crate::rita_exit::database::approval::handle_approval_registration;
+use crate::rita_exit::database::approval::pending_approval_message;
 use crate::rita_exit::database::database_tools::client_conflict;
 use crate::rita_exit::database::database_tools::create_or_update_user_record;
 use crate::rita_exit::database::database_tools::delete_client;
 use crate::rita_exit::database::database_tools::get_client;
+use crate::rita_exit::database::database_tools::get_client_details;
 use crate::rita_exit::database::database_tools::get_database_connection;
+use crate::rita_exit::database::database_tools::get_plan;
 use crate::rita_exit::database::database_tools::set_client_timestamp;
 use crate::rita_exit::database::database_tools::update_client;
 use crate::rita_exit::database::database_tools::update_low_balance_notification_time;
@@ -17,12 +21,16 @@ use crate::rita_exit::database::database_tools::verify_client;
 use crate::rita_exit::database::database_tools::verify_db_client;
 use crate::rita_exit::database::email::handle_email_registration;
 use crate::rita_exit::database::email::send_low_balance_email;
+use crate::rita_exit::database::email::send_withdraw_held_email;
 use crate::rita_exit::database::geoip::get_country;
 use crate::rita_exit::database::geoip::get_gateway_ip_bulk;
 use crate::rita_exit::database::geoip::get_gateway_ip_single;
 use crate::rita_exit::database::geoip::verify_ip;
+use crate::rita_exit::database::quota::quota_blocked;
+use crate::rita_exit::database::quota::quota_exhausted;
 use crate::rita_exit::database::sms::handle_sms_registration;
 use crate::rita_exit::database::sms::send_low_balance_sms;
+use crate::rita_exit::database::sms::send_withdraw_held_sms;
 use crate::rita_exit::database::struct_tools::display_hashset;
 use crate::rita_exit::database::struct_tools::to_exit_client;
 use crate::rita_exit::database::struct_tools::to_identity;
@@ -38,7 +46,8 @@ use crate::KI;
 use crate::SETTING;
 use ::actix::SystemService;
 use althea_kernel_interface::ExitClient;
-use althea_types::{ExitClientDetails, ExitClientIdentity, ExitDetails, ExitState, ExitVerifMode};
+use althea_types::Identity;
+use althea_types::{ExitClientIdentity, ExitDetails, ExitState, ExitVerifMode};
 use diesel;
 use diesel::prelude::PgConnection;
 use exit_db::schema;
@@ -57,16 +66,24 @@ use std::time::Instant;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::util::FutureExt;
 
+mod approval;
 pub mod database_tools;
 pub mod db_client;
 mod email;
-mod geoip;
+pub mod geoip;
+pub mod quota;
 mod sms;
+pub mod sms_provider;
 pub mod struct_tools;
 
 /// one day in seconds
 pub const ONE_DAY: i64 = 86400;
 
+/// Guaranteed bandwidth in kbit/s for clients in good standing that are not on a plan
+pub const DEFAULT_GUARANTEED_KBIT: u32 = 500_000;
+/// Maximum bandwidth in kbit/s for clients in good standing that are not on a plan
+pub const DEFAULT_BURST_KBIT: u32 = 1_000_000;
+
 pub fn get_exit_info() -> ExitDetails {
     const UPDATE_INTERVAL: Duration = Duration::from_secs(60);
     let last_update = EXIT_PRICE.read().unwrap().1;
@@ -93,6 +110,7 @@ pub fn get_exit_info() -> ExitDetails {
         verif_mode: match EXIT_VERIF_SETTINGS.clone() {
             Some(ExitVerifSettings::Email(_mailer_settings)) => ExitVerifMode::Email,
             Some(ExitVerifSettings::Phone(_phone_settings)) => ExitVerifMode::Phone,
+            Some(ExitVerifSettings::Approval(_approval_settings)) => ExitVerifMode::Approval,
             None => ExitVerifMode::Off,
         },
     }
@@ -137,6 +155,13 @@ pub fn signup_client(client: ExitClientIdentity) -> impl Future<Item = ExitState
                             Err(e) => return Box::new(future::err(e)),
                         };
 
+                    // the operator has denied this client, no verification method can change that
+                    if their_record.denied {
+                        return Box::new(future::ok(ExitState::Denied {
+                            message: their_record.approval_message,
+                        }));
+                    }
+
                     // either update and grab an existing entry or create one
                     match (verify_status, EXIT_VERIF_SETTINGS.clone()) {
                         (true, Some(ExitVerifSettings::Email(mailer))) => {
@@ -147,21 +172,28 @@ pub fn signup_client(client: ExitClientIdentity) -> impl Future<Item = ExitState
                                 mailer.email_cooldown as i64,
                             ))
                         }
-                        (true, Some(ExitVerifSettings::Phone(phone))) => Box::new(
-                            handle_sms_registration(client, their_record, phone.auth_api_key),
-                        ),
+                        (true, Some(ExitVerifSettings::Phone(phone))) => {
+                            Box::new(handle_sms_registration(client, their_record, phone))
+                        }
+                        (true, Some(ExitVerifSettings::Approval(approval))) => {
+                            Box::new(future::result(handle_approval_registration(
+                                &their_record,
+                                &approval,
+                                &conn,
+                            )))
+                        }
                         (true, None) => {
                             match verify_client(&client, true, &conn) {
                                 Ok(_) => (),
                                 Err(e) => return Box::new(future::err(e)),
                             }
-                            let client_internal_ip = match their_record.internal_ip.parse() {
-                                Ok(ip) => ip,
-                                Err(e) => return Box::new(future::err(format_err!("{:?}", e))),
+                            let our_details = match get_client_details(&their_record, &conn) {
+                                Ok(details) => details,
+                                Err(e) => return Box::new(future::err(e)),
                             };
 
                             Box::new(future::ok(ExitState::Registered {
-                                our_details: ExitClientDetails { client_internal_ip },
+                                our_details,
                                 general_details: get_exit_info(),
                                 message: "Registration OK".to_string(),
                             }))
@@ -186,16 +218,29 @@ pub fn client_status(client: ExitClientIdentity, conn: &PgConnection) -> Result<
     if let Some(their_record) = get_client(&client, &conn)? {
         trace!("record exists, updating");
 
+        if their_record.denied {
+            return Ok(ExitState::Denied {
+                message: their_record.approval_message,
+            });
+        }
+
         if !verif_done(&their_record) {
+            let message = match EXIT_VERIF_SETTINGS.clone() {
+                Some(ExitVerifSettings::Approval(approval)) => {
+                    pending_approval_message(&their_record, &approval)
+                }
+                _ => "awaiting email verification".to_string(),
+            };
             return Ok(ExitState::Pending {
                 general_details: get_exit_info(),
-                message: "awaiting email verification".to_string(),
+                message,
                 email_code: None,
                 phone_code: None,
             });
         }
 
-        let current_ip = their_record.internal_ip.parse()?;
+        let our_details = get_client_details(&their_record, &conn)?;
+        let current_ip = our_details.client_internal_ip;
 
         let exit_network = &*EXIT_NETWORK_SETTINGS;
         let current_subnet =
@@ -210,14 +255,25 @@ pub fn client_status(client: ExitClientIdentity, conn: &PgConnection) -> Result<
 
         update_client(&client, &their_record, &conn)?;
 
+        withdraw_held_notification(&client, EXIT_VERIF_SETTINGS.clone());
         low_balance_notification(client, &their_record, EXIT_VERIF_SETTINGS.clone(), &conn);
 
+        let plan = if their_record.plan.is_empty() {
+            None
+        } else {
+            get_plan(&their_record.plan, &conn)?
+        };
+        let quota_settings = SETTING.get_quota_settings();
+        let message = if quota_exhausted(&their_record, plan.as_ref(), &quota_settings) {
+            "Data quota exhausted for this billing period".to_string()
+        } else {
+            "Registration OK".to_string()
+        };
+
         Ok(ExitState::Registered {
-            our_details: ExitClientDetails {
-                client_internal_ip: current_ip,
-            },
+            our_details,
             general_details: get_exit_info(),
-            message: "Registration OK".to_string(),
+            message,
         })
     } else {
         Ok(ExitState::New)
@@ -249,6 +305,8 @@ fn low_balance_notification(
                     return;
                 }
             }
+            // there's no way to contact clients registered by approval
+            ExitVerifSettings::Approval(_) => return,
         }
     }
 
@@ -305,6 +363,33 @@ fn low_balance_notification(
     }
 }
 
+/// Tells a client's owner that their router held a withdraw. The client only reports each held
+/// withdraw until a status request gets through, so unlike the low balance notification there's
+/// no interval to keep track of
+fn withdraw_held_notification(client: &ExitClientIdentity, config: Option<ExitVerifSettings>) {
+    if client.withdraw_held != Some(true) {
+        return;
+    }
+    let res = match config {
+        Some(ExitVerifSettings::Phone(val)) => match client.reg_details.phone.clone() {
+            Some(number) => send_withdraw_held_sms(&number, val),
+            None => Err(format_err!("No phone number")),
+        },
+        Some(ExitVerifSettings::Email(val)) => match client.reg_details.email.clone() {
+            Some(email) => send_withdraw_held_email(&email, val),
+            None => Err(format_err!("No email")),
+        },
+        // there's no way to contact clients registered by approval
+        _ => return,
+    };
+    if let Err(e) = res {
+        warn!(
+            "Failed to notify {} of a held withdraw with {:?}",
+            client.global.wg_public_key, e
+        );
+    }
+}
+
 /// Every 5 seconds we vlaidate all online clients to make sure that they are in the right region
 /// we also do this in the client status requests but we want to handle the edge case of a modified
 /// client that doesn't make status requests
@@ -431,10 +516,12 @@ pub fn cleanup_exit_clients(
 pub fn setup_clients(
     clients_list: &[exit_db::models::Client],
     old_clients: &HashSet<ExitClient>,
+    plans: &HashMap<String, exit_db::models::Plan>,
 ) -> Result<HashSet<ExitClient>, Error> {
     use self::schema::clients::dsl::clients;
 
     let start = Instant::now();
+    let quota_settings = SETTING.get_quota_settings();
 
     // use hashset to ensure uniqueness and check for duplicate db entries
     let mut wg_clients = HashSet::new();
@@ -443,6 +530,18 @@ pub fn setup_clients(
 
     for c in clients_list.iter() {
         match (c.verified, to_exit_client(c.clone())) {
+            (true, Ok(_)) if c.denied => {
+                trace!(
+                    "{} has been denied by the operator, not adding to wg_exit",
+                    c.wg_pubkey
+                )
+            }
+            (true, Ok(_)) if quota_blocked(c, plans.get(&c.plan), &quota_settings) => {
+                trace!(
+                    "{} has exhausted their quota, not adding to wg_exit",
+                    c.wg_pubkey
+                )
+            }
             (true, Ok(exit_client_c)) => {
                 if !wg_clients.insert(exit_client_c) {
                     error!("Duplicate database entry! {}", c.wg_pubkey);
@@ -493,8 +592,13 @@ pub fn setup_clients(
 /// setting the htb class they are assigned to to a maximum speed of the free tier value.
 /// Unlike intermediary enforcement we do not need to subdivide the free tier to prevent
 /// ourselves from exceeding the upstream free tier. As an exit we are the upstream.
+/// Clients in good standing are shaped according to their service plan, or the exit
+/// defaults if they are not on one. Clients that have exhausted their data quota are
+/// limited to the free tier just like clients with unpaid debts if the exit is configured to
+/// throttle them.
 pub fn enforce_exit_clients(
     clients_list: Vec<exit_db::models::Client>,
+    plans: HashMap<String, exit_db::models::Plan>,
 ) -> Box<dyn Future<Item = (), Error = ()>> {
     let start = Instant::now();
     Box::new(
@@ -506,6 +610,7 @@ pub fn enforce_exit_clients(
                     let mut clients_by_id = HashMap::new();
                     let free_tier_limit = SETTING.get_payment().free_tier_throughput;
                     let close_threshold = SETTING.get_payment().close_threshold.clone();
+                    let quota_settings = SETTING.get_quota_settings();
                     for client in clients_list.iter() {
                         if let Ok(id) = to_identity(client) {
                             clients_by_id.insert(id, client);
@@ -527,10 +632,18 @@ pub fn enforce_exit_clients(
                                                 free_tier_limit,
                                                 &ip,
                                             )
+                                        } else if quota_exhausted(client, plans.get(&client.plan), &quota_settings) {
+                                            info!("Exit is enforcing on {} because they have exhausted their data quota", client.wg_pubkey);
+                                            KI.set_class_limit(
+                                                "wg_exit",
+                                                free_tier_limit,
+                                                free_tier_limit,
+                                                &ip,
+                                            )
                                         } else {
-                                            // set to 500mbps garunteed bandwidth and 1gbps
-                                            // absolute max
-                                            KI.set_class_limit("wg_exit", 500_000, 1_000_000, &ip)
+                                            let (guaranteed, burst) =
+                                                get_plan_limits(client, &plans);
+                                            KI.set_class_limit("wg_exit", guaranteed, burst, &ip)
                                         };
                                         if res.is_err() {
                                             panic!("Failed to limit {} with {:?}", ip, res);
@@ -583,3 +696,80 @@ pub fn enforce_exit_clients(
             }),
     )
 }
+
+/// Returns the guaranteed and burst bandwidth in kbit/s for a client in good standing
+fn get_plan_limits(
+    client: &exit_db::models::Client,
+    plans: &HashMap<String, exit_db::models::Plan>,
+) -> (u32, u32) {
+    match plans.get(&client.plan) {
+        Some(plan) if plan.guaranteed_kbit > 0 && plan.burst_kbit > 0 => {
+            let guaranteed = plan.guaranteed_kbit as u32;
+            let burst = plan.burst_kbit as u32;
+            // htb will refuse a ceil lower than the rate
+            (guaranteed.min(burst), burst.max(guaranteed))
+        }
+        _ => (DEFAULT_GUARANTEED_KBIT, DEFAULT_BURST_KBIT),
+    }
+}
+
+/// Builds a map of client to price per byte for every client on a service plan, clients
+/// not present in the result pay the exit wide price
+pub fn get_plan_prices(
+    clients_list: &[exit_db::models::Client],
+    plans: &HashMap<String, exit_db::models::Plan>,
+) -> HashMap<Identity, u64> {
+    let mut ret = HashMap::new();
+    for client in clients_list {
+        if let (Some(plan), Ok(id)) = (plans.get(&client.plan), to_identity(client)) {
+            ret.insert(id, plan.price_per_byte.max(0) as u64);
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_plan() -> exit_db::models::Plan {
+        exit_db::models::Plan {
+            name: "basic".to_string(),
+            price_per_byte: 20,
+            guaranteed_kbit: 10_000,
+            burst_kbit: 50_000,
+            monthly_data_cap: 0,
+        }
+    }
+
+    #[test]
+    fn test_plan_limits() {
+        let mut plans = HashMap::new();
+        plans.insert("basic".to_string(), test_plan());
+        let mut client = exit_db::models::Client::default();
+        assert_eq!(
+            get_plan_limits(&client, &plans),
+            (DEFAULT_GUARANTEED_KBIT, DEFAULT_BURST_KBIT)
+        );
+        client.plan = "basic".to_string();
+        assert_eq!(get_plan_limits(&client, &plans), (10_000, 50_000));
+        client.plan = "missing".to_string();
+        assert_eq!(
+            get_plan_limits(&client, &plans),
+            (DEFAULT_GUARANTEED_KBIT, DEFAULT_BURST_KBIT)
+        );
+    }
+
+    #[test]
+    fn test_plan_limits_inverted() {
+        let mut plan = test_plan();
+        plan.guaranteed_kbit = 80_000;
+        let mut plans = HashMap::new();
+        plans.insert("basic".to_string(), plan);
+        let client = exit_db::models::Client {
+            plan: "basic".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(get_plan_limits(&client, &plans), (50_000, 80_000));
+    }
+}
@@ -0,0 +1,245 @@
+//! Data quotas for exit clients. Usage through the exit tunnel is accumulated per client per
+//! billing period by the exit traffic watcher and stored in the database. Each client's quota is
+//! the data cap of their service plan if they are on one, otherwise the exit wide default quota.
+//! Once a client passes a configured warning percentage they are notified using the same method
+//! they verified with, once the quota is exhausted they are either throttled to the free tier or
+//! removed from the exit tunnel until the billing period ends.
+
+use crate::rita_exit::database::database_tools::set_quota_warning_level;
+use crate::rita_exit::database::email::send_quota_warning_email;
+use crate::rita_exit::database::secs_since_unix_epoch;
+use crate::rita_exit::database::sms::send_quota_warning_sms;
+use crate::EXIT_VERIF_SETTINGS;
+use diesel::prelude::PgConnection;
+use exit_db::models;
+use settings::exit::ExitQuotaSettings;
+use settings::exit::ExitVerifSettings;
+use settings::exit::QuotaAction;
+use std::collections::HashMap;
+
+/// Returns the quota in bytes for a client, None if the client has no quota
+pub fn get_client_quota(
+    client: &models::Client,
+    plan: Option<&models::Plan>,
+    settings: &ExitQuotaSettings,
+) -> Option<u64> {
+    match plan {
+        Some(plan) if plan.monthly_data_cap > 0 => Some(plan.monthly_data_cap as u64),
+        // the plan has no cap, it's unlimited
+        Some(_) => None,
+        None => {
+            if !client.plan.is_empty() {
+                warn!(
+                    "Client {} is assigned to missing plan {}",
+                    client.wg_pubkey, client.plan
+                );
+            }
+            settings.default_quota
+        }
+    }
+}
+
+/// Usage for the current billing period, if the period has ended but the traffic watcher
+/// has not yet reset the usage in the database we count it as zero
+pub fn current_period_usage(client: &models::Client, settings: &ExitQuotaSettings) -> u64 {
+    if secs_since_unix_epoch() - client.period_start > settings.billing_period as i64 {
+        0
+    } else {
+        client.period_usage.max(0) as u64
+    }
+}
+
+/// Where a billing period that has ended rolls over to, a whole number of periods after it
+/// started so that the periods stay aligned however late the rollover runs
+pub fn rolled_over_period_start(period_start: i64, now: i64, billing_period: i64) -> i64 {
+    let billing_period = billing_period.max(1);
+    period_start + ((now - period_start) / billing_period) * billing_period
+}
+
+/// Percentage of the quota used this period, saturating at u8::MAX
+fn quota_percent_used(usage: u64, quota: u64) -> u8 {
+    if quota == 0 {
+        return 100;
+    }
+    let percent = u128::from(usage) * 100 / u128::from(quota);
+    if percent > u128::from(u8::MAX) {
+        u8::MAX
+    } else {
+        percent as u8
+    }
+}
+
+/// True if the client has used all of their quota for this billing period
+pub fn quota_exhausted(
+    client: &models::Client,
+    plan: Option<&models::Plan>,
+    settings: &ExitQuotaSettings,
+) -> bool {
+    match get_client_quota(client, plan, settings) {
+        Some(quota) => current_period_usage(client, settings) >= quota,
+        None => false,
+    }
+}
+
+/// True if the client has exhausted their quota and the exit is configured to take them off
+/// the exit tunnel entirely in that case
+pub fn quota_blocked(
+    client: &models::Client,
+    plan: Option<&models::Plan>,
+    settings: &ExitQuotaSettings,
+) -> bool {
+    settings.exhausted_action == QuotaAction::Block && quota_exhausted(client, plan, settings)
+}
+
+/// Finds the highest warning level the client has passed but not been warned about yet
+fn next_warning_level(percent_used: u8, already_warned: u8, levels: &[u8]) -> Option<u8> {
+    levels
+        .iter()
+        .filter(|level| **level <= percent_used && **level > already_warned)
+        .max()
+        .cloned()
+}
+
+/// Sends any pending quota warnings using the configured verification method, once a warning
+/// level has been sent it's recorded in the database so that it is not sent again this period
+pub fn quota_notifications(
+    clients_list: &[models::Client],
+    plans: &HashMap<String, models::Plan>,
+    settings: &ExitQuotaSettings,
+    conn: &PgConnection,
+) {
+    trace!("Checking quota notifications");
+    if settings.warning_percentages.is_empty() {
+        return;
+    }
+
+    for client in clients_list {
+        if !client.verified {
+            continue;
+        }
+        let quota = match get_client_quota(client, plans.get(&client.plan), settings) {
+            Some(quota) => quota,
+            None => continue,
+        };
+        let percent_used = quota_percent_used(current_period_usage(client, settings), quota);
+        let already_warned = client.quota_warning_level.max(0) as u8;
+        let level =
+            match next_warning_level(percent_used, already_warned, &settings.warning_percentages) {
+                Some(level) => level,
+                None => continue,
+            };
+
+        let res = match EXIT_VERIF_SETTINGS.clone() {
+            Some(ExitVerifSettings::Email(mailer)) => {
+                send_quota_warning_email(&client.email, mailer, settings, level)
+            }
+            Some(ExitVerifSettings::Phone(phone)) => {
+                send_quota_warning_sms(&client.phone, phone, settings, level)
+            }
+            Some(ExitVerifSettings::Approval(_)) | None => {
+                trace!(
+                    "No notification method to warn {} of quota",
+                    client.wg_pubkey
+                );
+                continue;
+            }
+        };
+
+        match res {
+            Ok(_) => {
+                if let Err(e) = set_quota_warning_level(client, level, conn) {
+                    error!(
+                        "Failed to record quota warning for {} with {:?}",
+                        client.wg_pubkey, e
+                    );
+                }
+            }
+            Err(e) => warn!(
+                "Failed to warn {} of their quota usage with {:?}",
+                client.wg_pubkey, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_percent_used() {
+        assert_eq!(quota_percent_used(0, 100), 0);
+        assert_eq!(quota_percent_used(79, 100), 79);
+        assert_eq!(quota_percent_used(100, 100), 100);
+        assert_eq!(quota_percent_used(u64::MAX, 100), u8::MAX);
+        assert_eq!(quota_percent_used(1, 0), 100);
+    }
+
+    #[test]
+    fn test_next_warning_level() {
+        let levels = [50, 80, 100];
+        assert_eq!(next_warning_level(10, 0, &levels), None);
+        assert_eq!(next_warning_level(55, 0, &levels), Some(50));
+        assert_eq!(next_warning_level(55, 50, &levels), None);
+        // skipping straight past several levels only sends the highest one
+        assert_eq!(next_warning_level(120, 0, &levels), Some(100));
+        assert_eq!(next_warning_level(120, 100, &levels), None);
+    }
+
+    #[test]
+    fn test_plan_cap_overrides_default() {
+        let settings = ExitQuotaSettings {
+            default_quota: Some(1000),
+            ..Default::default()
+        };
+        let mut client = models::Client {
+            period_start: secs_since_unix_epoch(),
+            period_usage: 1500,
+            ..Default::default()
+        };
+        assert!(quota_exhausted(&client, None, &settings));
+
+        let mut plan = models::Plan {
+            name: "big".to_string(),
+            monthly_data_cap: 2000,
+            ..Default::default()
+        };
+        client.plan = "big".to_string();
+        assert!(!quota_exhausted(&client, Some(&plan), &settings));
+
+        // unlimited plans are never exhausted
+        plan.monthly_data_cap = 0;
+        client.period_usage = i64::MAX;
+        assert!(!quota_exhausted(&client, Some(&plan), &settings));
+    }
+
+    #[test]
+    fn test_expired_period() {
+        let settings = ExitQuotaSettings {
+            default_quota: Some(1000),
+            exhausted_action: QuotaAction::Block,
+            ..Default::default()
+        };
+        let client = models::Client {
+            period_start: secs_since_unix_epoch() - settings.billing_period as i64 - 1,
+            period_usage: 1500,
+            ..Default::default()
+        };
+        assert!(!quota_blocked(&client, None, &settings));
+    }
+
+    #[test]
+    fn test_rolled_over_period_start() {
+        // a rollover that runs late doesn't push the next period back
+        assert_eq!(
+            rolled_over_period_start(1000, 1000 + 3600 + 300, 3600),
+            4600
+        );
+        // periods missed entirely are skipped
+        assert_eq!(
+            rolled_over_period_start(1000, 1000 + 3 * 3600 + 5, 3600),
+            11800
+        );
+        assert_eq!(rolled_over_period_start(1000, 4600, 3600), 4600);
+    }
+}
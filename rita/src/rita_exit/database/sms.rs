@@ -1,108 +1,50 @@
+use crate::rita_exit::database::database_tools::get_client_details;
 use crate::rita_exit::database::database_tools::text_sent;
 use crate::rita_exit::database::database_tools::verify_client;
 use crate::rita_exit::database::get_database_connection;
 use crate::rita_exit::database::get_exit_info;
+use crate::rita_exit::database::sms_provider::get_sms_provider;
 use crate::rita_exit::database::struct_tools::texts_sent;
-use actix::Arbiter;
-use actix_web::client as actix_client;
-use actix_web::client::ClientResponse;
-use althea_types::{ExitClientDetails, ExitClientIdentity, ExitState};
+use althea_types::{ExitClientIdentity, ExitState};
 use failure::Error;
 use futures01::future;
-use futures01::future::Either;
 use futures01::future::Future;
+use handlebars::Handlebars;
 use phonenumber::PhoneNumber;
+use settings::exit::ExitQuotaSettings;
 use settings::exit::PhoneVerifSettings;
 
-#[derive(Serialize)]
-pub struct SmsCheck {
-    api_key: String,
-    verification_code: String,
-    phone_number: String,
-    country_code: String,
-}
-
-/// Posts to the validation endpoint with the code, will return success if the code
-/// is the same as the one sent to the user
-fn check_text(
-    number: String,
-    code: String,
-    api_key: String,
-) -> impl Future<Item = bool, Error = Error> {
-    trace!("About to check text message status for {}", number);
-    let number: PhoneNumber = match number.parse() {
-        Ok(number) => number,
-        Err(e) => return Either::A(future::err(e)),
-    };
-    let url = "https://api.authy.com/protected/json/phones/verification/check";
-    Either::B(
-        actix_client::get(&url)
-            .form(&SmsCheck {
-                api_key,
-                verification_code: code,
-                phone_number: number.national().to_string(),
-                country_code: number.code().value().to_string(),
-            })
-            .unwrap()
-            .send()
-            .from_err()
-            .and_then(|value| {
-                trace!("Got {} back from check text", value.status());
-                Ok(value.status().is_success())
-            }),
-    )
-}
-
-#[derive(Serialize)]
-pub struct SmsRequest {
-    api_key: String,
-    via: String,
-    phone_number: String,
-    country_code: String,
-}
-
-/// Sends the authy verification text by hitting the api endpoint
-fn send_text(number: String, api_key: String) -> impl Future<Item = ClientResponse, Error = Error> {
-    info!("Sending message for {}", number);
-    let url = "https://api.authy.com/protected/json/phones/verification/start";
-    let number: PhoneNumber = match number.parse() {
-        Ok(number) => number,
-        Err(e) => return Either::A(future::err(e)),
-    };
-    Either::B(
-        actix_client::post(&url)
-            .form(&SmsRequest {
-                api_key,
-                via: "sms".to_string(),
-                phone_number: number.national().to_string(),
-                country_code: number.code().value().to_string(),
-            })
-            .unwrap()
-            .send()
-            .from_err(),
-    )
-}
-
 /// Handles the minutia of phone registration states
 pub fn handle_sms_registration(
     client: ExitClientIdentity,
     their_record: exit_db::models::Client,
-    api_key: String,
+    phone: PhoneVerifSettings,
 ) -> impl Future<Item = ExitState, Error = Error> {
     info!(
         "Handling phone registration for {}",
         client.global.wg_public_key
     );
+    let provider = get_sms_provider(&phone);
     let text_num = texts_sent(&their_record);
     let sent_more_than_allowed_texts = text_num > 10;
+    let number: Option<PhoneNumber> = match client.reg_details.phone.clone() {
+        Some(number) => match number.parse() {
+            Ok(number) => Some(number),
+            Err(e) => {
+                return Box::new(future::err(e.into()))
+                    as Box<dyn Future<Item = ExitState, Error = Error>>
+            }
+        },
+        None => None,
+    };
     match (
-        client.reg_details.phone.clone(),
+        number,
         client.reg_details.phone_code.clone(),
         sent_more_than_allowed_texts,
     ) {
         // all texts exhausted, but they can still submit the correct code
         (Some(number), Some(code), true) => {
-            Box::new(check_text(number, code, api_key).and_then(move |result| {
+            Box::new(provider.check_code(&number, &code).and_then(move |result| {
                 get_database_connection().and_then(move |conn| {
                     if result {
                         verify_client(&client, true, &conn)?;
@@ -112,9 +54,7 @@ pub fn handle_sms_registration(
                             client.global.wg_public_key
                         );
                         Ok(ExitState::Registered {
-                            our_details: ExitClientDetails {
-                                client_internal_ip: their_record.internal_ip.parse()?,
-                            },
+                            our_details: get_client_details(&their_record, &conn)?,
                             general_details: get_exit_info(),
                             message: "Registration OK".to_string(),
                         })
@@ -138,7 +78,7 @@ pub fn handle_sms_registration(
         })),
         // user has attempts remaining and is requesting the code be resent
         (Some(number), None, false) => {
-            Box::new(send_text(number, api_key).and_then(move |_result| {
+            Box::new(provider.start_verification(&number).and_then(move |_| {
                 get_database_connection().and_then(move |conn| {
                     text_sent(&client, &conn, text_num)?;
                     Ok(ExitState::Pending {
@@ -152,7 +92,7 @@ pub fn handle_sms_registration(
         }
         // user has attempts remaining and is submitting a code
         (Some(number), Some(code), false) => {
-            Box::new(check_text(number, code, api_key).and_then(move |result| {
+            Box::new(provider.check_code(&number, &code).and_then(move |result| {
                 get_database_connection().and_then(move |conn| {
                     trace!("Check text returned {}", result);
                     if result {
@@ -163,9 +103,7 @@ pub fn handle_sms_registration(
                             client.global.wg_public_key
                         );
                         Ok(ExitState::Registered {
-                            our_details: ExitClientDetails {
-                                client_internal_ip: their_record.internal_ip.parse()?,
-                            },
+                            our_details: get_client_details(&their_record, &conn)?,
                             general_details: get_exit_info(),
                             message: "Registration OK".to_string(),
                         })
@@ -187,43 +125,36 @@ pub fn handle_sms_registration(
     }
 }
 
-#[derive(Serialize)]
-pub struct SmsNotification {
-    #[serde(rename = "To")]
-    to: String,
-    #[serde(rename = "From")]
-    from: String,
-    #[serde(rename = "Body")]
-    body: String,
-}
-
 pub fn send_low_balance_sms(number: &str, phone: PhoneVerifSettings) -> Result<(), Error> {
     info!("Sending low balance message for {}", number);
+    let body = phone.balance_notification_body.clone();
+    send_notification_sms(number, &phone, &body)
+}
 
-    let url = format!(
-        "https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json",
-        phone.twillio_account_id
-    );
+pub fn send_withdraw_held_sms(number: &str, phone: PhoneVerifSettings) -> Result<(), Error> {
+    info!("Sending withdraw held message for {}", number);
+    let body = phone.withdraw_held_body.clone();
+    send_notification_sms(number, &phone, &body)
+}
+
+/// Sends a data quota warning, the body is templated with the percentage of the quota used
+pub fn send_quota_warning_sms(
+    number: &str,
+    phone: PhoneVerifSettings,
+    quota: &ExitQuotaSettings,
+    percent: u8,
+) -> Result<(), Error> {
+    info!("Sending {}% quota warning message for {}", percent, number);
+    let reg = Handlebars::new();
+    let body = reg.render_template(&quota.warning_body, &json!({ "percent": percent }))?;
+    send_notification_sms(number, &phone, &body)
+}
+
+fn send_notification_sms(
+    number: &str,
+    phone: &PhoneVerifSettings,
+    body: &str,
+) -> Result<(), Error> {
     let number: PhoneNumber = number.parse()?;
-    let res = actix_client::post(&url)
-        .basic_auth(phone.twillio_account_id, Some(phone.twillio_auth_token))
-        .form(&SmsNotification {
-            to: number.to_string(),
-            from: phone.notification_number,
-            body: phone.balance_notification_body,
-        })
-        .unwrap()
-        .send()
-        .then(move |result| {
-            if result.is_err() {
-                warn!(
-                    "Low balance text to {} failed with {:?}",
-                    number.to_string(),
-                    result
-                );
-            }
-            Ok(())
-        });
-    Arbiter::spawn(res);
-    Ok(())
+    get_sms_provider(phone).send_notification(&number, body)
 }
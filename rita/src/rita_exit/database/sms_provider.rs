@@ -0,0 +1,294 @@
+//! SMS providers used by the exit for phone verification and notifications. The registration
+//! flow only talks to the SmsProvider trait, Authy/Twilio is used in production while the test
+//! provider writes verification codes and notifications to files so that integration tests can
+//! read them, just like the email test_dir mode.
+
+use actix::Arbiter;
+use actix_web::client as actix_client;
+use failure::Error;
+use futures01::future;
+use futures01::future::Future;
+use phonenumber::PhoneNumber;
+use rand::Rng;
+use settings::exit::PhoneVerifSettings;
+use std::fs::create_dir_all;
+use std::fs::read_to_string;
+use std::fs::write;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub trait SmsProvider {
+    /// Sends a verification code to the given number
+    fn start_verification(&self, number: &PhoneNumber)
+        -> Box<dyn Future<Item = (), Error = Error>>;
+    /// Resolves to true if the code is the one that was sent to the number
+    fn check_code(
+        &self,
+        number: &PhoneNumber,
+        code: &str,
+    ) -> Box<dyn Future<Item = bool, Error = Error>>;
+    /// Sends a text message to the given number, delivery is best effort
+    fn send_notification(&self, number: &PhoneNumber, body: &str) -> Result<(), Error>;
+}
+
+/// Selects the provider configured in the phone verification settings
+pub fn get_sms_provider(settings: &PhoneVerifSettings) -> Box<dyn SmsProvider> {
+    if settings.test {
+        Box::new(TestSmsProvider {
+            dir: settings.test_dir.clone(),
+        })
+    } else {
+        Box::new(TwilioSmsProvider {
+            settings: settings.clone(),
+        })
+    }
+}
+
+/// The credentials default to empty so that test mode doesn't need them, so check that a
+/// production config has all of them before anyone tries to register
+pub fn check_sms_settings(settings: &PhoneVerifSettings) -> Result<(), Error> {
+    if settings.test {
+        if settings.test_dir.is_empty() {
+            bail!("SMS test mode is enabled but no test_dir is set");
+        }
+        return Ok(());
+    }
+    let credentials = [
+        ("auth_api_key", &settings.auth_api_key),
+        ("notification_number", &settings.notification_number),
+        ("twillio_account_id", &settings.twillio_account_id),
+        ("twillio_auth_token", &settings.twillio_auth_token),
+    ];
+    let missing: Vec<&str> = credentials
+        .iter()
+        .filter(|(_, value)| value.is_empty())
+        .map(|(name, _)| *name)
+        .collect();
+    if !missing.is_empty() {
+        bail!("Phone verification is missing {}", missing.join(", "));
+    }
+    Ok(())
+}
+
+/// Authy for verification and Twilio for notifications, these use totally separate
+/// apis and credentials despite being the same company
+pub struct TwilioSmsProvider {
+    settings: PhoneVerifSettings,
+}
+
+#[derive(Serialize)]
+pub struct SmsCheck {
+    api_key: String,
+    verification_code: String,
+    phone_number: String,
+    country_code: String,
+}
+
+#[derive(Serialize)]
+pub struct SmsRequest {
+    api_key: String,
+    via: String,
+    phone_number: String,
+    country_code: String,
+}
+
+#[derive(Serialize)]
+pub struct SmsNotification {
+    #[serde(rename = "To")]
+    to: String,
+    #[serde(rename = "From")]
+    from: String,
+    #[serde(rename = "Body")]
+    body: String,
+}
+
+impl SmsProvider for TwilioSmsProvider {
+    /// Sends the authy verification text by hitting the api endpoint
+    fn start_verification(
+        &self,
+        number: &PhoneNumber,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        info!("Sending message for {}", number);
+        let url = "https://api.authy.com/protected/json/phones/verification/start";
+        Box::new(
+            actix_client::post(&url)
+                .form(&SmsRequest {
+                    api_key: self.settings.auth_api_key.clone(),
+                    via: "sms".to_string(),
+                    phone_number: number.national().to_string(),
+                    country_code: number.code().value().to_string(),
+                })
+                .unwrap()
+                .send()
+                .from_err()
+                .and_then(|_| Ok(())),
+        )
+    }
+
+    /// Posts to the validation endpoint with the code, will return success if the code
+    /// is the same as the one sent to the user
+    fn check_code(
+        &self,
+        number: &PhoneNumber,
+        code: &str,
+    ) -> Box<dyn Future<Item = bool, Error = Error>> {
+        trace!("About to check text message status for {}", number);
+        let url = "https://api.authy.com/protected/json/phones/verification/check";
+        Box::new(
+            actix_client::get(&url)
+                .form(&SmsCheck {
+                    api_key: self.settings.auth_api_key.clone(),
+                    verification_code: code.to_string(),
+                    phone_number: number.national().to_string(),
+                    country_code: number.code().value().to_string(),
+                })
+                .unwrap()
+                .send()
+                .from_err()
+                .and_then(|value| {
+                    trace!("Got {} back from check text", value.status());
+                    Ok(value.status().is_success())
+                }),
+        )
+    }
+
+    fn send_notification(&self, number: &PhoneNumber, body: &str) -> Result<(), Error> {
+        let url = format!(
+            "https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json",
+            self.settings.twillio_account_id
+        );
+        let number = number.to_string();
+        let res = actix_client::post(&url)
+            .basic_auth(
+                self.settings.twillio_account_id.clone(),
+                Some(self.settings.twillio_auth_token.clone()),
+            )
+            .form(&SmsNotification {
+                to: number.clone(),
+                from: self.settings.notification_number.clone(),
+                body: body.to_string(),
+            })
+            .unwrap()
+            .send()
+            .then(move |result| {
+                if result.is_err() {
+                    warn!("Notification text to {} failed with {:?}", number, result);
+                }
+                Ok(())
+            });
+        Arbiter::spawn(res);
+        Ok(())
+    }
+}
+
+/// Writes verification codes to `<test_dir>/<number>.code` and appends notifications
+/// to `<test_dir>/<number>.notifications` instead of sending anything
+pub struct TestSmsProvider {
+    dir: String,
+}
+
+impl TestSmsProvider {
+    fn path_for(&self, number: &PhoneNumber, extension: &str) -> PathBuf {
+        PathBuf::from(&self.dir).join(format!("{}.{}", number, extension))
+    }
+}
+
+impl SmsProvider for TestSmsProvider {
+    fn start_verification(
+        &self,
+        number: &PhoneNumber,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        let mut rng = rand::thread_rng();
+        let code: u64 = rng.gen_range(0, 999_999);
+        info!("Writing test verification code for {}", number);
+        let res = create_dir_all(&self.dir)
+            .and_then(|_| write(self.path_for(number, "code"), format!("{:06}", code)));
+        Box::new(future::result(res.map_err(Error::from)))
+    }
+
+    fn check_code(
+        &self,
+        number: &PhoneNumber,
+        code: &str,
+    ) -> Box<dyn Future<Item = bool, Error = Error>> {
+        let res = match read_to_string(self.path_for(number, "code")) {
+            Ok(sent_code) => Ok(sent_code.trim() == code.trim()),
+            // no code was ever sent, so this one can't be right
+            Err(_) => Ok(false),
+        };
+        Box::new(future::result(res))
+    }
+
+    fn send_notification(&self, number: &PhoneNumber, body: &str) -> Result<(), Error> {
+        create_dir_all(&self.dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path_for(number, "notifications"))?;
+        writeln!(file, "{}", body)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    fn test_provider(name: &str) -> TestSmsProvider {
+        let dir = temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        TestSmsProvider {
+            dir: dir.to_str().unwrap().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_check_sms_settings() {
+        let mut settings = PhoneVerifSettings {
+            auth_api_key: "key".to_string(),
+            notification_number: "+18007664032".to_string(),
+            twillio_account_id: "account".to_string(),
+            twillio_auth_token: "token".to_string(),
+            ..Default::default()
+        };
+        assert!(check_sms_settings(&settings).is_ok());
+
+        settings.twillio_auth_token = String::new();
+        settings.auth_api_key = String::new();
+        let e = check_sms_settings(&settings).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Phone verification is missing auth_api_key, twillio_auth_token"
+        );
+
+        // test mode sends nothing so it only needs somewhere to write
+        settings.test = true;
+        assert!(check_sms_settings(&settings).is_err());
+        settings.test_dir = "/tmp/sms".to_string();
+        assert!(check_sms_settings(&settings).is_ok());
+    }
+
+    #[test]
+    fn test_file_provider_verification() {
+        let provider = test_provider("rita_test_sms_verification");
+        let number: PhoneNumber = "+18007664032".parse().unwrap();
+        assert!(!provider.check_code(&number, "123456").wait().unwrap());
+
+        provider.start_verification(&number).wait().unwrap();
+        let code = read_to_string(provider.path_for(&number, "code")).unwrap();
+        assert!(provider.check_code(&number, &code).wait().unwrap());
+        assert!(!provider.check_code(&number, "not a code").wait().unwrap());
+    }
+
+    #[test]
+    fn test_file_provider_notification() {
+        let provider = test_provider("rita_test_sms_notification");
+        let number: PhoneNumber = "+18007664032".parse().unwrap();
+        provider.send_notification(&number, "first").unwrap();
+        provider.send_notification(&number, "second").unwrap();
+        let sent = read_to_string(provider.path_for(&number, "notifications")).unwrap();
+        assert_eq!(sent, "first\nsecond\n");
+    }
+}
@@ -1,5 +1,6 @@
 use althea_kernel_interface::ExitClient;
 use althea_types::ExitClientIdentity;
+use althea_types::ExitServicePlan;
 use althea_types::Identity;
 use arrayvec::ArrayString;
 use exit_db::models;
@@ -83,5 +84,27 @@ pub fn client_to_new_db_client(
         email_sent_time: 0,
         last_seen: 0,
         last_balance_warning_time: 0,
+        plan: String::new(),
+        period_start: 0,
+        period_usage: 0,
+        quota_warning_level: 0,
+        denied: false,
+        approval_message: String::new(),
+    }
+}
+
+/// Converts a database plan entry into the form we send to clients, the database
+/// only has signed types so we clamp anything negative to zero on the way out
+pub fn to_service_plan(plan: &models::Plan) -> ExitServicePlan {
+    ExitServicePlan {
+        name: plan.name.clone(),
+        price_per_byte: plan.price_per_byte.max(0) as u64,
+        guaranteed_kbit: plan.guaranteed_kbit.max(0) as u32,
+        burst_kbit: plan.burst_kbit.max(0) as u32,
+        monthly_data_cap: if plan.monthly_data_cap > 0 {
+            Some(plan.monthly_data_cap as u64)
+        } else {
+            None
+        },
     }
 }
@@ -3,7 +3,12 @@
 
 use crate::rita_common::debt_keeper::DebtKeeper;
 use crate::rita_common::debt_keeper::GetDebtsList;
+use crate::rita_exit::database::database_tools::create_or_update_plan;
 use crate::rita_exit::database::database_tools::get_database_connection;
+use crate::rita_exit::database::database_tools::get_pending_clients;
+use crate::rita_exit::database::database_tools::get_plans;
+use crate::rita_exit::database::database_tools::set_client_approval;
+use crate::rita_exit::database::database_tools::set_client_plan;
 #[cfg(feature = "development")]
 use crate::rita_exit::database::db_client::DbClient;
 #[cfg(feature = "development")]
@@ -21,6 +26,8 @@ use althea_types::WgKey;
 use althea_types::{
     EncryptedExitClientIdentity, EncryptedExitState, ExitClientIdentity, ExitState,
 };
+use exit_db::models::Client;
+use exit_db::models::Plan;
 use failure::Error;
 use futures01::future;
 use futures01::Future;
@@ -276,3 +283,100 @@ pub fn nuke_db(_req: HttpRequest) -> Box<Future<Item = HttpResponse, Error = Err
         .and_then(move |_| Ok(HttpResponse::NoContent().finish()))
         .responder()
 }
+
+/// Lists all the service plans configured on this exit
+pub fn get_service_plans(_req: HttpRequest) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    trace!("/plans GET hit");
+    Box::new(get_database_connection().and_then(|conn| {
+        let plans: Vec<Plan> = get_plans(&conn)?.into_iter().map(|(_, v)| v).collect();
+        Ok(HttpResponse::Ok().json(plans))
+    }))
+}
+
+/// Creates a service plan, or updates an existing plan with the same name
+pub fn set_service_plan(plan: Json<Plan>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let plan = plan.into_inner();
+    info!("/plans POST hit with {:?}", plan);
+    Box::new(get_database_connection().and_then(move |conn| {
+        match create_or_update_plan(&plan, &conn) {
+            Ok(_) => Ok(HttpResponse::Ok().json(())),
+            Err(e) => Ok(HttpResponse::BadRequest().json(format!("{}", e))),
+        }
+    }))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlanAssignment {
+    pub wg_pubkey: WgKey,
+    /// the plan name, or an empty string to return the client to the defaults
+    pub plan: String,
+}
+
+/// Assigns a registered client to a service plan
+pub fn assign_service_plan(
+    assignment: Json<PlanAssignment>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let assignment = assignment.into_inner();
+    info!("/plans/assign hit with {:?}", assignment);
+    Box::new(get_database_connection().and_then(move |conn| {
+        match set_client_plan(&assignment.wg_pubkey.to_string(), &assignment.plan, &conn) {
+            Ok(_) => Ok(HttpResponse::Ok().json(())),
+            Err(e) => Ok(HttpResponse::BadRequest().json(format!("{}", e))),
+        }
+    }))
+}
+
+/// Lists clients waiting on an operator decision, only meaningful in approval mode
+pub fn get_pending_clients_http(
+    _req: HttpRequest,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    trace!("/clients/pending hit");
+    Box::new(get_database_connection().and_then(|conn| {
+        let clients: Vec<Client> = get_pending_clients(&conn)?;
+        Ok(HttpResponse::Ok().json(clients))
+    }))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApprovalDecision {
+    pub wg_pubkey: WgKey,
+    /// shown to the client along with the decision
+    #[serde(default)]
+    pub message: String,
+}
+
+fn set_approval(
+    decision: ApprovalDecision,
+    approved: bool,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    Box::new(get_database_connection().and_then(move |conn| {
+        match set_client_approval(
+            &decision.wg_pubkey.to_string(),
+            approved,
+            &decision.message,
+            &conn,
+        ) {
+            Ok(_) => Ok(HttpResponse::Ok().json(())),
+            Err(e) => Ok(HttpResponse::BadRequest().json(format!("{}", e))),
+        }
+    }))
+}
+
+/// Approves a client registration, the client is set up on the next exit loop
+pub fn approve_client(
+    decision: Json<ApprovalDecision>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let decision = decision.into_inner();
+    info!("/clients/approve hit with {:?}", decision);
+    set_approval(decision, true)
+}
+
+/// Denies a client registration, if the client was already set up it's removed
+/// from the exit tunnel on the next exit loop
+pub fn deny_client(
+    decision: Json<ApprovalDecision>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let decision = decision.into_inner();
+    info!("/clients/deny hit with {:?}", decision);
+    set_approval(decision, false)
+}
@@ -19,10 +19,14 @@
 //! actix work together on this on properly, not that I've every seen simple actors like the loop crash
 //! very often.
 
+use crate::rita_common::babel_manager;
 use crate::rita_exit::database::database_tools::get_database_connection;
+use crate::rita_exit::database::database_tools::get_plans;
+use crate::rita_exit::database::quota::quota_notifications;
 use crate::rita_exit::database::struct_tools::clients_to_ids;
 use crate::rita_exit::database::{
-    cleanup_exit_clients, enforce_exit_clients, setup_clients, validate_clients_region,
+    cleanup_exit_clients, enforce_exit_clients, get_plan_prices, setup_clients,
+    validate_clients_region,
 };
 use crate::rita_exit::network_endpoints::*;
 use crate::rita_exit::traffic_watcher::{TrafficWatcher, Watch};
@@ -35,9 +39,6 @@ use actix::{
 use actix_web::http::Method;
 use actix_web::{server, App};
 use althea_kernel_interface::ExitClient;
-use babel_monitor::open_babel_stream;
-use babel_monitor::parse_routes;
-use babel_monitor::start_connection;
 use diesel::query_dsl::RunQueryDsl;
 use diesel::r2d2::ConnectionManager;
 use diesel::r2d2::PooledConnection;
@@ -118,7 +119,6 @@ impl Handler<Tick> for RitaLoop {
     fn handle(&mut self, msg: Tick, _ctx: &mut Context<Self>) -> Self::Result {
         let start = Instant::now();
         use exit_db::schema::clients::dsl::clients;
-        let babel_port = SETTING.get_network().babel_port;
         info!("Exit tick!");
 
         // opening a database connection takes at least several milliseconds, as the database server
@@ -127,22 +127,20 @@ impl Handler<Tick> for RitaLoop {
         let conn = msg.0;
 
         let clients_list = clients.load::<models::Client>(&conn)?;
+        let plans = get_plans(&conn)?;
         let ids = clients_to_ids(clients_list.clone());
+        let plan_prices = get_plan_prices(&clients_list, &plans);
 
         // watch and bill for traffic
         Arbiter::spawn(
-            open_babel_stream(babel_port)
-                .from_err()
-                .and_then(|stream| {
-                    start_connection(stream).and_then(|stream| {
-                        parse_routes(stream).and_then(|routes| {
-                            TrafficWatcher::from_registry().do_send(Watch {
-                                users: ids,
-                                routes: routes.1,
-                            });
-                            Ok(())
-                        })
-                    })
+            babel_manager::get_routes()
+                .and_then(|routes| {
+                    TrafficWatcher::from_registry().do_send(Watch {
+                        users: ids,
+                        routes,
+                        plan_prices,
+                    });
+                    Ok(())
                 })
                 .timeout(EXIT_LOOP_TIMEOUT)
                 .then(|ret| {
@@ -154,7 +152,7 @@ impl Handler<Tick> for RitaLoop {
         );
 
         // Create and update client tunnels
-        match setup_clients(&clients_list, &self.wg_clients) {
+        match setup_clients(&clients_list, &self.wg_clients, &plans) {
             Ok(wg_clients) => self.wg_clients = wg_clients,
             Err(e) => error!("Setup clients failed with {:?}", e),
         }
@@ -166,6 +164,9 @@ impl Handler<Tick> for RitaLoop {
             error!("Exit client cleanup failed with {:?}", res);
         }
 
+        // warn clients who are approaching their data quota
+        quota_notifications(&clients_list, &plans, &SETTING.get_quota_settings(), &conn);
+
         // Make sure no one we are setting up is geoip unauthorized
         if !SETTING.get_allowed_countries().is_empty() {
             Arbiter::spawn(validate_clients_region(clients_list.clone()));
@@ -173,7 +174,7 @@ impl Handler<Tick> for RitaLoop {
 
         // handle enforcement on client tunnels by querying debt keeper
         // this consumes client list, you can move it up in exchange for a clone
-        Arbiter::spawn(enforce_exit_clients(clients_list));
+        Arbiter::spawn(enforce_exit_clients(clients_list, plans));
 
         info!(
             "Completed Rita sync loop in {}s {}ms, all vars should be dropped",
@@ -185,7 +186,7 @@ impl Handler<Tick> for RitaLoop {
 }
 
 fn setup_exit_wg_tunnel() {
-    if let Err(e) = KI.setup_wg_if_named("wg_exit") {
+    if let Err(e) = KI.create_wg_interface("wg_exit") {
         warn!("exit setup returned {}", e)
     }
     KI.one_time_exit_setup(
@@ -1,5 +1,5 @@
 //! Traffic watcher monitors system traffic by interfacing with KernelInterface to create and check
-//! iptables and ipset counters on each per hop tunnel (the WireGuard tunnel between two devices). These counts
+//! iptables and ipset (or nftables) counters on each per hop tunnel (the WireGuard tunnel between two devices). These counts
 //! are then stored and used to compute amounts for bills.
 //!
 //! This is the exit specific billing code used to determine how exits should be compensted. Which is
@@ -11,11 +11,16 @@
 use crate::rita_common::debt_keeper;
 use crate::rita_common::debt_keeper::DebtKeeper;
 use crate::rita_common::debt_keeper::Traffic;
+use crate::rita_common::usage_tracker::PeerUsage;
+use crate::rita_common::usage_tracker::PeerUsageType;
+use crate::rita_common::usage_tracker::UpdatePeerUsage;
 use crate::rita_common::usage_tracker::UpdateUsage;
 use crate::rita_common::usage_tracker::UsageTracker;
 use crate::rita_common::usage_tracker::UsageType;
+use crate::rita_exit::database::database_tools::add_client_usage;
+use crate::rita_exit::database::database_tools::get_database_connection;
 use crate::SETTING;
-use ::actix::{Actor, Context, Handler, Message, Supervised, SystemService};
+use ::actix::{Actor, Arbiter, Context, Handler, Message, Supervised, SystemService};
 use althea_kernel_interface::wg_iface_counter::prepare_usage_history;
 use althea_kernel_interface::wg_iface_counter::WgUsage;
 use althea_kernel_interface::KI;
@@ -23,14 +28,24 @@ use althea_types::Identity;
 use althea_types::WgKey;
 use babel_monitor::Route;
 use failure::Error;
+use futures01::Future;
 use ipnetwork::IpNetwork;
 use settings::exit::RitaExitSettings;
 use settings::RitaCommonSettings;
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often per client usage is written to the database for quota tracking, this is
+/// one query per client so we batch it up rather than saving every round
+const USAGE_SAVE_INTERVAL: Duration = Duration::from_secs(300);
 
 pub struct TrafficWatcher {
     last_seen_bytes: HashMap<WgKey, WgUsage>,
+    /// bytes used per client since usage was last saved to the database
+    unsaved_usage: HashMap<WgKey, u64>,
+    last_usage_save: Instant,
 }
 
 impl Actor for TrafficWatcher {
@@ -47,6 +62,8 @@ impl Default for TrafficWatcher {
     fn default() -> TrafficWatcher {
         TrafficWatcher {
             last_seen_bytes: HashMap::new(),
+            unsaved_usage: HashMap::new(),
+            last_usage_save: Instant::now(),
         }
     }
 }
@@ -54,6 +71,9 @@ impl Default for TrafficWatcher {
 pub struct Watch {
     pub users: Vec<Identity>,
     pub routes: Vec<Route>,
+    /// price per byte for clients on a service plan, anyone not in
+    /// this map is billed at the exit price
+    pub plan_prices: HashMap<Identity, u64>,
 }
 
 impl Message for Watch {
@@ -64,7 +84,32 @@ impl Handler<Watch> for TrafficWatcher {
     type Result = Result<(), Error>;
 
     fn handle(&mut self, msg: Watch, _: &mut Context<Self>) -> Self::Result {
-        watch(&mut self.last_seen_bytes, &msg.routes, &msg.users)
+        let usage = watch(
+            &mut self.last_seen_bytes,
+            &msg.routes,
+            &msg.users,
+            &msg.plan_prices,
+        )?;
+        for (key, bytes) in usage {
+            *self.unsaved_usage.entry(key).or_insert(0) += bytes;
+        }
+
+        if self.last_usage_save.elapsed() > USAGE_SAVE_INTERVAL {
+            self.last_usage_save = Instant::now();
+            let usage = std::mem::replace(&mut self.unsaved_usage, HashMap::new());
+            let billing_period = SETTING.get_quota_settings().billing_period as i64;
+            Arbiter::spawn(
+                get_database_connection()
+                    .and_then(move |conn| add_client_usage(&usage, billing_period, &conn))
+                    .then(|res| {
+                        if let Err(e) = res {
+                            error!("Failed to save exit client usage with {:?}", e);
+                        }
+                        Ok(())
+                    }),
+            );
+        }
+        Ok(())
     }
 }
 
@@ -186,11 +231,13 @@ fn debts_logging(debts: &HashMap<Identity, i128>) {
 }
 
 /// This traffic watcher watches how much traffic each we send and receive from each client.
+/// Returns the total bytes moved by each client this round for quota tracking.
 pub fn watch(
     usage_history: &mut HashMap<WgKey, WgUsage>,
     routes: &[Route],
     clients: &[Identity],
-) -> Result<(), Error> {
+    plan_prices: &HashMap<Identity, u64>,
+) -> Result<HashMap<WgKey, u64>, Error> {
     let our_price = SETTING.get_exit_network().exit_price;
     let our_id = match SETTING.get_identity() {
         Some(id) => id,
@@ -222,6 +269,9 @@ pub fn watch(
     counters_logging(&counters, &usage_history, our_price as u32);
 
     let mut debts = HashMap::new();
+    let mut client_usage: HashMap<WgKey, u64> = HashMap::new();
+    // bytes sent to and received from each client
+    let mut client_bytes: HashMap<Identity, (u64, u64)> = HashMap::new();
 
     // Setup the debts table
     for (_, ident) in identities.clone() {
@@ -238,10 +288,13 @@ pub fn watch(
         match state {
             (Some(id), Some(_dest), Some(history)) => match debts.get_mut(&id) {
                 Some(debt) => {
+                    let our_price = *plan_prices.get(id).unwrap_or(&our_price);
                     let used = bytes.download - history.download;
                     let value = i128::from(our_price) * i128::from(used);
                     trace!("We are billing for {} bytes input (client output) times a exit price of {} for a total of -{}", used, our_price, value);
                     *debt -= value;
+                    *client_usage.entry(wg_key).or_insert(0) += used;
+                    client_bytes.entry(*id).or_insert((0, 0)).1 += used;
                     // update history so that we know what was used from previous cycles
                     history.download = bytes.download;
                 }
@@ -268,10 +321,13 @@ pub fn watch(
         match state {
             (Some(id), Some(dest), Some(history)) => match debts.get_mut(&id) {
                 Some(debt) => {
+                    let our_price = *plan_prices.get(id).unwrap_or(&our_price);
                     let used = bytes.upload - history.upload;
                     let value = i128::from(dest + our_price) * i128::from(used);
                     trace!("We are billing for {} bytes output (client input) times a exit dest price of {} for a total of -{}", used, dest + our_price, value);
                     *debt -= value;
+                    *client_usage.entry(wg_key).or_insert(0) += used;
+                    client_bytes.entry(*id).or_insert((0, 0)).0 += used;
                     history.upload = bytes.upload;
                 }
                 // debts is generated from identities, this should be impossible
@@ -289,6 +345,18 @@ pub fn watch(
 
     debts_logging(&debts);
 
+    let mut peer_usage = Vec::new();
+    for (id, (up, down)) in client_bytes {
+        peer_usage.push(PeerUsage {
+            kind: PeerUsageType::ExitClient,
+            id,
+            up,
+            down,
+            billed: debts.get(&id).cloned().unwrap_or(0).into(),
+        });
+    }
+    UsageTracker::from_registry().do_send(UpdatePeerUsage { usage: peer_usage });
+
     let mut traffic_vec = Vec::new();
     for (from, amount) in debts {
         traffic_vec.push(Traffic {
@@ -301,5 +369,5 @@ pub fn watch(
     };
     DebtKeeper::from_registry().do_send(update);
 
-    Ok(())
+    Ok(client_usage)
 }
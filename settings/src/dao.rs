@@ -9,6 +9,14 @@ fn default_oracle_enabled() -> bool {
     true
 }
 
+fn default_oracle_quorum() -> usize {
+    1
+}
+
+fn default_oracle_history_file() -> String {
+    "/etc/rita-oracle-history.json".to_string()
+}
+
 fn default_use_oracle_price() -> bool {
     true
 }
@@ -43,4 +51,25 @@ pub struct SubnetDAOSettings {
     /// configured
     #[serde(default = "default_oracle_url")]
     pub oracle_url: Option<String>,
+    /// More oracles queried alongside oracle_url, they should all serve the same signed update
+    #[serde(default)]
+    pub oracle_mirror_urls: Vec<String>,
+    /// How many of the configured oracles have to serve the same valid update before it's
+    /// applied
+    #[serde(default = "default_oracle_quorum")]
+    pub oracle_quorum: usize,
+    /// Addresses whose signature makes an oracle update valid, updates not signed by one of
+    /// them are rejected. If this is empty unsigned updates from older oracles are accepted
+    #[serde(default)]
+    pub oracle_signers: Vec<Address>,
+    /// When set oracle updates are fetched and verified but only recorded as pending instead
+    /// of being applied, so the change can be reviewed first
+    #[serde(default)]
+    pub oracle_dry_run: bool,
+    /// Where the history of applied oracle updates is kept
+    #[serde(default = "default_oracle_history_file")]
+    pub oracle_history_file: String,
+    /// The sequence of the last oracle update applied, only newer signed updates are accepted
+    #[serde(default)]
+    pub oracle_last_sequence: u64,
 }
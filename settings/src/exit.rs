@@ -47,6 +47,14 @@ pub struct ExitNetworkSettings {
     /// api credentials for Maxmind geoip
     pub geoip_api_user: Option<String>,
     pub geoip_api_key: Option<String>,
+    /// path to a local MaxMind format (mmdb) country database, when configured it is
+    /// consulted before the web api, which becomes an optional fallback
+    #[serde(default)]
+    pub geoip_db_path: Option<String>,
+    /// path to persist the cache of web api lookups to so that they survive restarts, entries
+    /// expire after 30 days
+    #[serde(default)]
+    pub geoip_cache_path: Option<String>,
     /// The our public key for the wg_exit tunnel
     pub wg_public_key: WgKey,
     /// Our private key for the wg_exit tunnel, not an option because it's better
@@ -71,6 +79,8 @@ impl ExitNetworkSettings {
             entry_timeout: 0,
             geoip_api_user: None,
             geoip_api_key: None,
+            geoip_db_path: None,
+            geoip_cache_path: None,
             wg_public_key: WgKey::from_str("Ha2YlTfDimJNboqxOSCh6M29W/H0jKtB4utitjaTO3A=").unwrap(),
             wg_private_key: WgKey::from_str("mFFBLqQYrycxfHo10P9l8I2G7zbw8tia4WkGGgjGCn8=")
                 .unwrap(),
@@ -97,6 +107,14 @@ fn default_balance_notification_email_body() -> String {
     String::from("Your Althea router has a low balance! Your service will be slow until more funds are added. Visit althea.net/add-funds")
 }
 
+fn default_withdraw_held_email_subject() -> String {
+    String::from("Althea withdraw held")
+}
+
+fn default_withdraw_held_body() -> String {
+    String::from("A withdraw from your Althea router was held by its withdraw policy. If you didn't make it, cancel it from your router's dashboard.")
+}
+
 /// These are the settings for email verification
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
 pub struct EmailVerifSettings {
@@ -118,6 +136,12 @@ pub struct EmailVerifSettings {
     #[serde(default = "default_balance_notification_email_body")]
     pub balance_notification_body: String,
 
+    #[serde(default = "default_withdraw_held_email_subject")]
+    pub withdraw_held_subject: String,
+
+    #[serde(default = "default_withdraw_held_body")]
+    pub withdraw_held_body: String,
+
     #[serde(default)]
     pub test: bool,
     #[serde(default)]
@@ -146,26 +170,52 @@ fn default_balance_notification_text_body() -> String {
 /// These are the settings for text message verification using the twillio api
 /// note that while you would expect the authentication and text notification flow
 /// to be the same they are in fact totally different and each have seperate
-/// credentials below
+/// credentials below. When test is set no texts are sent, instead codes and
+/// notifications are written to files in test_dir for integration tests to read.
+/// The credentials are only optional in test mode, the exit refuses to start
+/// without them otherwise
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
 pub struct PhoneVerifSettings {
     /// API key used for the authenticaiton calls
+    #[serde(default)]
     pub auth_api_key: String,
     /// The Twillio number used to send the notification message
+    #[serde(default)]
     pub notification_number: String,
     /// The Twillio account id used to authenticate for notifications
+    #[serde(default)]
     pub twillio_account_id: String,
     /// The auth token used to authenticate for notifications
+    #[serde(default)]
     pub twillio_auth_token: String,
+    #[serde(default)]
+    pub test: bool,
+    #[serde(default)]
+    pub test_dir: String,
     /// the text for the balance notification
     #[serde(default = "default_balance_notification_text_body")]
     pub balance_notification_body: String,
+    /// the text sent when a client reports that its withdraw policy held a withdraw
+    #[serde(default = "default_withdraw_held_body")]
+    pub withdraw_held_body: String,
     /// time in seconds between notifications
     pub balance_notification_interval: u32,
     /// True if the exit should notify clients when they have a low balance
     pub notify_low_balance: bool,
 }
 
+fn default_approval_pending_message() -> String {
+    String::from("Awaiting approval from the exit operator")
+}
+
+/// These are the settings for manual approval of clients by the exit operator
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Default)]
+pub struct ApprovalVerifSettings {
+    /// The message shown to clients while they wait for the operator
+    #[serde(default = "default_approval_pending_message")]
+    pub pending_message: String,
+}
+
 /// Struct containing the different types of supported verification
 /// and their respective settings
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
@@ -173,6 +223,77 @@ pub struct PhoneVerifSettings {
 pub enum ExitVerifSettings {
     Email(EmailVerifSettings),
     Phone(PhoneVerifSettings),
+    Approval(ApprovalVerifSettings),
+}
+
+/// What the exit does with a client that has used up their data quota for
+/// the current billing period
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub enum QuotaAction {
+    /// limit the client to the free tier throughput until the period ends
+    Throttle,
+    /// remove the client from the exit tunnel until the period ends
+    Block,
+}
+
+fn default_billing_period() -> u64 {
+    // 30 days
+    2_592_000
+}
+
+fn default_quota_action() -> QuotaAction {
+    QuotaAction::Throttle
+}
+
+fn default_quota_warning_percentages() -> Vec<u8> {
+    vec![80, 100]
+}
+
+fn default_quota_warning_subject() -> String {
+    String::from("Althea data usage warning")
+}
+
+fn default_quota_warning_body() -> String {
+    // templated using the handlebars language
+    String::from(
+        "Your Althea router has used {{percent}}% of its data allowance for this billing period.",
+    )
+}
+
+/// Settings for data quotas on exit clients, the quota for any given client is
+/// the data cap of their service plan if they have one, otherwise the default quota
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ExitQuotaSettings {
+    /// length of a billing period in seconds, usage is reset at the end of each period
+    #[serde(default = "default_billing_period")]
+    pub billing_period: u64,
+    /// quota in bytes for clients that are not on a service plan, None for no quota
+    #[serde(default)]
+    pub default_quota: Option<u64>,
+    #[serde(default = "default_quota_action")]
+    pub exhausted_action: QuotaAction,
+    /// percentages of the quota at which clients are warned via the configured
+    /// verification method (email or sms)
+    #[serde(default = "default_quota_warning_percentages")]
+    pub warning_percentages: Vec<u8>,
+    #[serde(default = "default_quota_warning_subject")]
+    pub warning_subject: String,
+    /// the {{percent}} template is replaced with the percentage of the quota used
+    #[serde(default = "default_quota_warning_body")]
+    pub warning_body: String,
+}
+
+impl Default for ExitQuotaSettings {
+    fn default() -> Self {
+        ExitQuotaSettings {
+            billing_period: default_billing_period(),
+            default_quota: None,
+            exhausted_action: default_quota_action(),
+            warning_percentages: default_quota_warning_percentages(),
+            warning_subject: default_quota_warning_subject(),
+            warning_body: default_quota_warning_body(),
+        }
+    }
 }
 
 /// This is the main settings struct for rita_exit
@@ -195,6 +316,8 @@ pub struct RitaExitSettingsStruct {
     allowed_countries: HashSet<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     verif_settings: Option<ExitVerifSettings>, // mailer's successor with new verif methods readiness
+    #[serde(default)]
+    quota: ExitQuotaSettings,
     #[serde(skip)]
     future: bool,
 }
@@ -214,6 +337,7 @@ impl RitaExitSettingsStruct {
             exit_network: ExitNetworkSettings::test_default(),
             allowed_countries: HashSet::new(),
             verif_settings: None,
+            quota: ExitQuotaSettings::default(),
             future: false,
         }
     }
@@ -233,6 +357,7 @@ pub trait RitaExitSettings {
     fn get_allowed_countries<'ret, 'me: 'ret>(
         &'me self,
     ) -> RwLockReadGuardRef<'ret, RitaExitSettingsStruct, HashSet<String>>;
+    fn get_quota_settings(&self) -> ExitQuotaSettings;
 }
 
 impl RitaExitSettings for Arc<RwLock<RitaExitSettingsStruct>> {
@@ -258,6 +383,9 @@ impl RitaExitSettings for Arc<RwLock<RitaExitSettingsStruct>> {
     fn get_verif_settings(&self) -> Option<ExitVerifSettings> {
         self.read().unwrap().verif_settings.clone()
     }
+    fn get_quota_settings(&self) -> ExitQuotaSettings {
+        self.read().unwrap().quota.clone()
+    }
     fn get_verif_settings_mut<'ret, 'me: 'ret>(
         &'me self,
     ) -> RwLockWriteGuardRefMut<'ret, RitaExitSettingsStruct, Option<ExitVerifSettings>> {
@@ -127,6 +127,10 @@ pub struct NetworkSettings {
     /// the maximum bandwidth of the fastest interface of the device.
     #[serde(default = "default_starting_bandwidth_limit")]
     pub starting_bandwidth_limit: usize,
+    /// Count per destination traffic with eBPF programs attached to the tunnels instead of
+    /// firewall rules, falls back to the firewall if the kernel can't load them
+    #[serde(default)]
+    pub ebpf_counters: bool,
 }
 
 impl Default for NetworkSettings {
@@ -135,6 +139,7 @@ impl Default for NetworkSettings {
             bandwidth_limit_enabled: default_bandwidth_limit_enabled(),
             minimum_bandwidth_limit: default_minimum_bandwidth_limit(),
             starting_bandwidth_limit: default_starting_bandwidth_limit(),
+            ebpf_counters: false,
             backup_created: false,
             metric_factor: default_metric_factor(),
             mesh_ip: None,
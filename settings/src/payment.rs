@@ -1,5 +1,6 @@
 use althea_types::SystemChain;
 use clarity::{Address, PrivateKey};
+use failure::{bail, Error};
 use num256::{Int256, Uint256};
 use std::str::FromStr;
 
@@ -71,6 +72,14 @@ fn default_debts_file() -> String {
     "/etc/rita-debts.json".to_string()
 }
 
+fn default_bridge_history_file() -> String {
+    "/etc/rita-bridge-history.json".to_string()
+}
+
+fn default_withdraw_queue_file() -> String {
+    "/etc/rita-withdraw-queue.json".to_string()
+}
+
 fn default_bridge_addresses() -> TokenBridgeAddresses {
     TokenBridgeAddresses {
         uniswap_address: Address::from_str("0x2a1530C4C41db0B0b2bB646CB5Eb1A67b7158667").unwrap(),
@@ -111,6 +120,210 @@ fn default_max_gas() -> u64 {
     XDAI_MAX_GAS
 }
 
+/// Which source automatic pricing takes the local fee from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub enum AutoPricingEngine {
+    /// the price set by the oracle
+    Oracle,
+    /// the price computed on this router by the local pricing engine
+    Local,
+}
+
+impl Default for AutoPricingEngine {
+    fn default() -> Self {
+        AutoPricingEngine::Oracle
+    }
+}
+
+impl FromStr for AutoPricingEngine {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<AutoPricingEngine, Error> {
+        match s.to_lowercase().as_str() {
+            "oracle" => Ok(AutoPricingEngine::Oracle),
+            "local" => Ok(AutoPricingEngine::Local),
+            _ => bail!("Unknown pricing engine {}", s),
+        }
+    }
+}
+
+fn default_local_pricing_ceiling() -> u32 {
+    default_max_fee()
+}
+
+fn default_local_pricing_step() -> u32 {
+    10
+}
+
+fn default_relay_capacity_mbps() -> u32 {
+    100
+}
+
+fn default_local_pricing_interval() -> u64 {
+    3600
+}
+
+/// Configures the local pricing engine, which sets local_fee from the relay demand and link
+/// utilization this router sees and the prices of its neighbors
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct LocalPricingSettings {
+    /// Selects where automatic pricing gets the local fee from, only used while automatic
+    /// pricing is enabled with use_oracle_price
+    #[serde(default)]
+    pub engine: AutoPricingEngine,
+    /// The lowest fee the engine will set in wei/byte
+    #[serde(default)]
+    pub floor: u32,
+    /// The highest fee the engine will set in wei/byte, max_fee applies as well
+    #[serde(default = "default_local_pricing_ceiling")]
+    pub ceiling: u32,
+    /// How far the fee may move in one adjustment, in percent of the current fee
+    #[serde(default = "default_local_pricing_step")]
+    pub max_step_percent: u32,
+    /// The combined throughput of our relay links, used to judge how busy they are
+    #[serde(default = "default_relay_capacity_mbps")]
+    pub relay_capacity_mbps: u32,
+    /// How often the fee is adjusted in seconds
+    #[serde(default = "default_local_pricing_interval")]
+    pub adjustment_interval: u64,
+}
+
+impl Default for LocalPricingSettings {
+    fn default() -> Self {
+        LocalPricingSettings {
+            engine: AutoPricingEngine::default(),
+            floor: 0,
+            ceiling: default_local_pricing_ceiling(),
+            max_step_percent: default_local_pricing_step(),
+            relay_capacity_mbps: default_relay_capacity_mbps(),
+            adjustment_interval: default_local_pricing_interval(),
+        }
+    }
+}
+
+fn default_token_transfer_gas() -> u64 {
+    100_000
+}
+
+fn default_gas_warning_level() -> Uint256 {
+    (10_000_000_000_000_000u64).into()
+}
+
+/// An ERC-20 token used for payments in place of the native coin of the system chain, gas
+/// for its transfers is still paid in the native coin
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct PaymentToken {
+    /// The token contract
+    pub contract_address: Address,
+    /// The token's symbol, for display only
+    pub symbol: String,
+    /// The gas limit used for transfer calls
+    #[serde(default = "default_token_transfer_gas")]
+    pub transfer_gas: u64,
+    /// The level of native coin balance below which we warn that we will soon be unable to pay gas
+    #[serde(default = "default_gas_warning_level")]
+    pub gas_warning_level: Uint256,
+}
+
+/// How much of our balance a sweep leaves behind
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub enum SweepReserve {
+    /// This many times the balance_warning_level
+    WarningLevelMultiple(u32),
+    /// A fixed amount in wei
+    Fixed(Uint256),
+}
+
+impl Default for SweepReserve {
+    fn default() -> Self {
+        SweepReserve::WarningLevelMultiple(2)
+    }
+}
+
+fn default_minimum_sweep() -> Uint256 {
+    default_balance_warning_level()
+}
+
+/// Configures automatic withdraws of our earnings to another address, a sweep happens when
+/// the interval has passed since the last one or the balance reaches the threshold
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct SweepSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Where swept funds are sent
+    #[serde(default)]
+    pub destination: Option<Address>,
+    #[serde(default)]
+    pub reserve: SweepReserve,
+    /// Sweep on this schedule, in seconds between sweeps
+    #[serde(default)]
+    pub interval: Option<u64>,
+    /// Sweep whenever the balance reaches this amount in wei
+    #[serde(default)]
+    pub threshold: Option<Uint256>,
+    /// Sweeps smaller than this are skipped so gas isn't spent moving dust
+    #[serde(default = "default_minimum_sweep")]
+    pub minimum: Uint256,
+    /// When the last sweep was started, seconds since the unix epoch
+    #[serde(default)]
+    pub last_sweep: Option<u64>,
+}
+
+impl Default for SweepSettings {
+    fn default() -> Self {
+        SweepSettings {
+            enabled: false,
+            destination: None,
+            reserve: SweepReserve::default(),
+            interval: None,
+            threshold: None,
+            minimum: default_minimum_sweep(),
+            last_sweep: None,
+        }
+    }
+}
+
+fn default_withdraw_delay() -> u64 {
+    86400
+}
+
+/// Spending rules for withdraws made from the dashboard. A withdraw to an address that isn't on
+/// the whitelist or over the daily limit is held until it's approved, a withdraw of at least
+/// delay_threshold waits out the delay first. Held withdraws can be cancelled until they're sent
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct WithdrawPolicy {
+    /// The most that can be withdrawn in any 24 hours without approval, in wei
+    #[serde(default)]
+    pub daily_limit: Option<Uint256>,
+    /// If not empty only these addresses can be withdrawn to without approval
+    #[serde(default)]
+    pub whitelist: Vec<Address>,
+    /// Withdraws of at least this amount in wei are delayed
+    #[serde(default)]
+    pub delay_threshold: Option<Uint256>,
+    /// How long a delayed or approved withdraw waits before it's sent, in seconds
+    #[serde(default = "default_withdraw_delay")]
+    pub delay: u64,
+}
+
+impl WithdrawPolicy {
+    /// Whether any rule is configured, while one is the eth private key can't be exported or
+    /// replaced through the dashboard
+    pub fn is_set(&self) -> bool {
+        self.daily_limit.is_some() || !self.whitelist.is_empty() || self.delay_threshold.is_some()
+    }
+}
+
+impl Default for WithdrawPolicy {
+    fn default() -> Self {
+        WithdrawPolicy {
+            daily_limit: None,
+            whitelist: Vec::new(),
+            delay_threshold: None,
+            delay: default_withdraw_delay(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct TokenBridgeAddresses {
     pub uniswap_address: Address,
@@ -131,6 +344,9 @@ pub struct PaymentSettings {
     /// A price limit, we will not pay more than this
     #[serde(default = "default_max_fee")]
     pub max_fee: u32,
+    /// Settings for the local pricing engine
+    #[serde(default)]
+    pub local_pricing: LocalPricingSettings,
     /// For non-channel payments only, determines how much to multiply the nominal gas price
     /// to get the pay_threshold values and then again for the close_threshold
     #[serde(default = "default_dynamic_fee_multiplier")]
@@ -155,8 +371,16 @@ pub struct PaymentSettings {
     pub eth_private_key: Option<PrivateKey>,
     // Our own eth Address, derived from the private key on startup and not stored
     pub eth_address: Option<Address>,
+    /// Our balance in the coin we pay with, the payment token if one is configured
     #[serde(default)]
     pub balance: Uint256,
+    /// Our balance of the native coin of the system chain, which pays for gas. The same as
+    /// balance unless a payment token is configured
+    #[serde(default)]
+    pub gas_balance: Uint256,
+    /// If set payments are made in this ERC-20 token rather than the native coin
+    #[serde(default)]
+    pub payment_token: Option<PaymentToken>,
     #[serde(default)]
     pub nonce: Uint256,
     #[serde(default)]
@@ -176,11 +400,24 @@ pub struct PaymentSettings {
     /// but has xdai
     #[serde(default = "default_system_chain")]
     pub withdraw_chain: SystemChain,
+    /// Automatic withdraws of our earnings, these can't be changed through the dashboard
+    #[serde(default)]
+    pub sweep: SweepSettings,
+    /// Spending rules for withdraws, these can't be changed through the dashboard
+    #[serde(default)]
+    pub withdraw_policy: WithdrawPolicy,
+    /// Full file path for the queue of held withdraws
+    #[serde(default = "default_withdraw_queue_file")]
+    pub withdraw_queue_file: String,
     /// Full file path for Debts storage
     #[serde(default = "default_debts_file")]
     pub debts_file: String,
     #[serde(default = "default_bridge_enabled")]
     pub bridge_enabled: bool,
+    /// Where the token bridge records its deposits and withdraws so they can be resumed
+    /// after a restart
+    #[serde(default = "default_bridge_history_file")]
+    pub bridge_history_file: String,
     /// A value used to divide and add to a payment, essentailly a cheating tool for
     /// payment convergence. Computed as payment_amount + (payment_amount/fudge_factor)
     /// so a factor of 100 would be a 1% overpayment this helps cover up errors in accounting
@@ -225,6 +462,7 @@ impl Default for PaymentSettings {
         PaymentSettings {
             local_fee: default_local_fee(),
             max_fee: default_max_fee(),
+            local_pricing: LocalPricingSettings::default(),
             dynamic_fee_multiplier: default_dynamic_fee_multiplier(),
             free_tier_throughput: default_free_tier_throughput(),
             client_can_use_free_tier: default_client_can_use_free_tier(),
@@ -238,14 +476,20 @@ impl Default for PaymentSettings {
             eth_private_key: None,
             eth_address: None,
             balance: 0u64.into(),
+            gas_balance: 0u64.into(),
+            payment_token: None,
             nonce: 0u64.into(),
             gas_price: 0u64.into(), // 10 gwei
             net_version: None,
             node_list: Vec::new(),
             system_chain: default_system_chain(),
             withdraw_chain: default_system_chain(),
+            sweep: SweepSettings::default(),
+            withdraw_policy: WithdrawPolicy::default(),
+            withdraw_queue_file: default_withdraw_queue_file(),
             debts_file: default_debts_file(),
             bridge_enabled: default_bridge_enabled(),
+            bridge_history_file: default_bridge_history_file(),
             fudge_factor: 0u8,
             debt_limit_enabled: default_debt_limit_enabled(),
             apply_incoming_credit_immediately: default_apply_incoming_credit(),